@@ -89,7 +89,7 @@ impl InternalObserver {
     /// accordingly
     fn message_receiver(&self, message: Message) {
         match message {
-            Message::Publish(publish) => {
+            Message::Publish { publish, .. } => {
                 self.add_publish(publish);
             }
             Message::Connected(result) => {
@@ -104,6 +104,18 @@ impl InternalObserver {
             Message::Unsubscribed(result) => {
                 self.unsubscribed(result);
             }
+            Message::QoSDowngraded {
+                topic,
+                requested,
+                granted,
+            } => {
+                self.status_message(&format!(
+                    "Suscrito a {} con QoS {} (se pidió QoS {})",
+                    topic,
+                    u8::from(granted),
+                    u8::from(requested)
+                ));
+            }
             Message::InternalError(error) => {
                 alert(&format!(
                     "Error interno: {}\n\nSe recomienda reiniciar el cliente",
@@ -112,6 +124,9 @@ impl InternalObserver {
                 let dis: Button = self.builder().object("discon_btn").unwrap();
                 dis.clicked();
             }
+            // El cierre lo inicia la interfaz al desconectarse,
+            // que ya se actualiza en ese momento
+            Message::Closed { .. } => (),
         }
     }
 
@@ -223,7 +238,7 @@ impl InternalObserver {
             qos_msg.push_str(" (duplicate)");
         }
         let label_qos: Label = Label::new(Some(&qos_msg));
-        let label_payload: Label = Label::new(Some(publish.payload()));
+        let label_payload: Label = Label::new(Some(&String::from_utf8_lossy(publish.payload())));
         label_topic.set_line_wrap(true);
         label_qos.set_line_wrap(true);
         label_payload.set_line_wrap(true);
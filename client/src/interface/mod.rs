@@ -32,6 +32,11 @@ use packets::unsubscribe::Unsubscribe;
 use self::subscription_list::SubscriptionList;
 use self::utils::{Icon, InterfaceUtils};
 
+/// Error shown when the client id is empty but clean session is not set,
+/// since the server only accepts empty ids with clean session
+const MSG_EMPTY_ID_WITHOUT_CLEAN_SESSION: &str =
+    "Para conectarse sin id de cliente, se debe activar clean session";
+
 /// Controller for the client. It both creates the
 /// internal client and handles all the user inputs
 /// from the interface
@@ -228,6 +233,8 @@ impl Controller {
             .parse::<u8>()
             .unwrap();
 
+        validate_client_id(&client_id, clean_session)?;
+
         // Create the connect builder
         let mut connect_builder = ConnectBuilder::new(&client_id, keep_alive, clean_session)?;
         if !user_name.is_empty() {
@@ -336,8 +343,9 @@ impl Controller {
             qos,
             retain,
             &topic_entry.text().to_string(),
-            &msg.text(&msg.start_iter(), &msg.end_iter(), false)
-                .ok_or_else(|| ClientError::new("Se debe completar el campo de mensaje"))?,
+            msg.text(&msg.start_iter(), &msg.end_iter(), false)
+                .ok_or_else(|| ClientError::new("Se debe completar el campo de mensaje"))?
+                .as_str(),
             id,
         )?;
 
@@ -459,3 +467,34 @@ impl Controller {
         self.remove_all_children_from_listbox("sub_msgs");
     }
 }
+
+/// Checks that the client id can be used with the given clean session
+/// value. An empty client id is only accepted with clean session,
+/// so that the server can assign one
+fn validate_client_id(client_id: &str, clean_session: bool) -> Result<(), ClientError> {
+    if client_id.is_empty() && !clean_session {
+        return Err(ClientError::new(MSG_EMPTY_ID_WITHOUT_CLEAN_SESSION));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{validate_client_id, MSG_EMPTY_ID_WITHOUT_CLEAN_SESSION};
+
+    #[test]
+    fn test_empty_id_without_clean_session_is_rejected() {
+        let err = validate_client_id("", false).unwrap_err();
+        assert_eq!(err.to_string(), MSG_EMPTY_ID_WITHOUT_CLEAN_SESSION);
+    }
+
+    #[test]
+    fn test_empty_id_with_clean_session_is_accepted() {
+        assert!(validate_client_id("", true).is_ok());
+    }
+
+    #[test]
+    fn test_non_empty_id_is_accepted() {
+        assert!(validate_client_id("client", false).is_ok());
+    }
+}
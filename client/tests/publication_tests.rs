@@ -81,3 +81,34 @@ fn test_send_publish_qos1() {
     let recv_publish = Publish::read_from(&mut stream, buf[0]).unwrap();
     assert_eq!(pub_bytes, recv_publish.encode().unwrap());
 }
+
+#[test]
+fn test_publish_confirm_qos1() {
+    let mut server = ServerMock::new();
+    let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+    let observer = ObserverMock::new();
+    let mut client = Client::new(
+        &format!("localhost:{}", server.port),
+        observer.clone(),
+        connect,
+    )
+    .unwrap();
+    let connack = Connack::new(false, ConnackReturnCode::Accepted);
+    thread::sleep(Duration::from_millis(500));
+    let (mut stream, _) = server.accept_connection(connack);
+
+    let publish = Publish::new(false, QoSLevel1, false, "topic", "msg", Some(123)).unwrap();
+    let confirm = client.publish_confirm(publish);
+
+    // Este wait es para no mandar el puback antes de que el cliente llegue a mandar su publish en el otro thread
+    thread::sleep(Duration::from_millis(1000));
+    let puback = Puback::new(123).unwrap();
+    stream.write_all(&puback.encode().unwrap()).unwrap();
+
+    let recv_puback = confirm
+        .recv_timeout(Duration::from_millis(2000))
+        .unwrap()
+        .unwrap()
+        .unwrap();
+    assert_eq!(puback.encode(), recv_puback.encode());
+}
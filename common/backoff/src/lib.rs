@@ -0,0 +1,159 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+/// Exponential backoff policy for retry loops.
+///
+/// The first call to [`Backoff::next_delay`] returns the base
+/// delay, and every following call multiplies the previous one
+/// by the factor, up to the maximum delay
+///
+/// # Examples
+///
+/// ```
+/// use backoff::Backoff;
+/// use std::time::Duration;
+///
+/// let mut backoff = Backoff::new(
+///     Duration::from_millis(100),
+///     2,
+///     Duration::from_millis(300),
+/// );
+/// assert_eq!(backoff.next_delay(), Duration::from_millis(100));
+/// assert_eq!(backoff.next_delay(), Duration::from_millis(200));
+/// assert_eq!(backoff.next_delay(), Duration::from_millis(300));
+/// assert_eq!(backoff.next_delay(), Duration::from_millis(300));
+/// ```
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    base: Duration,
+    factor: u32,
+    max: Duration,
+    jitter: Option<f64>,
+    current: Option<Duration>,
+}
+
+impl Backoff {
+    /// Creates a new Backoff without jitter, that starts
+    /// at `base` and is multiplied by `factor` on each
+    /// delay, without exceeding `max`
+    pub fn new(base: Duration, factor: u32, max: Duration) -> Self {
+        Self {
+            base,
+            factor,
+            max,
+            jitter: None,
+            current: None,
+        }
+    }
+
+    /// Adds jitter to the returned delays. Each delay `d` is
+    /// replaced by a random one in the range `[d * (1 - ratio), d]`,
+    /// so that clients retrying at the same time do not do it
+    /// in sync
+    ///
+    /// # Panics
+    ///
+    /// Panics if ratio is not in the range `[0, 1]`
+    pub fn with_jitter(mut self, ratio: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&ratio),
+            "El jitter debe estar entre 0 y 1"
+        );
+        self.jitter = Some(ratio);
+        self
+    }
+
+    /// Returns how long to wait before the next retry
+    pub fn next_delay(&mut self) -> Duration {
+        let delay = match self.current {
+            None => self.base,
+            Some(current) => current.checked_mul(self.factor).unwrap_or(self.max),
+        }
+        .min(self.max);
+        self.current = Some(delay);
+
+        match self.jitter {
+            Some(ratio) if ratio > 0.0 => {
+                let min = delay.mul_f64(1.0 - ratio);
+                rand::thread_rng().gen_range(min..=delay)
+            }
+            _ => delay,
+        }
+    }
+
+    /// Restarts the backoff, so that the next delay
+    /// is the base one. It should be called after a
+    /// successful attempt
+    pub fn reset(&mut self) {
+        self.current = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ms(millis: u64) -> Duration {
+        Duration::from_millis(millis)
+    }
+
+    #[test]
+    fn test_delays_grow_until_max() {
+        let mut backoff = Backoff::new(ms(100), 2, ms(1000));
+        let delays: Vec<Duration> = (0..7).map(|_| backoff.next_delay()).collect();
+        assert_eq!(
+            delays,
+            vec![
+                ms(100),
+                ms(200),
+                ms(400),
+                ms(800),
+                ms(1000),
+                ms(1000),
+                ms(1000)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_reset_starts_from_base() {
+        let mut backoff = Backoff::new(ms(100), 2, ms(1000));
+        backoff.next_delay();
+        backoff.next_delay();
+        backoff.reset();
+        assert_eq!(backoff.next_delay(), ms(100));
+    }
+
+    #[test]
+    fn test_base_greater_than_max_returns_max() {
+        let mut backoff = Backoff::new(ms(5000), 2, ms(1000));
+        assert_eq!(backoff.next_delay(), ms(1000));
+    }
+
+    #[test]
+    fn test_overflow_returns_max() {
+        let mut backoff = Backoff::new(Duration::MAX / 2, 4, Duration::MAX);
+        backoff.next_delay();
+        assert_eq!(backoff.next_delay(), Duration::MAX);
+    }
+
+    #[test]
+    fn test_jitter_stays_within_bounds() {
+        let mut backoff = Backoff::new(ms(100), 2, ms(1000)).with_jitter(0.5);
+        let expected = [100, 200, 400, 800, 1000, 1000, 1000];
+        for _ in 0..100 {
+            for max in expected {
+                let delay = backoff.next_delay();
+                assert!(delay >= ms(max / 2) && delay <= ms(max));
+            }
+            backoff.reset();
+        }
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_jitter_greater_than_one_panics() {
+        Backoff::new(ms(100), 2, ms(1000)).with_jitter(1.5);
+    }
+}
@@ -0,0 +1,122 @@
+use std::{
+    io::{self, Write},
+    sync::{Arc, Condvar, Mutex},
+    time::{Duration, Instant},
+};
+
+use tracing_appender::non_blocking::NonBlocking;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Bytes sent to a non-blocking writer and bytes
+/// actually written and flushed by its worker
+#[derive(Debug, Default)]
+struct Progress {
+    sent: u64,
+    written: u64,
+    flushed: u64,
+}
+
+/// Keeps track of the bytes that go through a non-blocking
+/// writer, so that it is possible to wait until all of them
+/// reach the underlying writer
+#[derive(Debug, Clone, Default)]
+pub(crate) struct FlushTracker {
+    progress: Arc<(Mutex<Progress>, Condvar)>,
+}
+
+impl FlushTracker {
+    /// Waits until every byte sent so far is flushed, for at
+    /// most `timeout`. Returns false if the timeout elapsed
+    pub(crate) fn wait_flushed(&self, timeout: Duration) -> bool {
+        let (lock, condvar) = &*self.progress;
+        let deadline = Instant::now() + timeout;
+        let mut progress = match lock.lock() {
+            Ok(progress) => progress,
+            Err(_) => return false,
+        };
+        let target = progress.sent;
+        while progress.flushed < target {
+            let now = Instant::now();
+            if now >= deadline {
+                return false;
+            }
+            progress = match condvar.wait_timeout(progress, deadline - now) {
+                Ok((progress, _)) => progress,
+                Err(_) => return false,
+            };
+        }
+        true
+    }
+
+    #[doc(hidden)]
+    fn update<F: FnOnce(&mut Progress)>(&self, action: F) {
+        let (lock, condvar) = &*self.progress;
+        if let Ok(mut progress) = lock.lock() {
+            action(&mut progress);
+            condvar.notify_all();
+        }
+    }
+}
+
+/// [`NonBlocking`] writer that records the bytes sent to its worker
+#[derive(Clone)]
+pub(crate) struct TrackedWriter {
+    writer: NonBlocking,
+    tracker: FlushTracker,
+}
+
+impl TrackedWriter {
+    pub(crate) fn new(writer: NonBlocking, tracker: FlushTracker) -> Self {
+        Self { writer, tracker }
+    }
+}
+
+impl Write for TrackedWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes = self.writer.write(buf)?;
+        self.tracker
+            .update(|progress| progress.sent += bytes as u64);
+        Ok(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+impl<'a> MakeWriter<'a> for TrackedWriter {
+    type Writer = TrackedWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Writer used by the worker of a [`NonBlocking`] writer, that
+/// records the bytes that were written and flushed
+pub(crate) struct NotifyingWriter<W: Write> {
+    writer: W,
+    tracker: FlushTracker,
+}
+
+impl<W: Write> NotifyingWriter<W> {
+    pub(crate) fn new(writer: W, tracker: FlushTracker) -> Self {
+        Self { writer, tracker }
+    }
+}
+
+impl<W: Write> Write for NotifyingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let bytes = self.writer.write(buf)?;
+        self.tracker
+            .update(|progress| progress.written += bytes as u64);
+        Ok(bytes)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()?;
+        self.tracker
+            .update(|progress| progress.flushed = progress.written);
+        Ok(())
+    }
+}
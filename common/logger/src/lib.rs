@@ -1,3 +1,5 @@
+use std::time::Duration;
+
 use tracing::{Level, Subscriber};
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{
@@ -6,31 +8,77 @@ use tracing_subscriber::{
     Registry,
 };
 
+use crate::flush::{FlushTracker, NotifyingWriter, TrackedWriter};
+pub use crate::logger_error::LoggerError;
+
+mod flush;
+mod logger_error;
+
 const LOG_PREFIX: &str = "log.";
+/// Maximum time [`Logger::flush`] waits for each output
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
 
 /// Logger structs. Holds the subscriber guards.
 /// If they were dropped, nothing would be logged.
 pub struct Logger {
     _file_guard: WorkerGuard,
     _stdout_guard: WorkerGuard,
+    file_tracker: FlushTracker,
+    stdout_tracker: FlushTracker,
 }
 
 impl Logger {
-    pub fn new(log_path: &str, file_level: Level, stdout_level: Level) -> Self {
+    /// Creates a Logger that writes to hourly files in `log_path`
+    /// and to the standard output, and sets it as the global
+    /// subscriber of the process.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if a global subscriber was already set, for
+    /// example by a previous Logger
+    pub fn new(
+        log_path: &str,
+        file_level: Level,
+        stdout_level: Level,
+    ) -> Result<Self, LoggerError> {
+        let file_tracker = FlushTracker::default();
+        let stdout_tracker = FlushTracker::default();
         let file_appender = tracing_appender::rolling::hourly(log_path, LOG_PREFIX);
-        let (file, _file_guard) = tracing_appender::non_blocking(file_appender);
-        let (stdout, _stdout_guard) = tracing_appender::non_blocking(std::io::stdout());
+        let (file, _file_guard) = tracing_appender::non_blocking(NotifyingWriter::new(
+            file_appender,
+            file_tracker.clone(),
+        ));
+        let (stdout, _stdout_guard) = tracing_appender::non_blocking(NotifyingWriter::new(
+            std::io::stdout(),
+            stdout_tracker.clone(),
+        ));
+        let file = TrackedWriter::new(file, file_tracker.clone());
+        let stdout = TrackedWriter::new(stdout, stdout_tracker.clone());
 
         tracing::subscriber::set_global_default(Self::get_subscriber(
             file.with_max_level(file_level),
             stdout.with_max_level(stdout_level),
-        ))
-        .expect("Error inicializando el logger");
+        ))?;
 
-        Self {
+        Ok(Self {
             _file_guard,
             _stdout_guard,
-        }
+            file_tracker,
+            stdout_tracker,
+        })
+    }
+
+    /// Blocks until every event logged so far is written to the
+    /// log file and the standard output, so that they are not lost
+    /// if the process exits without dropping the Logger.
+    ///
+    /// Returns false if some of them could not be written in time,
+    /// for example because they were discarded while the outputs
+    /// were overloaded
+    pub fn flush(&self) -> bool {
+        let file_flushed = self.file_tracker.wait_flushed(FLUSH_TIMEOUT);
+        let stdout_flushed = self.stdout_tracker.wait_flushed(FLUSH_TIMEOUT);
+        file_flushed && stdout_flushed
     }
 
     /// Sets up the tracing log subscriber and returns it
@@ -54,3 +102,35 @@ impl Logger {
             )
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+
+    use tracing::{info, Level};
+
+    use super::Logger;
+
+    #[test]
+    fn test_flush_writes_events_to_disk() {
+        let log_path = env::temp_dir().join(format!("logger_test_{}", std::process::id()));
+        let logger = Logger::new(log_path.to_str().unwrap(), Level::INFO, Level::ERROR).unwrap();
+
+        for i in 0..100 {
+            info!("Evento de prueba {}", i);
+        }
+        assert!(logger.flush());
+
+        // Los eventos ya estan en disco antes de dropear el logger
+        let mut logs = String::new();
+        for entry in fs::read_dir(&log_path).unwrap() {
+            logs += &fs::read_to_string(entry.unwrap().path()).unwrap();
+        }
+        for i in 0..100 {
+            assert!(logs.contains(&format!("Evento de prueba {}", i)));
+        }
+
+        drop(logger);
+        fs::remove_dir_all(log_path).unwrap();
+    }
+}
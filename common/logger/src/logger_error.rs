@@ -0,0 +1,28 @@
+use std::{error::Error, fmt::Display};
+
+use tracing::subscriber::SetGlobalDefaultError;
+
+#[derive(Debug)]
+pub struct LoggerError {
+    msg: String,
+}
+
+impl Display for LoggerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.msg)
+    }
+}
+
+impl Error for LoggerError {
+    fn description(&self) -> &str {
+        &self.msg
+    }
+}
+
+impl From<SetGlobalDefaultError> for LoggerError {
+    fn from(error: SetGlobalDefaultError) -> LoggerError {
+        LoggerError {
+            msg: format!("LoggerError: Could not set the subscriber ({})", error),
+        }
+    }
+}
@@ -0,0 +1,19 @@
+use std::{env, fs};
+
+use logger::Logger;
+use tracing::Level;
+
+#[test]
+fn test_second_logger_returns_error() {
+    let log_path = env::temp_dir().join(format!("logger_twice_test_{}", std::process::id()));
+    let path = log_path.to_str().unwrap();
+
+    let first = Logger::new(path, Level::INFO, Level::ERROR);
+    assert!(first.is_ok());
+    // Ya hay un subscriber global, asi que el segundo no se puede establecer
+    let second = Logger::new(path, Level::INFO, Level::ERROR);
+    assert!(second.is_err());
+
+    drop(first);
+    let _ = fs::remove_dir_all(log_path);
+}
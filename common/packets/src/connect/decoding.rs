@@ -114,6 +114,7 @@ impl Connect {
             },
             last_will: Connect::get_will(buf)?,
             keep_alive: 0,
+            connect_flags: buf[0],
         })
     }
 
@@ -125,7 +126,7 @@ impl Connect {
     }
 
     fn get_client_id(&mut self, bytes: &mut impl Read) -> PacketResult<()> {
-        let string = Field::new_from_stream(bytes).ok_or_else(PacketError::new)?;
+        let string = Field::read_strict(bytes)?;
         self.client_id = string.value;
         Ok(())
     }
@@ -142,7 +143,7 @@ impl Connect {
 
     fn get_auth(&mut self, bytes: &mut impl Read) -> PacketResult<()> {
         if let Some(user_name) = &mut self.user_name {
-            let user = Field::new_from_stream(bytes).ok_or_else(PacketError::new)?;
+            let user = Field::read_strict(bytes)?;
             *user_name = user.value;
         }
         if let Some(pw) = &mut self.password {
@@ -123,6 +123,7 @@ impl ConnectBuilder {
                 password: None,
                 last_will: None,
                 keep_alive,
+                connect_flags: 0,
             },
         })
     }
@@ -148,11 +149,12 @@ impl ConnectBuilder {
     ///
     /// Returns error if the packet fields do not meet the
     /// requirements of the MQTT V3.1.1 protocol
-    pub fn build(self) -> PacketResult<Connect> {
+    pub fn build(mut self) -> PacketResult<Connect> {
         if self.connect.password.is_some() && self.connect.user_name.is_none() {
             return Err(PacketError::new_msg(USER_NAME_WITHOUT_PASSWORD_MSG));
         }
 
+        self.connect.connect_flags = self.connect.flags();
         Ok(self.connect)
     }
 }
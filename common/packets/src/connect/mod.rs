@@ -49,6 +49,8 @@ pub struct Connect {
     password: Option<String>,
     last_will: Option<LastWill>,
     keep_alive: u16,
+    #[serde(default)]
+    connect_flags: u8,
 }
 
 impl Connect {
@@ -88,6 +90,19 @@ impl Connect {
         self.keep_alive
     }
 
+    /// Get the connect flags byte, as it was received when decoding
+    /// the packet or as it is encoded when building it
+    pub fn connect_flags(&self) -> u8 {
+        self.connect_flags
+    }
+
+    /// Set the clean session flag, so that the session
+    /// is handled as if the client had asked for it
+    pub fn set_clean_session(&mut self) {
+        self.clean_session = true;
+        self.connect_flags |= CLEAN_SESSION;
+    }
+
     /// Set the client Id if it is None
     /// If not None, it silently does nothing
     pub fn set_id(&mut self, id: String) {
@@ -95,6 +95,26 @@ fn test_invalid_reserved_flag() {
     );
 }
 
+#[test]
+fn test_invalid_fixed_header_reserved_bits() {
+    let mut v = Field::new_from_string("MQTT").unwrap().encode();
+    v.push(4u8); // Nivel
+    v.push(0u8); //Flags
+    v.append(&mut vec![0u8, 60u8]); //Keep alive
+    v.append(&mut Field::new_from_string("id").unwrap().encode());
+
+    let mut bytes = vec![v.len() as u8];
+    bytes.append(&mut v);
+    let mut stream = Cursor::new(bytes);
+
+    assert_eq!(
+        Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE | INVALID_RESERVED)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidReservedBits
+    );
+}
+
 #[test]
 fn test_will_flag_0_topic_message_1() {
     let mut v = Field::new_from_string("MQTT").unwrap().encode();
@@ -115,6 +135,26 @@ fn test_will_flag_0_topic_message_1() {
     );
 }
 
+#[test]
+fn test_will_qos_without_will_flag_should_raise_error() {
+    let mut v = Field::new_from_string("MQTT").unwrap().encode();
+    v.push(4u8); // Nivel
+    v.push((QoSLevel::QoSLevel1 as u8) << WILL_QOS_SHIFT); // Flags
+    v.append(&mut vec![0u8, 60u8]); // Keep alive
+    v.append(&mut Field::new_from_string("id").unwrap().encode());
+
+    let mut bytes = vec![v.len() as u8];
+    bytes.append(&mut v);
+    let mut stream = Cursor::new(bytes);
+
+    assert_eq!(
+        Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidFlags
+    );
+}
+
 #[test]
 fn test_username_missing_but_needed() {
     let mut v = Field::new_from_string("MQTT").unwrap().encode();
@@ -130,6 +170,47 @@ fn test_username_missing_but_needed() {
     assert!(Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE).is_err());
 }
 
+#[test]
+fn test_invalid_utf8_client_id() {
+    let mut v = Field::new_from_string("MQTT").unwrap().encode();
+    v.push(4u8); // Nivel
+    v.push(0u8); // Flags
+    v.append(&mut vec![0u8, 60u8]); // Keep alive
+    v.append(&mut vec![0u8, 3u8, b'i', 0xFF, b'd']); // Client id invalido
+
+    let mut bytes = vec![v.len() as u8];
+    bytes.append(&mut v);
+    let mut stream = Cursor::new(bytes);
+
+    assert_eq!(
+        Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidUtf8
+    );
+}
+
+#[test]
+fn test_invalid_utf8_user_name() {
+    let mut v = Field::new_from_string("MQTT").unwrap().encode();
+    v.push(4u8); // Nivel
+    v.push(USER_NAME_PRESENT); // Flags
+    v.append(&mut vec![0u8, 60u8]); // Keep alive
+    v.append(&mut Field::new_from_string("id").unwrap().encode());
+    v.append(&mut vec![0u8, 2u8, 0xC0, 0x80]); // User name invalido
+
+    let mut bytes = vec![v.len() as u8];
+    bytes.append(&mut v);
+    let mut stream = Cursor::new(bytes);
+
+    assert_eq!(
+        Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE)
+            .unwrap_err()
+            .kind(),
+        ErrorKind::InvalidUtf8
+    );
+}
+
 #[test]
 fn test_username_present_as_remaining_bytes_should_raise_error() {
     let mut v = Field::new_from_string("MQTT").unwrap().encode();
@@ -225,6 +306,32 @@ fn test_will_flag_username_password() {
     assert_eq!(packet.password().unwrap(), "contraseñaSuperSecreta");
 }
 
+#[test]
+fn test_connect_flags() {
+    let flags = LAST_WILL_PRESENT
+        | ((QoSLevel::QoSLevel1 as u8) << WILL_QOS_SHIFT)
+        | USER_NAME_PRESENT
+        | PASSWORD_PRESENT
+        | CLEAN_SESSION;
+    let mut v = Field::new_from_string("MQTT").unwrap().encode();
+    v.push(4u8); // Nivel
+    v.push(flags); // Flags
+    v.append(&mut vec![0u8, 60u8]); // Keep alive
+    v.append(&mut Field::new_from_string("id").unwrap().encode());
+    v.append(&mut Field::new_from_string("soyUnTopic").unwrap().encode());
+    v.append(&mut Field::new_from_string("soyUnMensaje").unwrap().encode());
+    v.append(&mut Field::new_from_string("usuario").unwrap().encode());
+    v.append(&mut Field::new_from_string("contraseña").unwrap().encode());
+
+    let mut bytes = vec![v.len() as u8];
+    bytes.append(&mut v);
+    let mut stream = Cursor::new(bytes);
+
+    let packet = Connect::read_from(&mut stream, CONNECT_CONTROL_BYTE).unwrap();
+
+    assert_eq!(packet.connect_flags(), 0b11001110);
+}
+
 // client_side tests
 #[test]
 fn test_basics() {
@@ -270,6 +377,7 @@ fn test_username_password() {
             0, 4, 112, 97, 115, 115 // password: (0) (4) pass
         ]
     );
+    assert_eq!(packet.connect_flags(), 194);
 }
 
 #[test]
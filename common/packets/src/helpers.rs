@@ -40,12 +40,13 @@ pub enum PacketType {
     Disconnect,
 }
 
-impl TryFrom<u8> for PacketType {
-    type Error = PacketError;
-
-    fn try_from(control_byte: u8) -> Result<Self, Self::Error> {
-        let packet_type_bytes = (control_byte & PACKET_TYPE_MASK) >> PACKET_TYPE_SHIFT;
-        match packet_type_bytes {
+impl PacketType {
+    /// Returns the packet type whose wire code (the 4 most significant
+    /// bits of the control byte, already shifted) is `code`. Codes 0
+    /// and 15 are reserved, so they return an error of kind
+    /// [`ErrorKind::InvalidControlPacketType`]
+    pub fn from_code(code: u8) -> PacketResult<Self> {
+        match code {
             CONNECT_PACKET_TYPE_BITS => Ok(PacketType::Connect),
             CONNACK_PACKET_TYPE_BITS => Ok(PacketType::Connack),
             PUBLISH_PACKET_TYPE_BITS => Ok(PacketType::Publish),
@@ -69,11 +70,11 @@ impl TryFrom<u8> for PacketType {
             )),
         }
     }
-}
 
-impl From<PacketType> for u8 {
-    fn from(packet_type: PacketType) -> Self {
-        match packet_type {
+    /// Returns the wire code of the packet type, that is, the
+    /// 4 most significant bits of its control byte, unshifted
+    pub fn code(self) -> u8 {
+        match self {
             PacketType::Connect => CONNECT_PACKET_TYPE_BITS,
             PacketType::Connack => CONNACK_PACKET_TYPE_BITS,
             PacketType::Publish => PUBLISH_PACKET_TYPE_BITS,
@@ -92,6 +93,20 @@ impl From<PacketType> for u8 {
     }
 }
 
+impl TryFrom<u8> for PacketType {
+    type Error = PacketError;
+
+    fn try_from(control_byte: u8) -> Result<Self, Self::Error> {
+        PacketType::from_code((control_byte & PACKET_TYPE_MASK) >> PACKET_TYPE_SHIFT)
+    }
+}
+
+impl From<PacketType> for u8 {
+    fn from(packet_type: PacketType) -> Self {
+        packet_type.code()
+    }
+}
+
 #[inline(always)]
 pub fn compare_reserved_bytes(control_byte: u8, expected_reserved_bits: u8) -> bool {
     (control_byte & RESERVED_BITS_MASK) == expected_reserved_bits
@@ -149,13 +164,63 @@ impl fmt::Display for PacketType {
 
 #[cfg(test)]
 mod tests {
-    use crate::helpers::PacketType;
+    use std::convert::TryFrom;
+
+    use crate::{helpers::PacketType, packet_error::ErrorKind};
 
     use super::build_control_byte;
 
+    const ALL_PACKET_TYPES: [(u8, PacketType); 14] = [
+        (1, PacketType::Connect),
+        (2, PacketType::Connack),
+        (3, PacketType::Publish),
+        (4, PacketType::Puback),
+        (5, PacketType::PubRec),
+        (6, PacketType::PubRel),
+        (7, PacketType::PubComp),
+        (8, PacketType::Subscribe),
+        (9, PacketType::Suback),
+        (10, PacketType::Unsubscribe),
+        (11, PacketType::Unsuback),
+        (12, PacketType::PingReq),
+        (13, PacketType::PingResp),
+        (14, PacketType::Disconnect),
+    ];
+
     #[test]
     fn test_build_connect_control_byte() {
         let control_byte = build_control_byte(PacketType::Connect, 0);
         assert_eq!(control_byte, 0b00010000);
     }
+
+    #[test]
+    fn test_packet_type_codes() {
+        for (code, packet_type) in ALL_PACKET_TYPES {
+            assert_eq!(PacketType::from_code(code).unwrap(), packet_type);
+            assert_eq!(packet_type.code(), code);
+        }
+    }
+
+    #[test]
+    fn test_packet_type_codes_round_trip() {
+        for code in 1..=14 {
+            assert_eq!(PacketType::from_code(code).unwrap().code(), code);
+        }
+    }
+
+    #[test]
+    fn test_packet_type_from_control_byte() {
+        for (_, packet_type) in ALL_PACKET_TYPES {
+            let control_byte = build_control_byte(packet_type, 0b1111);
+            assert_eq!(PacketType::try_from(control_byte).unwrap(), packet_type);
+        }
+    }
+
+    #[test]
+    fn test_packet_type_invalid_codes() {
+        for code in [0, 15, 16, u8::MAX] {
+            let err = PacketType::from_code(code).unwrap_err();
+            assert_eq!(err.kind(), ErrorKind::InvalidControlPacketType);
+        }
+    }
 }
@@ -7,7 +7,10 @@ pub mod packet_reader;
 pub mod pingreq;
 pub mod pingresp;
 pub mod puback;
+pub mod pubcomp;
 pub mod publish;
+pub mod pubrec;
+pub mod pubrel;
 pub mod qos;
 pub mod suback;
 pub mod subscribe;
@@ -17,6 +17,8 @@ pub enum ErrorKind {
     TopicNameMustBeAtLeastOneCharacterLong,
     TopicNameMustNotHaveWildcards,
     InvalidTopicName,
+    InvalidUtf8,
+    MalformedRemainingLength,
     InvalidReturnCode,
     WouldBlock,
     UnexpectedEof,
@@ -1,4 +1,4 @@
-use crate::packet_error::{PacketError, PacketResult};
+use crate::packet_error::{ErrorKind, PacketError, PacketResult};
 use std::io::{Cursor, Read};
 
 const MAX_MULTIPLIER: usize = 128 * 128 * 128;
@@ -34,7 +34,13 @@ impl RemainingLength {
         })
     }
 
-    /// Returns the encoded remaining length from a given stream    
+    /// Returns the encoded remaining length from a given stream
+    ///
+    /// # Errors
+    ///
+    /// If the remaining length is encoded with more than 4 bytes (that is, the
+    /// 4th byte has the continuation bit set), this function returns a PacketError
+    /// of kind [`ErrorKind::MalformedRemainingLength`], without reading further
     pub fn from_encoded<T: Read>(stream: &mut T) -> PacketResult<Self> {
         let mut multiplier: u32 = 1;
         let mut length: u32 = 0;
@@ -48,7 +54,10 @@ impl RemainingLength {
                 break;
             }
             if multiplier as usize > MAX_MULTIPLIER {
-                return Err(PacketError::new_msg("Malformed Remaining Length"));
+                return Err(PacketError::new_kind(
+                    "Malformed Remaining Length",
+                    ErrorKind::MalformedRemainingLength,
+                ));
             }
         }
         Ok(Self { length })
@@ -85,6 +94,7 @@ mod tests {
     use std::io::Cursor;
 
     use super::RemainingLength;
+    use crate::packet_error::ErrorKind;
 
     #[test]
     fn test_encode() {
@@ -156,4 +166,14 @@ mod tests {
 
         assert!(remaining.is_err());
     }
+
+    #[test]
+    fn test_decode_five_continuation_bytes_is_malformed() {
+        let mut stream = Cursor::new(vec![0xFF; 5]);
+        let error = RemainingLength::from_encoded(&mut stream).err().unwrap();
+
+        assert_eq!(error.kind(), ErrorKind::MalformedRemainingLength);
+        // No lee mas alla del cuarto byte de largo
+        assert_eq!(stream.position(), 4);
+    }
 }
@@ -76,10 +76,11 @@ impl MQTTDecoding for Publish {
 
 impl Publish {
     #[doc(hidden)]
-    fn read_payload(bytes: &mut impl Read) -> PacketResult<String> {
+    fn read_payload(bytes: &mut impl Read) -> PacketResult<Vec<u8>> {
+        // El payload es todo lo que queda del remaining length
         let mut payload_buf = vec![];
         let _ = bytes.read_to_end(&mut payload_buf);
-        Ok(String::from_utf8(payload_buf)?)
+        Ok(payload_buf)
     }
 
     #[doc(hidden)]
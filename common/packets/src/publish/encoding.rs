@@ -35,7 +35,7 @@ impl MQTTEncoding for Publish {
         let mut bytes = vec![];
         bytes.append(&mut self.fixed_header()?);
         bytes.append(&mut self.variable_header());
-        bytes.append(&mut Vec::from(self.payload.as_bytes()));
+        bytes.extend_from_slice(&self.payload);
         Ok(bytes)
     }
 }
@@ -56,7 +56,7 @@ impl Publish {
         qos: QoSLevel,
         retain_flag: bool,
         topic_name: &str,
-        topic_message: &str,
+        topic_message: impl Into<Vec<u8>>,
         packet_identifier: Option<u16>,
     ) -> PacketResult<Self> {
         if packet_identifier.is_some() && qos == QoSLevel::QoSLevel0 {
@@ -77,7 +77,7 @@ impl Publish {
             qos,
             retain_flag,
             dup_flag,
-            payload: topic_message.to_string(),
+            payload: topic_message.into(),
         })
     }
 
@@ -122,7 +122,7 @@ impl Publish {
     fn fixed_header(&self) -> PacketResult<MQTTBytes> {
         let mut fixed_header = vec![];
         let variable_header_len = self.variable_header().len();
-        let message_len = self.payload.as_bytes().len();
+        let message_len = self.payload.len();
         let remaining_length = RemainingLength::from_uncoded(variable_header_len + message_len)?;
         let control_byte = build_control_byte(PacketType::Publish, self.reserved_bits());
         fixed_header.push(control_byte);
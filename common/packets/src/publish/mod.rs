@@ -1,5 +1,13 @@
+use std::{
+    fmt,
+    str::{self, Utf8Error},
+};
+
 use crate::qos::QoSLevel;
-use serde::{Deserialize, Serialize};
+use serde::{
+    de::{SeqAccess, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
 
 mod decoding;
 mod encoding;
@@ -26,7 +34,44 @@ pub struct Publish {
     qos: QoSLevel,
     retain_flag: bool,
     dup_flag: bool,
-    payload: String,
+    #[serde(deserialize_with = "deserialize_payload")]
+    payload: Vec<u8>,
+}
+
+#[doc(hidden)]
+/// Reads the payload either as a sequence of bytes or as a string,
+/// which is how it was serialized before payloads could be binary
+fn deserialize_payload<'de, D>(deserializer: D) -> Result<Vec<u8>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct PayloadVisitor;
+
+    impl<'de> Visitor<'de> for PayloadVisitor {
+        type Value = Vec<u8>;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a string or a sequence of bytes")
+        }
+
+        fn visit_str<E: serde::de::Error>(self, value: &str) -> Result<Vec<u8>, E> {
+            Ok(value.as_bytes().to_vec())
+        }
+
+        fn visit_bytes<E: serde::de::Error>(self, value: &[u8]) -> Result<Vec<u8>, E> {
+            Ok(value.to_vec())
+        }
+
+        fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Vec<u8>, A::Error> {
+            let mut payload = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+            while let Some(byte) = seq.next_element()? {
+                payload.push(byte);
+            }
+            Ok(payload)
+        }
+    }
+
+    deserializer.deserialize_any(PayloadVisitor)
 }
 
 impl Publish {
@@ -50,10 +95,15 @@ impl Publish {
     pub fn dup_flag(&self) -> bool {
         self.dup_flag
     }
-    /// Gets the payload from a Publish packet
-    pub fn payload(&self) -> &str {
+    /// Gets the payload from a Publish packet, as raw bytes
+    pub fn payload(&self) -> &[u8] {
         &self.payload
     }
+    /// Gets the payload from a Publish packet as text. It fails
+    /// if the payload is not valid UTF-8
+    pub fn payload_str(&self) -> Result<&str, Utf8Error> {
+        str::from_utf8(&self.payload)
+    }
 
     #[doc(hidden)]
     pub fn set_max_qos(&mut self, max_qos: QoSLevel) {
@@ -4,7 +4,7 @@ use crate::publish::Publish;
 use crate::qos::QoSLevel;
 use crate::traits::{MQTTDecoding, MQTTEncoding};
 use crate::utf8::Field;
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 
 #[test]
 fn test_dup_flag_0_with_qos_level_different_from_0_should_raise_invalid_dup_flag() {
@@ -30,6 +30,22 @@ fn test_qos_level_3_should_raise_invalid_qos_level_error() {
     assert_eq!(result, expected_error);
 }
 
+#[test]
+fn test_complete_publish_with_qos_level_3_should_raise_invalid_qos_level_error() {
+    // Publish bien formado salvo por los bits de QoS en 11, con dup y retain
+    let control_byte = 0b00111111u8;
+    let mut remaining_data = Field::new_from_string("a/b").unwrap().encode();
+    remaining_data.extend([0, 10]); // packet identifier
+    remaining_data.extend(b"mensaje");
+    let mut bytes = vec![remaining_data.len() as u8];
+    bytes.append(&mut remaining_data);
+    let mut stream = Cursor::new(bytes);
+    let result = Publish::read_from(&mut stream, control_byte)
+        .unwrap_err()
+        .kind();
+    assert_eq!(result, ErrorKind::InvalidQoSLevel);
+}
+
 #[test]
 fn test_packet_control_type_5_should_raise_invalid_control_packet_type_error() {
     let control_byte = 0b100000u8;
@@ -59,7 +75,7 @@ fn test_publish_packet_with_qos_level_0_must_not_have_a_packet_id() {
         qos: QoSLevel::QoSLevel0,
         retain_flag: false,
         dup_flag: false,
-        payload: "mensaje".to_string(),
+        payload: b"mensaje".to_vec(),
     };
     let result = Publish::read_from(&mut stream, control_byte).unwrap();
     assert_eq!(expected, result);
@@ -85,7 +101,7 @@ fn test_publish_packet_with_qos_level_1_must_have_a_packet_id() {
         qos: QoSLevel::QoSLevel1,
         retain_flag: false,
         dup_flag: false,
-        payload: "mensaje".to_string(),
+        payload: b"mensaje".to_vec(),
     };
     let result = Publish::read_from(&mut stream, control_byte).unwrap();
     assert_eq!(expected, result);
@@ -109,11 +125,11 @@ fn test_publish_packet_might_have_zero_length_payload() {
         qos: QoSLevel::QoSLevel0,
         retain_flag: false,
         dup_flag: false,
-        payload: "".to_string(),
+        payload: vec![],
     };
     let result = Publish::read_from(&mut stream, control_byte).unwrap();
     assert_eq!(expected, result);
-    assert_eq!(expected.payload(), "");
+    assert_eq!(expected.payload(), b"");
 }
 
 #[test]
@@ -134,7 +150,7 @@ fn test_publish_packet_topics_should_be_case_sensitive() {
         qos: QoSLevel::QoSLevel0,
         retain_flag: false,
         dup_flag: false,
-        payload: "".to_string(),
+        payload: vec![],
     };
     let result = Publish::read_from(&mut stream, control_byte).unwrap();
     assert_ne!(expected, result);
@@ -373,3 +389,29 @@ fn test_set_retain() {
     packet.set_retain_flag(false);
     assert!(!packet.retain_flag());
 }
+
+#[test]
+fn test_binary_payload_round_trip() {
+    let payload = vec![0x00, 0xff, 0xfe, 0x80, 0x0a];
+    let packet = Publish::new(
+        false,
+        QoSLevel::QoSLevel1,
+        false,
+        "topic",
+        payload.clone(),
+        Some(350),
+    )
+    .unwrap();
+    let mut bytes = Cursor::new(packet.encode().unwrap());
+    let mut control_byte = [0u8; 1];
+    bytes.read_exact(&mut control_byte).unwrap();
+    let result = Publish::read_from(&mut bytes, control_byte[0]).unwrap();
+    assert_eq!(result.payload(), payload);
+    assert!(result.payload_str().is_err());
+}
+
+#[test]
+fn test_payload_str() {
+    let packet = Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "mensaje", None).unwrap();
+    assert_eq!(packet.payload_str().unwrap(), "mensaje");
+}
@@ -2,6 +2,7 @@ use crate::{
     helpers::{build_control_byte, PacketType},
     packet_error::PacketResult,
     packet_reader::RemainingLength,
+    qos::QoSLevel,
     traits::{MQTTBytes, MQTTEncoding},
 };
 
@@ -50,6 +51,29 @@ impl Suback {
         })
     }
 
+    /// Returns a new Suback packet struct from a given subscribe packet id and the
+    /// result of the subscription to each Topic Filter, in the same order as in
+    /// the Subscribe packet
+    ///
+    /// A granted QoS is mapped to its return code (QoS 2 is not supported, so it is
+    /// granted as QoS 1) and a failed subscription to [FAILURE], so the return codes
+    /// are always valid
+    pub fn from_results(results: Vec<Result<QoSLevel, ()>>, subscribe_packet_id: u16) -> Self {
+        let return_codes = results
+            .into_iter()
+            .map(|result| match result {
+                Ok(QoSLevel::QoSLevel0) => SUCCESS_MAXIMUM_QOS_0,
+                Ok(QoSLevel::QoSLevel1 | QoSLevel::QoSLevel2) => SUCCESS_MAXIMUM_QOS_1,
+                Err(()) => FAILURE,
+            })
+            .collect();
+        Self {
+            return_codes,
+            subscribe_packet_id,
+            topics: Vec::new(),
+        }
+    }
+
     #[doc(hidden)]
     fn fixed_header(&self) -> PacketResult<MQTTBytes> {
         let control_byte = build_control_byte(PacketType::Suback, RESERVED_BITS);
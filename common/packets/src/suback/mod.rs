@@ -16,10 +16,10 @@ const MSG_INVALID_RETURN_CODE: &str = "Allowed return codes are 0x00, 0x01, 0x80
 const SUCCESS_MAXIMUM_QOS_0: u8 = 0;
 #[doc(hidden)]
 const SUCCESS_MAXIMUM_QOS_1: u8 = 1;
-#[doc(hidden)]
-const FAILURE: u8 = 0x80;
+/// Return code for a Topic Filter whose subscription failed
+pub const FAILURE: u8 = 0x80;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Client/Server side structure for Suback packet
 pub struct Suback {
     return_codes: Vec<u8>,
@@ -41,6 +41,10 @@ impl Suback {
     pub fn topics(&self) -> &Vec<TopicFilter> {
         &self.topics
     }
+    /// Get the suback's return codes, one per subscribed topic
+    pub fn return_codes(&self) -> &[u8] {
+        &self.return_codes
+    }
 
     #[doc(hidden)]
     fn verify_return_codes_from_vec(return_codes: &[u8]) -> PacketResult<()> {
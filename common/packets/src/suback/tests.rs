@@ -2,6 +2,7 @@ use super::*;
 
 use crate::{
     packet_error::ErrorKind,
+    qos::QoSLevel,
     traits::{MQTTDecoding, MQTTEncoding},
 };
 use std::io::Cursor;
@@ -46,6 +47,18 @@ fn test_control_byte_from_stream_other_than_9_should_raise_invalid_control_packe
     assert_eq!(result, expected_error);
 }
 
+#[test]
+fn test_reserved_bits_other_than_0_should_raise_invalid_reserved_bits_error() {
+    let stream_aux = vec![3, 0, 1, 0];
+    let control_byte = 0b10010001;
+    let mut stream = Cursor::new(stream_aux);
+    let result = Suback::read_from(&mut stream, control_byte)
+        .unwrap_err()
+        .kind();
+    let expected_error = ErrorKind::InvalidReservedBits;
+    assert_eq!(result, expected_error);
+}
+
 #[test]
 fn test_correct_suback_with_packet_id_1_and_return_codes_0_from_stream() {
     let stream_aux = vec![6, 0, 1, 0, 0, 0, 0];
@@ -70,3 +83,14 @@ fn test_stream_with_return_code_3_should_raise_invalid_return_code() {
     let expected_error = ErrorKind::InvalidReturnCode;
     assert_eq!(result, expected_error);
 }
+
+#[test]
+fn test_suback_from_results() {
+    let results = vec![Ok(QoSLevel::QoSLevel0), Ok(QoSLevel::QoSLevel1), Err(())];
+    let suback = Suback::from_results(results, 3);
+    assert_eq!(suback.return_codes(), [0, 1, FAILURE]);
+    assert_eq!(
+        suback.encode().unwrap(),
+        vec![CONTROL_BYTE_SUBACK, 5, 0, 3, 0, 1, 0x80]
+    );
+}
@@ -2,7 +2,7 @@ use std::io::Read;
 
 use super::*;
 use crate::{
-    helpers::check_reserved_bits,
+    helpers::{check_packet_type, check_reserved_bits, PacketType},
     packet_error::{ErrorKind, PacketError, PacketResult},
     packet_reader,
     traits::MQTTDecoding,
@@ -15,9 +15,17 @@ impl MQTTDecoding for Subscribe {
     /// It is assumed that the first identifier byte has already been read.
     fn read_from<T: Read>(stream: &mut T, control_byte: u8) -> PacketResult<Subscribe> {
         check_reserved_bits(control_byte, RESERVED_BITS)?;
+        check_packet_type(control_byte, PacketType::Subscribe)?;
         let mut bytes = packet_reader::read_remaining_bytes(stream)?;
 
         let packet_identifier = Self::get_identifier(&mut bytes)?;
+        // Un SUBSCRIBE sin payload es una violación del protocolo [MQTT-3.8.3-3]
+        if bytes.position() >= bytes.get_ref().len() as u64 {
+            return Err(PacketError::new_kind(
+                "No topic filters found",
+                ErrorKind::InvalidProtocol,
+            ));
+        }
         let mut topics = Vec::new();
 
         while let Some(field) = Field::new_from_stream(&mut bytes) {
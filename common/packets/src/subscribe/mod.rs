@@ -10,7 +10,7 @@ mod tests;
 #[doc(hidden)]
 const RESERVED_BITS: u8 = 2;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Subscribe {
     packet_identifier: u16,
     topics: Vec<TopicFilter>,
@@ -53,4 +53,20 @@ impl Subscribe {
             topic.set_max_qos(max_qos);
         }
     }
+
+    #[doc(hidden)]
+    /// Sets max QoS for each Topic Filter in a Subscribe packet for which
+    /// the given predicate returns true
+    /// This is intended to be used by the server to enforce a QoS policy on
+    /// certain topics, regardless of what the client requested
+    pub fn set_max_qos_matching<F>(&mut self, max_qos: QoSLevel, predicate: F)
+    where
+        F: Fn(&TopicFilter) -> bool,
+    {
+        for topic in self.topics.iter_mut() {
+            if predicate(topic) {
+                topic.set_max_qos(max_qos);
+            }
+        }
+    }
 }
@@ -4,7 +4,7 @@ use crate::utf8::Field;
 
 use super::Subscribe;
 use super::*;
-use std::io::Cursor;
+use std::io::{Cursor, Read};
 
 const CONTROL_BYTE: u8 = 0b10000010;
 
@@ -32,6 +32,21 @@ fn test_subscribe_with_no_topics_should_raise_error() {
     assert_eq!(result, expected_error);
 }
 
+#[test]
+fn test_subscribe_with_empty_payload_does_not_read_next_packet() {
+    let mut v: Vec<u8> = Vec::new();
+    v.extend_from_slice(&[2, 123, 5]); // remaining length, identifier
+    v.extend_from_slice(&[0b11000000, 0]); // PINGREQ
+
+    let mut stream = Cursor::new(v);
+    let packet = Subscribe::read_from(&mut stream, CONTROL_BYTE);
+    assert_eq!(packet.err().unwrap().kind(), ErrorKind::InvalidProtocol);
+
+    let mut next = [0u8; 1];
+    stream.read_exact(&mut next).unwrap();
+    assert_eq!(next[0], 0b11000000);
+}
+
 #[test]
 fn test_one_topic() {
     let mut v: Vec<u8> = Vec::new();
@@ -86,6 +101,35 @@ fn test_invalid_reserved_flags() {
     assert_eq!(result, expected_error);
 }
 
+#[test]
+fn test_subscribe_with_flags_0000_is_invalid() {
+    // [MQTT-3.8.1-1]: los flags del header deben ser 0010
+    let control_byte = 0b10000000;
+    let mut v: Vec<u8> = Vec::new();
+    v.extend_from_slice(&[0, 5]); // identifier
+    v.extend(Field::new_from_string("unTopic").unwrap().encode());
+    v.push(0); // QoS level 0
+
+    v.insert(0, v.len() as u8);
+    let err = Subscribe::read_from(&mut Cursor::new(v), control_byte).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::InvalidReservedBits);
+}
+
+#[test]
+fn test_control_byte_other_than_8_should_raise_invalid_control_packet_type_error() {
+    let unsubscribe_control_byte = 0b10100010;
+    let mut v: Vec<u8> = Vec::new();
+    v.extend_from_slice(&[0, 5]); // identifier
+    v.extend(Field::new_from_string("unTopic").unwrap().encode());
+    v.push(1); // QoS level 1
+
+    v.insert(0, v.len() as u8);
+    let packet = Subscribe::read_from(&mut Cursor::new(v), unsubscribe_control_byte).unwrap_err();
+    let result = packet.kind();
+    let expected_error = ErrorKind::InvalidControlPacketType;
+    assert_eq!(result, expected_error);
+}
+
 #[test]
 fn test_invalid_qos() {
     let mut v: Vec<u8> = Vec::new();
@@ -141,3 +185,16 @@ fn test_subscribe_encode_2_topics() {
         ]
     );
 }
+
+#[test]
+fn test_set_max_qos_matching_only_caps_matching_topics() {
+    let topic1 = TopicFilter::new("sensors/temp", QoSLevel::QoSLevel1).unwrap();
+    let topic2 = TopicFilter::new("alerts", QoSLevel::QoSLevel1).unwrap();
+    let mut subscribe = Subscribe::new(vec![topic1, topic2], 2);
+    subscribe.set_max_qos_matching(QoSLevel::QoSLevel0, |topic| {
+        topic.name().starts_with("sensors")
+    });
+    let topics = subscribe.topics();
+    assert_eq!(topics[0].qos(), QoSLevel::QoSLevel0);
+    assert_eq!(topics[1].qos(), QoSLevel::QoSLevel1);
+}
@@ -4,6 +4,15 @@ use crate::packet_error::{ErrorKind, PacketError, PacketResult};
 use crate::qos::QoSLevel;
 use crate::utf8::Field;
 
+#[doc(hidden)]
+const SEP: &str = "/";
+#[doc(hidden)]
+const MULTI_LEVEL_WILDCARD: &str = "#";
+#[doc(hidden)]
+const SINGLE_LEVEL_WILDCARD: &str = "+";
+#[doc(hidden)]
+const RESERVED_TOPIC_PREFIX: &str = "$";
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct TopicFilter {
     /// Topic for a subscribe packet
@@ -103,6 +112,124 @@ impl TopicFilter {
     }
 }
 
+/// How a single level of a topic filter was compared against a topic name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LevelMatch {
+    /// The filter level is equal to the topic level
+    Exact(String),
+    /// The single-level wildcard (`+`) consumed the given topic level
+    SingleLevelWildcard(String),
+    /// The multi-level wildcard (`#`) consumed the given remaining levels
+    /// of the topic (which may be empty, since it also matches the parent level)
+    MultiLevelWildcard(String),
+    /// The filter level does not match the topic level. Either of them is None
+    /// if the filter or the topic name ran out of levels
+    Mismatch {
+        filter: Option<String>,
+        topic: Option<String>,
+    },
+    /// A wildcard at the first level of the filter cannot match a topic name
+    /// starting with `$` (MQTT-4.7.2-1)
+    ReservedTopic(String),
+}
+
+/// Explanation of why a topic name matches (or doesn't match) a topic filter,
+/// level by level
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MatchExplanation {
+    levels: Vec<LevelMatch>,
+}
+
+impl MatchExplanation {
+    /// Returns true if the topic name matches the topic filter
+    pub fn matches(&self) -> bool {
+        self.mismatch_level().is_none()
+    }
+
+    /// Returns the result of the comparison of each level, up to
+    /// the first one that did not match
+    pub fn levels(&self) -> &[LevelMatch] {
+        &self.levels
+    }
+
+    /// Returns the (zero-based) index of the level that did not match,
+    /// or None if the topic name matches the topic filter
+    pub fn mismatch_level(&self) -> Option<usize> {
+        self.levels.iter().position(|level| {
+            matches!(
+                level,
+                LevelMatch::Mismatch { .. } | LevelMatch::ReservedTopic(_)
+            )
+        })
+    }
+}
+
+/// Compares a topic name against a topic filter level by level, explaining
+/// which level failed, or which wildcard consumed which levels
+///
+/// # Examples
+///
+/// ```
+/// use packets::topic_filter::{explain_match, LevelMatch};
+///
+/// let explanation = explain_match("a/+/c", "a/b/d");
+/// assert!(!explanation.matches());
+/// assert_eq!(explanation.mismatch_level(), Some(2));
+///
+/// let explanation = explain_match("a/#", "a/b/c");
+/// assert!(explanation.matches());
+/// assert_eq!(
+///     explanation.levels()[1],
+///     LevelMatch::MultiLevelWildcard("b/c".to_string())
+/// );
+/// ```
+pub fn explain_match(filter: &str, topic: &str) -> MatchExplanation {
+    let mut levels = Vec::new();
+    let mut topic_levels = topic.split(SEP);
+
+    for (i, filter_level) in filter.split(SEP).enumerate() {
+        let is_wildcard =
+            filter_level == MULTI_LEVEL_WILDCARD || filter_level == SINGLE_LEVEL_WILDCARD;
+        if i == 0 && is_wildcard && topic.starts_with(RESERVED_TOPIC_PREFIX) {
+            let first = topic_levels.next().unwrap_or_default();
+            levels.push(LevelMatch::ReservedTopic(first.to_string()));
+            return MatchExplanation { levels };
+        }
+
+        if filter_level == MULTI_LEVEL_WILDCARD {
+            let rest = topic_levels.collect::<Vec<&str>>().join(SEP);
+            levels.push(LevelMatch::MultiLevelWildcard(rest));
+            return MatchExplanation { levels };
+        }
+
+        let level = match topic_levels.next() {
+            Some(topic_level) if filter_level == SINGLE_LEVEL_WILDCARD => {
+                LevelMatch::SingleLevelWildcard(topic_level.to_string())
+            }
+            Some(topic_level) if filter_level == topic_level => {
+                LevelMatch::Exact(topic_level.to_string())
+            }
+            topic_level => LevelMatch::Mismatch {
+                filter: Some(filter_level.to_string()),
+                topic: topic_level.map(str::to_string),
+            },
+        };
+        let mismatch = matches!(level, LevelMatch::Mismatch { .. });
+        levels.push(level);
+        if mismatch {
+            return MatchExplanation { levels };
+        }
+    }
+
+    if let Some(topic_level) = topic_levels.next() {
+        levels.push(LevelMatch::Mismatch {
+            filter: None,
+            topic: Some(topic_level.to_string()),
+        });
+    }
+    MatchExplanation { levels }
+}
+
 #[cfg(test)]
 mod tests {
 
@@ -190,4 +317,70 @@ mod tests {
         let topic = TopicFilter::new("+/+/+/+", QoSLevel::QoSLevel0);
         assert!(topic.is_ok());
     }
+
+    #[test]
+    fn test_explain_match_mismatch_at_third_level() {
+        let explanation = explain_match("a/+/c", "a/b/d");
+        assert!(!explanation.matches());
+        assert_eq!(explanation.mismatch_level(), Some(2));
+        assert_eq!(
+            explanation.levels(),
+            &[
+                LevelMatch::Exact("a".to_string()),
+                LevelMatch::SingleLevelWildcard("b".to_string()),
+                LevelMatch::Mismatch {
+                    filter: Some("c".to_string()),
+                    topic: Some("d".to_string())
+                }
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explain_match_multi_level_wildcard_consumes_rest() {
+        let explanation = explain_match("a/#", "a/b/c");
+        assert!(explanation.matches());
+        assert_eq!(
+            explanation.levels(),
+            &[
+                LevelMatch::Exact("a".to_string()),
+                LevelMatch::MultiLevelWildcard("b/c".to_string())
+            ]
+        );
+    }
+
+    #[test]
+    fn test_explain_match_multi_level_wildcard_matches_parent() {
+        let explanation = explain_match("a/#", "a");
+        assert!(explanation.matches());
+        assert_eq!(
+            explanation.levels()[1],
+            LevelMatch::MultiLevelWildcard("".to_string())
+        );
+    }
+
+    #[test]
+    fn test_explain_match_different_number_of_levels() {
+        let explanation = explain_match("a/b", "a/b/c");
+        assert_eq!(explanation.mismatch_level(), Some(2));
+
+        let explanation = explain_match("a/b/c", "a/b");
+        assert_eq!(
+            explanation.levels()[2],
+            LevelMatch::Mismatch {
+                filter: Some("c".to_string()),
+                topic: None
+            }
+        );
+    }
+
+    #[test]
+    fn test_explain_match_wildcard_does_not_match_reserved_topics() {
+        let explanation = explain_match("#", "$SYS/uptime");
+        assert!(!explanation.matches());
+        assert_eq!(
+            explanation.levels(),
+            &[LevelMatch::ReservedTopic("$SYS".to_string())]
+        );
+    }
 }
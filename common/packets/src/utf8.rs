@@ -2,7 +2,7 @@ use std::io::Read;
 
 use serde::{Deserialize, Serialize};
 
-use crate::packet_error::PacketError;
+use crate::packet_error::{ErrorKind, PacketError, PacketResult};
 
 const MAX_FIELD_LEN: usize = 65535;
 
@@ -53,19 +53,40 @@ impl Field {
     /// ```
     ///
     pub fn new_from_stream<T: Read>(stream: &mut T) -> Option<Self> {
+        Field::read_strict(stream).ok()
+    }
+
+    /// Creates a Field struct from a stream of bytes, as
+    /// [`Field::new_from_stream`] does, but reports why it failed
+    ///
+    /// # Errors
+    ///
+    /// If the field is not well-formed UTF-8, or contains the null
+    /// character (U+0000), this function returns a PacketError of kind
+    /// [`ErrorKind::InvalidUtf8`]. If the stream could not be read, it
+    /// returns the corresponding PacketError
+    ///
+    pub fn read_strict<T: Read>(stream: &mut T) -> PacketResult<Self> {
         let mut buf: [u8; 2] = [0; 2];
-        stream.read_exact(&mut buf).ok()?;
+        stream.read_exact(&mut buf)?;
 
         let size = u16::from_be_bytes(buf) as usize;
         let mut buf_string = vec![0; size];
-        if stream.read_exact(&mut buf_string).is_err() {
-            return None;
+        stream.read_exact(&mut buf_string)?;
+
+        let value = String::from_utf8(buf_string).map_err(|err| {
+            PacketError::new_kind(
+                format!("Cadena UTF-8 mal formada ({})", err),
+                ErrorKind::InvalidUtf8,
+            )
+        })?;
+        if value.contains('\0') {
+            return Err(PacketError::new_kind(
+                "Cadena UTF-8 con caracter nulo",
+                ErrorKind::InvalidUtf8,
+            ));
         }
-
-        let value = std::str::from_utf8_mut(&mut buf_string).ok()?;
-        Some(Self {
-            value: value.into(),
-        })
+        Ok(Self { value })
     }
 
     /// Encodes a Field struct into a UTF-8 string
@@ -114,6 +135,7 @@ mod tests {
     use std::io::Cursor;
 
     use super::Field;
+    use crate::packet_error::ErrorKind;
 
     #[test]
     fn test_decode() {
@@ -145,4 +167,20 @@ mod tests {
         let field = Field::new_from_string(msg).unwrap();
         assert_eq!(field.encode(), vec![0, 1, 32]);
     }
+
+    #[test]
+    fn test_read_strict_invalid_utf8() {
+        let bytes: [u8; 4] = [0, 2, 0xC3, 0x28];
+        let mut cursor = Cursor::new(bytes);
+        let err = Field::read_strict(&mut cursor).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidUtf8);
+    }
+
+    #[test]
+    fn test_read_strict_null_character() {
+        let bytes: [u8; 4] = [0, 2, 97, 0];
+        let mut cursor = Cursor::new(bytes);
+        let err = Field::read_strict(&mut cursor).unwrap_err();
+        assert_eq!(err.kind(), ErrorKind::InvalidUtf8);
+    }
 }
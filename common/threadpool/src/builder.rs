@@ -0,0 +1,53 @@
+use std::time::Duration;
+
+use crate::{ThreadPool, THREAD_WAIT_TIMEOUT};
+
+/// Number of threads of a pool built with the default configuration
+const DEFAULT_THREADS: usize = 4;
+
+/// Builder to configure a ThreadPool before starting it.
+/// ThreadPool::new(n) is equivalent to ThreadPoolBuilder::new().threads(n).build()
+#[derive(Debug, Clone)]
+pub struct ThreadPoolBuilder {
+    #[doc(hidden)]
+    threads: usize,
+    #[doc(hidden)]
+    recovery_interval: Duration,
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        Self {
+            threads: DEFAULT_THREADS,
+            recovery_interval: THREAD_WAIT_TIMEOUT,
+        }
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// Creates a ThreadPoolBuilder with the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the amount of threads the pool starts with. It can be
+    /// changed later with [`ThreadPool::set_size`]. Defaults to 4
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    /// Sets how long the pool waits for a thread to be free before
+    /// checking if any of them panicked and reviving it. A longer
+    /// interval uses less CPU when every thread is busy, but takes
+    /// longer to notice a panicked thread. Defaults to 1 millisecond
+    pub fn recovery_interval(mut self, interval: Duration) -> Self {
+        self.recovery_interval = interval;
+        self
+    }
+
+    /// Starts a ThreadPool with the given configuration
+    pub fn build(self) -> ThreadPool {
+        ThreadPool::start(self.threads, self.recovery_interval)
+    }
+}
@@ -0,0 +1,29 @@
+use std::sync::mpsc::Receiver;
+
+use crate::ThreadPoolError;
+
+/// Handle of a job submitted with [`crate::ThreadPool::submit`],
+/// that allows to wait for the value it returns
+#[derive(Debug)]
+pub struct JobHandle<R> {
+    receiver: Receiver<R>,
+}
+
+impl<R> JobHandle<R> {
+    pub(crate) fn new(receiver: Receiver<R>) -> Self {
+        JobHandle { receiver }
+    }
+
+    /// Blocks until the job finishes, and returns its result.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the job did not finish, either because it
+    /// panicked or because the pool was dropped before executing it
+    pub fn join(self) -> Result<R, ThreadPoolError> {
+        // Si se dropeó el sender sin enviar nada, la tarea no terminó
+        self.receiver
+            .recv()
+            .map_err(|_| ThreadPoolError::job_lost())
+    }
+}
@@ -1,34 +1,78 @@
 use std::{
     sync::{
+        atomic::{AtomicUsize, Ordering},
         mpsc::{self, channel, Receiver, Sender},
-        Arc,
+        Arc, Mutex, RwLock,
     },
     thread::{self, JoinHandle},
     time::Duration,
 };
 
+pub use builder::ThreadPoolBuilder;
+pub use job_handle::JobHandle;
 pub use threadpool_error::ThreadPoolError;
+mod builder;
+mod job_handle;
 mod threadpool_error;
 type Job = Box<dyn FnOnce() + Send + 'static>;
-type WorkerId = usize;
+type PanicCallback = Arc<dyn Fn(WorkerId) + Send + Sync + 'static>;
+
+/// Identifier of a worker thread of a [`ThreadPool`]. A worker
+/// that is revived after panicking keeps its identifier
+pub type WorkerId = usize;
 
 // Cuanto debe esperar a que se libere un thread (máximo)
 // antes de verificar si hubo alguno que haya hecho panic
 // Si es muy grande, hay más chances de que no se entere que perdió un thread
 // Si es muy chico, pierde más tiempo verificando si perdió alguno
+// Es el valor por defecto, se puede cambiar con ThreadPoolBuilder::recovery_interval
 const THREAD_WAIT_TIMEOUT: Duration = Duration::from_micros(1000); // 0.001s
 
 /// ThreadPool implementation
 /// Allows to execute jobs concurrently
-/// with a fixed number of threads
+/// with a number of threads that can be changed
+/// at runtime with [`ThreadPool::set_size`]
 #[derive(Clone)]
 pub struct ThreadPool {
-    job_sender: Sender<Job>, // Sender por el que se le envían las tareas al ThreadManager
-    _thread_manager_handler: Arc<ManagerHandle>, // Handler del thread que ejecuta al ThreadManager
+    job_sender: Sender<Message>, // Sender por el que se le envían las tareas al ThreadManager
+    status: Arc<PoolStatus>,     // Estado compartido con el ThreadManager y los workers
+    thread_manager_handler: Arc<ManagerHandle>, // Handler del thread que ejecuta al ThreadManager
 } // Es importante que el sender este definido primero para que se dropee antes, sino el manager va a quedar bloqueado
 
+/// Snapshot of the state of a [`ThreadPool`], as returned by [`ThreadPool::stats`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    /// Amount of threads of the pool
+    pub workers: usize,
+    /// Amount of threads that are executing a job
+    pub busy: usize,
+    /// Amount of submitted jobs that did not start executing yet
+    pub queued: usize,
+}
+
+// Mensajes que recibe el ThreadManager desde la interfaz de la ThreadPool
+enum Message {
+    NewJob(Job),
+    Resize, // Avisa que cambió target_size, para que el manager no se quede esperando una tarea
+    Shutdown, // Avisa que se cerró la pool, el manager termina de repartir las tareas y sale
+}
+
+// Estado de la ThreadPool, compartido entre la interfaz, el ThreadManager y los workers
+#[derive(Default)]
+struct PoolStatus {
+    target_size: AtomicUsize, // Cantidad de threads que se pidió tener
+    size: AtomicUsize,        // Cantidad de threads que tiene el ThreadManager
+    active: AtomicUsize,      // Cantidad de threads ejecutando una tarea
+    pending: AtomicUsize,     // Cantidad de tareas enviadas que todavía no empezaron a ejecutarse
+    // Si se llamó a shutdown, en cuyo caso no se aceptan más tareas. Se mantiene tomado
+    // mientras se envía una tarea, así ninguna llega al manager después del Shutdown
+    closed: RwLock<bool>,
+    on_panic: RwLock<Option<PanicCallback>>, // Se llama cada vez que se revive un thread que paniqueó
+}
+
 // Información que se guarda el ThreadManager de cada worker thread
 struct ThreadInfo {
+    id: WorkerId,                    // El id con el que el thread avisa que está libre
     handler: Option<JoinHandle<()>>, // El handler para hacer join al thread
     job_sender: Sender<Job>,         // El canal para envíar tareas al thread
     alive_receiver: Receiver<bool>, // Un receiver por el que no se envía info, se usa para verificar
@@ -39,132 +83,387 @@ struct ThreadInfo {
 // de hacer de intermediario entre la interfaz de la ThreadPool y los worker threads
 struct ThreadManager {
     threads: Vec<ThreadInfo>,           // El vector de threads
+    retired: Vec<JoinHandle<()>>, // Threads quitados al achicar la pool, que pueden estar terminando una tarea
+    next_id: WorkerId,            // El id que se le asigna al próximo thread que se cree
+    status: Arc<PoolStatus>,      // Estado compartido con la ThreadPool
     ready_receiver: Receiver<WorkerId>, // Por donde se recibe la id de los threads que están libres
-    job_receiver: Receiver<Job>,        // Por donde se reciben las tareas
+    job_receiver: Receiver<Message>, // Por donde se reciben las tareas
     ready_sender: Sender<WorkerId>, // Una copia del receiver que se usa para saber que threads están libres
-                                    // (se guarda para dársela a los threads que se revivan al haber paniqueado)
+    // (se guarda para dársela a los threads que se revivan al haber paniqueado)
+    recovery_interval: Duration, // Cuanto esperar a que se libere un thread antes de revisar si alguno paniqueó
 }
 
 // Guarda el handle del thread que ejecuta al ThreadManager, cosa de hacerle join cuando se dropee
-struct ManagerHandle(Option<JoinHandle<()>>);
-
-impl Drop for ManagerHandle {
-    fn drop(&mut self) {
+// (o antes, si se llama a shutdown)
+struct ManagerHandle(Mutex<Option<JoinHandle<()>>>);
+
+impl ManagerHandle {
+    // Espera a que termine el ThreadManager, si es que no se le hizo join antes
+    fn join(&self) {
+        let handle = match self.0.lock() {
+            Ok(mut handle) => handle.take(),
+            Err(poisoned) => poisoned.into_inner().take(),
+        };
         // Si falla acá mucho no se puede hacer
-        if let Some(handle) = self.0.take() {
+        if let Some(handle) = handle {
             let _res = handle.join();
         }
     }
 }
 
+impl Drop for ManagerHandle {
+    fn drop(&mut self) {
+        self.join();
+    }
+}
+
+// Se crea mientras un worker ejecuta una tarea, y al dropearse (incluso si la tarea
+// paniqueó) lo deja de contar como activo
+struct ActiveGuard(Arc<PoolStatus>);
+
+impl ActiveGuard {
+    fn new(status: Arc<PoolStatus>) -> Self {
+        status.active.fetch_add(1, Ordering::Relaxed);
+        ActiveGuard(status)
+    }
+}
+
+impl Drop for ActiveGuard {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
 impl ThreadManager {
-    // Crea el ThreadManager con amount threads, recibe tareas por el job_receiver hasta que se cierre el sender
-    fn new(amount: usize, job_receiver: Receiver<Job>) -> Self {
+    // Crea el ThreadManager con los threads indicados en el status, recibe tareas por el job_receiver
+    // hasta que se cierre el sender
+    fn new(
+        status: Arc<PoolStatus>,
+        job_receiver: Receiver<Message>,
+        recovery_interval: Duration,
+    ) -> Self {
         let (ready_sender, ready_receiver) = channel();
 
-        let ready_sender_clone = ready_sender.clone();
-        let threads = Self::initialize_threads(amount, ready_sender_clone);
-        ThreadManager {
-            threads,
+        let mut manager = ThreadManager {
+            threads: Vec::new(),
+            retired: Vec::new(),
+            next_id: 0,
+            status,
             ready_receiver,
             job_receiver,
             ready_sender,
-        }
+            recovery_interval,
+        };
+        manager.resize();
+        manager
     }
 
     // Comienza a esperar por una tarea. Cuando se cierra el job_sender que tiene
     // la threadpool sale del ciclo infinito
     fn run(&mut self) {
-        while let Ok(job) = self.job_receiver.recv() {
-            let i = self.get_free_thread();
-            // Nunca debería fallar ya que me mandó la señal de que está listo
-            let _res = self.threads[i].job_sender.send(job);
+        while let Ok(message) = self.job_receiver.recv() {
+            match message {
+                Message::NewJob(job) => self.dispatch(job),
+                Message::Resize => self.resize(),
+                // Todas las tareas se enviaron antes que este mensaje, así que ya se repartieron.
+                // Al dropearse, el manager espera a que los workers terminen sus tareas
+                Message::Shutdown => return,
+            }
         }
     }
 
+    // Le envía la tarea al primer thread worker que esté libre
+    fn dispatch(&mut self, job: Job) {
+        let i = self.get_free_thread();
+        self.status.pending.fetch_sub(1, Ordering::Relaxed);
+        // Nunca debería fallar ya que me mandó la señal de que está listo
+        let _res = self.threads[i].job_sender.send(job);
+    }
+
     // Obtiene el índice del un thread worker libre
     // Espera hasta que haya uno disponible, y en caso de que no haya ninguno,
     // intenta resucitar threads que puedan haber paniqueado
-    fn get_free_thread(&mut self) -> WorkerId {
-        let mut resultado = self.ready_receiver.recv_timeout(THREAD_WAIT_TIMEOUT);
-        while resultado.is_err() {
-            // Cabe la posibilidad que alguno haya paniqueado, asi que intento arreglarlo
-            self.recover_threads();
-            resultado = self.ready_receiver.recv_timeout(THREAD_WAIT_TIMEOUT);
+    fn get_free_thread(&mut self) -> usize {
+        loop {
+            match self.ready_receiver.recv_timeout(self.recovery_interval) {
+                Ok(id) => {
+                    // Si no lo encuentra es un thread que se quitó al achicar la pool
+                    if let Some(i) = self.threads.iter().position(|thread| thread.id == id) {
+                        return i;
+                    }
+                }
+                Err(_) => {
+                    // Cabe la posibilidad que alguno haya paniqueado, asi que intento arreglarlo
+                    self.recover_threads();
+                    // O que se haya pedido cambiar el tamaño mientras todos estaban ocupados
+                    self.resize();
+                }
+            }
+        }
+    }
+
+    // Agrega o quita threads hasta llegar al tamaño pedido. Los threads que se quitan
+    // terminan la tarea que estén ejecutando antes de salir
+    fn resize(&mut self) {
+        let target_size = self.status.target_size.load(Ordering::Relaxed);
+        while self.threads.len() < target_size {
+            let id = self.next_id;
+            self.next_id += 1;
+            self.threads.push(Self::spawn_thread(
+                id,
+                self.status.clone(),
+                self.ready_sender.clone(),
+            ));
+        }
+        while self.threads.len() > target_size {
+            if let Some(mut thread) = self.threads.pop() {
+                // Al dropear el job_sender, el worker sale del loop cuando termine su tarea
+                if let Some(handle) = thread.handler.take() {
+                    self.retired.push(handle);
+                }
+            }
+        }
+        self.status
+            .size
+            .store(self.threads.len(), Ordering::Relaxed);
+        self.join_retired(false);
+    }
+
+    // Hace join a los threads quitados de la pool. Si wait es falso, sólo a los que ya terminaron
+    fn join_retired(&mut self, wait: bool) {
+        let (finished, running) = self
+            .retired
+            .drain(..)
+            .partition(|handle| wait || handle.is_finished());
+        self.retired = running;
+        for handle in finished {
+            let _ = handle.join();
         }
-        resultado.unwrap()
     }
 
     // Recorre la lista de threads y revive a aquellos que estén muertos (lo hace con el ready_receiver)
     fn recover_threads(&mut self) {
-        for (id, thread) in self.threads.iter_mut().enumerate() {
+        for thread in self.threads.iter_mut() {
             if let Err(mpsc::TryRecvError::Disconnected) = thread.alive_receiver.try_recv() {
                 // Murio el thread
-                Self::reset_thread(thread, id, self.ready_sender.clone());
+                if let Some(handle) = thread.handler.take() {
+                    let _res = handle.join();
+                }
+                // Se llama sin el lock tomado, por si el callback se quiere reemplazar
+                let on_panic = match self.status.on_panic.read() {
+                    Ok(on_panic) => on_panic.clone(),
+                    Err(_) => None,
+                };
+                if let Some(callback) = on_panic {
+                    callback(thread.id);
+                }
+                *thread =
+                    Self::spawn_thread(thread.id, self.status.clone(), self.ready_sender.clone());
             }
         }
     }
 
-    // Resucita un thread muerto. Le hace join y lo inicia de nuevo, actualizando los channels
-    fn reset_thread(thread: &mut ThreadInfo, id: WorkerId, ready_sender: Sender<WorkerId>) {
-        if let Some(handle) = thread.handler.take() {
-            let _res = handle.join();
-        }
-
+    // Inicia un thread worker con el id dado, creando sus canales de comunicación
+    fn spawn_thread(
+        id: WorkerId,
+        status: Arc<PoolStatus>,
+        ready_sender: Sender<WorkerId>,
+    ) -> ThreadInfo {
         let (alive_sender, alive_receiver) = channel();
         let (job_sender, job_receiver) = channel();
 
-        thread.handler = Some(thread::spawn(move || {
-            worker(job_receiver, alive_sender, ready_sender, id)
-        }));
-
-        thread.job_sender = job_sender;
-        thread.alive_receiver = alive_receiver;
-    }
-
-    // Crea el vector de amount threads workers, inicializándolos con sus canales de comunicación
-    fn initialize_threads(amount: usize, ready_sender: Sender<WorkerId>) -> Vec<ThreadInfo> {
-        let mut threads = Vec::new();
-        for i in 0..amount {
-            let (alive_sender, alive_receiver) = channel();
-            let (job_sender, job_receiver) = channel();
-            let rs = ready_sender.clone();
-
-            let handler = thread::spawn(move || worker(job_receiver, alive_sender, rs, i));
+        let handler =
+            thread::spawn(move || worker(job_receiver, alive_sender, ready_sender, status, id));
 
-            threads.push(ThreadInfo {
-                handler: Some(handler),
-                alive_receiver,
-                job_sender,
-            });
+        ThreadInfo {
+            id,
+            handler: Some(handler),
+            alive_receiver,
+            job_sender,
         }
-        threads
     }
 }
 
 impl ThreadPool {
     /// Creates a new threadpool with the given amount of threads.
     /// The threadpool uses an extra thread for internal processing.
+    ///
+    /// To change the rest of its configuration, use a [`ThreadPoolBuilder`]
     pub fn new(amount: usize) -> ThreadPool {
-        let (sender, receiver): (Sender<Job>, Receiver<Job>) = mpsc::channel();
+        ThreadPoolBuilder::new().threads(amount).build()
+    }
+
+    // Inicia la threadpool con la configuración que recibe del ThreadPoolBuilder
+    fn start(amount: usize, recovery_interval: Duration) -> ThreadPool {
+        let (sender, receiver): (Sender<Message>, Receiver<Message>) = mpsc::channel();
+        let status = Arc::new(PoolStatus::default());
+        status.target_size.store(amount, Ordering::Relaxed);
+        let status_copy = status.clone();
         let handler = thread::spawn(move || {
-            ThreadManager::new(amount, receiver).run();
+            ThreadManager::new(status_copy, receiver, recovery_interval).run();
         });
 
         ThreadPool {
             job_sender: sender,
-            _thread_manager_handler: Arc::new(ManagerHandle(Some(handler))),
+            status,
+            thread_manager_handler: Arc::new(ManagerHandle(Mutex::new(Some(handler)))),
         }
     }
 
     /// Submits a job to the thread pool.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the pool was shut down (see [`ThreadPool::shutdown`])
     pub fn execute<F>(&self, job: F) -> Result<(), ThreadPoolError>
     where
         F: FnOnce() + Send + 'static,
     {
-        self.job_sender.send(Box::new(job))?;
+        // Si el lock quedó envenenado, el valor igual es válido
+        let closed = self
+            .status
+            .closed
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        if *closed {
+            return Err(ThreadPoolError::closed());
+        }
+        self.status.pending.fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = self.job_sender.send(Message::NewJob(Box::new(job))) {
+            self.status.pending.fetch_sub(1, Ordering::Relaxed);
+            return Err(err.into());
+        }
+        Ok(())
+    }
+
+    /// Submits a job to the thread pool. It is an alias of
+    /// [`ThreadPool::execute`], which is the preferred name.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the pool was shut down (see [`ThreadPool::shutdown`])
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::sync::mpsc::channel;
+    /// use threadpool::ThreadPool;
+    ///
+    /// let pool = ThreadPool::new(2);
+    /// let (sender, receiver) = channel();
+    /// let sender_copy = sender.clone();
+    /// pool.execute(move || sender.send("execute").unwrap()).unwrap();
+    /// pool.spawn(move || sender_copy.send("spawn").unwrap()).unwrap();
+    ///
+    /// let mut received: Vec<&str> = receiver.iter().take(2).collect();
+    /// received.sort();
+    /// assert_eq!(received, ["execute", "spawn"]);
+    /// ```
+    pub fn spawn<F>(&self, job: F) -> Result<(), ThreadPoolError>
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.execute(job)
+    }
+
+    /// Submits a job to the thread pool, returning a [`JobHandle`]
+    /// to wait for the value the job returns.
+    ///
+    /// # Errors
+    ///
+    /// Returns error if the pool was shut down (see [`ThreadPool::shutdown`])
+    pub fn submit<F, R>(&self, job: F) -> Result<JobHandle<R>, ThreadPoolError>
+    where
+        F: FnOnce() -> R + Send + 'static,
+        R: Send + 'static,
+    {
+        let (sender, receiver) = channel();
+        self.execute(move || {
+            // Si falla es porque se dropeó el handle, nadie espera el resultado
+            let _res = sender.send(job());
+        })?;
+        Ok(JobHandle::new(receiver))
+    }
+
+    /// Sets a callback that is called with the id of a worker thread
+    /// each time it is revived after a job panicked in it, replacing
+    /// the previous one (if any).
+    ///
+    /// The callback runs in the thread that distributes the jobs, so
+    /// it should return quickly and must not panic
+    pub fn on_panic(&self, callback: impl Fn(WorkerId) + Send + Sync + 'static) {
+        if let Ok(mut on_panic) = self.status.on_panic.write() {
+            *on_panic = Some(Arc::new(callback));
+        }
+    }
+
+    /// Changes the amount of threads of the pool.
+    ///
+    /// New threads are started right away, while the
+    /// removed ones finish the job they are executing
+    /// (if any) before exiting
+    ///
+    /// # Errors
+    ///
+    /// Returns error if `new_size` is 0, since the submitted
+    /// jobs would never be executed
+    pub fn set_size(&self, new_size: usize) -> Result<(), ThreadPoolError> {
+        if new_size == 0 {
+            return Err(ThreadPoolError::invalid_size());
+        }
+        self.status.target_size.store(new_size, Ordering::Relaxed);
+        self.job_sender.send(Message::Resize)?;
         Ok(())
     }
+
+    /// Shuts down the pool, waiting for every job that was already
+    /// submitted to finish before returning.
+    ///
+    /// From then on, [`ThreadPool::execute`] fails on every clone of
+    /// this pool. Its worker threads and the thread of the manager are
+    /// joined, so no job is executing once it returns
+    pub fn shutdown(self) {
+        {
+            let mut closed = self
+                .status
+                .closed
+                .write()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            if !*closed {
+                *closed = true;
+                // Si falla, el manager ya salió del loop
+                let _res = self.job_sender.send(Message::Shutdown);
+            }
+        }
+        self.thread_manager_handler.join();
+    }
+
+    /// Returns the amount of threads of the pool
+    pub fn size(&self) -> usize {
+        self.status.size.load(Ordering::Relaxed)
+    }
+
+    /// Returns the amount of threads that are executing a job
+    pub fn active_count(&self) -> usize {
+        self.status.active.load(Ordering::Relaxed)
+    }
+
+    /// Returns the amount of submitted jobs that did not
+    /// start executing yet
+    pub fn pending_count(&self) -> usize {
+        self.status.pending.load(Ordering::Relaxed)
+    }
+
+    /// Returns the amount of threads, busy threads and queued jobs
+    /// of the pool. Each value is read separately, so while the pool
+    /// is working they may not add up exactly
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            workers: self.size(),
+            busy: self.active_count(),
+            queued: self.pending_count(),
+        }
+    }
 }
 
 impl Drop for ThreadManager {
@@ -179,6 +478,7 @@ impl Drop for ThreadManager {
                 let _ = handle.join();
             }
         }
+        self.join_retired(true);
     }
 }
 
@@ -187,6 +487,7 @@ fn worker(
     job_receiver: Receiver<Job>,
     _alive: Sender<bool>,
     ready_sender: Sender<WorkerId>,
+    status: Arc<PoolStatus>,
     id: WorkerId,
 ) {
     if let Err(_err) = ready_sender.send(id) {
@@ -194,7 +495,9 @@ fn worker(
     }
 
     for job in job_receiver {
+        let active = ActiveGuard::new(status.clone());
         job();
+        drop(active);
         if let Err(_err) = ready_sender.send(id) {
             // Si falla por alguna razón, que muera el thread y de última después se recupera
             break;
@@ -204,10 +507,14 @@ fn worker(
 
 #[cfg(test)]
 mod tests {
-    use super::ThreadPool;
+    use super::{PoolStats, ThreadPool, ThreadPoolBuilder};
     use std::{
-        sync::{Arc, Mutex},
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc, Mutex,
+        },
         thread,
+        time::Duration,
     };
 
     #[test]
@@ -293,6 +600,251 @@ mod tests {
         assert_eq!(*x.lock().unwrap(), y * 2);
     }
 
+    #[test]
+    fn test_set_size_changes_size_and_counts() {
+        let threadpool = ThreadPool::new(2);
+        let x = Arc::new(Mutex::new(0));
+
+        // Ocupo los dos threads y dejo una tarea pendiente
+        for _ in 0..3 {
+            let x_copy = x.clone();
+            let _res = threadpool.execute(move || {
+                thread::sleep(Duration::from_millis(300));
+                *x_copy.lock().unwrap() += 1;
+            });
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(threadpool.size(), 2);
+        assert_eq!(threadpool.active_count(), 2);
+        assert_eq!(threadpool.pending_count(), 1);
+
+        // Al agrandarla, la tarea pendiente empieza sin esperar
+        threadpool.set_size(4).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(threadpool.size(), 4);
+        assert_eq!(threadpool.active_count(), 3);
+        assert_eq!(threadpool.pending_count(), 0);
+
+        // Al achicarla, las tareas en ejecución terminan igual
+        threadpool.set_size(1).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(threadpool.size(), 1);
+        drop(threadpool);
+        assert_eq!(*x.lock().unwrap(), 3);
+    }
+
+    #[test]
+    fn test_resizing_does_not_lose_jobs() {
+        let x = Arc::new(Mutex::new(0));
+        let mut y = 0;
+
+        let threadpool = ThreadPool::new(4);
+        for (i, size) in [(0, 10), (300, 2), (600, 10)] {
+            for j in i..i + 300 {
+                y += j;
+                let x_copy = x.clone();
+                threadpool
+                    .execute(move || {
+                        thread::sleep(Duration::from_micros(500));
+                        *x_copy.lock().unwrap() += j;
+                    })
+                    .unwrap();
+            }
+            // Cambio el tamaño con tareas todavía en cola
+            threadpool.set_size(size).unwrap();
+        }
+        threadpool.set_size(2).unwrap();
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(threadpool.size(), 2);
+        drop(threadpool);
+
+        assert_eq!(*x.lock().unwrap(), y);
+    }
+
+    #[test]
+    fn test_stats_of_saturated_pool() {
+        let threadpool = ThreadPool::new(4);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(
+            threadpool.stats(),
+            PoolStats {
+                workers: 4,
+                busy: 0,
+                queued: 0
+            }
+        );
+
+        for _ in 0..6 {
+            threadpool
+                .execute(|| thread::sleep(Duration::from_millis(300)))
+                .unwrap();
+        }
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(
+            threadpool.stats(),
+            PoolStats {
+                workers: 4,
+                busy: 4,
+                queued: 2
+            }
+        );
+    }
+
+    #[test]
+    fn test_shutdown_waits_for_pending_jobs() {
+        let x = Arc::new(Mutex::new(0));
+        let mut y = 0;
+
+        let threadpool = ThreadPool::new(4);
+        let threadpool_clone = threadpool.clone();
+        for i in 0..1000 {
+            y += i;
+            let x_copy = x.clone();
+            threadpool
+                .execute(move || {
+                    *x_copy.lock().unwrap() += i;
+                })
+                .unwrap();
+        }
+        threadpool.shutdown();
+
+        assert_eq!(*x.lock().unwrap(), y);
+        assert!(threadpool_clone.execute(|| {}).is_err());
+    }
+
+    #[test]
+    fn test_submit_returns_job_results() {
+        let threadpool = ThreadPool::new(4);
+        let handles: Vec<_> = (0..20)
+            .map(|i| threadpool.submit(move || i * i).unwrap())
+            .collect();
+
+        let results: Vec<i32> = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect();
+        assert_eq!(results, (0..20).map(|i| i * i).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_join_panicked_job_fails() {
+        let threadpool = ThreadPool::new(2);
+        let handle = threadpool
+            .submit(|| -> i32 { panic!("Test panic") })
+            .unwrap();
+        assert!(handle.join().is_err());
+
+        let handle = threadpool.submit(|| "ok").unwrap();
+        assert_eq!(handle.join().unwrap(), "ok");
+    }
+
+    #[test]
+    fn test_on_panic_is_called_for_revived_threads() {
+        let panics = Arc::new(AtomicUsize::new(0));
+        let panics_copy = panics.clone();
+
+        // Con un solo thread, cada tarea tiene que esperar a que se reviva
+        let threadpool = ThreadPool::new(1);
+        threadpool.on_panic(move |_id| {
+            panics_copy.fetch_add(1, Ordering::Relaxed);
+        });
+        for _ in 0..5 {
+            threadpool.execute(|| panic!("Test panic")).unwrap();
+        }
+        threadpool.submit(|| {}).unwrap().join().unwrap();
+
+        assert!(panics.load(Ordering::Relaxed) >= 5);
+    }
+
+    #[test]
+    fn test_on_panic_callback_can_replace_itself() {
+        let replaced = Arc::new(AtomicUsize::new(0));
+        let replaced_copy = replaced.clone();
+
+        let threadpool = ThreadPool::new(1);
+        let threadpool_clone = threadpool.clone();
+        threadpool.on_panic(move |_id| {
+            let replaced_copy = replaced_copy.clone();
+            threadpool_clone.on_panic(move |_id| {
+                replaced_copy.fetch_add(1, Ordering::Relaxed);
+            });
+        });
+        for _ in 0..2 {
+            threadpool.execute(|| panic!("Test panic")).unwrap();
+        }
+        threadpool.submit(|| {}).unwrap().join().unwrap();
+
+        assert_eq!(replaced.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_pool_with_long_recovery_interval_revives_threads() {
+        let panics = Arc::new(AtomicUsize::new(0));
+        let panics_copy = panics.clone();
+
+        let threadpool = ThreadPoolBuilder::new()
+            .threads(1)
+            .recovery_interval(Duration::from_millis(50))
+            .build();
+        threadpool.on_panic(move |_id| {
+            panics_copy.fetch_add(1, Ordering::Relaxed);
+        });
+        threadpool.execute(|| panic!("Test panic")).unwrap();
+
+        assert_eq!(threadpool.submit(|| 10).unwrap().join().unwrap(), 10);
+        assert_eq!(panics.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn test_shutdown_while_other_clones_submit() {
+        let x = Arc::new(AtomicUsize::new(0));
+        let accepted = Arc::new(AtomicUsize::new(0));
+
+        let threadpool = ThreadPool::new(4);
+        let handles: Vec<_> = (0..4)
+            .map(|_| {
+                let threadpool_clone = threadpool.clone();
+                let x_copy = x.clone();
+                let accepted_copy = accepted.clone();
+                thread::spawn(move || {
+                    for _ in 0..1000 {
+                        let x_copy = x_copy.clone();
+                        let job = move || {
+                            x_copy.fetch_add(1, Ordering::Relaxed);
+                        };
+                        if threadpool_clone.execute(job).is_err() {
+                            break;
+                        }
+                        accepted_copy.fetch_add(1, Ordering::Relaxed);
+                    }
+                })
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(1));
+        let status = threadpool.status.clone();
+        threadpool.shutdown();
+
+        // Cada tarea aceptada antes del shutdown se ejecutó
+        let executed = x.load(Ordering::Relaxed);
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert_eq!(executed, accepted.load(Ordering::Relaxed));
+        assert_eq!(status.pending.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_set_size_zero_fails() {
+        let threadpool = ThreadPool::new(2);
+        assert!(threadpool.set_size(0).is_err());
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(threadpool.size(), 2);
+
+        // La pool sigue funcionando y se puede cerrar
+        assert_eq!(threadpool.submit(|| 1).unwrap().join().unwrap(), 1);
+        threadpool.shutdown();
+    }
+
     fn sum(x: Arc<Mutex<i32>>, threadpool: ThreadPool) -> i32 {
         let mut y = 0;
         for i in 0..1000 {
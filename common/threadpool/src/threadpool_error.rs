@@ -1,6 +1,6 @@
 use std::{error::Error, fmt::Display, sync::mpsc::SendError};
 
-use super::Job;
+use super::Message;
 
 #[derive(Debug)]
 pub struct ThreadPoolError {
@@ -25,10 +25,28 @@ impl ThreadPoolError {
             msg: "ThreadPoolError: Could not send job".to_string(),
         }
     }
+
+    pub(crate) fn closed() -> ThreadPoolError {
+        ThreadPoolError {
+            msg: "ThreadPoolError: The pool was shut down".to_string(),
+        }
+    }
+
+    pub(crate) fn invalid_size() -> ThreadPoolError {
+        ThreadPoolError {
+            msg: "ThreadPoolError: The pool must have at least one thread".to_string(),
+        }
+    }
+
+    pub(crate) fn job_lost() -> ThreadPoolError {
+        ThreadPoolError {
+            msg: "ThreadPoolError: The job did not return a value".to_string(),
+        }
+    }
 }
 
-impl From<SendError<Job>> for ThreadPoolError {
-    fn from(error: SendError<Job>) -> ThreadPoolError {
+impl From<SendError<Message>> for ThreadPoolError {
+    fn from(error: SendError<Message>) -> ThreadPoolError {
         ThreadPoolError {
             msg: format!("ThreadPoolError: Could not send job ({})", error),
         }
@@ -12,14 +12,22 @@ mod server;
 mod setup;
 
 fn main() {
-    let _logger = Logger::new("logs", Level::INFO, Level::TRACE);
+    let logger =
+        Logger::new("logs", Level::INFO, Level::TRACE).expect("Error inicializando el logger");
 
     match setup::initialize_server() {
-        Err(e) => error!("Error inicializando el servidor: {}", e),
-        Ok(_guards) => {
+        Err(e) => {
+            error!("Error inicializando el servidor: {}", e);
+            logger.flush();
+        }
+        Ok(guards) => {
             info!("Presione [ENTER] para detener la ejecución del servidor");
             let mut buf = [0u8; 1];
             std::io::stdin().read_exact(&mut buf).unwrap_or(());
+            if let Err(e) = setup::shutdown(guards, &logger) {
+                // El logger ya no es confiable, se informa por stderr
+                eprintln!("Error deteniendo el servidor: {}", e);
+            }
         }
     }
 }
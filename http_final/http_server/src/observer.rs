@@ -10,8 +10,8 @@ pub struct Observer {
 impl ObserverTrait for Observer {
     fn update(&self, msg: Message) {
         match msg {
-            Message::Publish(publish) => {
-                let payload = publish.payload();
+            Message::Publish { publish, .. } => {
+                let payload = String::from_utf8_lossy(publish.payload());
                 self.sender
                     .lock()
                     .unwrap()
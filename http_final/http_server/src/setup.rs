@@ -1,4 +1,4 @@
-use crate::{instrument, Observer, Server, ServerGuard, ServerResult};
+use crate::{instrument, Logger, Observer, Server, ServerGuard, ServerResult};
 use config::config::Config;
 use mqtt_client::Client;
 use packets::connect::{Connect, ConnectBuilder};
@@ -9,7 +9,7 @@ use packets::PacketResult;
 use std::env;
 use std::sync::mpsc::{Receiver, Sender};
 use std::sync::{mpsc, Arc};
-use tracing::debug;
+use tracing::{debug, info};
 
 // Structures that cannot be dropped until the
 // server stops
@@ -42,6 +42,22 @@ pub fn initialize_server() -> ServerResult<Guards> {
     Ok((server_guard, client))
 }
 
+/// Stops the server in order: it stops accepting connections and
+/// waits for its threads to finish, then disconnects the MQTT client
+/// and finally flushes the logger, instead of relying on the order in
+/// which they are dropped. Returns an error if some of the logs could
+/// not be written
+pub fn shutdown((server_guard, client): Guards, logger: &Logger) -> ServerResult<()> {
+    info!("Deteniendo el servidor");
+    drop(server_guard);
+    drop(client);
+    info!("Servidor detenido");
+    if !logger.flush() {
+        return Err("No se pudieron escribir todos los logs durante el apagado".into());
+    }
+    Ok(())
+}
+
 #[instrument(skip(arg_num))]
 #[doc(hidden)]
 fn make_config(config_file: &str, arg_num: usize) -> ServerResult<Config> {
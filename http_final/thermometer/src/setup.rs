@@ -3,12 +3,13 @@ use config::config::Config;
 use mqtt_client::{Client, Message};
 use packets::connect::{Connect, ConnectBuilder};
 use packets::PacketResult;
-use std::io::Read;
+use std::env;
+use std::io::{Read, Write};
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
 use std::sync::Arc;
+use std::thread::{self, JoinHandle};
 use std::time::Duration;
-use std::{env, thread};
 
 #[doc(hidden)]
 const KEEP_ALIVE: u16 = 0;
@@ -29,13 +30,30 @@ pub fn init() -> ClientResult<()> {
         if let Err(e) = thermometer.publish() {
             println!("Error publicando temperaturas: {:?}", e);
         }
+        thermometer
     });
 
     println!("Presione [ENTER] para detener la ejecucion del cliente\n____________\n");
     let mut buf = [0u8; 1];
     std::io::stdin().read_exact(&mut buf).unwrap_or(());
+    shutdown(&stop, handle)
+}
+
+/// Stops the thermometer in order: it stops publishing and waits for
+/// the publishing thread to finish, then disconnects the MQTT client
+/// and finally flushes the standard output, instead of relying on the
+/// order in which they are dropped
+#[doc(hidden)]
+fn shutdown(stop: &AtomicBool, handle: JoinHandle<Thermometer>) -> ClientResult<()> {
+    println!("Deteniendo el termometro");
     stop.store(true, Ordering::Relaxed);
-    let _ = handle.join();
+    let thermometer = handle
+        .join()
+        .map_err(|_| "El thread que publica las temperaturas termino con un panic")?;
+    // Al liberarlo se desconecta del broker
+    drop(thermometer);
+    println!("Termometro detenido");
+    std::io::stdout().flush()?;
     Ok(())
 }
 
@@ -51,7 +51,10 @@ impl Thermometer {
         while !self.stop.load(Ordering::Relaxed) {
             temperature = self.measure_temperature(Some(temperature));
             let publish = self.create_publish(temperature)?;
-            println!("- - - - - - -\n{:}", publish.payload());
+            println!(
+                "- - - - - - -\n{:}",
+                String::from_utf8_lossy(publish.payload())
+            );
             self.client.publish(publish)?;
             let time_sent = time::Instant::now();
             match self.receiver.recv_timeout(self.config.period) {
@@ -91,7 +94,7 @@ impl Thermometer {
             QoSLevel::QoSLevel0,
             false,
             &self.config.topic,
-            &temperature.to_string(),
+            temperature.to_string(),
             None,
         )
     }
@@ -0,0 +1,207 @@
+use std::net::TcpStream;
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::Duration;
+
+use packets::connect::Connect;
+use socket2::{SockRef, TcpKeepalive};
+use threadpool::ThreadPool;
+
+use super::client_sender::{ClientSender, ResendPolicy};
+use super::offline_queue::OfflineOverflow;
+use super::transport::Transport;
+use super::{Client, ClientError};
+use crate::observer::{Observer, SharedObserver};
+
+/// Number of threads used by default to send the
+/// packets requested by the user (subscribe, publish, etc.)
+const DEFAULT_OPERATION_THREADS: usize = 1;
+
+/// Builder to configure a Client before connecting it.
+/// Client::new() is equivalent to ClientBuilder::new().connect()
+#[derive(Debug, Clone)]
+pub struct ClientBuilder {
+    #[doc(hidden)]
+    operation_threads: usize,
+    #[doc(hidden)]
+    resend: ResendPolicy,
+    #[doc(hidden)]
+    tcp_nodelay: bool,
+    #[doc(hidden)]
+    tcp_keepalive: Option<Duration>,
+    #[doc(hidden)]
+    offline_capacity: Option<usize>,
+    #[doc(hidden)]
+    offline_overflow: OfflineOverflow,
+}
+
+impl Default for ClientBuilder {
+    fn default() -> Self {
+        Self {
+            operation_threads: DEFAULT_OPERATION_THREADS,
+            resend: ResendPolicy::default(),
+            tcp_nodelay: true,
+            tcp_keepalive: None,
+            offline_capacity: None,
+            offline_overflow: OfflineOverflow::default(),
+        }
+    }
+}
+
+impl ClientBuilder {
+    /// Creates a ClientBuilder with the default configuration
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets how many threads are used to send the packets requested
+    /// by the user (subscribe, publish, etc.), besides the ones used
+    /// to listen to the server and keep the connection alive. Defaults
+    /// to 1, so the packets are sent one at a time
+    pub fn operation_threads(mut self, threads: usize) -> Self {
+        self.operation_threads = threads;
+        self
+    }
+
+    /// Sets how much time to wait for the acknowledgement of a packet
+    /// before sending it again for the first time. Defaults to 5 seconds
+    pub fn resend_timeout(mut self, timeout: Duration) -> Self {
+        self.resend.timeout = timeout;
+        self
+    }
+
+    /// Sets by how much the wait between resends of a packet is multiplied
+    /// after each resend, up to the maximum set with
+    /// [`ClientBuilder::max_resend_timeout`]. Defaults to 1, so the
+    /// packet is resent at a fixed interval
+    pub fn resend_backoff_factor(mut self, factor: u32) -> Self {
+        self.resend.backoff_factor = factor;
+        self
+    }
+
+    /// Sets the maximum time to wait between resends of a packet
+    /// that was not acknowledged. Defaults to 10 seconds
+    pub fn max_resend_timeout(mut self, timeout: Duration) -> Self {
+        self.resend.max_timeout = timeout;
+        self
+    }
+
+    /// Sets how many times a packet that was not acknowledged is sent
+    /// again before failing. Defaults to 3
+    pub fn max_retries(mut self, retries: u16) -> Self {
+        self.resend.max_retries = retries;
+        self
+    }
+
+    /// Sets whether TCP_NODELAY is set on the connection, so that small
+    /// packets are sent right away instead of being buffered. Defaults
+    /// to true
+    pub fn tcp_nodelay(mut self, nodelay: bool) -> Self {
+        self.tcp_nodelay = nodelay;
+        self
+    }
+
+    /// Sets SO_KEEPALIVE on the connection, so that TCP keepalive probes
+    /// are sent after it is idle for the given time, to detect a dead
+    /// server. It is not set by default
+    pub fn tcp_keepalive(mut self, time: Duration) -> Self {
+        self.tcp_keepalive = Some(time);
+        self
+    }
+
+    /// Enables the offline queue of the client. The publishes made with
+    /// Client::publish() while the client is not connected (before the
+    /// server accepts the connection, after it is lost, or while
+    /// reconnecting) are kept, up to capacity, and sent in order once it
+    /// connects. When it is full, one of them is
+    /// dropped according to the given overflow policy. Publishes are not
+    /// queued by default
+    pub fn offline_queue(mut self, capacity: usize, overflow: OfflineOverflow) -> Self {
+        self.offline_capacity = Some(capacity);
+        self.offline_overflow = overflow;
+        self
+    }
+
+    /// Creates a new Client with this configuration, which connects to the
+    /// TCP Listener on the given address by sending the given CONNECT packet,
+    /// as Client::new() does.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if operation_threads is zero, or the connection
+    /// could not be established
+    pub fn connect<T: Observer>(
+        self,
+        address: &str,
+        observer: T,
+        connect: Connect,
+    ) -> Result<Client<T>, ClientError> {
+        let stream = TcpStream::connect(address)?;
+        self.set_socket_options(&stream)?;
+        self.connect_transport(stream, observer, connect)
+    }
+
+    /// Creates a new Client with this configuration, which connects to the
+    /// server on the other end of the given stream by sending the given
+    /// CONNECT packet, instead of using a TcpStream. The TCP socket options
+    /// are not applied to it.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if operation_threads is zero, or the connection
+    /// could not be established
+    pub fn connect_transport<T: Observer, S: Transport>(
+        self,
+        stream: S,
+        observer: T,
+        connect: Connect,
+    ) -> Result<Client<T, S>, ClientError> {
+        if self.operation_threads == 0 {
+            return Err(ClientError::new(
+                "El cliente necesita al menos un thread para enviar paquetes",
+            ));
+        }
+        let keep_alive = connect.keep_alive();
+
+        let observer = SharedObserver::new(observer);
+        let mut sender = ClientSender::new(stream.try_clone()?, observer.clone())
+            .with_resend_policy(self.resend);
+        if let Some(capacity) = self.offline_capacity {
+            sender = sender.with_offline_queue(capacity, self.offline_overflow);
+        }
+        let mut ret = Client {
+            thread_pool: ThreadPool::new(self.threads(keep_alive)),
+            stop: Arc::new(AtomicBool::new(false)),
+            sender: Arc::new(sender),
+            observer: observer.clone(),
+            builder: self,
+        };
+
+        ret.connect(connect, stream, observer)?;
+
+        ret.setup_keep_alive(keep_alive)?;
+
+        Ok(ret)
+    }
+
+    /// Returns how many threads the client needs
+    /// with the given keep alive
+    pub(crate) fn threads(&self, keep_alive: u16) -> usize {
+        // Uno para escuchar al servidor y otro para el pingreq
+        let mut threads = self.operation_threads + 2;
+        if keep_alive == 0 {
+            threads -= 1; // no lo necesito para el pingreq
+        }
+        threads
+    }
+
+    /// Sets on the stream the configured socket
+    /// options (TCP_NODELAY and SO_KEEPALIVE)
+    pub(crate) fn set_socket_options(&self, stream: &TcpStream) -> std::io::Result<()> {
+        stream.set_nodelay(self.tcp_nodelay)?;
+        if let Some(time) = self.tcp_keepalive {
+            SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        Ok(())
+    }
+}
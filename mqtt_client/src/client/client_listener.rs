@@ -1,33 +1,26 @@
 use std::{
-    io::{self, Read},
+    convert::TryFrom,
+    io,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc, Mutex,
     },
-    time::Duration,
 };
 
 use packets::{
-    connack::Connack,
-    packet_error::{ErrorKind, PacketError},
-    pingresp::PingResp,
-    traits::MQTTDecoding,
-    unsuback::Unsuback,
+    connack::Connack, helpers::PacketType, packet_error::ErrorKind, pingresp::PingResp,
+    traits::MQTTDecoding, unsuback::Unsuback,
 };
-use packets::{puback::Puback, publish::Publish, suback::Suback};
+use packets::{puback::Puback, publish::Publish, qos::QoSLevel, suback::Suback};
 use threadpool::ThreadPool;
 
 use crate::{client::PendingAck, observer::Observer};
 
 use crate::observer::Message;
 
+use super::transport::ReadTimeout;
 use super::{ClientError, STOP_TIMEOUT};
 
-/// ReadTimeout trait from which the listener reads the packets
-pub(crate) trait ReadTimeout: Read + Send + Sync + 'static {
-    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()>;
-}
-
 /// The packet listener of the client. It is responsible
 /// for receiving all packets from the server, and
 /// acknowledging the ones in which it is required.
@@ -40,20 +33,6 @@ pub(crate) struct ClientListener<T: Observer, R: ReadTimeout, A: AckSender> {
     threadpool: ThreadPool,
 }
 
-enum PacketType {
-    Connect,
-    Connack,
-    Publish,
-    Puback,
-    Subscribe,
-    Suback,
-    Unsubscribe,
-    Unsuback,
-    Pingreq,
-    Pingresp,
-    Disconnect,
-}
-
 /// Under which errors should the listener send
 /// a Connected(Err()) to the observer instead of
 /// stopping and sending an InternalError(Err())
@@ -167,13 +146,13 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
 
     #[doc(hidden)]
     fn handle_packet(&mut self, header: u8) -> Result<(), ClientError> {
-        match get_code_type(header >> 4) {
+        match PacketType::from_code(header >> 4) {
             Ok(packet) => match packet {
                 PacketType::Publish => self.handle_publish(header),
                 PacketType::Puback => self.handle_puback(header),
                 PacketType::Suback => self.handle_suback(header),
                 PacketType::Unsuback => self.handle_unsuback(header),
-                PacketType::Pingresp => self.handle_pingresp(header),
+                PacketType::PingResp => self.handle_pingresp(header),
                 PacketType::Connack => self.handle_connack(header),
                 _ => Err(ClientError::new("Received an unsupported packet type")),
             },
@@ -189,7 +168,11 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
     fn handle_publish(&mut self, header: u8) -> Result<(), ClientError> {
         let publish = Publish::read_from(&mut self.stream, header)?;
         let id_opt = publish.packet_id();
-        self.observer.update(Message::Publish(publish));
+        let was_retained = publish.retain_flag();
+        self.observer.update(Message::Publish {
+            publish,
+            was_retained,
+        });
 
         // Si tiene id no es QoS 0
         if let Some(id) = id_opt {
@@ -238,11 +221,19 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
 
         let mut lock = self.pending_ack.lock()?;
 
-        if let Some(PendingAck::Subscribe(subscribe)) = lock.as_ref() {
+        if let Some(PendingAck::Subscribe(subscribe, completion)) = lock.as_ref() {
             if subscribe.packet_identifier() == suback.packet_id() {
                 suback.set_topics(subscribe.topics());
+                if let Some(completion) = completion {
+                    // Si el receiver ya no existe, se venció el tiempo de espera
+                    let _ = completion.send(Ok(suback.clone()));
+                }
                 lock.take();
+                let downgrades = downgraded_topics(&suback);
                 self.observer.update(Message::Subscribed(Ok(suback)));
+                for downgrade in downgrades {
+                    self.observer.update(downgrade);
+                }
             }
         }
 
@@ -302,24 +293,27 @@ impl<T: Observer, R: ReadTimeout, A: AckSender> ClientListener<T, R, A> {
 }
 
 #[doc(hidden)]
-fn get_code_type(code: u8) -> Result<PacketType, PacketError> {
-    match code {
-        1 => Ok(PacketType::Connect),
-        2 => Ok(PacketType::Connack),
-        3 => Ok(PacketType::Publish),
-        4 => Ok(PacketType::Puback),
-        8 => Ok(PacketType::Subscribe),
-        9 => Ok(PacketType::Suback),
-        10 => Ok(PacketType::Unsubscribe),
-        11 => Ok(PacketType::Unsuback),
-        12 => Ok(PacketType::Pingreq),
-        13 => Ok(PacketType::Pingresp),
-        14 => Ok(PacketType::Disconnect),
-        _ => Err(PacketError::new_kind(
-            &format!("Received invalid packet type: {}", code),
-            ErrorKind::InvalidControlPacketType,
-        )),
-    }
+/// Returns a QoSDowngraded message for each topic of the suback
+/// that was granted with a lower QoS than the one requested.
+/// Failed subscriptions are not considered downgrades
+fn downgraded_topics(suback: &Suback) -> Vec<Message> {
+    suback
+        .topics()
+        .iter()
+        .zip(suback.return_codes())
+        .filter_map(|(topic, code)| {
+            let granted = QoSLevel::try_from(*code).ok()?;
+            if u8::from(granted) < u8::from(topic.qos()) {
+                Some(Message::QoSDowngraded {
+                    topic: topic.name().to_string(),
+                    requested: topic.qos(),
+                    granted,
+                })
+            } else {
+                None
+            }
+        })
+        .collect()
 }
 
 #[cfg(test)]
@@ -484,10 +478,10 @@ mod tests {
     fn test_suback() {
         let observer = ObserverMock::new();
         let topic = TopicFilter::new("topic", QoSLevel1).unwrap();
-        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::Subscribe(Subscribe::new(
-            vec![topic],
-            123,
-        )))));
+        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::Subscribe(
+            Subscribe::new(vec![topic], 123),
+            None,
+        ))));
         let stop = Arc::new(AtomicBool::new(false));
         let stream = Cursor::new(vec![0b10010000, 3, 0, 123, 0]);
         let mut listener = ClientListener::new(
@@ -509,14 +503,58 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_suback_qos_downgraded() {
+        let observer = ObserverMock::new();
+        let topics = vec![
+            TopicFilter::new("topic", QoSLevel1).unwrap(),
+            TopicFilter::new("other", QoSLevel1).unwrap(),
+        ];
+        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::Subscribe(
+            Subscribe::new(topics, 123),
+            None,
+        ))));
+        let stop = Arc::new(AtomicBool::new(false));
+        // El servidor otorga QoS 0 al primer topic y QoS 1 al segundo
+        let stream = Cursor::new(vec![0b10010000, 4, 0, 123, 0, 1]);
+        let mut listener = ClientListener::new(
+            stream,
+            pending_ack.clone(),
+            observer.clone(),
+            stop,
+            SenderMock::new(),
+            ThreadPool::new(1),
+        )
+        .unwrap();
+        listener.wait_for_packets();
+
+        let msgs = observer.messages.lock().unwrap();
+        assert!(matches!(msgs[0], Message::Subscribed(Ok(_))));
+        let downgrades: Vec<&Message> = msgs
+            .iter()
+            .filter(|msg| matches!(msg, Message::QoSDowngraded { .. }))
+            .collect();
+        assert_eq!(downgrades.len(), 1);
+        if let Message::QoSDowngraded {
+            topic,
+            requested,
+            granted,
+        } = downgrades[0]
+        {
+            assert_eq!(topic, "topic");
+            assert_eq!(*requested, QoSLevel1);
+            assert_eq!(*granted, QoSLevel::QoSLevel0);
+        }
+    }
+
     #[test]
     fn test_suback_different_id() {
         let observer = ObserverMock::new();
         let topic = TopicFilter::new("topic", QoSLevel1).unwrap();
-        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::Subscribe(Subscribe::new(
-            vec![topic],
-            34,
-        )))));
+        let pending_ack = Arc::new(Mutex::new(Some(PendingAck::Subscribe(
+            Subscribe::new(vec![topic], 34),
+            None,
+        ))));
         let stop = Arc::new(AtomicBool::new(false));
         let stream = Cursor::new(vec![0b10010000, 3, 0, 123, 0]);
         let mut listener = ClientListener::new(
@@ -749,15 +787,49 @@ mod tests {
             Some(PendingAck::PingReq(_))
         ));
         let mut msgs = observer.messages.lock().unwrap();
-        assert!(matches!(msgs[0], Message::Publish(_)));
-        if let Message::Publish(publish) = msgs.remove(0) {
+        assert!(matches!(msgs[0], Message::Publish { .. }));
+        if let Message::Publish {
+            publish,
+            was_retained,
+        } = msgs.remove(0)
+        {
+            assert!(!was_retained);
             assert_eq!(publish.packet_id(), None);
             assert_eq!(publish.topic_name(), "topic");
-            assert_eq!(publish.payload(), "msg");
+            assert_eq!(publish.payload(), b"msg");
         }
         assert_eq!(*sender.times_called.lock().unwrap(), 0);
     }
 
+    #[test]
+    fn test_retained_publish() {
+        let observer = ObserverMock::new();
+        let pending_ack = Arc::new(Mutex::new(None));
+        let stop = Arc::new(AtomicBool::new(false));
+        let publish = Publish::new(false, QoSLevel0, true, "topic", "msg", None).unwrap();
+        let stream = Cursor::new(publish.encode().unwrap());
+        let sender = SenderMock::new();
+        let mut listener = ClientListener::new(
+            stream,
+            pending_ack,
+            observer.clone(),
+            stop,
+            sender,
+            ThreadPool::new(1),
+        )
+        .unwrap();
+        listener.wait_for_packets();
+
+        let msgs = observer.messages.lock().unwrap();
+        assert!(matches!(
+            msgs[0],
+            Message::Publish {
+                was_retained: true,
+                ..
+            }
+        ));
+    }
+
     #[test]
     fn test_publish_qos1() {
         let observer = ObserverMock::new();
@@ -782,11 +854,16 @@ mod tests {
             Some(PendingAck::PingReq(_))
         ));
         let mut msgs = observer.messages.lock().unwrap();
-        assert!(matches!(msgs[0], Message::Publish(_)));
-        if let Message::Publish(publish) = msgs.remove(0) {
+        assert!(matches!(msgs[0], Message::Publish { .. }));
+        if let Message::Publish {
+            publish,
+            was_retained,
+        } = msgs.remove(0)
+        {
+            assert!(!was_retained);
             assert_eq!(publish.packet_id(), Some(123));
             assert_eq!(publish.topic_name(), "topic");
-            assert_eq!(publish.payload(), "msg");
+            assert_eq!(publish.payload(), b"msg");
         }
         thread::sleep(Duration::from_millis(500));
         assert_eq!(*sender.times_called.lock().unwrap(), 1);
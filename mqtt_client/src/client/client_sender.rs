@@ -1,28 +1,42 @@
 use std::io::Write;
 use std::sync::atomic::AtomicBool;
-use std::sync::{Arc, Mutex};
-use std::time::Duration;
+use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::{Duration, Instant};
 use std::{thread, time};
 
+use backoff::Backoff;
+
 use packets::connect::Connect;
 use packets::disconnect::Disconnect;
 use packets::pingreq::PingReq;
 use packets::puback::Puback;
 use packets::qos::QoSLevel;
 use packets::subscribe::Subscribe;
+use packets::topic_filter::TopicFilter;
 use packets::traits::MQTTEncoding;
 use packets::unsubscribe::Unsubscribe;
 
 use crate::observer::{Message, Observer};
 use packets::publish::Publish;
 
-use super::{ClientError, PendingAck};
+use super::offline_queue::{OfflineOverflow, OfflineQueue, PushResult};
+use super::{ClientError, PendingAck, SubscribeCompletion};
 use crate::client::client_listener::AckSender;
 
 /// How much time should the sender wait until it tries
-/// to resend an unacknowledged packet.
+/// to resend an unacknowledged packet for the first time.
 pub(crate) const RESEND_TIMEOUT: Duration = Duration::from_millis(5000);
 
+/// By how much the wait between resends is multiplied after
+/// each unacknowledged resend. By default it does not grow,
+/// so a packet is resent every RESEND_TIMEOUT.
+pub(crate) const RESEND_BACKOFF_FACTOR: u32 = 1;
+
+/// The maximum time the sender should wait between
+/// resends of an unacknowledged packet.
+pub(crate) const MAX_RESEND_TIMEOUT: Duration = Duration::from_millis(10000);
+
 /// How often should the sender check pending_ack after
 /// sending a packet that needs acknowledgement to see
 /// if it was acknowledged.
@@ -32,12 +46,44 @@ pub(crate) const ACK_CHECK: Duration = Duration::from_millis(500);
 /// resend an unacknowledged packet.
 pub(crate) const MAX_RETRIES: u16 = 3;
 
+/// How the sender resends the packets that were not acknowledged.
+/// By default, it uses RESEND_TIMEOUT, RESEND_BACKOFF_FACTOR,
+/// MAX_RESEND_TIMEOUT and MAX_RETRIES
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct ResendPolicy {
+    /// How much time to wait until the first resend
+    pub timeout: Duration,
+    /// By how much the wait is multiplied after each resend
+    pub backoff_factor: u32,
+    /// The maximum time to wait between resends
+    pub max_timeout: Duration,
+    /// The maximum number of resends
+    pub max_retries: u16,
+}
+
+impl Default for ResendPolicy {
+    fn default() -> Self {
+        Self {
+            timeout: RESEND_TIMEOUT,
+            backoff_factor: RESEND_BACKOFF_FACTOR,
+            max_timeout: MAX_RESEND_TIMEOUT,
+            max_retries: MAX_RETRIES,
+        }
+    }
+}
+
 /// The packet sender of the client. It is responsible
 /// for sending all packets to the server.
 pub(crate) struct ClientSender<T: Observer, W: Write> {
     stream: Mutex<W>,
+    resend: ResendPolicy,
     pending_ack: Arc<Mutex<Option<PendingAck>>>,
     observer: Arc<T>,
+    subscriptions: Mutex<Vec<Subscribe>>,
+    /// When the last packet was written to the stream
+    last_sent: Mutex<Instant>,
+    /// Publishes made while the client is not connected
+    offline: Mutex<OfflineQueue>,
 }
 
 impl<T: Observer, W: Write + Send + 'static> AckSender for ClientSender<T, W> {
@@ -54,11 +100,51 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
     pub fn new(stream: W, observer: T) -> Self {
         Self {
             stream: Mutex::new(stream),
+            resend: ResendPolicy::default(),
             pending_ack: Arc::new(Mutex::new(None)),
             observer: Arc::new(observer),
+            subscriptions: Mutex::new(Vec::new()),
+            last_sent: Mutex::new(Instant::now()),
+            offline: Mutex::new(OfflineQueue::new(None, OfflineOverflow::default())),
         }
     }
 
+    /// Replaces the default ResendPolicy of the sender
+    pub fn with_resend_policy(mut self, resend: ResendPolicy) -> Self {
+        self.resend = resend;
+        self
+    }
+
+    /// Enables the offline queue of the sender, which keeps up to
+    /// capacity publishes made while the client is not connected
+    pub fn with_offline_queue(mut self, capacity: usize, overflow: OfflineOverflow) -> Self {
+        self.offline = Mutex::new(OfflineQueue::new(Some(capacity), overflow));
+        self
+    }
+
+    /// Replaces the stream the packets are written to, for
+    /// when the client connects to the server again
+    pub fn replace_stream(&self, stream: W) -> Result<(), ClientError> {
+        *self.stream.lock()? = stream;
+        Ok(())
+    }
+
+    /// Returns how much time passed since the last packet
+    /// was written to the stream
+    pub fn idle_time(&self) -> Duration {
+        self.last_sent
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .elapsed()
+    }
+
+    #[doc(hidden)]
+    fn write_packet(&self, stream: &mut W, bytes: &[u8]) -> Result<(), ClientError> {
+        stream.write_all(bytes)?;
+        *self.last_sent.lock()? = Instant::now();
+        Ok(())
+    }
+
     /// Gets the pending_ack lock of the sender. This is used
     /// by the sender after sending a packet to check if it was
     /// acknowledged. If it was, it expects the lock to be
@@ -69,7 +155,8 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
 
     #[doc(hidden)]
     fn _puback(&self, puback: Puback) -> Result<(), ClientError> {
-        self.stream.lock()?.write_all(&puback.encode()?)?;
+        let mut lock = self.stream.lock()?;
+        self.write_packet(&mut lock, &puback.encode()?)?;
         Ok(())
     }
 
@@ -81,7 +168,7 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
             .lock()?
             .replace(PendingAck::Connect(connect));
 
-        lock.write_all(&bytes)?;
+        self.write_packet(&mut lock, &bytes)?;
 
         if !self.wait_for_ack(&mut lock, &bytes)? {
             return Err(ClientError::new("No se pudo establecer la conexión"));
@@ -92,8 +179,9 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
 
     /// Sends a CONNECT packet to the server.
     /// After sending the packet, it will set pending_ack to PendingAck::Connect()
-    /// and will wait until it is None. Every time a RESEND_TIMEOUT duration passes,
-    /// it will resend the package up to a maximum of MAX_RETRIES times, after which
+    /// and will wait until it is None. Every time a resend timeout passes (RESEND_TIMEOUT
+    /// by default, see ResendPolicy), it will
+    /// resend the package up to a maximum of MAX_RETRIES times, after which
     /// it fails.
     /// If it fails, it sets failure_stop to true and sends a Message::Connected
     /// with the error to the observer and pending_ack is set to None.
@@ -105,37 +193,83 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
     }
 
     #[doc(hidden)]
-    fn _subscribe(&self, subscribe: Subscribe) -> Result<(), ClientError> {
+    fn _subscribe(
+        &self,
+        subscribe: Subscribe,
+        remember: bool,
+        completion: Option<SubscribeCompletion>,
+    ) -> Result<(), ClientError> {
         let mut lock = self.stream.lock()?;
 
         let bytes = subscribe.encode()?;
+        let stored = subscribe.clone();
         self.pending_ack
             .lock()?
-            .replace(PendingAck::Subscribe(subscribe));
+            .replace(PendingAck::Subscribe(subscribe, completion));
 
-        lock.write_all(&bytes)?;
+        self.write_packet(&mut lock, &bytes)?;
 
         if !self.wait_for_ack(&mut lock, &bytes)? {
             return Err(ClientError::new("No se recibió paquete suback"));
         }
 
+        if remember {
+            self.subscriptions.lock()?.push(stored);
+        }
         Ok(())
     }
 
     /// Sends a SUBSCRIBE packet to the server.
     /// After sending the packet, it will set pending_ack to PendingAck::Subscribe()
-    /// and will wait until it is None. Every time a RESEND_TIMEOUT duration passes,
-    /// it will resend the package up to a maximum of MAX_RETRIES times, after which
+    /// and will wait until it is None. Every time a resend timeout passes (RESEND_TIMEOUT
+    /// by default, see ResendPolicy), it will
+    /// resend the package up to a maximum of MAX_RETRIES times, after which
     /// it fails.
     /// If it fails, it sends a Message::Subscribed with the error to the observer
     /// and pending_ack is set to None.
+    /// If it succeeds, the SUBSCRIBE is stored so that it can be sent
+    /// again with send_resubscribe().
     pub fn send_subscribe(&self, subscribe: Subscribe) {
-        if let Err(err) = self._subscribe(subscribe) {
+        if let Err(err) = self._subscribe(subscribe, true, None) {
             self.observer.update(Message::Subscribed(Err(err)));
         }
     }
 
-    pub fn _publish(&self, mut publish: Publish) -> Result<(), ClientError> {
+    /// Sends a SUBSCRIBE packet to the server, behaving like send_subscribe, but
+    /// the listener also sends the SUBACK through the given completion channel
+    /// once it arrives.
+    ///
+    /// On success, the observer is notified the same way as with send_subscribe.
+    /// On failure, the error is only sent through the completion channel.
+    pub fn send_subscribe_sync(&self, subscribe: Subscribe, completion: SubscribeCompletion) {
+        if let Err(err) = self._subscribe(subscribe, true, Some(completion.clone())) {
+            // Si el receiver ya no existe, nadie espera el resultado
+            let _ = completion.send(Err(err));
+        }
+    }
+
+    /// Sends again every SUBSCRIBE packet stored by send_subscribe(), in
+    /// the same order, as send_subscribe() does. It is meant to restore the
+    /// subscriptions of a session after connecting to the server again.
+    /// If one of them fails, it sends a Message::Subscribed with the error
+    /// to the observer and continues with the rest.
+    pub fn send_resubscribe(&self) {
+        let subscriptions = match self.subscriptions.lock() {
+            Ok(subscriptions) => subscriptions.clone(),
+            Err(err) => {
+                self.observer.update(Message::Subscribed(Err(err.into())));
+                return;
+            }
+        };
+        for subscribe in subscriptions {
+            if let Err(err) = self._subscribe(subscribe, false, None) {
+                self.observer.update(Message::Subscribed(Err(err)));
+            }
+        }
+    }
+
+    #[doc(hidden)]
+    fn _publish(&self, mut publish: Publish) -> Result<Option<Puback>, ClientError> {
         let mut lock = self.stream.lock()?;
         let bytes = publish.encode()?;
         let qos = publish.qos();
@@ -144,7 +278,7 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
             *self.pending_ack.lock()? = Some(PendingAck::Publish(publish.clone()));
         }
 
-        lock.write_all(&bytes)?;
+        self.write_packet(&mut lock, &bytes)?;
 
         publish.set_dup(true);
         let resend_bytes = publish.encode()?;
@@ -153,18 +287,21 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
             if !self.wait_for_ack(&mut lock, &resend_bytes)? {
                 return Err(ClientError::new("No se recibió paquete puback (QoS 1)"));
             }
-        } else {
-            self.observer.update(Message::Published(Ok(None)));
+            let id = publish
+                .packet_id()
+                .ok_or_else(|| ClientError::new("Publish QoS 1 sin packet id"))?;
+            return Ok(Some(Puback::new(id)?));
         }
 
-        Ok(())
+        Ok(None)
     }
 
     /// Sends a PUBLISH packet to the server.
     ///
     /// If the packet has QoSLevel 1:
     /// After sending the packet, it will set pending_ack to PendingAck::Publish() and
-    /// will wait until it is None. Every time a RESEND_TIMEOUT duration passes, it will
+    /// will wait until it is None. Every time a resend timeout passes (RESEND_TIMEOUT
+    /// by default, see ResendPolicy), it will
     /// resend the package with the DUP flag set up to a maximum of MAX_RETRIES times,
     /// after which it fails.
     ///
@@ -177,8 +314,83 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
     /// If it fails, it sends a Message::Published with the error to the observer
     /// (and pending_ack is set to None if QoSLevel was 1)
     pub fn send_publish(&self, publish: Publish) {
-        if let Err(err) = self._publish(publish) {
-            self.observer.update(Message::Published(Err(err)));
+        match self._publish(publish) {
+            Ok(None) => self.observer.update(Message::Published(Ok(None))),
+            Ok(Some(_)) => (), // El listener ya le mandó el puback al observer
+            Err(err) => self.observer.update(Message::Published(Err(err))),
+        }
+    }
+
+    /// Sends a PUBLISH packet to the server, behaving like send_publish, but
+    /// the result of the operation is also sent through the given confirm channel
+    /// once the publish is acknowledged (QoSLevel 1), written (QoSLevel 0) or fails.
+    ///
+    /// On success, the observer is notified the same way as with send_publish.
+    /// On failure, the error is only sent through the confirm channel.
+    pub fn send_publish_confirm(
+        &self,
+        publish: Publish,
+        confirm: Sender<Result<Option<Puback>, ClientError>>,
+    ) {
+        let result = self._publish(publish);
+        if let Ok(None) = result {
+            self.observer.update(Message::Published(Ok(None)));
+        }
+        // Si el receiver ya no existe, nadie espera la confirmación
+        let _ = confirm.send(result);
+    }
+
+    /// Marks the client as not connected, so that the following
+    /// publishes are queued (if the offline queue is enabled)
+    /// until flush_offline() is called
+    pub fn set_offline(&self) {
+        self.offline
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .set_offline();
+    }
+
+    /// Queues the given publish if the offline queue is enabled and the
+    /// client is not connected. Returns it back if it must be sent right
+    /// away instead. If the queue is full, the publish dropped according
+    /// to its overflow policy is notified to the observer with a
+    /// Message::Published with an error
+    pub fn queue_offline(&self, publish: Publish) -> Option<Publish> {
+        let queued = self
+            .offline
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(publish);
+        match queued {
+            PushResult::Send(publish) => Some(publish),
+            PushResult::Queued => None,
+            PushResult::Dropped(dropped) => {
+                self.observer
+                    .update(Message::Published(Err(ClientError::new(&format!(
+                        "Cola de publicaciones offline llena, se descartó el mensaje a {}",
+                        dropped.topic_name()
+                    )))));
+                None
+            }
+        }
+    }
+
+    /// Sends, in order and as send_publish() does, every publish queued
+    /// while the client was not connected, and then marks it as connected
+    /// so that publishes are no longer queued
+    pub fn flush_offline(&self) {
+        loop {
+            // Se suelta el lock antes de mandarlo, para que los publish
+            // que lleguen mientras tanto se encolen detrás
+            let next = self
+                .offline
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pop();
+            match next {
+                Some(publish) => self.send_publish(publish),
+                None => return,
+            }
         }
     }
 
@@ -190,7 +402,7 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
             .lock()?
             .replace(PendingAck::PingReq(pingreq));
 
-        lock.write_all(&bytes)?;
+        self.write_packet(&mut lock, &bytes)?;
         if !self.wait_for_ack(&mut lock, &bytes)? {
             return Err(ClientError::new(
                 "El servidor no respondió al pingreq, ¿esta en línea?",
@@ -202,8 +414,9 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
 
     /// Sends a PINGREQ packet to the server.
     /// After sending the packet, it will set pending_ack to PendingAck::PingReq()
-    /// and will wait until it is None. Every time a RESEND_TIMEOUT duration passes,
-    /// it will resend the package up to a maximum of MAX_RETRIES times, after which
+    /// and will wait until it is None. Every time a resend timeout passes (RESEND_TIMEOUT
+    /// by default, see ResendPolicy), it will
+    /// resend the package up to a maximum of MAX_RETRIES times, after which
     /// it fails.
     /// If it fails, it sends a Message::InternalError() with the error to the observer
     /// and pending_ack is set to None.
@@ -216,42 +429,78 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
 
     #[doc(hidden)]
     fn _disconnect(&self, disconnect: Disconnect) -> Result<(), ClientError> {
-        self.stream.lock()?.write_all(&disconnect.encode()?)?;
+        let mut lock = self.stream.lock()?;
+        self.write_packet(&mut lock, &disconnect.encode()?)?;
         Ok(())
     }
 
     /// Sends a DISCONNECT packet to the server.
-    /// If it fails, it sends a Message::InternalError with the error to the observer
-    pub fn send_disconnect(&self) {
+    /// If it fails, it sends a Message::InternalError with the error to the observer.
+    /// Returns true if the DISCONNECT was sent
+    pub fn send_disconnect(&self) -> bool {
         let disconnect = Disconnect::new();
         if let Err(err) = self._disconnect(disconnect) {
             self.observer.update(Message::InternalError(err));
+            return false;
         }
+        true
     }
 
     #[doc(hidden)]
     fn _unsubscribe(&self, unsubscribe: Unsubscribe) -> Result<(), ClientError> {
         let mut lock = self.stream.lock()?;
         let bytes = unsubscribe.encode()?;
+        let filters: Vec<String> = unsubscribe
+            .topic_filters()
+            .iter()
+            .map(|filter| filter.name().to_string())
+            .collect();
         self.pending_ack
             .lock()?
             .replace(PendingAck::Unsubscribe(unsubscribe));
-        lock.write_all(&bytes)?;
+        self.write_packet(&mut lock, &bytes)?;
 
         if !self.wait_for_ack(&mut lock, &bytes)? {
             return Err(ClientError::new("No se recibió paquete unsuback"));
         }
 
+        self.forget_subscriptions(&filters)?;
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    // Saca los topic filters dados de los SUBSCRIBE guardados, descartando
+    // los que quedan sin ninguno
+    fn forget_subscriptions(&self, filters: &[String]) -> Result<(), ClientError> {
+        let mut subscriptions = self.subscriptions.lock()?;
+        *subscriptions = subscriptions
+            .drain(..)
+            .filter_map(|subscribe| {
+                let topics: Vec<TopicFilter> = subscribe
+                    .topics()
+                    .into_iter()
+                    .filter(|topic| !filters.iter().any(|filter| filter == topic.name()))
+                    .collect();
+                if topics.is_empty() {
+                    None
+                } else {
+                    Some(Subscribe::new(topics, subscribe.packet_identifier()))
+                }
+            })
+            .collect();
         Ok(())
     }
 
     /// Sends an UNSUBSCRIBE packet to the server.
     /// After sending the packet, it will set pending_ack to PendingAck::Unsubscribe()
-    /// and will wait until it is None. Every time a RESEND_TIMEOUT duration passes,
-    /// it will resend the package up to a maximum of MAX_RETRIES times, after which
+    /// and will wait until it is None. Every time a resend timeout passes (RESEND_TIMEOUT
+    /// by default, see ResendPolicy), it will
+    /// resend the package up to a maximum of MAX_RETRIES times, after which
     /// it fails.
     /// If it fails, it sends a Message::Unsubscribed with the error to the observer
     /// and pending_ack is set to None.
+    /// If it succeeds, its topic filters are removed from the SUBSCRIBE packets
+    /// stored by send_subscribe().
     pub fn send_unsubscribe(&self, unsubscribe: Unsubscribe) {
         if let Err(err) = self._unsubscribe(unsubscribe) {
             self.observer.update(Message::Unsubscribed(Err(err)));
@@ -266,19 +515,24 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
         resend_bytes: &[u8],
     ) -> Result<bool, ClientError> {
         let mut retries = 0;
-        let mut last = time::Instant::now();
+        let mut backoff = Backoff::new(
+            self.resend.timeout,
+            self.resend.backoff_factor,
+            self.resend.max_timeout,
+        );
+        let mut resend_at = time::Instant::now() + backoff.next_delay();
 
         thread::sleep(ACK_CHECK);
-        while retries < MAX_RETRIES {
+        while retries < self.resend.max_retries {
             match self.pending_ack.lock()?.as_mut() {
                 None => {
                     return Ok(true);
                 }
                 Some(_) => {
                     let now = time::Instant::now();
-                    if last + RESEND_TIMEOUT < now {
-                        unlocked_stream.write_all(resend_bytes)?;
-                        last = time::Instant::now();
+                    if resend_at < now {
+                        self.write_packet(unlocked_stream, resend_bytes)?;
+                        resend_at = time::Instant::now() + backoff.next_delay();
                         retries += 1;
                     }
                 }
@@ -301,13 +555,15 @@ impl<T: Observer, W: Write> ClientSender<T, W> {
 mod tests {
     use std::{
         io::{Cursor as IoCursor, Write},
-        sync::{atomic::AtomicBool, Arc, Mutex},
+        sync::{atomic::AtomicBool, mpsc, Arc, Mutex},
         thread,
-        time::Instant,
+        time::{Duration, Instant},
     };
 
     use crate::{
-        client::{client_listener::AckSender, client_sender::MAX_RETRIES, ClientError, PendingAck},
+        client::{
+            client_listener::AckSender, client_sender::MAX_RETRIES, Client, ClientError, PendingAck,
+        },
         observer::Message,
     };
     use packets::{
@@ -471,7 +727,7 @@ mod tests {
 
         assert!(matches!(
             take_ack(&client_sender),
-            Some(PendingAck::Subscribe(_))
+            Some(PendingAck::Subscribe(..))
         ));
         // Debería haber puesto en el pending_ack un PendingAck::Subscribe()
 
@@ -515,6 +771,49 @@ mod tests {
         // Debería haber mandado el error al observer
     }
 
+    #[test]
+    fn test_resubscribe_sends_stored_subscriptions() {
+        let topics = vec![
+            TopicFilter::new("cars/wheels", QoSLevel::QoSLevel0).unwrap(),
+            TopicFilter::new("cars/doors", QoSLevel::QoSLevel1).unwrap(),
+        ];
+        let subscribe = Subscribe::new(topics, 123);
+        let unsubscribe = Unsubscribe::new(
+            124,
+            vec![TopicFilter::new("cars/wheels", QoSLevel::QoSLevel0).unwrap()],
+        )
+        .unwrap();
+        let expected = Subscribe::new(
+            vec![TopicFilter::new("cars/doors", QoSLevel::QoSLevel1).unwrap()],
+            123,
+        );
+        let mut bytes = subscribe.encode().unwrap();
+        bytes.append(&mut unsubscribe.encode().unwrap());
+        bytes.append(&mut expected.encode().unwrap());
+
+        let stream = Cursor::new();
+        let observer = ObserverMock::new();
+        let client_sender = Arc::new(ClientSender::new(stream.clone(), observer.clone()));
+
+        let client_sender_clone = client_sender.clone();
+        let handle = thread::spawn(move || {
+            client_sender_clone.send_subscribe(subscribe);
+            client_sender_clone.send_unsubscribe(unsubscribe);
+            // Se reconecta al servidor
+            client_sender_clone.send_resubscribe();
+        });
+        for _ in 0..3 {
+            assert!(take_ack(&client_sender).is_some());
+        }
+        handle.join().unwrap();
+
+        assert_eq!(stream.content(), bytes);
+        // Debería haber vuelto a mandar el subscribe, sin el topic del que se desuscribió
+
+        assert!(observer.messages.lock().unwrap().is_empty());
+        // No le debería haber mandado nada al observer
+    }
+
     #[test]
     fn test_unsubscribe() {
         let unsubscribe = Unsubscribe::new(
@@ -780,6 +1079,65 @@ mod tests {
         // Debería haber mandado el error al observer
     }
 
+    fn contains_pingreq(content: &[u8]) -> bool {
+        let pingreq = PingReq::new().encode().unwrap();
+        content
+            .windows(pingreq.len())
+            .any(|window| window == pingreq.as_slice())
+    }
+
+    #[test]
+    fn test_keep_alive_not_sent_while_publishing() {
+        let stream = Cursor::new();
+        let observer = ObserverMock::new();
+        let client_sender = Arc::new(ClientSender::new(stream.clone(), observer));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let sender = client_sender.clone();
+        let stop_copy = stop.clone();
+        let handle = thread::spawn(move || {
+            Client::<ObserverMock>::keep_alive(sender, stop_copy, Duration::from_secs(1));
+        });
+
+        // Publico seguido durante más tiempo que el keep alive
+        let start = Instant::now();
+        while start.elapsed() < Duration::from_millis(2500) {
+            let publish =
+                Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "msg", None).unwrap();
+            client_sender.send_publish(publish);
+            thread::sleep(Duration::from_millis(300));
+        }
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert!(!contains_pingreq(&stream.content()));
+        // No debería haber mandado ningún pingreq
+    }
+
+    #[test]
+    fn test_keep_alive_sent_when_idle() {
+        let stream = Cursor::new();
+        let observer = ObserverMock::new();
+        let client_sender = Arc::new(ClientSender::new(stream.clone(), observer));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let sender = client_sender.clone();
+        let stop_copy = stop.clone();
+        let handle = thread::spawn(move || {
+            Client::<ObserverMock>::keep_alive(sender, stop_copy, Duration::from_secs(1));
+        });
+
+        assert!(matches!(
+            take_ack(&client_sender),
+            Some(PendingAck::PingReq(_))
+        ));
+        // Debería haber mandado un pingreq al no mandar otros paquetes
+        stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        handle.join().unwrap();
+
+        assert!(contains_pingreq(&stream.content()));
+    }
+
     #[test]
     fn test_publish_qos0() {
         let publish = Publish::new(
@@ -835,6 +1193,97 @@ mod tests {
         // Debería haber mandado el error al observer
     }
 
+    #[test]
+    fn test_publish_confirm_qos1() {
+        let publish = Publish::new(
+            false,
+            QoSLevel::QoSLevel1,
+            false,
+            "car/wheels",
+            "wow such wheel",
+            Some(123),
+        )
+        .unwrap();
+
+        let stream = Cursor::new();
+        let observer = ObserverMock::new();
+
+        let client_sender = Arc::new(ClientSender::new(stream, observer.clone()));
+        let (confirm_sender, confirm_receiver) = mpsc::channel();
+
+        let client_sender_clone = client_sender.clone();
+        let handle = thread::spawn(move || {
+            client_sender_clone.send_publish_confirm(publish, confirm_sender);
+        });
+
+        assert!(matches!(
+            take_ack(&client_sender),
+            Some(PendingAck::Publish(_))
+        ));
+        // Simula que el listener recibió el puback
+
+        handle.join().unwrap();
+
+        let puback = confirm_receiver.recv().unwrap().unwrap().unwrap();
+        assert_eq!(puback.packet_id(), 123);
+        // Debería haber confirmado con el puback correspondiente
+
+        assert!(observer.messages.lock().unwrap().is_empty());
+        // El puback al observer lo manda el listener, no el sender
+    }
+
+    #[test]
+    fn test_publish_confirm_qos1_fail() {
+        let publish = Publish::new(
+            false,
+            QoSLevel::QoSLevel1,
+            false,
+            "car/wheels",
+            "wow such wheel",
+            Some(123),
+        )
+        .unwrap();
+
+        let observer = ObserverMock::new();
+
+        let client_sender = ClientSender::new(Cursor::new(), observer.clone());
+        let (confirm_sender, confirm_receiver) = mpsc::channel();
+
+        client_sender.send_publish_confirm(publish, confirm_sender);
+
+        assert!(confirm_receiver.recv().unwrap().is_err());
+        // Debería haber mandado el error por el canal
+
+        assert!(observer.messages.lock().unwrap().is_empty());
+        // No le debería haber mandado el error al observer
+    }
+
+    #[test]
+    fn test_publish_confirm_qos0() {
+        let publish = Publish::new(
+            false,
+            QoSLevel::QoSLevel0,
+            false,
+            "car/wheels",
+            "wow such wheel",
+            None,
+        )
+        .unwrap();
+
+        let observer = ObserverMock::new();
+
+        let client_sender = ClientSender::new(Cursor::new(), observer.clone());
+        let (confirm_sender, confirm_receiver) = mpsc::channel();
+
+        client_sender.send_publish_confirm(publish, confirm_sender);
+
+        assert!(matches!(confirm_receiver.recv().unwrap(), Ok(None)));
+        assert!(matches!(
+            observer.messages.lock().unwrap()[0],
+            Message::Published(Ok(None))
+        ));
+    }
+
     #[test]
     fn test_send_puback() {
         let puback = Puback::new(123).unwrap();
@@ -1,31 +1,44 @@
+use std::io::Write;
 use std::sync::atomic::AtomicBool;
+use std::sync::mpsc::{self, Receiver, Sender};
 use std::sync::Arc;
-use std::{io, thread};
+use std::thread;
 use std::{net::TcpStream, time::Duration};
 
+mod builder;
 pub mod client_error;
 mod client_listener;
 mod client_sender;
+mod offline_queue;
+mod transport;
 
+pub use builder::ClientBuilder;
 use client_listener::ClientListener;
 use client_sender::ClientSender;
+pub use offline_queue::OfflineOverflow;
 use packets::connect::Connect;
 use packets::pingreq::PingReq;
+use packets::puback::Puback;
+use packets::suback::Suback;
 use packets::subscribe::Subscribe;
 use packets::unsubscribe::Unsubscribe;
+pub use transport::{ReadTimeout, Transport};
 
-use crate::observer::Observer;
+use crate::observer::{Message, Observer, SharedObserver};
 pub use client_error::ClientError;
 use packets::publish::Publish;
+pub use threadpool::PoolStats;
 use threadpool::ThreadPool;
 
-use self::client_listener::ReadTimeout;
+/// Channel through which the listener delivers the SUBACK of a
+/// SUBSCRIBE sent with Client::subscribe_sync()
+pub(crate) type SubscribeCompletion = Sender<Result<Suback, ClientError>>;
 
 /// Enum for Pending Acknowledgments of sent packets
 /// Common interface for the listener and the sender
 #[derive(Debug)]
 pub(crate) enum PendingAck {
-    Subscribe(Subscribe),
+    Subscribe(Subscribe, Option<SubscribeCompletion>),
     Unsubscribe(Unsubscribe),
     PingReq(PingReq),
     Publish(Publish),
@@ -33,17 +46,15 @@ pub(crate) enum PendingAck {
 }
 
 /// Internal Client. Fully functional MQTT Client
-/// which lacks any I/O to the user
-pub struct Client<T: Observer> {
+/// which lacks any I/O to the user. It communicates
+/// with the server through a TcpStream by default,
+/// or through any other Transport
+pub struct Client<T: Observer, S: Transport = TcpStream> {
     thread_pool: ThreadPool,
     stop: Arc<AtomicBool>,
-    sender: Arc<ClientSender<T, TcpStream>>,
-}
-
-impl ReadTimeout for TcpStream {
-    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
-        self.set_read_timeout(dur)
-    }
+    sender: Arc<ClientSender<SharedObserver<T>, S>>,
+    observer: SharedObserver<T>,
+    builder: ClientBuilder,
 }
 
 /// How often should the listener and ping sender check to see if they should stop
@@ -58,26 +69,50 @@ impl<T: Observer> Client<T> {
     /// The client must be initialized with an Observer to receive the different
     /// Messages the client sends after relevant events (defined in the trait Observer).
     /// If the connect packet has a Keep Alive set, it will automatically send and receive
-    /// the PingReq and PingResp packets
+    /// the PingReq and PingResp packets.
+    /// To change the default configuration of the client, use a ClientBuilder instead
     pub fn new(address: &str, observer: T, connect: Connect) -> Result<Client<T>, ClientError> {
+        ClientBuilder::new().connect(address, observer, connect)
+    }
+
+    /// Connects the client again to the TCP Listener on the given address, by
+    /// sending the given CONNECT packet, replacing the previous connection.
+    /// The client does not reconnect on its own, so this must be invoked by
+    /// the application, for example after an InternalError() message.
+    /// The result is sent to the Observer with a Connected() message, as
+    /// when the client is created. Once connected, the publishes kept by the
+    /// offline queue (see ClientBuilder::offline_queue()) are sent in order.
+    /// The subscriptions are not restored, for that use resubscribe().
+    pub fn reconnect(&mut self, address: &str, connect: Connect) -> Result<(), ClientError> {
         let stream = TcpStream::connect(address)?;
-        let mut threads = 3;
-        let keep_alive = connect.keep_alive();
-        if keep_alive == 0 {
-            threads = 2; // no lo necesito para el pingreq
-        }
+        self.builder.set_socket_options(&stream)?;
+        self.reconnect_transport(stream, connect)
+    }
+}
 
-        let mut ret = Client {
-            thread_pool: ThreadPool::new(threads),
-            stop: Arc::new(AtomicBool::new(false)),
-            sender: Arc::new(ClientSender::new(stream.try_clone()?, observer.clone())),
-        };
+impl<T: Observer, S: Transport> Client<T, S> {
+    /// Replaces the Observer of the client, without reconnecting. Every Message
+    /// sent after this call goes to the new Observer, including the results of
+    /// the operations that were already in progress.
+    pub fn set_observer(&self, observer: T) {
+        self.observer.replace(observer);
+    }
 
-        ret.connect(connect, stream, observer)?;
+    /// Connects the client again to the server on the other end of the given
+    /// stream, as reconnect() does.
+    pub fn reconnect_transport(&mut self, stream: S, connect: Connect) -> Result<(), ClientError> {
+        // Frena el listener y el keep alive de la conexión anterior
+        self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
+        self.sender.set_offline();
 
-        ret.setup_keep_alive(keep_alive)?;
+        self.sender.replace_stream(stream.try_clone()?)?;
+        self.stop = Arc::new(AtomicBool::new(false));
 
-        Ok(ret)
+        let keep_alive = connect.keep_alive();
+        self.thread_pool
+            .set_size(self.builder.threads(keep_alive))?;
+        self.connect(connect, stream, self.observer.clone())?;
+        self.setup_keep_alive(keep_alive)
     }
 
     /// Sends the given SUBSCRIBE packet to the server. The Client then either returns
@@ -93,6 +128,48 @@ impl<T: Observer> Client<T> {
         Ok(())
     }
 
+    /// Sends the given SUBSCRIBE packet to the server and blocks until the
+    /// corresponding SUBACK arrives, returning it with the return code granted
+    /// for each topic filter. If the SUBACK does not arrive within the given
+    /// timeout, or the operation fails, it returns Err(ClientError).
+    /// Successful subscriptions are still notified to the Observer as with
+    /// subscribe(), while errors are only returned.
+    pub fn subscribe_sync(
+        &mut self,
+        subscribe: Subscribe,
+        timeout: Duration,
+    ) -> Result<Suback, ClientError> {
+        let (completion_sender, completion_receiver) = mpsc::channel();
+        let sender = self.sender.clone();
+
+        self.thread_pool.execute(move || {
+            sender.send_subscribe_sync(subscribe, completion_sender);
+        })?;
+
+        match completion_receiver.recv_timeout(timeout) {
+            Ok(result) => result,
+            Err(_) => Err(ClientError::new(
+                "No se recibió paquete suback dentro del tiempo de espera",
+            )),
+        }
+    }
+
+    /// Sends again to the server every SUBSCRIBE packet that was acknowledged,
+    /// without the topic filters that were unsubscribed since then, so that
+    /// the subscriptions are restored after connecting to the server again.
+    /// The client does not reconnect on its own, so this must be invoked by
+    /// the application. The result of each SUBSCRIBE is sent to the Observer
+    /// with a Subscribed() message, as with subscribe().
+    pub fn resubscribe(&mut self) -> Result<(), ClientError> {
+        let sender = self.sender.clone();
+
+        self.thread_pool.execute(move || {
+            sender.send_resubscribe();
+        })?;
+
+        Ok(())
+    }
+
     /// Sends the given UNSUBSCRIBE packet to the server. The Client then either returns
     /// Err(ClientError) or Ok(()). In the latter case, the result of the operation
     /// is sent to the Observer with a Unsubscribed() message.
@@ -113,7 +190,18 @@ impl<T: Observer> Client<T> {
     /// Error. If it succeeds, it sends a Published(Ok(None)) message if the packet
     /// had QoSLevel0 or Published(Ok(Some())) with the corresponding PUBACK if the
     /// packet had QoSLevel1. Behaviour is undefined for QoSLevel2.
+    /// If the offline queue is enabled (see ClientBuilder::offline_queue()) and
+    /// the client is not connected, the packet is queued instead, and sent once
+    /// it connects.
     pub fn publish(&mut self, publish: Publish) -> Result<(), ClientError> {
+        if self.stop.load(std::sync::atomic::Ordering::Relaxed) {
+            // Se perdió la conexión
+            self.sender.set_offline();
+        }
+        let publish = match self.sender.queue_offline(publish) {
+            Some(publish) => publish,
+            None => return Ok(()),
+        };
         let sender = self.sender.clone();
         self.thread_pool.execute(move || {
             sender.send_publish(publish);
@@ -122,12 +210,47 @@ impl<T: Observer> Client<T> {
         Ok(())
     }
 
+    /// Sends the given publish packet to the server and returns a Receiver through
+    /// which the result of that publish is delivered, independently of the Observer.
+    /// The Receiver gets Ok(None) once a QoSLevel0 packet is sent, Ok(Some()) with the
+    /// corresponding PUBACK once a QoSLevel1 packet is acknowledged, or Err(ClientError)
+    /// if the publish fails. Successful publishes are still notified to the Observer
+    /// as with publish(), while errors are only sent through the Receiver.
+    /// The packet is never queued by the offline queue.
+    /// Behaviour is undefined for QoSLevel2.
+    pub fn publish_confirm(
+        &mut self,
+        publish: Publish,
+    ) -> Receiver<Result<Option<Puback>, ClientError>> {
+        let (confirm_sender, confirm_receiver) = mpsc::channel();
+        let sender = self.sender.clone();
+        let confirm = confirm_sender.clone();
+
+        if let Err(err) = self.thread_pool.execute(move || {
+            sender.send_publish_confirm(publish, confirm);
+        }) {
+            let _ = confirm_sender.send(Err(err.into()));
+        }
+
+        confirm_receiver
+    }
+
+    /// Returns the usage of the internal thread pool of the client: how
+    /// many threads it has, how many are executing a job, and how many
+    /// operations are waiting for a free thread. The listener and the keep
+    /// alive always take one thread each, so when busy equals workers the
+    /// client is saturated: new operations wait until one of the ongoing
+    /// ones finishes, and the application should avoid issuing more of them.
+    pub fn pool_stats(&self) -> PoolStats {
+        self.thread_pool.stats()
+    }
+
     #[doc(hidden)]
     fn connect(
         &mut self,
         connect: Connect,
         read_stream: impl ReadTimeout,
-        observer: T,
+        observer: SharedObserver<T>,
     ) -> Result<(), ClientError> {
         let mut listener = ClientListener::new(
             read_stream,
@@ -141,7 +264,10 @@ impl<T: Observer> Client<T> {
         let sender = self.sender.clone();
         let stop = self.stop.clone();
         self.thread_pool.execute(move || {
-            sender.send_connect(connect, stop);
+            sender.send_connect(connect, stop.clone());
+            if !stop.load(std::sync::atomic::Ordering::Relaxed) {
+                sender.flush_offline();
+            }
         })?;
 
         self.thread_pool.execute(move || {
@@ -168,38 +294,556 @@ impl<T: Observer> Client<T> {
     }
 
     #[doc(hidden)]
-    fn keep_alive(
-        sender: Arc<ClientSender<T, TcpStream>>,
+    // Solo manda un pingreq si no se mandó ningún otro paquete
+    // dentro del keep alive, ya que cualquier paquete lo reinicia
+    fn keep_alive<O: Observer, W: Write>(
+        sender: Arc<ClientSender<O, W>>,
         stop: Arc<AtomicBool>,
         mut duration: Duration,
     ) {
-        let mut now = std::time::Instant::now();
         if duration > KEEP_ALIVE_SUBTRACTION {
             duration -= KEEP_ALIVE_SUBTRACTION;
         }
 
         while !stop.load(std::sync::atomic::Ordering::Relaxed) {
             thread::sleep(STOP_TIMEOUT);
-            if now.elapsed() > duration {
-                now = std::time::Instant::now();
+            if sender.idle_time() > duration {
                 sender.send_pingreq();
             }
         }
     }
 }
 
-impl<T: Observer> Drop for Client<T> {
+impl<T: Observer, S: Transport> Drop for Client<T, S> {
     /// The client automatically sends a disconnect packet before dropping and closing the connection.
     /// If this fails, an InternalError is sent to the observer but the connection is closed anyway.
+    /// Either way, a Closed message is the last one sent to the observer.
     fn drop(&mut self) {
         self.stop.store(true, std::sync::atomic::Ordering::Relaxed);
         let sender = self.sender.clone();
+        let observer = self.observer.clone();
         if let Err(err) = self.thread_pool.execute(move || {
-            sender.send_disconnect();
+            let clean = sender.send_disconnect();
+            observer.update(Message::Closed { clean });
         }) {
             let msg = "Error enviándo paquete disconnect, se desconectará de manera forzosa";
             self.sender
                 .send_error(ClientError::new(&format!("{}\n{}", msg, err)));
+            self.observer.update(Message::Closed { clean: false });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::io::{self, Read, Write};
+    use std::net::{TcpListener, TcpStream};
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Arc, Condvar, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use packets::connack::{Connack, ConnackReturnCode};
+    use packets::connect::{Connect, ConnectBuilder};
+    use packets::puback::Puback;
+    use packets::publish::Publish;
+    use packets::qos::QoSLevel;
+    use packets::suback::Suback;
+    use packets::subscribe::Subscribe;
+    use packets::topic_filter::TopicFilter;
+    use packets::traits::{MQTTDecoding, MQTTEncoding};
+    use socket2::SockRef;
+
+    use super::{Client, ClientBuilder, OfflineOverflow, PoolStats, ReadTimeout, Transport};
+    use crate::observer::{Message, Observer};
+
+    #[derive(Clone)]
+    struct ObserverMock {
+        pub messages: Arc<Mutex<Vec<Message>>>,
+    }
+
+    impl Observer for ObserverMock {
+        fn update(&self, message: Message) {
+            self.messages.lock().unwrap().push(message);
+        }
+    }
+
+    impl ObserverMock {
+        fn new() -> Self {
+            Self {
+                messages: Arc::new(Mutex::new(Vec::new())),
+            }
+        }
+
+        fn payloads(&self) -> Vec<String> {
+            self.messages
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|msg| match msg {
+                    Message::Publish { publish, .. } => {
+                        Some(publish.payload_str().unwrap().to_string())
+                    }
+                    _ => None,
+                })
+                .collect()
+        }
+
+        fn subscribe_failed(&self) -> bool {
+            self.messages
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|msg| matches!(msg, Message::Subscribed(Err(_))))
+        }
+
+        fn disconnected(&self) -> bool {
+            self.messages
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|msg| matches!(msg, Message::InternalError(_)))
+        }
+
+        fn closed(&self) -> bool {
+            self.messages
+                .lock()
+                .unwrap()
+                .iter()
+                .any(|msg| matches!(msg, Message::Closed { .. }))
+        }
+
+        fn wait_for_payloads(&self, n: usize) {
+            let start = Instant::now();
+            while self.payloads().len() < n && start.elapsed() < Duration::from_secs(5) {
+                thread::sleep(Duration::from_millis(10));
+            }
         }
     }
+
+    // Servidor que acepta la conexion y manda un publish
+    // con cada payload que recibe por el channel
+    fn start_server(payloads: Receiver<&'static str>) -> String {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 64];
+            let _ = stream.read(&mut buf).unwrap();
+            stream.write_all(&[0b00100000, 2, 0, 0]).unwrap(); // Connack
+            for payload in payloads {
+                let publish =
+                    Publish::new(false, QoSLevel::QoSLevel0, false, "topic", payload, None)
+                        .unwrap();
+                stream.write_all(&publish.encode().unwrap()).unwrap();
+            }
+            // Espera el disconnect antes de cerrar la conexion
+            let _ = stream.read(&mut buf);
+        });
+        address
+    }
+
+    // Servidor que acepta la conexion y la cierra despues del connack
+    fn start_closing_server() -> String {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 64];
+            let _ = stream.read(&mut buf).unwrap();
+            stream.write_all(&[0b00100000, 2, 0, 0]).unwrap(); // Connack
+        });
+        address
+    }
+
+    // Servidor que acepta la conexion y manda por el channel
+    // el payload de cada publish que recibe
+    fn start_receiving_server(payloads: Sender<String>) -> String {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut buf = [0u8; 64];
+            let _ = stream.read(&mut buf).unwrap();
+            stream.write_all(&[0b00100000, 2, 0, 0]).unwrap(); // Connack
+            let mut header = [0u8; 1];
+            while stream.read_exact(&mut header).is_ok() {
+                match Publish::read_from(&mut stream, header[0]) {
+                    Ok(publish) => payloads
+                        .send(publish.payload_str().unwrap().to_string())
+                        .unwrap(),
+                    Err(_) => break, // Disconnect
+                }
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn test_offline_queue_flushed_on_reconnect() {
+        let address = start_closing_server();
+        let observer = ObserverMock::new();
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+        let mut client = ClientBuilder::new()
+            .offline_queue(2, OfflineOverflow::DropOldest)
+            .connect(&address, observer.clone(), connect)
+            .unwrap();
+        let start = Instant::now();
+        while !observer.disconnected() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+
+        for payload in ["uno", "dos", "tres"] {
+            let publish =
+                Publish::new(false, QoSLevel::QoSLevel0, false, "topic", payload, None).unwrap();
+            client.publish(publish).unwrap();
+        }
+        let (server, payloads) = mpsc::channel();
+        let address = start_receiving_server(server);
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+        client.reconnect(&address, connect).unwrap();
+
+        let received: Vec<String> = (0..2)
+            .map(|_| payloads.recv_timeout(Duration::from_secs(5)).unwrap())
+            .collect();
+        // El primero se descarta porque la cola esta llena
+        assert_eq!(received, vec!["dos", "tres"]);
+        assert!(observer
+            .messages
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|msg| matches!(msg, Message::Published(Err(_)))));
+    }
+
+    #[test]
+    fn test_closed_is_last_message() {
+        let (server, payloads): (Sender<&str>, _) = mpsc::channel();
+        let address = start_server(payloads);
+        let observer = ObserverMock::new();
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+
+        let client = Client::new(&address, observer.clone(), connect).unwrap();
+        server.send("uno").unwrap();
+        observer.wait_for_payloads(1);
+        drop(client);
+
+        let start = Instant::now();
+        while !observer.closed() && start.elapsed() < Duration::from_secs(5) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(matches!(
+            observer.messages.lock().unwrap().last(),
+            Some(Message::Closed { clean: true })
+        ));
+    }
+
+    #[test]
+    fn test_set_observer() {
+        let (server, payloads): (Sender<&str>, _) = mpsc::channel();
+        let address = start_server(payloads);
+        let first = ObserverMock::new();
+        let second = ObserverMock::new();
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+
+        let client = Client::new(&address, first.clone(), connect).unwrap();
+        server.send("uno").unwrap();
+        first.wait_for_payloads(1);
+        client.set_observer(second.clone());
+        server.send("dos").unwrap();
+        second.wait_for_payloads(1);
+
+        assert_eq!(first.payloads(), vec!["uno"]);
+        assert_eq!(second.payloads(), vec!["dos"]);
+    }
+
+    #[test]
+    fn test_builder_resend_options() {
+        // El servidor nunca responde el subscribe
+        let (_server, payloads): (Sender<&str>, _) = mpsc::channel();
+        let address = start_server(payloads);
+        let observer = ObserverMock::new();
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+
+        let mut client = ClientBuilder::new()
+            .operation_threads(2)
+            .resend_timeout(Duration::from_millis(100))
+            .max_retries(1)
+            .connect(&address, observer.clone(), connect)
+            .unwrap();
+        let topic = TopicFilter::new("topic", QoSLevel::QoSLevel0).unwrap();
+        client.subscribe(Subscribe::new(vec![topic], 1)).unwrap();
+
+        // Con la configuracion por defecto tardaria mas de 15 segundos
+        let start = Instant::now();
+        while !observer.subscribe_failed() && start.elapsed() < Duration::from_secs(3) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(observer.subscribe_failed());
+    }
+
+    #[test]
+    fn test_builder_resend_backoff_factor() {
+        // El servidor nunca responde el subscribe
+        let (_server, payloads): (Sender<&str>, _) = mpsc::channel();
+        let address = start_server(payloads);
+        let observer = ObserverMock::new();
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+
+        let mut client = ClientBuilder::new()
+            .operation_threads(2)
+            .resend_timeout(Duration::from_millis(100))
+            .resend_backoff_factor(20)
+            .max_retries(2)
+            .connect(&address, observer.clone(), connect)
+            .unwrap();
+        let topic = TopicFilter::new("topic", QoSLevel::QoSLevel0).unwrap();
+        client.subscribe(Subscribe::new(vec![topic], 1)).unwrap();
+
+        // El segundo reenvio espera 2 segundos, sin backoff fallaria en 1 segundo
+        thread::sleep(Duration::from_millis(2000));
+        assert!(!observer.subscribe_failed());
+        let start = Instant::now();
+        while !observer.subscribe_failed() && start.elapsed() < Duration::from_secs(3) {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert!(observer.subscribe_failed());
+    }
+
+    #[test]
+    fn test_pool_stats_saturated() {
+        // El servidor nunca responde los subscribe
+        let (_server, payloads): (Sender<&str>, _) = mpsc::channel();
+        let address = start_server(payloads);
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+
+        let mut client = ClientBuilder::new()
+            .operation_threads(1)
+            .resend_timeout(Duration::from_millis(500))
+            .max_retries(1)
+            .connect(&address, ObserverMock::new(), connect)
+            .unwrap();
+        for id in 1..=3 {
+            let topic = TopicFilter::new("topic", QoSLevel::QoSLevel0).unwrap();
+            client.subscribe(Subscribe::new(vec![topic], id)).unwrap();
+        }
+
+        // El listener y un subscribe ocupan los 2 threads, los otros esperan
+        let start = Instant::now();
+        let mut stats = client.pool_stats();
+        let saturated = PoolStats {
+            workers: 2,
+            busy: 2,
+            queued: 2,
+        };
+        while stats != saturated && start.elapsed() < Duration::from_secs(3) {
+            thread::sleep(Duration::from_millis(10));
+            stats = client.pool_stats();
+        }
+        assert_eq!(stats, saturated);
+    }
+
+    #[test]
+    fn test_builder_without_operation_threads() {
+        let (_server, payloads): (Sender<&str>, _) = mpsc::channel();
+        let address = start_server(payloads);
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+
+        let result = ClientBuilder::new().operation_threads(0).connect(
+            &address,
+            ObserverMock::new(),
+            connect,
+        );
+
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_builder_socket_options() {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+
+        ClientBuilder::new().set_socket_options(&stream).unwrap();
+        assert!(stream.nodelay().unwrap());
+        assert!(!SockRef::from(&stream).keepalive().unwrap());
+
+        ClientBuilder::new()
+            .tcp_nodelay(false)
+            .tcp_keepalive(Duration::from_secs(30))
+            .set_socket_options(&stream)
+            .unwrap();
+        assert!(!stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    type Buffer = Arc<(Mutex<VecDeque<u8>>, Condvar)>;
+
+    // Extremo de un stream en memoria, que lee lo que se escribe en el otro
+    #[derive(Clone)]
+    struct MemoryStream {
+        incoming: Buffer,
+        outgoing: Buffer,
+        read_timeout: Arc<Mutex<Option<Duration>>>,
+    }
+
+    impl MemoryStream {
+        fn pair() -> (MemoryStream, MemoryStream) {
+            let first: Buffer = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+            let second: Buffer = Arc::new((Mutex::new(VecDeque::new()), Condvar::new()));
+            (
+                MemoryStream {
+                    incoming: first.clone(),
+                    outgoing: second.clone(),
+                    read_timeout: Arc::new(Mutex::new(None)),
+                },
+                MemoryStream {
+                    incoming: second,
+                    outgoing: first,
+                    read_timeout: Arc::new(Mutex::new(None)),
+                },
+            )
+        }
+    }
+
+    impl Read for MemoryStream {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let timeout = *self.read_timeout.lock().unwrap();
+            let (lock, ready) = &*self.incoming;
+            let mut incoming = lock.lock().unwrap();
+            while incoming.is_empty() {
+                match timeout {
+                    Some(timeout) => {
+                        let (guard, result) = ready.wait_timeout(incoming, timeout).unwrap();
+                        incoming = guard;
+                        if result.timed_out() && incoming.is_empty() {
+                            return Err(io::ErrorKind::TimedOut.into());
+                        }
+                    }
+                    None => incoming = ready.wait(incoming).unwrap(),
+                }
+            }
+            let n = buf.len().min(incoming.len());
+            for (byte, value) in buf.iter_mut().zip(incoming.drain(..n)) {
+                *byte = value;
+            }
+            Ok(n)
+        }
+    }
+
+    impl Write for MemoryStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let (lock, ready) = &*self.outgoing;
+            lock.lock().unwrap().extend(buf);
+            ready.notify_all();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl ReadTimeout for MemoryStream {
+        fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+            *self.read_timeout.lock().unwrap() = dur;
+            Ok(())
+        }
+    }
+
+    impl Transport for MemoryStream {
+        fn try_clone(&self) -> io::Result<Self> {
+            Ok(self.clone())
+        }
+    }
+
+    // Servidor en memoria que acepta la conexion, la suscripcion y el
+    // publish, y despues le reenvia al cliente el mensaje publicado
+    fn start_memory_server(mut stream: MemoryStream) {
+        thread::spawn(move || {
+            let mut header = [0u8; 1];
+            stream.read_exact(&mut header).unwrap();
+            Connect::read_from(&mut stream, header[0]).unwrap();
+            let connack = Connack::new(false, ConnackReturnCode::Accepted);
+            stream.write_all(&connack.encode().unwrap()).unwrap();
+
+            stream.read_exact(&mut header).unwrap();
+            let subscribe = Subscribe::read_from(&mut stream, header[0]).unwrap();
+            let suback = Suback::new_from_vec(vec![1], subscribe.packet_identifier()).unwrap();
+            stream.write_all(&suback.encode().unwrap()).unwrap();
+
+            stream.read_exact(&mut header).unwrap();
+            let publish = Publish::read_from(&mut stream, header[0]).unwrap();
+            let puback = Puback::new(publish.packet_id().unwrap()).unwrap();
+            stream.write_all(&puback.encode().unwrap()).unwrap();
+            let forwarded = Publish::new(
+                false,
+                QoSLevel::QoSLevel0,
+                false,
+                publish.topic_name(),
+                publish.payload(),
+                None,
+            )
+            .unwrap();
+            stream.write_all(&forwarded.encode().unwrap()).unwrap();
+        });
+    }
+
+    #[test]
+    fn test_memory_transport_full_flow() {
+        let (client_end, server_end) = MemoryStream::pair();
+        start_memory_server(server_end);
+        let observer = ObserverMock::new();
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+
+        let mut client = ClientBuilder::new()
+            .connect_transport(client_end, observer.clone(), connect)
+            .unwrap();
+        let topic = TopicFilter::new("topic", QoSLevel::QoSLevel1).unwrap();
+        client.subscribe(Subscribe::new(vec![topic], 1)).unwrap();
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel1, false, "topic", "hola", Some(2)).unwrap();
+        client.publish(publish).unwrap();
+        observer.wait_for_payloads(1);
+
+        assert_eq!(observer.payloads(), vec!["hola"]);
+        let messages = observer.messages.lock().unwrap();
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, Message::Connected(Ok(_)))));
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, Message::Subscribed(Ok(_)))));
+        assert!(messages
+            .iter()
+            .any(|msg| matches!(msg, Message::Published(Ok(Some(_))))));
+    }
+
+    #[test]
+    fn test_subscribe_sync_returns_suback() {
+        let (client_end, server_end) = MemoryStream::pair();
+        start_memory_server(server_end);
+        let observer = ObserverMock::new();
+        let connect = ConnectBuilder::new("id", 0, true).unwrap().build().unwrap();
+
+        let mut client = ClientBuilder::new()
+            .connect_transport(client_end, observer.clone(), connect)
+            .unwrap();
+        let topic = TopicFilter::new("topic", QoSLevel::QoSLevel1).unwrap();
+        let suback = client
+            .subscribe_sync(Subscribe::new(vec![topic], 1), Duration::from_secs(5))
+            .unwrap();
+
+        // Todavia no se mando ningun publish
+        assert_eq!(suback.packet_id(), 1);
+        assert_eq!(suback.return_codes(), &[1]);
+        assert_eq!(suback.topics()[0].name(), "topic");
+        assert!(observer.payloads().is_empty());
+
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel1, false, "topic", "hola", Some(2)).unwrap();
+        client.publish(publish).unwrap();
+        observer.wait_for_payloads(1);
+        assert_eq!(observer.payloads(), vec!["hola"]);
+    }
 }
@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+
+use packets::publish::Publish;
+
+/// What the client does with a new publish made while it is
+/// not connected when its offline queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OfflineOverflow {
+    /// The oldest queued publish is dropped to make room for it
+    #[default]
+    DropOldest,
+    /// The new publish is dropped
+    DropNewest,
+}
+
+/// Result of trying to queue a publish in the OfflineQueue
+pub(crate) enum PushResult {
+    /// The client is connected (or the queue is disabled),
+    /// so the publish must be sent right away
+    Send(Publish),
+    /// The publish was queued
+    Queued,
+    /// The queue was full, so this publish was dropped
+    Dropped(Publish),
+}
+
+/// Bounded queue of the publishes made while the client is not
+/// connected, which are sent in order once it connects
+#[derive(Debug)]
+pub(crate) struct OfflineQueue {
+    publishes: VecDeque<Publish>,
+    /// Maximum number of queued publishes. If None, the
+    /// queue is disabled and publishes are always sent
+    capacity: Option<usize>,
+    overflow: OfflineOverflow,
+    connected: bool,
+}
+
+impl OfflineQueue {
+    /// Creates a new OfflineQueue of a client that is not connected yet.
+    /// If capacity is None, publishes are never queued
+    pub fn new(capacity: Option<usize>, overflow: OfflineOverflow) -> Self {
+        Self {
+            publishes: VecDeque::new(),
+            capacity,
+            overflow,
+            connected: false,
+        }
+    }
+
+    /// Marks the client as not connected, so that
+    /// the following publishes are queued
+    pub fn set_offline(&mut self) {
+        self.connected = false;
+    }
+
+    /// Queues the publish if the queue is enabled and the client
+    /// is not connected, following the overflow policy if it is full
+    pub fn push(&mut self, publish: Publish) -> PushResult {
+        let capacity = match self.capacity {
+            Some(capacity) if !self.connected => capacity,
+            _ => return PushResult::Send(publish),
+        };
+        if self.publishes.len() < capacity {
+            self.publishes.push_back(publish);
+            return PushResult::Queued;
+        }
+        match self.overflow {
+            OfflineOverflow::DropNewest => PushResult::Dropped(publish),
+            OfflineOverflow::DropOldest => {
+                self.publishes.push_back(publish);
+                match self.publishes.pop_front() {
+                    Some(dropped) => PushResult::Dropped(dropped),
+                    None => PushResult::Queued,
+                }
+            }
+        }
+    }
+
+    /// Returns the oldest queued publish. If there are none left,
+    /// the client is marked as connected, so that publishes are
+    /// no longer queued
+    pub fn pop(&mut self) -> Option<Publish> {
+        let next = self.publishes.pop_front();
+        if next.is_none() {
+            self.connected = true;
+        }
+        next
+    }
+}
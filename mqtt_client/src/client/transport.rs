@@ -0,0 +1,34 @@
+use std::{
+    io::{self, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+/// ReadTimeout trait from which the listener reads the packets
+pub trait ReadTimeout: Read + Send + Sync + 'static {
+    /// Sets how long a read can block before failing with
+    /// TimedOut or WouldBlock. If None, reads block indefinitely
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()>;
+}
+
+/// Stream through which the client communicates with the server.
+/// It is implemented for TcpStream, but any other transport (TLS,
+/// in memory, etc.) can be used by implementing it and creating
+/// the client with ClientBuilder::connect_transport()
+pub trait Transport: ReadTimeout + Write + Sized {
+    /// Creates a new handle to the same stream, so that the client
+    /// can read from one of them while it writes to the other
+    fn try_clone(&self) -> io::Result<Self>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, dur: Option<Duration>) -> io::Result<()> {
+        self.set_read_timeout(dur)
+    }
+}
+
+impl Transport for TcpStream {
+    fn try_clone(&self) -> io::Result<Self> {
+        TcpStream::try_clone(self)
+    }
+}
@@ -1,4 +1,6 @@
 mod client;
 mod observer;
-pub use crate::client::{Client, ClientError};
+pub use crate::client::{
+    Client, ClientBuilder, ClientError, OfflineOverflow, PoolStats, ReadTimeout, Transport,
+};
 pub use crate::observer::*;
@@ -1,5 +1,8 @@
+use std::sync::{Arc, PoisonError, RwLock};
+
 use packets::{
-    connack::Connack, puback::Puback, publish::Publish, suback::Suback, unsuback::Unsuback,
+    connack::Connack, puback::Puback, publish::Publish, qos::QoSLevel, suback::Suback,
+    unsuback::Unsuback,
 };
 
 use crate::client::ClientError;
@@ -16,8 +19,28 @@ pub enum Message {
     Subscribed(Result<Suback, ClientError>),
     Unsubscribed(Result<Unsuback, ClientError>),
     Published(Result<Option<Puback>, ClientError>),
-    Publish(Publish),
+    /// A PUBLISH packet received from the server. `was_retained`
+    /// is true if it is a retained message delivered on subscription,
+    /// instead of a live update
+    Publish {
+        publish: Publish,
+        was_retained: bool,
+    },
+    /// Sent after a successful Subscribed message for each
+    /// topic that the server granted with a lower QoS than
+    /// the requested one
+    QoSDowngraded {
+        topic: String,
+        requested: QoSLevel,
+        granted: QoSLevel,
+    },
     InternalError(ClientError),
+    /// The last message sent by a client, once it is dropped and
+    /// fully shut down. `clean` is true if the DISCONNECT packet
+    /// was sent to the server, and false if it failed
+    Closed {
+        clean: bool,
+    },
 }
 
 /// Observer trait for the internal client
@@ -26,3 +49,41 @@ pub enum Message {
 pub trait Observer: Clone + Send + Sync + 'static {
     fn update(&self, msg: Message);
 }
+
+/// Observer shared by the sender and the listener of the
+/// client, which can be replaced by another one while the
+/// client is running
+#[derive(Clone)]
+pub(crate) struct SharedObserver<T: Observer> {
+    observer: Arc<RwLock<T>>,
+}
+
+impl<T: Observer> SharedObserver<T> {
+    pub fn new(observer: T) -> Self {
+        Self {
+            observer: Arc::new(RwLock::new(observer)),
+        }
+    }
+
+    /// Replaces the observer. Messages sent after this
+    /// call go to the new one
+    pub fn replace(&self, observer: T) {
+        *self
+            .observer
+            .write()
+            .unwrap_or_else(PoisonError::into_inner) = observer;
+    }
+}
+
+impl<T: Observer> Observer for SharedObserver<T> {
+    fn update(&self, msg: Message) {
+        // Se clona para no mantener el lock mientras se notifica,
+        // por si el observer lo reemplaza desde update()
+        let observer = self
+            .observer
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone();
+        observer.update(msg);
+    }
+}
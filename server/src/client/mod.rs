@@ -1,4 +1,5 @@
 use core::fmt;
+use std::collections::{HashSet, VecDeque};
 use std::time::{Duration, SystemTime};
 use std::{io::Write, vec};
 
@@ -11,13 +12,43 @@ use tracing::{debug, instrument};
 use crate::server::UNACK_RESENDING_FREQ;
 use crate::traits::{Close, Interrupt};
 use crate::{
+    client_id::ClientId,
     network_connection::NetworkConnection,
-    server::{server_error::ServerErrorKind, ClientId, ServerError, ServerResult},
+    server::{server_error::ServerErrorKind, ServerError, ServerResult},
 };
 
 #[cfg(test)]
 mod tests;
 
+/// Factor by which the minimum time between resends of an
+/// unacknowledged packet is multiplied after each resend
+const RESEND_BACKOFF_FACTOR: u32 = 2;
+/// Maximum number of times the minimum time between resends
+/// of an unacknowledged packet is multiplied
+const MAX_RESEND_BACKOFFS: u32 = 5;
+/// Maximum number of publishes queued while the client is paused
+pub const MAX_PAUSED_PUBLISHES: usize = 1000;
+/// Maximum number of publishes queued waiting for the previous
+/// ones to be acknowledged, when strict ordering or flow control
+/// is enabled
+pub const MAX_QUEUED_PUBLISHES: usize = 1000;
+
+/// [`Publish`] packet sent to a client that is waiting to be
+/// acknowledged.
+///
+/// It can be deserialized both from a map and from a sequence,
+/// so that the dumps made when unacknowledged packets were
+/// stored as `(SystemTime, Publish)` tuples can still be restored.
+#[derive(Debug, Serialize, Deserialize)]
+struct Unacknowledged {
+    /// When the packet was last sent
+    sent_at: SystemTime,
+    publish: Publish,
+    /// How many times the packet was resent
+    #[serde(default)]
+    resends: u32,
+}
+
 /// Represents the state of a client on the server.
 ///
 /// This structure only handles the state of the client
@@ -54,8 +85,45 @@ where
     /// received on the new connection.
     connect: Connect,
     /// Unacknowledged packets, along with the time they
-    /// were last sent.
-    unacknowledged: Vec<(SystemTime, Publish)>,
+    /// were last sent and how many times they were resent.
+    unacknowledged: Vec<Unacknowledged>,
+    /// If true, publishes are sent strictly in order: a
+    /// publish is not sent until every previous one has
+    /// been acknowledged, regardless of its QoS.
+    #[serde(default)]
+    strict_ordering: bool,
+    /// Publishes waiting for the previous ones to be
+    /// acknowledged, when strict ordering is enabled, or
+    /// held back because the client has too many packets
+    /// waiting to be acknowledged (see
+    /// [`Client::set_inflight_window`]).
+    #[serde(default)]
+    queued: VecDeque<Publish>,
+    /// Low and high water marks of packets waiting to be
+    /// acknowledged, if flow control is enabled.
+    #[serde(skip)]
+    inflight_window: Option<(usize, usize)>,
+    /// If true, the client reached the high water mark and new
+    /// publishes are held back until it drains below the low one.
+    #[serde(skip)]
+    throttled: bool,
+    /// Topic filters the client is currently subscribed to
+    #[serde(default)]
+    subscriptions: HashSet<String>,
+    /// If true, publishes are queued instead of being sent,
+    /// until the client is resumed.
+    #[serde(default)]
+    paused: bool,
+    /// Publishes queued while the client is paused.
+    #[serde(default)]
+    paused_queue: VecDeque<Publish>,
+    /// When the last connection was established.
+    #[serde(skip)]
+    connected_at: Option<SystemTime>,
+    /// Since when there are packets waiting to be acknowledged
+    /// in the current connection, if any.
+    #[serde(skip)]
+    pending_since: Option<SystemTime>,
 }
 
 impl<S, I> Client<S, I>
@@ -63,18 +131,90 @@ where
     S: Write + Interrupt + Send + Sync + 'static,
     I: fmt::Display,
 {
-    /// Create a new connected client
-    pub fn new(connect: Connect, network_connection: NetworkConnection<S, I>) -> Self {
+    /// Create a new connected client. `id` must be the id of the
+    /// [`Connect`] packet, already validated (or assigned by the
+    /// server, if the client did not send one)
+    pub fn new(
+        id: ClientId,
+        connect: Connect,
+        network_connection: NetworkConnection<S, I>,
+    ) -> Self {
         Self {
-            id: connect.client_id().to_owned(),
+            id,
             connect,
             unacknowledged: vec![],
             connection: Some(network_connection),
+            strict_ordering: false,
+            queued: VecDeque::new(),
+            inflight_window: None,
+            throttled: false,
+            subscriptions: HashSet::new(),
+            paused: false,
+            paused_queue: VecDeque::new(),
+            connected_at: Some(SystemTime::now()),
+            pending_since: None,
         }
     }
 
+    /// Enables or disables strict ordering of the publishes sent
+    /// to the client.
+    ///
+    /// With strict ordering, a [`Publish`] is only sent once every
+    /// previous QoS 1 packet has been acknowledged, so that the client
+    /// receives them in the same order they were published, regardless
+    /// of their QoS. At most [`MAX_QUEUED_PUBLISHES`] are queued (see
+    /// [`Client::send_publish`]). Disabling it sends every queued packet.
+    pub fn set_strict_ordering(&mut self, strict_ordering: bool) -> ServerResult<()> {
+        self.strict_ordering = strict_ordering;
+        self.send_queued()
+    }
+
+    /// Enables or disables flow control of the publishes sent to the
+    /// client, with the given low and high water marks.
+    ///
+    /// Once the client has `high` packets waiting to be acknowledged,
+    /// new publishes are held back instead of being sent, so that a
+    /// client that acknowledges slowly does not accumulate an endless
+    /// list of unacknowledged packets. They are queued with the same
+    /// limits as with strict ordering (see [`Client::send_publish`]),
+    /// and sent in order once it has less than `low` packets waiting.
+    /// Disabling it sends every held back packet.
+    pub fn set_inflight_window(
+        &mut self,
+        inflight_window: Option<(usize, usize)>,
+    ) -> ServerResult<()> {
+        self.inflight_window = inflight_window;
+        self.send_queued()
+    }
+
+    /// Stops the delivery of publishes to the client, without
+    /// disconnecting it. Until the client is resumed, publishes
+    /// are queued, up to [`MAX_PAUSED_PUBLISHES`] (see
+    /// [`Client::send_publish`]).
+    pub fn pause(&mut self) {
+        self.paused = true;
+    }
+
+    /// Resumes the delivery of publishes to the client, sending
+    /// the ones queued while it was paused, in order.
+    ///
+    /// If one of them could not be sent, the rest are still sent,
+    /// and the error of the first one is returned.
+    pub fn resume(&mut self) -> ServerResult<()> {
+        self.paused = false;
+        let mut result = self.send_queued();
+        while let Some(publish) = self.paused_queue.pop_front() {
+            if let Err(err) = self.send_publish(publish) {
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+        result
+    }
+
     /// Return the id of the client.
-    pub fn id(&self) -> &str {
+    pub fn id(&self) -> &ClientId {
         &self.id
     }
 
@@ -130,7 +270,11 @@ where
         S: Close,
     {
         if let Some(mut connection) = self.connection.take() {
-            connection.close()?;
+            // Si el cliente ya cerro su extremo, la conexion puede fallar
+            // al cerrarse, pero la sesion debe desconectarse igual
+            if let Err(err) = connection.close() {
+                debug!("Error cerrando la conexion: {}", err);
+            }
         }
 
         if gracefully {
@@ -148,7 +292,7 @@ where
                 last_will.topic.qos(),
                 last_will.retain_flag,
                 last_will.topic.name(),
-                &last_will.topic_message,
+                last_will.topic_message,
                 packet_identifier,
             )
             .expect(
@@ -160,6 +304,20 @@ where
         }
     }
 
+    /// Closes the connection with the client so that it cannot be
+    /// read or written from any end, without disconnecting the
+    /// client. Therefore, the end that reads from the connection
+    /// is responsible for disconnecting it.
+    pub fn close_connection(&mut self) -> ServerResult<()>
+    where
+        S: Close,
+    {
+        if let Some(connection) = &mut self.connection {
+            connection.close()?;
+        }
+        Ok(())
+    }
+
     /// Check that the id of the new connection
     /// matches the id of the client.
     ///
@@ -183,6 +341,9 @@ where
 
     /// Reconnects a client. Does not send the Connack packet.
     ///
+    /// If the new connection asks for a clean session, the previous
+    /// session state is discarded, including its subscriptions.
+    ///
     /// If the reconnection produces a Client TakeOver and LastWill
     /// was specified in the previous session, a [`Publish`] packet is
     /// returned. Otherwise, it returns None.
@@ -198,14 +359,39 @@ where
 
         if *new_connect.clean_session() {
             self.unacknowledged = vec![];
+            self.queued.clear();
+            self.paused_queue.clear();
+            self.throttled = false;
+            self.subscriptions.clear();
         }
 
         let last_will = self.disconnect(false)?;
         self.connection = Some(new_connection);
+        self.connected_at = Some(SystemTime::now());
+        self.pending_since = if self.unacknowledged.is_empty() {
+            None
+        } else {
+            self.connected_at
+        };
         self.connect = new_connect;
         Ok(last_will)
     }
 
+    /// Returns since when the client has packets waiting to be
+    /// acknowledged in the current connection, or None if it has none
+    pub fn pending_since(&self) -> Option<SystemTime> {
+        self.pending_since
+    }
+
+    /// Returns how long ago the current connection was
+    /// established, or None if the client is disconnected.
+    pub fn connected_for(&self) -> Option<Duration> {
+        if !self.connected() {
+            return None;
+        }
+        self.connected_at?.elapsed().ok()
+    }
+
     /// Returns the maximum idle time between communication with
     /// the client before the server decides to disconnect it
     /// (see [MQTT-3.1.2-24])
@@ -230,6 +416,51 @@ where
         self.connection.as_ref().map(|connection| connection.id())
     }
 
+    /// Returns the number of bytes read from and written to
+    /// the current connection of the client, if it is connected.
+    /// Otherwise, it returns None.
+    pub fn traffic(&self) -> Option<(u64, u64)> {
+        self.connection
+            .as_ref()
+            .map(|connection| (connection.bytes_read(), connection.bytes_written()))
+    }
+
+    /// Returns true if the client is a slow consumer: it reached the
+    /// high water mark of its in-flight window (see
+    /// [`Client::set_inflight_window`]) and has not drained below the
+    /// low one yet, so the publishes to it are being held back.
+    pub fn is_slow_consumer(&self) -> bool {
+        self.throttled
+    }
+
+    /// Registers a subscription of the client to the given topic
+    /// filter.
+    ///
+    /// If the client already has `max_subscriptions` subscriptions
+    /// and is not subscribed to the topic filter, it is not registered
+    /// and false is returned. Otherwise, it returns true.
+    pub fn add_subscription(
+        &mut self,
+        topic_filter: &str,
+        max_subscriptions: Option<usize>,
+    ) -> bool {
+        if let Some(max_subscriptions) = max_subscriptions {
+            if self.subscriptions.len() >= max_subscriptions
+                && !self.subscriptions.contains(topic_filter)
+            {
+                return false;
+            }
+        }
+        self.subscriptions.insert(topic_filter.to_string());
+        true
+    }
+
+    /// Removes the subscription of the client to the given topic
+    /// filter, if it exists.
+    pub fn remove_subscription(&mut self, topic_filter: &str) {
+        self.subscriptions.remove(topic_filter);
+    }
+
     /// Removes from the unacknowledged list, the packet whose
     /// *packet_id* matches the *packet_id* of the received [`Puback`]
     /// packet. If no packet meets this condition, it returns an
@@ -237,17 +468,19 @@ where
     #[instrument(skip(self, puback) fields(client_id = %self.id, packet_id = %puback.packet_id()))]
     pub fn acknowledge(&mut self, puback: Puback) -> ServerResult<()> {
         debug!("Acknowledge");
-        let idx = self.unacknowledged.iter().position(|publish| {
+        let idx = self.unacknowledged.iter().position(|unacknowledged| {
             puback.packet_id()
-                == publish
-                    .1
+                == unacknowledged
+                    .publish
                     .packet_id()
                     .expect("Se esperaba un paquete con identificador (QoS > 0)")
         });
         if let Some(idx) = idx {
             self.unacknowledged.remove(idx);
         }
+        self.send_queued()?;
         if self.unacknowledged.is_empty() {
+            self.pending_since = None;
             match self.keep_alive() {
                 None => {
                     if let Some(connection) = &mut self.connection {
@@ -269,47 +502,180 @@ where
     ///
     /// `min_elapsed_time` is the minimum time that must have elapsed
     /// between the last time the packet was sent and the moment the
-    /// method is executed, for the packet to be sent. If it is None,
-    /// 1 packet will be sent.
+    /// method is executed, for the packet to be sent. It is multiplied
+    /// by [`RESEND_BACKOFF_FACTOR`] each time the packet is resent, so
+    /// that clients that do not acknowledge it are not flooded. If it is
+    /// None, 1 packet will be sent.
     pub fn send_unacknowledged(&mut self, min_elapsed_time: Option<Duration>) -> ServerResult<()> {
         let now = SystemTime::now();
         if self.unacknowledged.is_empty() {
             return Ok(());
         }
 
-        let (last_time_published, publish) = self.unacknowledged.remove(0);
-        if let Some(min_elapsed_time) = min_elapsed_time {
-            if now.duration_since(last_time_published).unwrap() > min_elapsed_time {
-                self.send_packet(&publish)?;
-                self.unacknowledged.insert(0, (now, publish));
-            } else {
-                // No se envio, no actualizo la hora
-                self.unacknowledged
-                    .insert(0, (last_time_published, publish));
+        let mut unacknowledged = self.unacknowledged.remove(0);
+        let must_send = match min_elapsed_time {
+            Some(min_elapsed_time) => {
+                now.duration_since(unacknowledged.sent_at).unwrap()
+                    > resend_delay(min_elapsed_time, unacknowledged.resends)
             }
-        } else {
-            self.send_packet(&publish)?;
-            self.unacknowledged.insert(0, (now, publish));
+            None => true,
+        };
+        // Si no se envia, no se actualiza la hora
+        if must_send {
+            self.send_packet(&unacknowledged.publish)?;
+            unacknowledged.sent_at = now;
+            unacknowledged.resends += 1;
         }
+        self.unacknowledged.insert(0, unacknowledged);
 
         Ok(())
     }
 
     /// Sends a [`Publish`] packet to the client and, if applicable,
     /// adds it to the unacknowledged packet list.
-    pub fn send_publish(&mut self, mut publish: Publish) -> ServerResult<()> {
+    ///
+    /// If strict ordering is enabled and there are previous packets
+    /// waiting to be acknowledged, the packet is queued instead, and
+    /// it is sent once those are acknowledged. If the queue is full,
+    /// the oldest QoS 0 packet queued is discarded to make room for it,
+    /// or the packet itself if it is QoS 0 too. A QoS 1 packet is never
+    /// discarded: if there is no QoS 0 packet to discard, an error of
+    /// kind [`ServerErrorKind::QueueFull`] is returned instead.
+    ///
+    /// If the client is paused, the packet is queued until it is
+    /// resumed, in a different queue but with the same limits.
+    ///
+    /// If the client has too many packets waiting to be acknowledged,
+    /// the packet is held back in the same queue, with the same limits,
+    /// until it acknowledges some of them.
+    pub fn send_publish(&mut self, publish: Publish) -> ServerResult<()> {
+        if self.paused {
+            return push_bounded(
+                &mut self.paused_queue,
+                publish,
+                MAX_PAUSED_PUBLISHES,
+                &self.id,
+            );
+        }
+        // Si ya hay encolados se encola igual, para no desordenarlos
+        let must_queue = !self.queued.is_empty()
+            || self.throttled
+            || (self.strict_ordering && !self.unacknowledged.is_empty());
+        if must_queue {
+            return push_bounded(&mut self.queued, publish, MAX_QUEUED_PUBLISHES, &self.id);
+        }
+        self._send_publish(publish)
+    }
+
+    #[doc(hidden)]
+    /// Sends the queued packets, in order, until one of them needs
+    /// to be acknowledged (or all of them if strict ordering was
+    /// disabled), or until the client reaches the high water mark.
+    /// If the client was throttled, they are only sent once it
+    /// drained below the low water mark
+    fn send_queued(&mut self) -> ServerResult<()> {
+        if self.throttled {
+            match self.inflight_window {
+                Some((low, _)) if self.unacknowledged.len() >= low => return Ok(()),
+                _ => {
+                    debug!(
+                        "Cliente <{}> por debajo del limite, se reanudan los envios",
+                        self.id
+                    );
+                    self.throttled = false;
+                }
+            }
+        }
+        while !self.paused
+            && !self.throttled
+            && (!self.strict_ordering || self.unacknowledged.is_empty())
+        {
+            match self.queued.pop_front() {
+                Some(publish) => self._send_publish(publish)?,
+                None => break,
+            }
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    fn _send_publish(&mut self, mut publish: Publish) -> ServerResult<()> {
         if self.connected() {
             self.send_packet(&publish)?;
         }
         if publish.qos() == QoSLevel::QoSLevel1 {
             publish.set_dup(true);
-            self.unacknowledged.push((SystemTime::now(), publish));
-            if self.unacknowledged.len() > 1 {
-                if let Some(connection) = &mut self.connection {
-                    connection.alert(UNACK_RESENDING_FREQ)?;
+            let now = SystemTime::now();
+            self.unacknowledged.push(Unacknowledged {
+                sent_at: now,
+                publish,
+                resends: 0,
+            });
+            if self.pending_since.is_none() {
+                self.pending_since = Some(now);
+            }
+            // Se despierta la lectura para reenviarlo (y detectar si el
+            // cliente dejo de responder), aunque no haya otro pendiente
+            if let Some(connection) = &mut self.connection {
+                connection.alert(UNACK_RESENDING_FREQ)?;
+            }
+            if let Some((_, high)) = self.inflight_window {
+                if self.unacknowledged.len() >= high {
+                    debug!(
+                        "Cliente <{}> alcanzo el limite de paquetes sin confirmar, se retienen los envios",
+                        self.id
+                    );
+                    self.throttled = true;
                 }
             }
         }
         Ok(())
     }
 }
+
+/// Appends the publish to the queue of the client with the given id,
+/// unless it already has `max` publishes. In that case, the oldest QoS 0
+/// publish is discarded to make room for it, or the new one if it is QoS
+/// 0 too. If it is QoS 1 and there is no QoS 0 one to discard, it is not
+/// queued, and an error of kind [`ServerErrorKind::QueueFull`] is returned
+#[doc(hidden)]
+fn push_bounded(
+    queue: &mut VecDeque<Publish>,
+    publish: Publish,
+    max: usize,
+    id: &ClientId,
+) -> ServerResult<()> {
+    if queue.len() >= max {
+        if publish.qos() == QoSLevel::QoSLevel0 {
+            debug!("Cliente <{}>: cola llena, se descarta publish QoS 0", id);
+            return Ok(());
+        }
+        let oldest_qos0 = queue
+            .iter()
+            .position(|queued| queued.qos() == QoSLevel::QoSLevel0);
+        match oldest_qos0 {
+            Some(idx) => {
+                debug!("Cliente <{}>: cola llena, se descarta publish QoS 0", id);
+                queue.remove(idx);
+            }
+            None => {
+                let msg = format!(
+                    "Cliente <{}>: cola llena, se rechaza publish QoS 1 en {}",
+                    id,
+                    publish.topic_name()
+                );
+                return Err(ServerError::new_kind(&msg, ServerErrorKind::QueueFull));
+            }
+        }
+    }
+    queue.push_back(publish);
+    Ok(())
+}
+
+/// Returns the minimum time that must elapse before resending a
+/// packet that was already resent `resends` times
+#[doc(hidden)]
+fn resend_delay(min_elapsed_time: Duration, resends: u32) -> Duration {
+    let factor = RESEND_BACKOFF_FACTOR.saturating_pow(resends.min(MAX_RESEND_BACKOFFS));
+    min_elapsed_time.saturating_mul(factor)
+}
@@ -1,7 +1,7 @@
 use std::{
     io::{self, Read},
     thread,
-    time::Duration,
+    time::{Duration, Instant},
 };
 
 use packets::{
@@ -14,11 +14,21 @@ use packets::{
 };
 
 use crate::{
-    network_connection::NetworkConnection, server::server_error::ServerErrorKind,
+    client_id::{ClientId, ClientIdRules},
+    network_connection::NetworkConnection,
+    server::server_error::ServerErrorKind,
     test_helpers::iomock::IOMock,
 };
 
-use super::Client;
+use super::{Client, MAX_PAUSED_PUBLISHES, MAX_QUEUED_PUBLISHES};
+
+fn new_client(
+    connect: Connect,
+    network_connection: NetworkConnection<IOMock, u16>,
+) -> Client<IOMock, u16> {
+    let id = ClientId::new(connect.client_id(), &ClientIdRules::default()).unwrap();
+    Client::new(id, connect, network_connection)
+}
 
 fn make_publish(topic_name: &str, qos: QoSLevel) -> Publish {
     if qos == QoSLevel::QoSLevel0 {
@@ -82,12 +92,12 @@ fn test_creation() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let client = Client::new(connect, network_connection);
+    let client = new_client(connect, network_connection);
 
     assert_eq!(client.connect, connect_copy);
     assert!(client.connection.is_some());
     assert!(client.unacknowledged.is_empty());
-    assert_eq!(client.id, String::from("client_id"));
+    assert_eq!(client.id, "client_id");
 }
 
 #[test]
@@ -99,7 +109,7 @@ fn test_keep_alive_returns_correct_value() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let client = Client::new(connect, network_connection);
+    let client = new_client(connect, network_connection);
     assert_eq!(client.keep_alive(), Some(Duration::from_millis(1500)));
 }
 
@@ -113,7 +123,7 @@ fn test_publish_send_packet_through_network_connection() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.send_publish(publish).unwrap();
 
     let mut control = [0u8];
@@ -133,7 +143,7 @@ fn test_publish_does_not_save_packet_in_unacknowledged_if_qos_is_0() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.send_publish(publish).unwrap();
     assert!(client.unacknowledged.is_empty());
 }
@@ -149,9 +159,9 @@ fn test_publish_saves_packet_in_unacknowledged_if_qos_is_1() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.send_publish(publish).unwrap();
-    assert_eq!(client.unacknowledged[0].1, publish_copy);
+    assert_eq!(client.unacknowledged[0].publish, publish_copy);
 }
 
 #[test]
@@ -165,7 +175,7 @@ fn test_send_unacknowledged() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.send_publish(publish).unwrap();
     client.send_unacknowledged(None).unwrap();
 
@@ -193,7 +203,7 @@ fn test_send_unacknowledged_multiple_times() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.send_publish(publish).unwrap();
     client.send_unacknowledged(None).unwrap();
     client.send_unacknowledged(None).unwrap();
@@ -226,7 +236,7 @@ fn test_acknowledge_remove_packet_from_list() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.send_publish(publish).unwrap();
     client.acknowledge(puback).unwrap();
 
@@ -244,7 +254,7 @@ fn test_send_unacknowledged_inflight_messages_bigger_than_unacknowledged_should_
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.send_publish(publish).unwrap();
     client.send_unacknowledged(None).unwrap();
 
@@ -271,7 +281,7 @@ fn test_send_unacknowledged_min_elapsed_time_should_not_send_recent_packets() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.send_publish(publish).unwrap();
     client
         .send_unacknowledged(Some(Duration::from_secs(5)))
@@ -302,7 +312,7 @@ fn test_send_unacknowledged_min_elapsed_time_should_send_old_packets() {
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
 
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.send_publish(publish).unwrap();
     thread::sleep(Duration::from_millis(150));
 
@@ -333,7 +343,7 @@ fn test_send_unacknowledged_should_keep_order() {
     let mut publish1_copy = publish1.clone();
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
 
     client.send_publish(publish1).unwrap();
     client.send_publish(publish2).unwrap();
@@ -371,7 +381,7 @@ fn test_disconnect_gracefully_should_remove_last_will() {
     let connect = make_connect(0, true, Some(QoSLevel::QoSLevel0));
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.disconnect(true).unwrap();
     assert!(client.connect.last_will().is_none());
 }
@@ -381,7 +391,7 @@ fn test_disconnect_ungracefully_should_remove_last_will() {
     let connect = make_connect(0, true, Some(QoSLevel::QoSLevel0));
 
     let network_connection = NetworkConnection::new(0, IOMock::new());
-    let mut client = Client::new(connect, network_connection);
+    let mut client = new_client(connect, network_connection);
     client.disconnect(false).unwrap();
     assert!(client.connect.last_will().is_none());
 }
@@ -396,7 +406,7 @@ fn test_reconnect_updates_connection_info() {
 
     let network_connection_1 = NetworkConnection::new(0, IOMock::new());
     let network_connection_2 = NetworkConnection::new(1, IOMock::new());
-    let mut client = Client::new(connect_1, network_connection_1);
+    let mut client = new_client(connect_1, network_connection_1);
     client.reconnect(connect_2, network_connection_2).unwrap();
 
     assert_eq!(*client.connection_id().unwrap(), 1);
@@ -414,7 +424,7 @@ fn test_reconnect_with_clean_session_should_clean_unacknowledged() {
 
     let network_connection_1 = NetworkConnection::new(0, IOMock::new());
     let network_connection_2 = NetworkConnection::new(1, IOMock::new());
-    let mut client = Client::new(connect_1, network_connection_1);
+    let mut client = new_client(connect_1, network_connection_1);
     client.send_publish(publish).unwrap();
 
     client.reconnect(connect_2, network_connection_2).unwrap();
@@ -433,8 +443,254 @@ fn test_reconnect_does_not_work_with_different_client_id() {
 
     let network_connection_1 = NetworkConnection::new(0, IOMock::new());
     let network_connection_2 = NetworkConnection::new(1, IOMock::new());
-    let mut client = Client::new(connect_1, network_connection_1);
+    let mut client = new_client(connect_1, network_connection_1);
 
     let result = client.reconnect(connect_2, network_connection_2);
     assert_eq!(result.unwrap_err().kind(), ServerErrorKind::Irrecoverable);
 }
+
+#[test]
+fn test_strict_ordering_qos0_after_unacknowledged_qos1_is_sent_after_it() {
+    let connect = make_connect(0, true, None);
+
+    let publish_qos1 = make_publish("top", QoSLevel::QoSLevel1);
+    let publish_qos0 = make_publish("other", QoSLevel::QoSLevel0);
+
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+
+    let mut client = new_client(connect, network_connection);
+    client.set_strict_ordering(true).unwrap();
+    client.send_publish(publish_qos1.clone()).unwrap();
+    client.send_publish(publish_qos0.clone()).unwrap();
+
+    // El QoS 0 queda encolado hasta que se reconozca el QoS 1
+    assert_eq!(client.queued.len(), 1);
+
+    client.acknowledge(Puback::new(1).unwrap()).unwrap();
+    assert!(client.queued.is_empty());
+
+    let mut network_connection_copy = client.connection.unwrap().try_clone().unwrap();
+    let mut control = [0u8];
+
+    network_connection_copy.read_exact(&mut control).unwrap();
+    let first = Publish::read_from(&mut network_connection_copy, control[0]).unwrap();
+    assert_eq!(first, publish_qos1);
+
+    network_connection_copy.read_exact(&mut control).unwrap();
+    let second = Publish::read_from(&mut network_connection_copy, control[0]).unwrap();
+    assert_eq!(second, publish_qos0);
+}
+
+#[test]
+fn test_strict_ordering_queue_is_bounded_without_discarding_qos1() {
+    let connect = make_connect(0, false, None);
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+
+    let mut client = new_client(connect, network_connection);
+    client.set_strict_ordering(true).unwrap();
+    client.disconnect(true).unwrap();
+    // El primero queda sin confirmar, y el resto se encola
+    client
+        .send_publish(make_publish("first", QoSLevel::QoSLevel1))
+        .unwrap();
+    client
+        .send_publish(make_publish("qos0", QoSLevel::QoSLevel0))
+        .unwrap();
+    for _ in 1..MAX_QUEUED_PUBLISHES {
+        client
+            .send_publish(make_publish("qos1", QoSLevel::QoSLevel1))
+            .unwrap();
+    }
+    assert_eq!(client.queued.len(), MAX_QUEUED_PUBLISHES);
+
+    // Con la cola llena, se descarta el QoS 0 encolado
+    client
+        .send_publish(make_publish("last", QoSLevel::QoSLevel1))
+        .unwrap();
+    assert_eq!(client.queued.len(), MAX_QUEUED_PUBLISHES);
+    assert!(client
+        .queued
+        .iter()
+        .all(|publish| publish.qos() == QoSLevel::QoSLevel1));
+
+    // Un nuevo QoS 0 se descarta, y un nuevo QoS 1 se rechaza
+    client
+        .send_publish(make_publish("qos0", QoSLevel::QoSLevel0))
+        .unwrap();
+    let err = client
+        .send_publish(make_publish("rejected", QoSLevel::QoSLevel1))
+        .unwrap_err();
+    assert_eq!(err.kind(), ServerErrorKind::QueueFull);
+    assert_eq!(client.queued.len(), MAX_QUEUED_PUBLISHES);
+    assert_eq!(client.queued.back().unwrap().topic_name(), "last");
+}
+
+#[test]
+fn test_without_strict_ordering_qos0_is_sent_immediately() {
+    let connect = make_connect(0, true, None);
+
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+
+    let mut client = new_client(connect, network_connection);
+    client
+        .send_publish(make_publish("top", QoSLevel::QoSLevel1))
+        .unwrap();
+    client
+        .send_publish(make_publish("other", QoSLevel::QoSLevel0))
+        .unwrap();
+
+    assert!(client.queued.is_empty());
+    assert_eq!(client.unacknowledged.len(), 1);
+}
+
+#[test]
+fn test_send_unacknowledged_resends_with_increasing_delays() {
+    let connect = make_connect(0, true, None);
+    let publish = make_publish("top", QoSLevel::QoSLevel1);
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+
+    let mut client = new_client(connect, network_connection);
+    client.send_publish(publish).unwrap();
+    let packet_len = client.connection.as_ref().unwrap().bytes_written();
+
+    // Simulo los ticks del servidor, y registro cuando se reenvia
+    let start = Instant::now();
+    let mut resend_times = Vec::new();
+    let mut sent = packet_len;
+    while start.elapsed() < Duration::from_millis(500) {
+        client
+            .send_unacknowledged(Some(Duration::from_millis(50)))
+            .unwrap();
+        let written = client.connection.as_ref().unwrap().bytes_written();
+        if written > sent {
+            resend_times.push(start.elapsed());
+            sent = written;
+        }
+        thread::sleep(Duration::from_millis(5));
+    }
+
+    // Se reenvia a los 50ms, 150ms y 350ms aproximadamente
+    assert_eq!(resend_times.len(), 3);
+    let gaps: Vec<Duration> = resend_times.windows(2).map(|w| w[1] - w[0]).collect();
+    assert!(gaps[0] >= Duration::from_millis(100));
+    assert!(gaps[1] >= Duration::from_millis(200));
+    assert_eq!(client.unacknowledged[0].resends, 3);
+}
+
+#[test]
+fn test_paused_client_queue_is_bounded_without_discarding_qos1() {
+    let connect = make_connect(0, true, None);
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+
+    let mut client = new_client(connect, network_connection);
+    client.pause();
+    client
+        .send_publish(make_publish("qos0", QoSLevel::QoSLevel0))
+        .unwrap();
+    for _ in 1..MAX_PAUSED_PUBLISHES {
+        client
+            .send_publish(make_publish("qos1", QoSLevel::QoSLevel1))
+            .unwrap();
+    }
+    assert_eq!(client.connection.as_ref().unwrap().bytes_written(), 0);
+    // No se comparte la cola con la de orden estricto
+    assert!(client.queued.is_empty());
+
+    // Con la cola llena, se descarta el QoS 0 encolado
+    client
+        .send_publish(make_publish("last", QoSLevel::QoSLevel1))
+        .unwrap();
+    assert_eq!(client.paused_queue.len(), MAX_PAUSED_PUBLISHES);
+    assert!(client
+        .paused_queue
+        .iter()
+        .all(|publish| publish.qos() == QoSLevel::QoSLevel1));
+
+    // Un nuevo QoS 0 se descarta, y un nuevo QoS 1 se rechaza
+    client
+        .send_publish(make_publish("qos0", QoSLevel::QoSLevel0))
+        .unwrap();
+    let err = client
+        .send_publish(make_publish("rejected", QoSLevel::QoSLevel1))
+        .unwrap_err();
+    assert_eq!(err.kind(), ServerErrorKind::QueueFull);
+    assert_eq!(client.paused_queue.back().unwrap().topic_name(), "last");
+
+    client.resume().unwrap();
+    assert!(client.paused_queue.is_empty());
+    assert_eq!(client.unacknowledged.len(), MAX_PAUSED_PUBLISHES);
+}
+
+#[test]
+fn test_inflight_window_holds_back_publishes() {
+    let connect = make_connect(0, true, None);
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+
+    let mut client = new_client(connect, network_connection);
+    client.set_inflight_window(Some((1, 3))).unwrap();
+    for id in 1..=5 {
+        let publish = Publish::new(
+            false,
+            QoSLevel::QoSLevel1,
+            false,
+            "topic",
+            "message",
+            Some(id),
+        )
+        .unwrap();
+        client.send_publish(publish).unwrap();
+    }
+    assert_eq!(client.unacknowledged.len(), 3);
+    assert_eq!(client.queued.len(), 2);
+    assert!(client.is_slow_consumer());
+
+    // Con 2 sin confirmar no se baja del minimo
+    client.acknowledge(Puback::new(1).unwrap()).unwrap();
+    assert_eq!(client.unacknowledged.len(), 2);
+    assert_eq!(client.queued.len(), 2);
+    assert!(client.is_slow_consumer());
+
+    // Con 0 sin confirmar se envian los retenidos, en orden
+    client.acknowledge(Puback::new(2).unwrap()).unwrap();
+    client.acknowledge(Puback::new(3).unwrap()).unwrap();
+    assert!(client.queued.is_empty());
+    assert!(!client.is_slow_consumer());
+    let ids: Vec<_> = client
+        .unacknowledged
+        .iter()
+        .map(|unacknowledged| unacknowledged.publish.packet_id().unwrap())
+        .collect();
+    assert_eq!(ids, vec![4, 5]);
+}
+
+#[test]
+fn test_inflight_window_queue_is_bounded() {
+    let connect = make_connect(0, false, None);
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+
+    let mut client = new_client(connect, network_connection);
+    client.set_inflight_window(Some((1, 1))).unwrap();
+    client.disconnect(true).unwrap();
+    // El primero queda sin confirmar, y el resto se retiene
+    client
+        .send_publish(make_publish("first", QoSLevel::QoSLevel1))
+        .unwrap();
+    assert!(client.is_slow_consumer());
+    for _ in 0..MAX_QUEUED_PUBLISHES {
+        client
+            .send_publish(make_publish("qos1", QoSLevel::QoSLevel1))
+            .unwrap();
+    }
+    assert_eq!(client.queued.len(), MAX_QUEUED_PUBLISHES);
+
+    // Con la cola llena, un QoS 0 se descarta y un QoS 1 se rechaza
+    client
+        .send_publish(make_publish("qos0", QoSLevel::QoSLevel0))
+        .unwrap();
+    let err = client
+        .send_publish(make_publish("rejected", QoSLevel::QoSLevel1))
+        .unwrap_err();
+    assert_eq!(err.kind(), ServerErrorKind::QueueFull);
+    assert_eq!(client.queued.len(), MAX_QUEUED_PUBLISHES);
+    assert_eq!(client.unacknowledged.len(), 1);
+}
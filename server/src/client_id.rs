@@ -0,0 +1,215 @@
+use std::{borrow::Borrow, fmt, ops::Deref};
+
+use packets::connack::ConnackReturnCode;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    server::{server_error::ServerErrorKind, ServerError, ServerResult},
+    traits::{Config, DEFAULT_MAX_CLIENT_ID_LENGTH},
+};
+
+/// Prefix of the ids that the server assigns to the clients
+/// that connect without one. Clients can not use it
+pub(crate) const GENERATED_ID_PREFIX: &str = "__CLIENT__";
+/// Prefix of the ids with which the in-process subscribers
+/// are registered in the topic handler
+pub(crate) const LOCAL_SUBSCRIBER_PREFIX: &str = "$local/";
+
+/// Rules that the ids sent by the clients must follow
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIdRules {
+    /// Maximum length of the id, in bytes
+    pub max_length: usize,
+    /// If true, the id can only contain the characters
+    /// recommended by the protocol (see [MQTT-3.1.3-5])
+    pub strict: bool,
+}
+
+impl ClientIdRules {
+    /// Returns the rules set in the configuration of the server
+    pub fn from_config<C: Config>(config: &C) -> Self {
+        Self {
+            max_length: config.max_client_id_length(),
+            strict: config.strict_client_ids(),
+        }
+    }
+}
+
+impl Default for ClientIdRules {
+    fn default() -> Self {
+        Self {
+            max_length: DEFAULT_MAX_CLIENT_ID_LENGTH,
+            strict: false,
+        }
+    }
+}
+
+/// Id of a client. It is either one sent by the client that
+/// follows the [`ClientIdRules`] of the server, or one assigned
+/// by the server to a client that connected without one
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ClientId(String);
+
+impl ClientId {
+    /// Validates an id sent by a client
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`ServerErrorKind::ConnectionRefused`]
+    /// if the id is empty, it uses the prefix reserved for the generated
+    /// ids or it does not follow the given rules
+    pub fn new(id: &str, rules: &ClientIdRules) -> ServerResult<Self> {
+        if id.is_empty() {
+            return Err(rejected("ID de cliente vacia".to_string()));
+        }
+        if id.starts_with(GENERATED_ID_PREFIX) {
+            return Err(rejected("ID con prefijo invalido".to_string()));
+        }
+        if id.len() > rules.max_length {
+            return Err(rejected(format!(
+                "ID de cliente demasiado larga: {} bytes",
+                id.len()
+            )));
+        }
+        if rules.strict && !id.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(rejected(format!(
+                "ID de cliente con caracteres invalidos: {}",
+                id
+            )));
+        }
+        Ok(Self(id.to_string()))
+    }
+
+    /// Returns the id that the server assigns to the `n`-th
+    /// client that connected without one
+    pub(crate) fn generated(n: u32) -> Self {
+        Self(format!("{}{}", GENERATED_ID_PREFIX, n))
+    }
+
+    /// Returns the id of the `n`-th in-process subscriber
+    /// (see [`Server::subscribe_local`](crate::Server::subscribe_local))
+    pub(crate) fn local(n: u64) -> Self {
+        Self(format!("{}{}", LOCAL_SUBSCRIBER_PREFIX, n))
+    }
+
+    /// Returns the id as a string slice
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+/// Returns true if `id` was assigned by the server to a
+/// client that connected without one
+pub(crate) fn is_generated(id: &str) -> bool {
+    id.starts_with(GENERATED_ID_PREFIX)
+}
+
+#[doc(hidden)]
+fn rejected(msg: String) -> ServerError {
+    ServerError::new_kind(
+        msg,
+        ServerErrorKind::ConnectionRefused(ConnackReturnCode::IdentifierRejected),
+    )
+}
+
+impl Deref for ClientId {
+    type Target = str;
+
+    fn deref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl Borrow<str> for ClientId {
+    fn borrow(&self) -> &str {
+        &self.0
+    }
+}
+
+impl AsRef<str> for ClientId {
+    fn as_ref(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for ClientId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl PartialEq<str> for ClientId {
+    fn eq(&self, other: &str) -> bool {
+        self.0 == other
+    }
+}
+
+impl PartialEq<&str> for ClientId {
+    fn eq(&self, other: &&str) -> bool {
+        self.0 == *other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use packets::connack::ConnackReturnCode;
+
+    use super::{is_generated, ClientId, ClientIdRules, GENERATED_ID_PREFIX};
+    use crate::server::server_error::ServerErrorKind;
+
+    fn rules(max_length: usize, strict: bool) -> ClientIdRules {
+        ClientIdRules { max_length, strict }
+    }
+
+    fn is_rejected(id: &str, rules: &ClientIdRules) -> bool {
+        match ClientId::new(id, rules) {
+            Err(err) => {
+                err.kind()
+                    == ServerErrorKind::ConnectionRefused(ConnackReturnCode::IdentifierRejected)
+            }
+            Ok(_) => false,
+        }
+    }
+
+    #[test]
+    fn test_valid_id() {
+        let id = ClientId::new("client", &ClientIdRules::default()).unwrap();
+        assert_eq!(id, "client");
+        assert_eq!(id.as_str(), "client");
+    }
+
+    #[test]
+    fn test_empty_id() {
+        assert!(is_rejected("", &ClientIdRules::default()));
+    }
+
+    #[test]
+    fn test_reserved_prefix() {
+        let id = format!("{}1", GENERATED_ID_PREFIX);
+        assert!(is_rejected(&id, &ClientIdRules::default()));
+    }
+
+    #[test]
+    fn test_generated_ids_use_reserved_prefix() {
+        assert!(ClientId::generated(1).starts_with(GENERATED_ID_PREFIX));
+        assert!(is_generated(&ClientId::generated(1)));
+        assert!(!is_generated("id"));
+        assert_ne!(ClientId::generated(1), ClientId::generated(2));
+    }
+
+    #[test]
+    fn test_client_id_length() {
+        assert!(ClientId::new("abc", &rules(3, false)).is_ok());
+        assert!(is_rejected("abcd", &rules(3, false)));
+        // La longitud se mide en bytes
+        assert!(is_rejected("ñañ", &rules(4, false)));
+    }
+
+    #[test]
+    fn test_strict_client_id_characters() {
+        assert!(ClientId::new("Client01", &rules(23, true)).is_ok());
+        assert!(is_rejected("client-01", &rules(23, true)));
+        assert!(ClientId::new("client-01", &rules(23, false)).is_ok());
+    }
+}
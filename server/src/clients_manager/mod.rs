@@ -6,6 +6,7 @@ use std::{
     io::{Read, Write},
     ops::DerefMut,
     sync::Mutex,
+    time::Duration,
     vec,
 };
 
@@ -15,13 +16,12 @@ use tracing::{info, instrument};
 
 use crate::{
     client::Client,
+    client_id::{ClientId, ClientIdRules},
     network_connection::NetworkConnection,
-    server::{server_error::ServerErrorKind, ClientId, ClientIdArg, ServerError, ServerResult},
+    server::{server_error::ServerErrorKind, ClientIdArg, ServerError, ServerResult},
     traits::{Close, Interrupt, Login, LoginResult},
 };
 
-const GENERIC_ID_SUFFIX: &str = "__CLIENT__";
-
 /// Structure that manages the clients of the server.
 /// This includes connecting, reconnecting, disconnecting
 /// and authenticating clients
@@ -47,6 +47,23 @@ where
     /// client_id are connected. Useful to assign
     /// them a unique default ID
     generic_ids_counter: u32,
+    #[serde(skip, default = "Default::default")]
+    /// If a client takes over its own session within this
+    /// time since its previous connection was established,
+    /// its subscriptions are kept even if it asks for a
+    /// clean session
+    reconnect_grace: Option<Duration>,
+    #[serde(skip, default = "Default::default")]
+    /// Rules that the ids sent by the clients must follow
+    client_id_rules: ClientIdRules,
+    #[serde(skip, default = "Default::default")]
+    /// If true, every connection is handled as if
+    /// it asked for a clean session
+    force_clean_session: bool,
+    #[serde(skip, default = "Default::default")]
+    /// If true, clients without user_name or password are
+    /// rejected, even if there is no login method
+    require_credentials: bool,
 }
 
 /// Information related to the disconnection
@@ -62,6 +79,12 @@ pub struct DisconnectInfo {
     /// information that is not stored by the
     /// [ClientsManager]
     pub clean_session: bool,
+    /// True if the client was connected, and this
+    /// disconnection closed its current connection.
+    /// It is false if the connection had already been
+    /// replaced by a Client Take-Over, or the client
+    /// was already disconnected
+    pub disconnected: bool,
 }
 
 /// Information related to the connection of a client.
@@ -81,8 +104,14 @@ pub struct ConnectInfo {
     /// client_id. This session could be active (in
     /// case of a client takeover) or not (in case
     /// a client connect with clean_session false and
-    /// then disconnects)
+    /// then disconnects), and the client did not
+    /// ask to discard it with clean_session true
     pub session_present: bool,
+    /// Indicates if a previous session associated
+    /// with the client_id was discarded, because the
+    /// client connected with clean_session true. In
+    /// that case, its subscriptions must be removed
+    pub session_discarded: bool,
     /// Last Will packet specified in the client's
     /// previous session, in the event of a takeover
     /// reconnection. If this does not happen, or the
@@ -91,10 +120,28 @@ pub struct ConnectInfo {
     pub takeover_last_will: Option<Publish>,
 }
 
+/// Bandwidth used by a connected client
+/// in its current connection
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct ClientTraffic {
+    /// ID of the client
+    pub id: ClientId,
+    /// Bytes received from the client
+    pub bytes_read: u64,
+    /// Bytes sent to the client
+    pub bytes_written: u64,
+    /// True if the client is a slow consumer: the publishes to it are
+    /// held back because it has too many packets waiting to be
+    /// acknowledged (see [`Config::inflight_window`](crate::traits::Config::inflight_window))
+    pub slow_consumer: bool,
+}
+
 #[derive(Debug)]
 pub struct ShutdownInfo {
     pub clean_session_ids: Vec<ClientId>,
     pub last_will_packets: Vec<(ClientId, Publish)>,
+    /// Ids of the clients that were connected
+    pub disconnected_ids: Vec<ClientId>,
 }
 
 impl<S, I> ClientsManager<S, I>
@@ -109,6 +156,10 @@ where
             clients: HashMap::new(),
             login,
             generic_ids_counter: 0,
+            reconnect_grace: None,
+            client_id_rules: ClientIdRules::default(),
+            force_clean_session: false,
+            require_credentials: false,
         }
     }
 
@@ -126,11 +177,75 @@ where
         }
     }
 
+    /// Returns true if the manager has a session for the client,
+    /// whether it is connected or not
+    pub fn has_session(&self, id: &ClientIdArg) -> bool {
+        self.clients.contains_key(id)
+    }
+
+    /// Returns the number of clients that are currently connected
+    pub fn connected_count(&self) -> ServerResult<usize> {
+        let mut count = 0;
+        for session in self.clients.values() {
+            if session.lock()?.connected() {
+                count += 1;
+            }
+        }
+        Ok(count)
+    }
+
+    /// Returns the bandwidth used by each connected client,
+    /// sorted by client id
+    pub fn traffic(&self) -> ServerResult<Vec<ClientTraffic>> {
+        let mut traffic = Vec::new();
+        for (id, session) in &self.clients {
+            let session = session.lock()?;
+            if let Some((bytes_read, bytes_written)) = session.traffic() {
+                traffic.push(ClientTraffic {
+                    id: id.clone(),
+                    bytes_read,
+                    bytes_written,
+                    slow_consumer: session.is_slow_consumer(),
+                });
+            }
+        }
+        traffic.sort_by(|a, b| a.id.cmp(&b.id));
+        Ok(traffic)
+    }
+
     /// Replaces the login method
     pub fn set_auth(&mut self, login: Option<Box<dyn Login>>) {
         self.login = login;
     }
 
+    /// Sets the reconnect grace. A client that takes over its own
+    /// persistent session (clean_session false) within this time since
+    /// its previous connection was established is considered to have
+    /// reconnected briefly, so the Last Will of the replaced connection
+    /// is not published. Clean session takeovers are not affected
+    pub fn set_reconnect_grace(&mut self, reconnect_grace: Option<Duration>) {
+        self.reconnect_grace = reconnect_grace;
+    }
+
+    /// Sets the rules that the ids sent by the clients must follow
+    pub fn set_client_id_rules(&mut self, rules: ClientIdRules) {
+        self.client_id_rules = rules;
+    }
+
+    /// If true, every connection is handled as if it asked for a clean
+    /// session: the previous session of the client is discarded and
+    /// nothing is kept after it disconnects
+    pub fn set_force_clean_session(&mut self, force_clean_session: bool) {
+        self.force_clean_session = force_clean_session;
+    }
+
+    /// If true, clients that do not send both a user_name and a
+    /// password are rejected, regardless of the login method (even
+    /// if there is none, in which case they are not validated)
+    pub fn set_require_credentials(&mut self, require_credentials: bool) {
+        self.require_credentials = require_credentials;
+    }
+
     /// Tries to disconnect a client. If the client specified
     /// clean_session to false, its information is kept
     /// in (self.clients). Otherwise, it is deleted.
@@ -158,18 +273,22 @@ where
                 return Ok(DisconnectInfo {
                     publish_last_will: None,
                     clean_session: false,
+                    disconnected: false,
                 })
             }
             Err(e) => return Err(e),
         };
-        if let Some(old_id) = old_id {
-            if *network_connection.id() != old_id {
+        let disconnected = match old_id {
+            Some(old_id) if *network_connection.id() != old_id => {
                 return Ok(DisconnectInfo {
                     publish_last_will: None,
                     clean_session: false,
+                    disconnected: false,
                 });
             }
-        }
+            Some(_) => true,
+            None => false,
+        };
 
         let publish_last_will = self.client_do(id, |session| session.disconnect(gracefully))?;
         let clean_session;
@@ -189,6 +308,7 @@ where
         Ok(DisconnectInfo {
             publish_last_will,
             clean_session,
+            disconnected,
         })
     }
 
@@ -230,13 +350,14 @@ where
     /// send a Connack to the client, it returns an error of kind
     /// [`ServerErrorKind::ConnectionRefused`]
     fn check_credentials(&mut self, connect: &Connect) -> ServerResult<()> {
-        if connect.client_id().starts_with(GENERIC_ID_SUFFIX) {
+        if self.require_credentials
+            && (connect.user_name().is_none() || connect.password().is_none())
+        {
             return Err(ServerError::new_kind(
-                "ID con prefijo invalido",
-                ServerErrorKind::ConnectionRefused(ConnackReturnCode::IdentifierRejected),
+                "Clientes sin credenciales no estan permitidos",
+                ServerErrorKind::ConnectionRefused(ConnackReturnCode::NotAuthorized),
             ));
         }
-
         // No precisamos chequear las ids tomadas con check_taken_ids
         // porque en modo sin autenticacion cualquier cliente puede
         // hacer TakeOver
@@ -271,23 +392,26 @@ where
                 "Contraseña invalida",
                 ServerErrorKind::ConnectionRefused(ConnackReturnCode::BadUserNameOrPassword),
             )),
+            LoginResult::TemporarilyUnavailable => Err(ServerError::new_kind(
+                "No se pudieron verificar las credenciales, metodo de login no disponible",
+                ServerErrorKind::ConnectionRefused(ConnackReturnCode::ServerUnavailable),
+            )),
             LoginResult::Accepted => self.check_taken_ids(connect.client_id(), user_name),
         }
     }
 
     /// Creates a new generic ID. Guarantees that this id
     /// is unique
-    fn new_generic_id(&mut self) -> String {
+    fn new_generic_id(&mut self) -> ClientId {
         self.generic_ids_counter += 1;
-        let mut generic_id = String::from(GENERIC_ID_SUFFIX);
-        generic_id.push_str(&self.generic_ids_counter.to_string());
-        generic_id
+        ClientId::generated(self.generic_ids_counter)
     }
 
     /// Makes the necessary modifications in the [`Connect`] packet to
-    /// be able to create a client from its information. If it contains
-    /// invalid information, it returns an error of kind [`ServerErrorKind::ConnectionRefused`]
-    fn process_client_empty_id(&mut self, connect: &mut Connect) -> ServerResult<()> {
+    /// be able to create a client from its information, and returns
+    /// the id assigned to it. If it contains invalid information, it
+    /// returns an error of kind [`ServerErrorKind::ConnectionRefused`]
+    fn process_client_empty_id(&mut self, connect: &mut Connect) -> ServerResult<ClientId> {
         if !connect.clean_session() {
             Err(ServerError::new_kind(
                 "Clientes con id vacia deben tener clean session en true",
@@ -295,8 +419,8 @@ where
             ))
         } else {
             let id = self.new_generic_id();
-            connect.set_id(id);
-            Ok(())
+            connect.set_id(id.to_string());
+            Ok(id)
         }
     }
 
@@ -310,6 +434,13 @@ where
     /// it disconnects it (takeover) and returns the Last
     /// Will, if it was specified in the previous session.
     ///
+    /// A takeover never drops the subscriptions of the session,
+    /// unless the new connection asks for a clean session. In that
+    /// case, [`ConnectInfo::session_discarded`] is true. If a persistent
+    /// session is taken over within the reconnect grace (see
+    /// [`ClientsManager::set_reconnect_grace`]), the Last Will of the
+    /// previous connection is not returned.
+    ///
     /// Performs all the necessary checks to ensure that
     /// the session is valid
     #[instrument(skip(self, network_connection, connect) fields(socket_addr = %network_connection.id(), client_id = %connect.client_id()))]
@@ -321,29 +452,51 @@ where
     where
         S: Close,
     {
+        let id = match connect.client_id() {
+            "" => None,
+            id => Some(ClientId::new(id, &self.client_id_rules)?),
+        };
         self.check_credentials(&connect)?;
-
-        if connect.client_id().is_empty() {
-            self.process_client_empty_id(&mut connect)?;
+        let id = match id {
+            Some(id) => id,
+            None => self.process_client_empty_id(&mut connect)?,
+        };
+        if self.force_clean_session {
+            connect.set_clean_session();
         }
-        let id = connect.client_id().to_owned();
+        let clean_session = *connect.clean_session();
 
         let mut takeover_last_will = None;
         let session_present;
+        let session_discarded;
 
         // Hay una sesion_presente en el servidor con la misma ID
         if let Some(old_client) = self.clients.get(&id) {
             info!("Reconectando");
-            takeover_last_will = old_client.lock()?.reconnect(connect, network_connection)?;
-            session_present = true;
+            let mut old_client = old_client.lock()?;
+            // Si retoma su sesion persistente rapidamente, no se
+            // considera que la conexion anterior se haya caido
+            let within_grace = match (self.reconnect_grace, old_client.connected_for()) {
+                (Some(grace), Some(connected_for)) => !clean_session && connected_for <= grace,
+                _ => false,
+            };
+            let last_will = old_client.reconnect(connect, network_connection)?;
+            if !within_grace {
+                takeover_last_will = last_will;
+            }
+            // Si el cliente pidio clean session, la sesion anterior se descarta
+            session_present = !clean_session;
+            session_discarded = clean_session;
         } else {
-            let client = Client::new(connect, network_connection);
+            let client = Client::new(id.clone(), connect, network_connection);
             self.client_add(client);
             session_present = false;
+            session_discarded = false;
         }
         Ok(ConnectInfo {
             id,
             session_present,
+            session_discarded,
             takeover_last_will,
         })
     }
@@ -354,9 +507,14 @@ where
     {
         let mut clean_session_ids = vec![];
         let mut last_will_packets = vec![];
+        let mut disconnected_ids = vec![];
 
         for (id, client) in &self.clients {
-            if let Some(last_will) = client.lock()?.disconnect(gracefully)? {
+            let mut client = client.lock()?;
+            if client.connected() {
+                disconnected_ids.push(id.to_owned());
+            }
+            if let Some(last_will) = client.disconnect(gracefully)? {
                 last_will_packets.push((id.to_owned(), last_will));
             }
         }
@@ -375,6 +533,7 @@ where
         Ok(ShutdownInfo {
             clean_session_ids,
             last_will_packets,
+            disconnected_ids,
         })
     }
 }
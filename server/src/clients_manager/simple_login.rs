@@ -89,7 +89,13 @@ impl SimpleLogin {
             None => return Ok(LoginResult::UsernameNotFound),
         };
 
-        let mut reader = BufReader::new(File::open(path)?);
+        // Si no se puede abrir el archivo (por ejemplo, porque se esta
+        // reemplazando), el cliente puede volver a intentar mas tarde
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(_) => return Ok(LoginResult::TemporarilyUnavailable),
+        };
+        let mut reader = BufReader::new(file);
         let mut buf = String::new();
         while reader.read_line(&mut buf)? != 0 {
             let (found_user_name, found_password) =
@@ -122,7 +128,7 @@ mod tests {
     use crate::traits::{Login, LoginResult};
     use std::io::{self, BufRead, Cursor};
 
-    use crate::clients_manager::simple_login::SimpleLogin;
+    use crate::clients_manager::simple_login::{SimpleLogin, CACHE_SIZE};
 
     fn valid_accounts_file() -> impl BufRead {
         Cursor::new(
@@ -185,4 +191,17 @@ fdelu,fdelu",
 
         assert_eq!(error.kind(), io::ErrorKind::InvalidData);
     }
+
+    #[test]
+    fn test_missing_file_is_temporarily_unavailable() {
+        // Mas cuentas de las que entran en la cache, para que se busque en el archivo
+        let accounts: String = (0..=CACHE_SIZE)
+            .map(|i| format!("user{},password\n", i))
+            .collect();
+        let cursor = Cursor::new(accounts);
+        let mut login = SimpleLogin::new_from_stream(cursor, "tests/files/no_existe.csv").unwrap();
+
+        let result = login.login("NoCacheado", "password").unwrap();
+        assert_eq!(result, LoginResult::TemporarilyUnavailable);
+    }
 }
@@ -6,16 +6,18 @@ use packets::{
     publish::Publish,
     qos::QoSLevel,
     topic_filter::TopicFilter,
+    traits::MQTTEncoding,
 };
 
 use crate::{
-    clients_manager::{simple_login::SimpleLogin, ConnectInfo},
+    clients_manager::{simple_login::SimpleLogin, ClientTraffic, ConnectInfo},
     network_connection::NetworkConnection,
     server::{server_error::ServerErrorKind, ClientIdArg, ServerResult},
     test_helpers::iomock::IOMock,
 };
 
-use super::{ClientsManager, GENERIC_ID_SUFFIX};
+use super::ClientsManager;
+use crate::client_id::{ClientId, ClientIdRules, GENERATED_ID_PREFIX};
 
 fn make_manager_with_clients(
     ids: Vec<&ClientIdArg>,
@@ -58,8 +60,9 @@ fn test_new_session_with_id() {
     let connect_info = manager.new_session(network_connection, connect).unwrap();
 
     let expected = ConnectInfo {
-        id: String::from("client_id"),
+        id: ClientId::new("client_id", &ClientIdRules::default()).unwrap(),
         session_present: false,
+        session_discarded: false,
         takeover_last_will: None,
     };
 
@@ -143,6 +146,36 @@ fn test_invalid_username_should_fail() {
     );
 }
 
+#[test]
+fn test_require_credentials_without_login() {
+    let mut manager = ClientsManager::<IOMock, u16>::new(None);
+    manager.set_require_credentials(true);
+
+    let anonymous = ConnectBuilder::new("anonymous", 0, false)
+        .unwrap()
+        .build()
+        .unwrap();
+    let result = manager.new_session(NetworkConnection::new(0, IOMock::new()), anonymous);
+    assert_eq!(
+        result.unwrap_err().kind(),
+        ServerErrorKind::ConnectionRefused(ConnackReturnCode::NotAuthorized)
+    );
+
+    // Las credenciales no se validan, solo se exige que esten
+    let with_credentials = ConnectBuilder::new("client_id", 0, false)
+        .unwrap()
+        .with_user_name("cualquiera")
+        .unwrap()
+        .with_password("cualquiera")
+        .unwrap()
+        .build()
+        .unwrap();
+    manager
+        .new_session(NetworkConnection::new(1, IOMock::new()), with_credentials)
+        .unwrap();
+    assert!(manager.clients.contains_key("client_id"));
+}
+
 #[test]
 fn test_invalid_password_should_fail() {
     let iomock = IOMock::new();
@@ -206,7 +239,7 @@ fn test_ids_collision_should_fail() {
 
 #[test]
 fn test_connect_with_forbidden_id_should_fail() {
-    let result = make_manager_with_clients(vec![GENERIC_ID_SUFFIX], true, None);
+    let result = make_manager_with_clients(vec![GENERATED_ID_PREFIX], true, None);
     assert_eq!(
         result.unwrap_err().kind(),
         ServerErrorKind::ConnectionRefused(ConnackReturnCode::IdentifierRejected)
@@ -242,8 +275,9 @@ fn test_takeover() {
         .new_session(network_connection_2, connect_2)
         .unwrap();
     let expected = ConnectInfo {
-        id: String::from("client_id"),
+        id: ClientId::new("client_id", &ClientIdRules::default()).unwrap(),
         session_present: true,
+        session_discarded: false,
         takeover_last_will: None,
     };
     assert_eq!(connect_info, expected);
@@ -459,6 +493,106 @@ fn test_disconnect_persistent_session_should_be_remembered() {
     assert!(connect_info.session_present);
 }
 
+#[test]
+fn test_clean_session_over_persistent_session_should_discard_it() {
+    let iomock_1 = IOMock::new();
+    let iomock_2 = IOMock::new();
+
+    let connect_1 = ConnectBuilder::new("client_id", 0, false)
+        .unwrap()
+        .build()
+        .unwrap();
+    let connect_2 = ConnectBuilder::new("client_id", 0, true)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let network_connection_1 = NetworkConnection::new(0, iomock_1);
+    let network_connection_copy = network_connection_1.try_clone().unwrap();
+
+    let mut manager = ClientsManager::<IOMock, u16>::new(None);
+    manager
+        .new_session(network_connection_1, connect_1)
+        .unwrap();
+
+    manager
+        .disconnect("client_id", network_connection_copy, true)
+        .unwrap();
+
+    let network_connection_1 = NetworkConnection::new(0, iomock_2);
+    let connect_info = manager
+        .new_session(network_connection_1, connect_2)
+        .unwrap();
+
+    assert!(!connect_info.session_present);
+    assert!(connect_info.session_discarded);
+}
+
+#[test]
+fn test_clean_session_takeover_within_reconnect_grace_discards_session() {
+    let connect_1 = ConnectBuilder::new("client_id", 0, false)
+        .unwrap()
+        .build()
+        .unwrap();
+    let connect_2 = ConnectBuilder::new("client_id", 0, true)
+        .unwrap()
+        .build()
+        .unwrap();
+
+    let mut manager = ClientsManager::<IOMock, u16>::new(None);
+    manager.set_reconnect_grace(Some(Duration::from_secs(60)));
+    manager
+        .new_session(NetworkConnection::new(0, IOMock::new()), connect_1)
+        .unwrap();
+    manager
+        .client_do("client_id", |client| {
+            client.add_subscription("topic", None);
+            Ok(())
+        })
+        .unwrap();
+
+    let connect_info = manager
+        .new_session(NetworkConnection::new(1, IOMock::new()), connect_2)
+        .unwrap();
+
+    assert!(!connect_info.session_present);
+    assert!(connect_info.session_discarded);
+    // Descarta la suscripcion anterior, por lo que admite otra
+    let accepted = manager
+        .client_do("client_id", |client| {
+            Ok(client.add_subscription("other", Some(1)))
+        })
+        .unwrap();
+    assert!(accepted);
+}
+
+#[test]
+fn test_takeover_within_reconnect_grace_should_not_return_lastwill() {
+    let connect_1 = ConnectBuilder::new("client_id", 0, false)
+        .unwrap()
+        .with_last_will(LastWill::new(
+            TopicFilter::new("top", QoSLevel::QoSLevel0).unwrap(),
+            String::from("message"),
+            false,
+        ))
+        .build()
+        .unwrap();
+    let connect_2 = connect_1.clone();
+
+    let mut manager = ClientsManager::<IOMock, u16>::new(None);
+    manager.set_reconnect_grace(Some(Duration::from_secs(60)));
+    manager
+        .new_session(NetworkConnection::new(0, IOMock::new()), connect_1)
+        .unwrap();
+    let connect_info = manager
+        .new_session(NetworkConnection::new(1, IOMock::new()), connect_2)
+        .unwrap();
+
+    assert!(connect_info.session_present);
+    assert!(!connect_info.session_discarded);
+    assert!(connect_info.takeover_last_will.is_none());
+}
+
 #[test]
 fn test_disconnect_non_persistent_session_should_not_be_remembered() {
     let iomock_1 = IOMock::new();
@@ -490,3 +624,36 @@ fn test_disconnect_non_persistent_session_should_not_be_remembered() {
 
     assert!(!connect_info.session_present);
 }
+
+#[test]
+fn test_traffic_of_connected_clients() {
+    let mut manager = make_manager_with_clients(vec!["b", "a"], false, None).unwrap();
+    let publish =
+        Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "message", None).unwrap();
+    let publish_len = publish.encode().unwrap().len() as u64;
+    manager
+        .client_do("a", |client| client.send_packet(&publish))
+        .unwrap();
+
+    let traffic = manager.traffic().unwrap();
+    let expected = vec![
+        ClientTraffic {
+            id: ClientId::new("a", &ClientIdRules::default()).unwrap(),
+            bytes_read: 0,
+            bytes_written: publish_len,
+            slow_consumer: false,
+        },
+        ClientTraffic {
+            id: ClientId::new("b", &ClientIdRules::default()).unwrap(),
+            bytes_read: 0,
+            bytes_written: 0,
+            slow_consumer: false,
+        },
+    ];
+    assert_eq!(traffic, expected);
+
+    // Los clientes desconectados no se incluyen
+    let network_connection = NetworkConnection::new(0, IOMock::new());
+    manager.disconnect("b", network_connection, true).unwrap();
+    assert_eq!(manager.traffic().unwrap().len(), 1);
+}
@@ -1,39 +1,136 @@
 use std::{
-    collections::HashMap,
-    fs::File,
+    collections::{HashMap, HashSet},
+    convert::TryFrom,
+    env,
+    fs::{self, File},
     io::{BufRead, BufReader, Read},
+    path::{Path, PathBuf},
+    sync::Arc,
     time::Duration,
 };
 
+use packets::qos::QoSLevel;
 use tracing::Level;
 
 use crate::{
     clients_manager::simple_login::SimpleLogin,
-    traits::{Config, Login},
+    traits::{
+        Config, DumpConfig, DumpFormat, Login, LowercaseTopics, RetainedOverflow, TopicNormalizer,
+        DEFAULT_DELIVERY_CHANNEL_BLOCK, DEFAULT_MAX_CLIENT_ID_LENGTH,
+    },
 };
 
 /// Config struct contains information which is needed from a Server
 #[derive(Debug, Clone)]
 pub struct FileConfig {
     port: u16,
-    dump_info: Option<(String, Duration)>,
+    dump_config: Option<DumpConfig>,
     log_path: String,
     accounts_path: Option<String>,
     ip: String,
     log_file_level: Level,
     log_stdout_level: Level,
+    strict_topic_levels: bool,
+    topic_qos_ceiling: Vec<(String, QoSLevel)>,
+    strict_ordering_clients: Vec<String>,
+    max_subscriptions_per_client: Option<usize>,
+    delivery_write_timeout: Option<Duration>,
+    threadpool_size_bounds: Option<(usize, usize)>,
+    max_client_id_length: usize,
+    strict_client_ids: bool,
+    publish_lock_retries: Option<u32>,
+    presence_topic_prefix: Option<String>,
+    reconnect_grace: Option<Duration>,
+    max_global_publishes_per_second: Option<u32>,
+    retained_history_depth: Option<usize>,
+    early_puback: bool,
+    max_retained_messages: Option<usize>,
+    retained_overflow: RetainedOverflow,
+    deduplicate_deliveries: bool,
+    broker_id: Option<String>,
+    force_clean_session: bool,
+    reconcile_interval: Option<Duration>,
+    tcp_nodelay: bool,
+    tcp_keepalive: Option<Duration>,
+    pending_ack_timeout: Option<Duration>,
+    retained_refresh: bool,
+    delivery_channel_capacity: Option<usize>,
+    delivery_channel_block: Duration,
+    suppress_resubscribe_retained: bool,
+    drop_empty_publishes: bool,
+    max_concurrent_connects: Option<usize>,
+    inflight_window: Option<(usize, usize)>,
+    require_credentials: bool,
+    lowercase_topics: bool,
+    generic_client_max_lifetime: Option<Duration>,
+    max_incoming_message_size: Option<usize>,
+    max_outgoing_message_size: Option<usize>,
 }
 
 const PORT_KEY: &str = "port";
 const DUMP_PATH_KEY: &str = "dump_path";
 const DUMP_TIME_KEY: &str = "dump_time";
+const DUMP_FORMAT_KEY: &str = "dump_format";
+const DUMP_ATOMIC_KEY: &str = "dump_atomic";
 const LOG_PATH_KEY: &str = "log_path";
 const ACCOUNTS_PATH_KEY: &str = "accounts_path";
 const IP_KEY: &str = "ip";
 const LOG_FILE_LEVEL_KEY: &str = "log_file_level";
 const LOG_STDOUT_LEVEL_KEY: &str = "log_stdout_level";
+const STRICT_TOPIC_LEVELS_KEY: &str = "strict_topic_levels";
+const TOPIC_QOS_CEILING_KEY: &str = "topic_qos_ceiling";
+const STRICT_ORDERING_CLIENTS_KEY: &str = "strict_ordering_clients";
+const MAX_SUBSCRIPTIONS_PER_CLIENT_KEY: &str = "max_subscriptions_per_client";
+const DELIVERY_WRITE_TIMEOUT_KEY: &str = "delivery_write_timeout";
+const THREADPOOL_MIN_SIZE_KEY: &str = "threadpool_min_size";
+const THREADPOOL_MAX_SIZE_KEY: &str = "threadpool_max_size";
+const MAX_CLIENT_ID_LENGTH_KEY: &str = "max_client_id_length";
+const STRICT_CLIENT_IDS_KEY: &str = "strict_client_ids";
+const PUBLISH_LOCK_RETRIES_KEY: &str = "publish_lock_retries";
+const PRESENCE_TOPIC_PREFIX_KEY: &str = "presence_topic_prefix";
+const RECONNECT_GRACE_KEY: &str = "reconnect_grace";
+const MAX_GLOBAL_PUBLISHES_PER_SECOND_KEY: &str = "max_global_publishes_per_second";
+const RETAINED_HISTORY_DEPTH_KEY: &str = "retained_history_depth";
+const EARLY_PUBACK_KEY: &str = "early_puback";
+const MAX_RETAINED_MESSAGES_KEY: &str = "max_retained_messages";
+const RETAINED_OVERFLOW_KEY: &str = "retained_overflow";
+const DEDUPLICATE_DELIVERIES_KEY: &str = "deduplicate_deliveries";
+const BROKER_ID_KEY: &str = "broker_id";
+const FORCE_CLEAN_SESSION_KEY: &str = "force_clean_session";
+const RECONCILE_INTERVAL_KEY: &str = "reconcile_interval";
+const TCP_NODELAY_KEY: &str = "tcp_nodelay";
+const TCP_KEEPALIVE_KEY: &str = "tcp_keepalive";
+const PENDING_ACK_TIMEOUT_KEY: &str = "pending_ack_timeout";
+const RETAINED_REFRESH_KEY: &str = "retained_refresh";
+const DELIVERY_CHANNEL_CAPACITY_KEY: &str = "delivery_channel_capacity";
+const DELIVERY_CHANNEL_BLOCK_KEY: &str = "delivery_channel_block";
+const SUPPRESS_RESUBSCRIBE_RETAINED_KEY: &str = "suppress_resubscribe_retained";
+const DROP_EMPTY_PUBLISHES_KEY: &str = "drop_empty_publishes";
+const MAX_CONCURRENT_CONNECTS_KEY: &str = "max_concurrent_connects";
+const INFLIGHT_LOW_WATER_KEY: &str = "inflight_low_water";
+const INFLIGHT_HIGH_WATER_KEY: &str = "inflight_high_water";
+const REQUIRE_CREDENTIALS_KEY: &str = "require_credentials";
+const LOWERCASE_TOPICS_KEY: &str = "lowercase_topics";
+const GENERIC_CLIENT_MAX_LIFETIME_KEY: &str = "generic_client_max_lifetime";
+const MAX_INCOMING_MESSAGE_SIZE_KEY: &str = "max_incoming_message_size";
+const MAX_OUTGOING_MESSAGE_SIZE_KEY: &str = "max_outgoing_message_size";
 
+/// Key of the lines that include another configuration file
+const INCLUDE_KEY: &str = "include";
 const SEP: &str = "=";
+const PORT_ENV: &str = "MQTT_PORT";
+const IP_ENV: &str = "MQTT_IP";
+const LOG_PATH_ENV: &str = "MQTT_LOG_PATH";
+const ACCOUNTS_PATH_ENV: &str = "MQTT_ACCOUNTS_PATH";
+const LOG_LEVEL_ENV: &str = "MQTT_LOG_LEVEL";
+const LOG_FILE_LEVEL_ENV: &str = "MQTT_LOG_FILE_LEVEL";
+const LOG_STDOUT_LEVEL_ENV: &str = "MQTT_LOG_STDOUT_LEVEL";
+
+/// Separator between each entry of the topic_qos_ceiling and
+/// strict_ordering_clients values
+const ENTRY_SEP: char = ';';
+/// Separator between the topic filter and the QoS of a topic_qos_ceiling entry
+const CEILING_QOS_SEP: char = ':';
 
 impl FileConfig {
     /// Returns a Config struct based on the path file
@@ -43,49 +140,455 @@ impl FileConfig {
     /// Each line of the file must consist of `field=value`:
     /// port, dump_path, dump_time, log_path, ip
     ///
+    /// If dump_path is not empty, dump_format (`pretty` or
+    /// `compact`) and dump_atomic can be specified too
+    ///
+    /// Optionally, strict_topic_levels, topic_qos_ceiling
+    /// (as `filter:qos;filter:qos`), strict_ordering_clients
+    /// (as `id;id`), max_subscriptions_per_client,
+    /// delivery_write_timeout (in seconds), both
+    /// threadpool_min_size and threadpool_max_size,
+    /// max_client_id_length, strict_client_ids,
+    /// publish_lock_retries, presence_topic_prefix,
+    /// reconnect_grace (in seconds),
+    /// max_global_publishes_per_second,
+    /// retained_history_depth, early_puback,
+    /// max_retained_messages, deduplicate_deliveries,
+    /// broker_id, force_clean_session, reconcile_interval
+    /// (in seconds), tcp_nodelay, tcp_keepalive (in seconds),
+    /// pending_ack_timeout (in seconds), retained_refresh,
+    /// delivery_channel_capacity,
+    /// suppress_resubscribe_retained, drop_empty_publishes,
+    /// max_concurrent_connects, require_credentials,
+    /// lowercase_topics, generic_client_max_lifetime (in
+    /// seconds), max_incoming_message_size,
+    /// max_outgoing_message_size (in bytes) and both
+    /// inflight_low_water and inflight_high_water can also
+    /// be specified
+    ///
+    /// If delivery_channel_capacity is specified,
+    /// delivery_channel_block (in milliseconds) can be
+    /// specified too
+    ///
+    /// If max_retained_messages is specified, retained_overflow
+    /// (`evict` or `reject`) can be specified too
+    ///
+    /// A line `include=<path>` reads the fields of another file (whose
+    /// path is relative to the file that includes it) as if they were
+    /// written in its place, so that they override the ones above it
+    /// and are overridden by the ones below it
+    ///
     /// # Errors
-    /// If the file following the path does not have the correct format, this function returns None
+    /// If the file following the path does not have the correct format,
+    /// or the included files include each other, this function returns None
     pub fn new(path: &str) -> Option<FileConfig> {
-        let config_file = File::open(path).ok()?;
-        FileConfig::new_from_file(config_file)
+        let path = Path::new(path);
+        let mut including = HashSet::new();
+        let config = FileConfig::read_entries(path, &mut including)?;
+        FileConfig::from_entries(config)
     }
 
-    /// Returns a Config struct from a valid configuration file
+    /// Returns a Config struct from a valid configuration file. The
+    /// paths of the included files are relative to the current directory
     ///
     /// If config_file path does not have the correct format, this function returns None
+    #[cfg(test)]
     pub fn new_from_file(config_file: impl Read) -> Option<FileConfig> {
-        let lines: Vec<String> = BufReader::new(config_file)
-            .lines()
-            .collect::<Result<Vec<_>, _>>()
-            .ok()?;
-        let mut config: HashMap<String, String> = lines
-            .iter()
-            .map(|line| {
-                let (key, value) = line.trim().split_once(SEP)?;
-                Some((key.to_string(), value.to_string()))
-            })
-            .collect::<Option<HashMap<_, _>>>()?;
+        let mut including = HashSet::new();
+        let config = FileConfig::parse_entries(config_file, Path::new(""), &mut including)?;
+        FileConfig::from_entries(config)
+    }
+
+    #[doc(hidden)]
+    /// Reads the fields of the file in `path`, along with the ones of
+    /// the files it includes. `including` has the files that are being
+    /// read, to detect the ones that include each other
+    fn read_entries(
+        path: &Path,
+        including: &mut HashSet<PathBuf>,
+    ) -> Option<HashMap<String, String>> {
+        let canonical = fs::canonicalize(path).ok()?;
+        if !including.insert(canonical.clone()) {
+            return None;
+        }
+        let base_dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let config = FileConfig::parse_entries(File::open(path).ok()?, base_dir, including);
+        including.remove(&canonical);
+        config
+    }
 
-        let dump_info;
+    #[doc(hidden)]
+    /// Parses the fields of `config_file`, resolving its includes
+    /// relative to `base_dir`
+    fn parse_entries(
+        config_file: impl Read,
+        base_dir: &Path,
+        including: &mut HashSet<PathBuf>,
+    ) -> Option<HashMap<String, String>> {
+        let mut config = HashMap::new();
+        for line in BufReader::new(config_file).lines() {
+            let line = line.ok()?;
+            let (key, value) = line.trim().split_once(SEP)?;
+            if key == INCLUDE_KEY {
+                config.extend(FileConfig::read_entries(&base_dir.join(value), including)?);
+            } else {
+                config.insert(key.to_string(), value.to_string());
+            }
+        }
+        Some(config)
+    }
+
+    #[doc(hidden)]
+    /// Returns a Config struct from the fields of the configuration files
+    fn from_entries(mut config: HashMap<String, String>) -> Option<FileConfig> {
+        let dump_config;
         let dump_path = config.remove(DUMP_PATH_KEY)?;
         if !dump_path.is_empty() {
             let dump_time = Duration::from_secs(config.remove(DUMP_TIME_KEY)?.parse().ok()?);
-            dump_info = Some((dump_path, dump_time));
+            let mut dump = DumpConfig::new(dump_path, dump_time);
+            if let Some(format) = config.remove(DUMP_FORMAT_KEY) {
+                dump.format = FileConfig::parse_dump_format(&format)?;
+            }
+            if let Some(atomic) = config.remove(DUMP_ATOMIC_KEY) {
+                dump.atomic = atomic.parse().ok()?;
+            }
+            dump_config = Some(dump);
         } else {
-            dump_info = None;
+            dump_config = None;
         }
 
+        let strict_topic_levels = match config.remove(STRICT_TOPIC_LEVELS_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+
+        let topic_qos_ceiling = match config.remove(TOPIC_QOS_CEILING_KEY) {
+            Some(value) => FileConfig::parse_topic_qos_ceiling(&value)?,
+            None => Vec::new(),
+        };
+
+        let strict_ordering_clients = config
+            .remove(STRICT_ORDERING_CLIENTS_KEY)
+            .map(|value| {
+                value
+                    .split(ENTRY_SEP)
+                    .filter(|id| !id.is_empty())
+                    .map(str::to_string)
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let max_subscriptions_per_client = match config.remove(MAX_SUBSCRIPTIONS_PER_CLIENT_KEY) {
+            Some(value) => Some(value.parse().ok()?),
+            None => None,
+        };
+
+        let delivery_write_timeout = match config.remove(DELIVERY_WRITE_TIMEOUT_KEY) {
+            Some(value) => Some(Duration::from_secs(value.parse().ok()?)),
+            None => None,
+        };
+
+        let threadpool_size_bounds = match (
+            config.remove(THREADPOOL_MIN_SIZE_KEY),
+            config.remove(THREADPOOL_MAX_SIZE_KEY),
+        ) {
+            (Some(min), Some(max)) => {
+                let (min, max) = (min.parse().ok()?, max.parse().ok()?);
+                if min == 0 || min > max {
+                    return None;
+                }
+                Some((min, max))
+            }
+            (None, None) => None,
+            _ => return None,
+        };
+
+        let max_client_id_length = match config.remove(MAX_CLIENT_ID_LENGTH_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => DEFAULT_MAX_CLIENT_ID_LENGTH,
+        };
+
+        let strict_client_ids = match config.remove(STRICT_CLIENT_IDS_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+
+        let publish_lock_retries = match config.remove(PUBLISH_LOCK_RETRIES_KEY) {
+            Some(value) => Some(value.parse().ok()?),
+            None => None,
+        };
+
+        let presence_topic_prefix = config
+            .remove(PRESENCE_TOPIC_PREFIX_KEY)
+            .filter(|prefix| !prefix.is_empty());
+
+        let reconnect_grace = match config.remove(RECONNECT_GRACE_KEY) {
+            Some(value) => Some(Duration::from_secs(value.parse().ok()?)),
+            None => None,
+        };
+
+        let max_global_publishes_per_second =
+            match config.remove(MAX_GLOBAL_PUBLISHES_PER_SECOND_KEY) {
+                Some(value) => match value.parse().ok()? {
+                    0 => return None,
+                    rate => Some(rate),
+                },
+                None => None,
+            };
+
+        let retained_history_depth = match config.remove(RETAINED_HISTORY_DEPTH_KEY) {
+            Some(value) => Some(value.parse().ok()?),
+            None => None,
+        };
+
+        let early_puback = match config.remove(EARLY_PUBACK_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+
+        let max_retained_messages = match config.remove(MAX_RETAINED_MESSAGES_KEY) {
+            Some(value) => match value.parse().ok()? {
+                0 => return None,
+                max => Some(max),
+            },
+            None => None,
+        };
+        let retained_overflow = match config.remove(RETAINED_OVERFLOW_KEY) {
+            Some(value) => FileConfig::parse_retained_overflow(&value)?,
+            None => RetainedOverflow::default(),
+        };
+
+        let deduplicate_deliveries = match config.remove(DEDUPLICATE_DELIVERIES_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+        let force_clean_session = match config.remove(FORCE_CLEAN_SESSION_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+        let reconcile_interval = match config.remove(RECONCILE_INTERVAL_KEY) {
+            Some(value) => match value.parse().ok()? {
+                0 => return None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            None => None,
+        };
+        let tcp_nodelay = match config.remove(TCP_NODELAY_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => true,
+        };
+        let tcp_keepalive = match config.remove(TCP_KEEPALIVE_KEY) {
+            Some(value) => match value.parse().ok()? {
+                0 => return None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            None => None,
+        };
+        let pending_ack_timeout = match config.remove(PENDING_ACK_TIMEOUT_KEY) {
+            Some(value) => match value.parse().ok()? {
+                0 => return None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            None => None,
+        };
+        let retained_refresh = match config.remove(RETAINED_REFRESH_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+        let delivery_channel_capacity = match config.remove(DELIVERY_CHANNEL_CAPACITY_KEY) {
+            Some(value) => match value.parse().ok()? {
+                0 => return None,
+                capacity => Some(capacity),
+            },
+            None => None,
+        };
+        let delivery_channel_block = match config.remove(DELIVERY_CHANNEL_BLOCK_KEY) {
+            Some(value) => Duration::from_millis(value.parse().ok()?),
+            None => DEFAULT_DELIVERY_CHANNEL_BLOCK,
+        };
+        let suppress_resubscribe_retained = match config.remove(SUPPRESS_RESUBSCRIBE_RETAINED_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+        let drop_empty_publishes = match config.remove(DROP_EMPTY_PUBLISHES_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+        let max_concurrent_connects = match config.remove(MAX_CONCURRENT_CONNECTS_KEY) {
+            Some(value) => match value.parse().ok()? {
+                0 => return None,
+                max => Some(max),
+            },
+            None => None,
+        };
+        let inflight_window = match (
+            config.remove(INFLIGHT_LOW_WATER_KEY),
+            config.remove(INFLIGHT_HIGH_WATER_KEY),
+        ) {
+            (Some(low), Some(high)) => {
+                let (low, high) = (low.parse().ok()?, high.parse().ok()?);
+                if low == 0 || low > high {
+                    return None;
+                }
+                Some((low, high))
+            }
+            (None, None) => None,
+            _ => return None,
+        };
+        let require_credentials = match config.remove(REQUIRE_CREDENTIALS_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+        let lowercase_topics = match config.remove(LOWERCASE_TOPICS_KEY) {
+            Some(value) => value.parse().ok()?,
+            None => false,
+        };
+        let generic_client_max_lifetime = match config.remove(GENERIC_CLIENT_MAX_LIFETIME_KEY) {
+            Some(value) => match value.parse().ok()? {
+                0 => return None,
+                secs => Some(Duration::from_secs(secs)),
+            },
+            None => None,
+        };
+        let max_incoming_message_size = match config.remove(MAX_INCOMING_MESSAGE_SIZE_KEY) {
+            Some(value) => match value.parse().ok()? {
+                0 => return None,
+                max => Some(max),
+            },
+            None => None,
+        };
+        let max_outgoing_message_size = match config.remove(MAX_OUTGOING_MESSAGE_SIZE_KEY) {
+            Some(value) => match value.parse().ok()? {
+                0 => return None,
+                max => Some(max),
+            },
+            None => None,
+        };
+
         Some(FileConfig {
             port: config.remove(PORT_KEY)?.parse().ok()?,
-            dump_info,
+            dump_config,
             log_path: config.remove(LOG_PATH_KEY)?,
             accounts_path: config.remove(ACCOUNTS_PATH_KEY),
             ip: config.remove(IP_KEY)?,
             log_file_level: config.remove(LOG_FILE_LEVEL_KEY)?.parse().ok()?,
             log_stdout_level: config.remove(LOG_STDOUT_LEVEL_KEY)?.parse().ok()?,
+            strict_topic_levels,
+            topic_qos_ceiling,
+            strict_ordering_clients,
+            max_subscriptions_per_client,
+            delivery_write_timeout,
+            threadpool_size_bounds,
+            max_client_id_length,
+            strict_client_ids,
+            publish_lock_retries,
+            presence_topic_prefix,
+            reconnect_grace,
+            max_global_publishes_per_second,
+            retained_history_depth,
+            early_puback,
+            max_retained_messages,
+            retained_overflow,
+            deduplicate_deliveries,
+            broker_id: config
+                .remove(BROKER_ID_KEY)
+                .filter(|broker_id| !broker_id.is_empty()),
+            force_clean_session,
+            reconcile_interval,
+            tcp_nodelay,
+            tcp_keepalive,
+            pending_ack_timeout,
+            retained_refresh,
+            delivery_channel_capacity,
+            delivery_channel_block,
+            suppress_resubscribe_retained,
+            drop_empty_publishes,
+            max_concurrent_connects,
+            inflight_window,
+            require_credentials,
+            lowercase_topics,
+            generic_client_max_lifetime,
+            max_incoming_message_size,
+            max_outgoing_message_size,
         })
     }
 
+    /// Overrides the values of the configuration with the ones of the
+    /// environment variables that are set, so that it can be changed
+    /// without modifying the file (env > file > default):
+    /// MQTT_PORT, MQTT_IP, MQTT_LOG_PATH, MQTT_ACCOUNTS_PATH,
+    /// MQTT_LOG_FILE_LEVEL and MQTT_LOG_STDOUT_LEVEL. MQTT_LOG_LEVEL
+    /// sets both log levels, unless the specific one is also set
+    ///
+    /// # Errors
+    /// If one of the variables does not have the correct format, this function returns None
+    pub fn with_env_overlay(self) -> Option<FileConfig> {
+        self.with_overlay(|key| env::var(key).ok())
+    }
+
+    #[doc(hidden)]
+    /// Overrides the values of the configuration with the ones
+    /// returned by `var` for each environment variable
+    fn with_overlay(mut self, var: impl Fn(&str) -> Option<String>) -> Option<FileConfig> {
+        if let Some(port) = var(PORT_ENV) {
+            self.port = port.parse().ok()?;
+        }
+        if let Some(ip) = var(IP_ENV) {
+            self.ip = ip;
+        }
+        if let Some(log_path) = var(LOG_PATH_ENV) {
+            self.log_path = log_path;
+        }
+        if let Some(accounts_path) = var(ACCOUNTS_PATH_ENV) {
+            self.accounts_path = Some(accounts_path);
+        }
+        if let Some(level) = var(LOG_LEVEL_ENV) {
+            self.log_file_level = level.parse().ok()?;
+            self.log_stdout_level = self.log_file_level;
+        }
+        if let Some(level) = var(LOG_FILE_LEVEL_ENV) {
+            self.log_file_level = level.parse().ok()?;
+        }
+        if let Some(level) = var(LOG_STDOUT_LEVEL_ENV) {
+            self.log_stdout_level = level.parse().ok()?;
+        }
+        Some(self)
+    }
+
+    #[doc(hidden)]
+    /// Parses a dump format, either `pretty` or `compact`
+    fn parse_dump_format(value: &str) -> Option<DumpFormat> {
+        match value {
+            "pretty" => Some(DumpFormat::Pretty),
+            "compact" => Some(DumpFormat::Compact),
+            _ => None,
+        }
+    }
+
+    #[doc(hidden)]
+    /// Parses a retained overflow, either `evict` or `reject`
+    fn parse_retained_overflow(value: &str) -> Option<RetainedOverflow> {
+        match value {
+            "evict" => Some(RetainedOverflow::EvictOldest),
+            "reject" => Some(RetainedOverflow::Reject),
+            _ => None,
+        }
+    }
+
+    #[doc(hidden)]
+    /// Parses a list of `filter:qos` entries separated by `;`
+    fn parse_topic_qos_ceiling(value: &str) -> Option<Vec<(String, QoSLevel)>> {
+        value
+            .split(ENTRY_SEP)
+            .filter(|entry| !entry.is_empty())
+            .map(|entry| {
+                let (filter, qos) = entry.rsplit_once(CEILING_QOS_SEP)?;
+                let qos = QoSLevel::try_from(qos.parse::<u8>().ok()?).ok()?;
+                Some((filter.to_string(), qos))
+            })
+            .collect()
+    }
+
     /// Returns the file log level
     pub fn log_file_level(&self) -> Level {
         self.log_file_level
@@ -102,10 +605,8 @@ impl Config for FileConfig {
         self.port
     }
 
-    fn dump_info(&self) -> Option<(&str, Duration)> {
-        self.dump_info
-            .as_ref()
-            .map(|dump_info| (dump_info.0.as_str(), dump_info.1))
+    fn dump_config(&self) -> Option<DumpConfig> {
+        self.dump_config.clone()
     }
 
     fn log_path(&self) -> &str {
@@ -120,16 +621,163 @@ impl Config for FileConfig {
         let login = SimpleLogin::new(self.accounts_path.as_ref()?).ok()?;
         Some(Box::new(login))
     }
+
+    fn strict_topic_levels(&self) -> bool {
+        self.strict_topic_levels
+    }
+
+    fn topic_qos_ceiling(&self) -> &[(String, QoSLevel)] {
+        &self.topic_qos_ceiling
+    }
+
+    fn strict_ordering(&self, client_id: &str) -> bool {
+        self.strict_ordering_clients
+            .iter()
+            .any(|id| id.as_str() == client_id)
+    }
+
+    fn max_subscriptions_per_client(&self) -> Option<usize> {
+        self.max_subscriptions_per_client
+    }
+
+    fn delivery_write_timeout(&self) -> Option<Duration> {
+        self.delivery_write_timeout
+    }
+
+    fn threadpool_size_bounds(&self) -> Option<(usize, usize)> {
+        self.threadpool_size_bounds
+    }
+
+    fn max_client_id_length(&self) -> usize {
+        self.max_client_id_length
+    }
+
+    fn strict_client_ids(&self) -> bool {
+        self.strict_client_ids
+    }
+
+    fn publish_lock_retries(&self) -> Option<u32> {
+        self.publish_lock_retries
+    }
+
+    fn presence_topic_prefix(&self) -> Option<&str> {
+        self.presence_topic_prefix.as_deref()
+    }
+
+    fn reconnect_grace(&self) -> Option<Duration> {
+        self.reconnect_grace
+    }
+
+    fn max_global_publishes_per_second(&self) -> Option<u32> {
+        self.max_global_publishes_per_second
+    }
+
+    fn retained_history_depth(&self) -> Option<usize> {
+        self.retained_history_depth
+    }
+
+    fn early_puback(&self) -> bool {
+        self.early_puback
+    }
+
+    fn max_retained_messages(&self) -> Option<usize> {
+        self.max_retained_messages
+    }
+
+    fn retained_overflow(&self) -> RetainedOverflow {
+        self.retained_overflow
+    }
+
+    fn deduplicate_deliveries(&self) -> bool {
+        self.deduplicate_deliveries
+    }
+
+    fn broker_id(&self) -> Option<&str> {
+        self.broker_id.as_deref()
+    }
+
+    fn force_clean_session(&self) -> bool {
+        self.force_clean_session
+    }
+
+    fn reconcile_interval(&self) -> Option<Duration> {
+        self.reconcile_interval
+    }
+
+    fn tcp_nodelay(&self) -> bool {
+        self.tcp_nodelay
+    }
+
+    fn tcp_keepalive(&self) -> Option<Duration> {
+        self.tcp_keepalive
+    }
+
+    fn pending_ack_timeout(&self) -> Option<Duration> {
+        self.pending_ack_timeout
+    }
+
+    fn retained_refresh(&self) -> bool {
+        self.retained_refresh
+    }
+
+    fn delivery_channel_capacity(&self) -> Option<usize> {
+        self.delivery_channel_capacity
+    }
+
+    fn delivery_channel_block(&self) -> Duration {
+        self.delivery_channel_block
+    }
+
+    fn suppress_resubscribe_retained(&self) -> bool {
+        self.suppress_resubscribe_retained
+    }
+
+    fn drop_empty_publishes(&self) -> bool {
+        self.drop_empty_publishes
+    }
+
+    fn max_concurrent_connects(&self) -> Option<usize> {
+        self.max_concurrent_connects
+    }
+
+    fn inflight_window(&self) -> Option<(usize, usize)> {
+        self.inflight_window
+    }
+
+    fn require_credentials(&self) -> bool {
+        self.require_credentials
+    }
+
+    fn topic_normalizer(&self) -> Option<Arc<dyn TopicNormalizer>> {
+        if self.lowercase_topics {
+            Some(Arc::new(LowercaseTopics))
+        } else {
+            None
+        }
+    }
+
+    fn generic_client_max_lifetime(&self) -> Option<Duration> {
+        self.generic_client_max_lifetime
+    }
+
+    fn max_incoming_message_size(&self) -> Option<usize> {
+        self.max_incoming_message_size
+    }
+
+    fn max_outgoing_message_size(&self) -> Option<usize> {
+        self.max_outgoing_message_size
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::{io::Cursor, time::Duration};
+    use std::{collections::HashMap, env, io::Cursor, path::PathBuf, time::Duration};
 
+    use packets::qos::QoSLevel;
     use tracing::Level;
 
     use crate::config::FileConfig;
-    use crate::traits::Config;
+    use crate::traits::{Config, DumpFormat, RetainedOverflow};
 
     #[test]
     fn test_valid_file() {
@@ -146,13 +794,17 @@ log_stdout_level=info",
 
         let config = FileConfig::new_from_file(cursor).unwrap();
         assert_eq!(config.port(), 8080);
-        assert_eq!(config.dump_info().unwrap().0, "foo.txt");
-        assert_eq!(config.dump_info().unwrap().1, Duration::from_secs(10));
+        assert_eq!(config.dump_config().unwrap().path, PathBuf::from("foo.txt"));
+        assert_eq!(
+            config.dump_config().unwrap().interval,
+            Duration::from_secs(10)
+        );
         assert_eq!(config.log_path(), "bar.txt");
         assert!(config.authenticator().is_none());
         assert_eq!(config.ip(), "localhost");
         assert_eq!(config.log_file_level(), Level::ERROR);
         assert_eq!(config.log_stdout_level(), Level::INFO);
+        assert!(!config.strict_topic_levels());
     }
 
     #[test]
@@ -170,8 +822,11 @@ accounts_path=
 
         let config = FileConfig::new_from_file(cursor).unwrap();
         assert_eq!(config.port(), 8080);
-        assert_eq!(config.dump_info().unwrap().0, "foo.txt");
-        assert_eq!(config.dump_info().unwrap().1, Duration::from_secs(10));
+        assert_eq!(config.dump_config().unwrap().path, PathBuf::from("foo.txt"));
+        assert_eq!(
+            config.dump_config().unwrap().interval,
+            Duration::from_secs(10)
+        );
         assert_eq!(config.log_path(), "bar.txt");
         assert!(config.authenticator().is_none());
         assert_eq!(config.ip(), "localhost");
@@ -227,9 +882,843 @@ log_stdout_level=trace",
         let config = FileConfig::new_from_file(cursor).unwrap();
 
         assert_eq!(config.port(), 8080);
-        assert!(config.dump_info().is_none());
+        assert!(config.dump_config().is_none());
         assert_eq!(config.log_path(), "bar.txt");
         assert!(config.authenticator().is_none());
         assert_eq!(config.ip(), "localhost");
     }
+
+    #[test]
+    fn test_dump_format_and_atomic() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=foo.json
+dump_time=10
+dump_format=compact
+dump_atomic=true
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace",
+        );
+
+        let dump_config = FileConfig::new_from_file(cursor)
+            .unwrap()
+            .dump_config()
+            .unwrap();
+
+        assert_eq!(dump_config.path, PathBuf::from("foo.json"));
+        assert_eq!(dump_config.format, DumpFormat::Compact);
+        assert!(dump_config.atomic);
+    }
+
+    #[test]
+    fn test_invalid_dump_format() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=foo.json
+dump_time=10
+dump_format=xml
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_none());
+    }
+
+    #[test]
+    fn test_strict_topic_levels() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+strict_topic_levels=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(config.strict_topic_levels());
+    }
+
+    #[test]
+    fn test_topic_qos_ceiling() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+topic_qos_ceiling=sensors/#:0;alerts/+:1",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(
+            config.topic_qos_ceiling(),
+            &[
+                ("sensors/#".to_string(), QoSLevel::QoSLevel0),
+                ("alerts/+".to_string(), QoSLevel::QoSLevel1)
+            ]
+        );
+    }
+
+    #[test]
+    fn test_strict_ordering_clients() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+strict_ordering_clients=id1;id2",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(config.strict_ordering("id1"));
+        assert!(config.strict_ordering("id2"));
+        assert!(!config.strict_ordering("id3"));
+    }
+
+    #[test]
+    fn test_max_subscriptions_per_client() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+max_subscriptions_per_client=10",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.max_subscriptions_per_client(), Some(10));
+    }
+
+    #[test]
+    fn test_delivery_write_timeout() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+delivery_write_timeout=3",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(
+            config.delivery_write_timeout(),
+            Some(Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn test_threadpool_size_bounds() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+threadpool_min_size=2
+threadpool_max_size=16",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.threadpool_size_bounds(), Some((2, 16)));
+    }
+
+    #[test]
+    fn test_threadpool_size_bounds_without_max_is_invalid() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+threadpool_min_size=2",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_none());
+    }
+
+    #[test]
+    fn test_client_id_rules() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+max_client_id_length=23
+strict_client_ids=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.max_client_id_length(), 23);
+        assert!(config.strict_client_ids());
+    }
+
+    #[test]
+    fn test_publish_lock_retries() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+publish_lock_retries=100",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.publish_lock_retries(), Some(100));
+    }
+
+    #[test]
+    fn test_presence_topic_prefix() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+presence_topic_prefix=clients",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.presence_topic_prefix(), Some("clients"));
+    }
+
+    #[test]
+    fn test_reconnect_grace() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+reconnect_grace=5",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.reconnect_grace(), Some(Duration::from_secs(5)));
+    }
+
+    #[test]
+    fn test_max_global_publishes_per_second() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+max_global_publishes_per_second=100",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.max_global_publishes_per_second(), Some(100));
+    }
+
+    #[test]
+    fn test_zero_max_global_publishes_per_second_is_invalid() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+max_global_publishes_per_second=0",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_none());
+    }
+
+    #[test]
+    fn test_retained_history_depth() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+retained_history_depth=3",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.retained_history_depth(), Some(3));
+    }
+
+    #[test]
+    fn test_early_puback() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+early_puback=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(config.early_puback());
+    }
+
+    #[test]
+    fn test_max_retained_messages() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+max_retained_messages=10
+retained_overflow=reject",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.max_retained_messages(), Some(10));
+        assert_eq!(config.retained_overflow(), RetainedOverflow::Reject);
+    }
+
+    #[test]
+    fn test_retained_overflow_defaults_to_evict() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+max_retained_messages=10",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.retained_overflow(), RetainedOverflow::EvictOldest);
+    }
+
+    #[test]
+    fn test_deduplicate_deliveries() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+deduplicate_deliveries=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(config.deduplicate_deliveries());
+    }
+
+    #[test]
+    fn test_env_overlay_overrides_file() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace",
+        );
+        env::set_var("MQTT_PORT", "1883");
+
+        let config = FileConfig::new_from_file(cursor)
+            .unwrap()
+            .with_env_overlay()
+            .unwrap();
+        env::remove_var("MQTT_PORT");
+
+        assert_eq!(config.port(), 1883);
+        assert_eq!(config.ip(), "localhost");
+    }
+
+    #[test]
+    fn test_overlay_log_levels() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace",
+        );
+        let vars: HashMap<&str, &str> = vec![
+            ("MQTT_IP", "0.0.0.0"),
+            ("MQTT_LOG_LEVEL", "info"),
+            ("MQTT_LOG_STDOUT_LEVEL", "debug"),
+        ]
+        .into_iter()
+        .collect();
+
+        let config = FileConfig::new_from_file(cursor)
+            .unwrap()
+            .with_overlay(|key| vars.get(key).map(|value| value.to_string()))
+            .unwrap();
+
+        assert_eq!(config.ip(), "0.0.0.0");
+        assert_eq!(config.port(), 8080);
+        assert_eq!(config.log_file_level(), Level::INFO);
+        assert_eq!(config.log_stdout_level(), Level::DEBUG);
+    }
+
+    #[test]
+    fn test_invalid_overlay_value() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace",
+        );
+
+        let config = FileConfig::new_from_file(cursor)
+            .unwrap()
+            .with_overlay(|key| (key == "MQTT_PORT").then(|| "puerto".to_string()));
+
+        assert!(config.is_none());
+    }
+
+    #[test]
+    fn test_broker_id() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+broker_id=norte",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.broker_id(), Some("norte"));
+    }
+
+    #[test]
+    fn test_force_clean_session() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+force_clean_session=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(config.force_clean_session());
+    }
+
+    #[test]
+    fn test_reconcile_interval() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+reconcile_interval=60",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.reconcile_interval(), Some(Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn test_reconcile_interval_zero_is_invalid() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+reconcile_interval=0",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_none());
+    }
+
+    #[test]
+    fn test_tcp_options() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+tcp_nodelay=false
+tcp_keepalive=30",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(!config.tcp_nodelay());
+        assert_eq!(config.tcp_keepalive(), Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_pending_ack_timeout() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+pending_ack_timeout=20",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.pending_ack_timeout(), Some(Duration::from_secs(20)));
+    }
+
+    #[test]
+    fn test_retained_refresh() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+retained_refresh=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(config.retained_refresh());
+    }
+
+    #[test]
+    fn test_delivery_channel() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+delivery_channel_capacity=64
+delivery_channel_block=250",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.delivery_channel_capacity(), Some(64));
+        assert_eq!(config.delivery_channel_block(), Duration::from_millis(250));
+    }
+
+    #[test]
+    fn test_suppress_resubscribe_retained() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+suppress_resubscribe_retained=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(config.suppress_resubscribe_retained());
+    }
+
+    #[test]
+    fn test_drop_empty_publishes() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+drop_empty_publishes=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(config.drop_empty_publishes());
+    }
+
+    #[test]
+    fn test_max_concurrent_connects() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+max_concurrent_connects=16",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.max_concurrent_connects(), Some(16));
+    }
+
+    #[test]
+    fn test_max_concurrent_connects_zero_is_invalid() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+max_concurrent_connects=0",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_none());
+    }
+
+    #[test]
+    fn test_inflight_window() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+inflight_low_water=4
+inflight_high_water=16",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.inflight_window(), Some((4, 16)));
+    }
+
+    #[test]
+    fn test_inflight_window_low_over_high_is_invalid() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+inflight_low_water=16
+inflight_high_water=4",
+        );
+
+        assert!(FileConfig::new_from_file(cursor).is_none());
+    }
+
+    #[test]
+    fn test_require_credentials() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+require_credentials=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert!(config.require_credentials());
+    }
+
+    #[test]
+    fn test_lowercase_topics() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+lowercase_topics=true",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        let normalizer = config.topic_normalizer().unwrap();
+        assert_eq!(normalizer.normalize("Sensors/Temp/#"), "sensors/temp/#");
+    }
+
+    #[test]
+    fn test_generic_client_max_lifetime() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+generic_client_max_lifetime=60",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(
+            config.generic_client_max_lifetime(),
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn test_include_overrides_previous_keys() {
+        let config = FileConfig::new("tests/files/config/base.txt").unwrap();
+
+        assert_eq!(config.ip(), "0.0.0.0");
+        assert_eq!(config.max_concurrent_connects(), Some(8));
+        // Las lineas debajo del include lo sobreescriben
+        assert_eq!(config.log_stdout_level(), Level::TRACE);
+    }
+
+    #[test]
+    fn test_cyclic_include_is_invalid() {
+        assert!(FileConfig::new("tests/files/config/cycle_a.txt").is_none());
+    }
+
+    #[test]
+    fn test_max_message_sizes() {
+        let cursor = Cursor::new(
+            "port=8080
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=trace
+max_incoming_message_size=1024
+max_outgoing_message_size=512",
+        );
+
+        let config = FileConfig::new_from_file(cursor).unwrap();
+
+        assert_eq!(config.max_incoming_message_size(), Some(1024));
+        assert_eq!(config.max_outgoing_message_size(), Some(512));
+    }
 }
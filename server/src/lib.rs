@@ -1,13 +1,32 @@
-use std::io::Read;
+use std::{
+    io::{self, Read},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
 
-use tracing::info;
+use signal_hook::consts::{SIGINT, SIGTERM};
 
+use tracing::{error, info};
+
+pub use crate::client_id::ClientId;
+pub use crate::clients_manager::ClientTraffic;
 use crate::config::FileConfig;
-use logger::Logger;
-pub use crate::server::{Server, ServerController};
+pub use crate::server::{
+    LocalSubscriber, Server, ServerController, ServerError, ServerEvent, ServerEventListener,
+    ServerResult, SERVER_SOURCE_ID,
+};
+pub use crate::topic_handler::{
+    Origin, SubscriptionEvent, SubscriptionListener, TopicTreeSnapshot,
+};
 pub use crate::traits::Config;
+use logger::Logger;
 
 mod client;
+mod client_id;
 mod clients_manager;
 mod config;
 mod network_connection;
@@ -16,19 +35,29 @@ mod test_helpers;
 mod topic_handler;
 pub mod traits;
 
-/// Initializes the server
+/// Number of threads of the server ThreadPool
+const THREADPOOL_SIZE: usize = 8;
+/// How often [`run_until_signal`] checks if a signal was received
+const SIGNAL_CHECK_FREQ: Duration = Duration::from_millis(200);
+
+/// Initializes the server, and runs it until a line is read from
+/// the standard input. The configuration file can be overridden with
+/// environment variables (see [`FileConfig::with_env_overlay`])
 pub fn init(config_path: &str) {
-    let config = FileConfig::new(config_path).expect("Error cargando la configuracion");
+    let config = FileConfig::new(config_path)
+        .and_then(FileConfig::with_env_overlay)
+        .expect("Error cargando la configuracion");
 
-    let _logger = Logger::new(
+    let logger = Logger::new(
         config.log_path(),
         config.log_file_level(),
         config.log_stdout_level(),
-    );
+    )
+    .expect("Error inicializando el logger");
 
-    let threadpool_size = 8;
-    let server = Server::new(config, threadpool_size).expect("Error iniciando el servidor");
+    let server = Server::new(config, THREADPOOL_SIZE).expect("Error iniciando el servidor");
     let controller = server
+        .clone()
         .run()
         .expect("Error iniciando ejecución del servidor");
 
@@ -36,5 +65,92 @@ pub fn init(config_path: &str) {
 
     let mut buf = [0u8; 1];
     std::io::stdin().read_exact(&mut buf).unwrap_or(());
+    let _ = shutdown(&server, controller, Some(&logger));
+}
+
+/// Initializes the server, and runs it until the process receives
+/// SIGTERM or SIGINT (see [`run_until_signal`]). The configuration
+/// file can be overridden with environment variables, as in [`init`]
+pub fn init_until_signal(config_path: &str) {
+    let config = FileConfig::new(config_path)
+        .and_then(FileConfig::with_env_overlay)
+        .expect("Error cargando la configuracion");
+
+    let logger = Logger::new(
+        config.log_path(),
+        config.log_file_level(),
+        config.log_stdout_level(),
+    )
+    .expect("Error inicializando el logger");
+
+    serve_until_signal(config, Some(&logger)).expect("Error ejecutando el servidor");
+}
+
+/// Runs a server with the given config until the process receives
+/// SIGTERM or SIGINT, and then shuts it down cleanly.
+///
+/// Unlike [`init`], it does not need a standard input, so it can be
+/// used to run the server as a service or embedded in another program.
+/// Note that the signal handlers remain registered after it returns.
+pub fn run_until_signal<C: Config>(config: C) -> io::Result<()> {
+    serve_until_signal(config, None)
+}
+
+#[doc(hidden)]
+/// Runs the server as [`run_until_signal`] does, flushing
+/// the given logger once it is shut down
+fn serve_until_signal<C: Config>(config: C, logger: Option<&Logger>) -> io::Result<()> {
+    let signaled = Arc::new(AtomicBool::new(false));
+    for signal in [SIGTERM, SIGINT] {
+        signal_hook::flag::register(signal, signaled.clone())?;
+    }
+
+    let server = Server::new(config, THREADPOOL_SIZE)
+        .ok_or_else(|| io::Error::other("Error iniciando el servidor"))?;
+    let controller = server.clone().run()?;
+
+    info!("Envie SIGTERM o SIGINT para detener la ejecucion del servidor");
+    while !signaled.load(Ordering::Relaxed) {
+        thread::sleep(SIGNAL_CHECK_FREQ);
+    }
+    info!("Señal recibida - Deteniendo el servidor");
+    shutdown(&server, controller, logger).map_err(|err| io::Error::other(err.to_string()))
+}
+
+/// Shuts down a server started with [`Server::run`], in order: it stops
+/// accepting connections, disconnects the clients (sending their last
+/// wills) and joins the server threads, waits for the packets still in
+/// the ThreadPool, then dumps the state of the server and finally
+/// flushes the logger, if any.
+///
+/// Once it returns, the dump file (if the config has one) is up to date
+/// and every event logged so far is written, instead of relying on the
+/// order in which everything is dropped. If the dump fails, the logger
+/// is flushed anyway and the error is returned. Otherwise, it returns
+/// an error if some of the logs could not be written
+pub fn shutdown<C: Config>(
+    server: &Server<C>,
+    controller: ServerController,
+    logger: Option<&Logger>,
+) -> ServerResult<()> {
+    info!("Apagando servidor");
     drop(controller);
+    if let Err(err) = server.drain_pool() {
+        error!("Error esperando a que termine la ThreadPool: {}", err);
+    }
+    let dumped = server.dump();
+    if let Err(err) = &dumped {
+        error!(
+            "Error realizando el Dump durante el apagado del servidor: {}",
+            err
+        );
+    }
+    let flushed = logger.is_none_or(Logger::flush);
+    dumped?;
+    if !flushed {
+        return Err(ServerError::new_msg(
+            "No se pudieron escribir todos los logs durante el apagado del servidor",
+        ));
+    }
+    Ok(())
 }
@@ -1,6 +1,10 @@
 use std::env;
 
-use server::init;
+use server::{init, init_until_signal};
+
+/// Flag to run the server until it receives SIGTERM or SIGINT,
+/// instead of reading from the standard input
+const UNTIL_SIGNAL_FLAG: &str = "--until-signal";
 
 fn get_config_path(default_path: Option<String>) -> String {
     let args: Vec<String> = env::args().collect();
@@ -14,5 +18,9 @@ fn get_config_path(default_path: Option<String>) -> String {
 }
 fn main() {
     let config_path: String = get_config_path(Some("./config.txt".to_string()));
-    init(&config_path);
+    if env::args().any(|arg| arg == UNTIL_SIGNAL_FLAG) {
+        init_until_signal(&config_path);
+    } else {
+        init(&config_path);
+    }
 }
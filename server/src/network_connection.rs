@@ -1,5 +1,9 @@
 use std::{
     io::{self},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     time::Duration,
 };
 
@@ -14,6 +18,12 @@ use crate::{
 pub struct NetworkConnection<S, I> {
     id: I,
     stream: S,
+    /// Bytes read from the stream. It is shared
+    /// with the copies of the connection
+    bytes_read: Arc<AtomicU64>,
+    /// Bytes written to the stream. It is shared
+    /// with the copies of the connection
+    bytes_written: Arc<AtomicU64>,
 }
 
 impl<S, I> NetworkConnection<S, I> {
@@ -26,17 +36,32 @@ impl<S, I> NetworkConnection<S, I> {
     pub fn id(&self) -> &I {
         &self.id
     }
+    /// Returns the number of bytes read from the connection,
+    /// including those read through any of its copies
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read.load(Ordering::Relaxed)
+    }
+    /// Returns the number of bytes written to the connection,
+    /// including those written through any of its copies
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written.load(Ordering::Relaxed)
+    }
 }
 
 impl<S: io::Read, I> io::Read for NetworkConnection<S, I> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
-        self.stream.read(buf)
+        let bytes = self.stream.read(buf)?;
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+        Ok(bytes)
     }
 }
 
 impl<S: io::Write, I> io::Write for NetworkConnection<S, I> {
     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
-        self.stream.write(buf)
+        let bytes = self.stream.write(buf)?;
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+        Ok(bytes)
     }
 
     fn flush(&mut self) -> io::Result<()> {
@@ -56,7 +81,12 @@ impl<S: Interrupt, I> Interrupt for NetworkConnection<S, I> {
 
 impl<S, I> NetworkConnection<S, I> {
     pub fn new(id: I, stream: S) -> Self {
-        Self { id, stream }
+        Self {
+            id,
+            stream,
+            bytes_read: Arc::new(AtomicU64::new(0)),
+            bytes_written: Arc::new(AtomicU64::new(0)),
+        }
     }
 
     pub fn close(&mut self) -> io::Result<()>
@@ -80,6 +110,55 @@ impl<S, I> NetworkConnection<S, I> {
         Ok(NetworkConnection {
             id: self.id,
             stream,
+            bytes_read: self.bytes_read.clone(),
+            bytes_written: self.bytes_written.clone(),
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+
+    use packets::{pingreq::PingReq, publish::Publish, qos::QoSLevel, traits::MQTTEncoding};
+
+    use super::NetworkConnection;
+    use crate::test_helpers::iomock::IOMock;
+
+    #[test]
+    fn test_counts_bytes_read_and_written() {
+        let mut connection = NetworkConnection::new(0, IOMock::new());
+        let publish = Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "message", None)
+            .unwrap()
+            .encode()
+            .unwrap();
+        let pingreq = PingReq::new().encode().unwrap();
+
+        connection.write_all(&publish).unwrap();
+        connection.write_all(&pingreq).unwrap();
+        let written = (publish.len() + pingreq.len()) as u64;
+        assert_eq!(connection.bytes_written(), written);
+        assert_eq!(connection.bytes_read(), 0);
+
+        let mut buf = vec![0; publish.len()];
+        connection.read_exact(&mut buf).unwrap();
+        assert_eq!(connection.bytes_read(), publish.len() as u64);
+        assert_eq!(connection.bytes_written(), written);
+    }
+
+    #[test]
+    fn test_copies_share_counters() {
+        let mut connection = NetworkConnection::new(0, IOMock::new());
+        let mut copy = connection.try_clone().unwrap();
+        let pingreq = PingReq::new().encode().unwrap();
+
+        copy.write_all(&pingreq).unwrap();
+        assert_eq!(connection.bytes_written(), pingreq.len() as u64);
+
+        let mut buf = vec![0; pingreq.len()];
+        copy.read_exact(&mut buf).unwrap();
+        connection.write_all(&pingreq).unwrap();
+        assert_eq!(copy.bytes_read(), pingreq.len() as u64);
+        assert_eq!(copy.bytes_written(), 2 * pingreq.len() as u64);
+    }
+}
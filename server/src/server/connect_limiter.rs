@@ -0,0 +1,120 @@
+use std::sync::{Condvar, Mutex};
+
+use super::ServerResult;
+use crate::traits::Config;
+
+/// Semaphore shared by every thread that connects a client, which limits
+/// how many connections are processed at the same time (see
+/// [`Config::max_concurrent_connects`](crate::traits::Config::max_concurrent_connects))
+///
+/// When many clients reconnect at once (for example, after the server
+/// restarts), the ones over the limit wait for their turn instead of
+/// all of them contending for the lock of the clients manager
+pub(super) struct ConnectLimiter {
+    max_in_flight: usize,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+/// Permit to process a connection, taken from a [`ConnectLimiter`].
+/// It is returned to the limiter when dropped
+pub(super) struct ConnectPermit<'a> {
+    limiter: &'a ConnectLimiter,
+}
+
+impl ConnectLimiter {
+    /// Creates a limiter that allows `max_in_flight` connections
+    /// to be processed at the same time. It must be greater than zero
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            max_in_flight: max_in_flight.max(1),
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Creates the limiter specified by the configuration, if any
+    pub fn from_config<C: Config>(config: &C) -> Option<Self> {
+        config
+            .max_concurrent_connects()
+            .filter(|max| *max > 0)
+            .map(ConnectLimiter::new)
+    }
+
+    /// Takes a permit to process a connection, waiting
+    /// until one is available
+    pub fn acquire(&self) -> ServerResult<ConnectPermit<'_>> {
+        let mut in_flight = self.in_flight.lock()?;
+        while *in_flight >= self.max_in_flight {
+            in_flight = self.released.wait(in_flight)?;
+        }
+        *in_flight += 1;
+        Ok(ConnectPermit { limiter: self })
+    }
+
+    #[doc(hidden)]
+    fn release(&self) {
+        // Si el lock esta envenenado igual se devuelve el permiso,
+        // sino las conexiones siguientes esperarian para siempre
+        let mut in_flight = self
+            .in_flight
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        *in_flight -= 1;
+        self.released.notify_one();
+    }
+}
+
+impl Drop for ConnectPermit<'_> {
+    fn drop(&mut self) {
+        self.limiter.release();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicUsize, Ordering},
+            Arc,
+        },
+        thread,
+        time::Duration,
+    };
+
+    use super::ConnectLimiter;
+
+    #[test]
+    fn test_permits_are_returned_on_drop() {
+        let limiter = ConnectLimiter::new(1);
+        for _ in 0..3 {
+            let _permit = limiter.acquire().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_concurrency_is_bounded() {
+        let limiter = Arc::new(ConnectLimiter::new(3));
+        let current = Arc::new(AtomicUsize::new(0));
+        let peak = Arc::new(AtomicUsize::new(0));
+        let handles: Vec<_> = (0..20)
+            .map(|_| {
+                let limiter = limiter.clone();
+                let current = current.clone();
+                let peak = peak.clone();
+                thread::spawn(move || {
+                    let _permit = limiter.acquire().unwrap();
+                    let now = current.fetch_add(1, Ordering::SeqCst) + 1;
+                    peak.fetch_max(now, Ordering::SeqCst);
+                    thread::sleep(Duration::from_millis(20));
+                    current.fetch_sub(1, Ordering::SeqCst);
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(peak.load(Ordering::SeqCst) <= 3);
+        assert_eq!(current.load(Ordering::SeqCst), 0);
+    }
+}
@@ -1,56 +1,94 @@
 use std::{
-    fs::{self},
-    io::{self},
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
     net::{SocketAddr, TcpStream},
-    path::MAIN_SEPARATOR,
-    sync::{Arc, Mutex, RwLock},
+    path::{Path, PathBuf},
+    sync::{atomic::AtomicU64, Arc, Mutex, RwLock},
 };
 
 use serde_json::json;
 use threadpool::ThreadPool;
 use tracing::debug;
 
-use crate::{clients_manager::ClientsManager, topic_handler::TopicHandler, Config, Server};
+use crate::{
+    client_id::LOCAL_SUBSCRIBER_PREFIX, clients_manager::ClientsManager,
+    topic_handler::TopicHandler, traits::DumpFormat, Config, Server,
+};
+
+use super::{
+    connect_limiter::ConnectLimiter, publish_limiter::PublishLimiter,
+    server_error::ServerErrorKind, ServerError, ServerResult,
+};
 
-use super::{server_error::ServerErrorKind, ServerError, ServerResult};
+/// Suffix of the temporary file used by atomic dumps
+const TEMP_DUMP_SUFFIX: &str = ".tmp";
 
 impl<C: Config> Server<C> {
+    /// Restores a server from the dump file specified in the config.
+    /// If there is no dump file, it returns None
     pub fn try_restore(config: &C, threadpool_size: usize) -> ServerResult<Option<Arc<Server<C>>>> {
-        let dump_path = match config.dump_info() {
-            Some(dump_info) => dump_info.0,
+        let dump_path = match config.dump_config() {
+            Some(dump_config) => dump_config.path,
             None => return Ok(None),
         };
 
-        let json_str = match fs::read_to_string(dump_path) {
-            Ok(json_str) => json_str,
+        let dump_file = match File::open(dump_path) {
+            Ok(dump_file) => dump_file,
             Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
             Err(err) => return Err(ServerError::from(err)),
         };
 
-        let (topic_handler, mut clients_manager) = Server::<C>::restore_from_json(&json_str)?;
+        Server::try_restore_from_reader(BufReader::new(dump_file), config, threadpool_size)
+            .map(Some)
+    }
+
+    /// Restores a server from a dump read from `reader`, with
+    /// the format written by [`Server::dump_to_writer`]
+    pub fn try_restore_from_reader(
+        reader: impl Read,
+        config: &C,
+        threadpool_size: usize,
+    ) -> ServerResult<Arc<Server<C>>> {
+        let (topic_handler, mut clients_manager) = Server::<C>::restore_from_reader(reader)?;
         let shutdown_info = clients_manager.get_mut()?.shutdown(false)?;
         clients_manager.get_mut()?.set_auth(config.authenticator());
+        Server::apply_config(&topic_handler, clients_manager.get_mut()?, config)?;
         for client_id in shutdown_info.clean_session_ids {
             topic_handler.remove_client(&client_id)?;
         }
+        // Los callbacks de los suscriptores locales no se guardan en el dump
+        for client_id in topic_handler.subscribed_clients()? {
+            if client_id.starts_with(LOCAL_SUBSCRIBER_PREFIX) {
+                topic_handler.remove_client(&client_id)?;
+            }
+        }
 
         let server = Server {
             clients_manager,
             config: config.clone(),
             topic_handler,
             pool: Mutex::new(ThreadPool::new(threadpool_size)),
+            publish_limiter: PublishLimiter::from_config(config),
+            connect_limiter: ConnectLimiter::from_config(config),
+            dropped_deliveries: Arc::new(AtomicU64::new(0)),
+            oversized_deliveries: AtomicU64::new(0),
+            dump_lock: Mutex::new(()),
+            local_subscribers: RwLock::new(HashMap::new()),
+            next_local_id: AtomicU64::new(0),
+            event_listener: RwLock::new(None),
         };
         let server = Arc::new(server);
         for (id, last_will) in shutdown_info.last_will_packets {
             server.send_last_will(last_will, &id)?;
         }
-        Ok(Some(server))
+        Ok(server)
     }
 
-    fn restore_from_json(
-        json_str: &str,
+    fn restore_from_reader(
+        reader: impl Read,
     ) -> ServerResult<(TopicHandler, RwLock<ClientsManager<TcpStream, SocketAddr>>)> {
-        let json: serde_json::Value = match serde_json::from_str(json_str) {
+        let json: serde_json::Value = match serde_json::from_reader(reader) {
             Ok(json) => json,
             Err(err) => {
                 return Err(ServerError::new_kind(
@@ -61,8 +99,12 @@ impl<C: Config> Server<C> {
         };
 
         if let serde_json::Value::Object(mut obj) = json {
-            let topic_handler = obj.remove("topic_handler").unwrap();
-            let clients_manager = obj.remove("clients_manager").unwrap();
+            let topic_handler = obj.remove("topic_handler").ok_or_else(|| {
+                ServerError::new_kind("Dump sin topic_handler", ServerErrorKind::DumpError)
+            })?;
+            let clients_manager = obj.remove("clients_manager").ok_or_else(|| {
+                ServerError::new_kind("Dump sin clients_manager", ServerErrorKind::DumpError)
+            })?;
             Ok((
                 serde_json::from_value(topic_handler).map_err(|err| {
                     ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError)
@@ -72,29 +114,117 @@ impl<C: Config> Server<C> {
                 })?,
             ))
         } else {
-            panic!("Invalid json");
+            Err(ServerError::new_kind(
+                "El dump no es un objeto JSON",
+                ServerErrorKind::DumpError,
+            ))
         }
     }
 
+    /// Writes the state of the server to the dump file
+    /// specified in the config, if any
     pub fn dump(&self) -> ServerResult<()> {
-        if let Some(dump_info) = self.config.dump_info() {
-            debug!("DUMP");
-            let topic_handler = serde_json::to_value(&self.topic_handler).map_err(|err| {
-                ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError)
-            })?;
-            let clients_manager = serde_json::to_value(&self.clients_manager).map_err(|err| {
-                ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError)
-            })?;
-            let json = json!({
-                "topic_handler": topic_handler,
-                "clients_manager": clients_manager
-            });
+        if let Some(dump_config) = self.config.dump_config() {
+            self.dump_to_file(&dump_config.path, dump_config.format, dump_config.atomic)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the state of the server to the dump file specified in
+    /// the config right away, without waiting for the dump interval.
+    /// It fails if there is no dump file in the config
+    pub fn snapshot(&self) -> ServerResult<()> {
+        match self.config.dump_config() {
+            Some(dump_config) => {
+                self.dump_to_file(&dump_config.path, dump_config.format, dump_config.atomic)
+            }
+            None => Err(ServerError::new_kind(
+                "No hay un archivo de dump configurado",
+                ServerErrorKind::DumpError,
+            )),
+        }
+    }
 
-            if let Some((folder, _)) = dump_info.0.rsplit_once(MAIN_SEPARATOR) {
+    /// Writes the state of the server to `path` right away, so that
+    /// it can be restored by a server whose dump file is `path`. It
+    /// uses the format of the dump config, if any, and it is always
+    /// written atomically
+    pub fn snapshot_to(&self, path: impl AsRef<Path>) -> ServerResult<()> {
+        let format = self
+            .config
+            .dump_config()
+            .map(|dump_config| dump_config.format)
+            .unwrap_or_default();
+        self.dump_to_file(path.as_ref(), format, true)
+    }
+
+    #[doc(hidden)]
+    /// Writes the state of the server to the file in `path`. If `atomic`
+    /// is true, it is written to a temporary file which then replaces it
+    fn dump_to_file(&self, path: &Path, format: DumpFormat, atomic: bool) -> ServerResult<()> {
+        let _dump_guard = self.dump_lock.lock()?;
+        debug!("DUMP");
+        if let Some(folder) = path.parent() {
+            if !folder.as_os_str().is_empty() {
                 fs::create_dir_all(folder)?;
             }
-            fs::write(dump_info.0, serde_json::to_string_pretty(&json)?)?;
+        }
+        let file_path = if atomic {
+            let mut temp_path = path.to_path_buf().into_os_string();
+            temp_path.push(TEMP_DUMP_SUFFIX);
+            PathBuf::from(temp_path)
+        } else {
+            path.to_path_buf()
+        };
+        let mut writer = BufWriter::new(File::create(&file_path)?);
+        self.write_dump(&mut writer, format)?;
+        writer.flush()?;
+        drop(writer);
+        if atomic {
+            fs::rename(file_path, path)?;
+        }
+        Ok(())
+    }
+
+    /// Writes the state of the server to `writer`, so that it
+    /// can be restored with [`Server::try_restore_from_reader`]
+    pub fn dump_to_writer(&self, writer: impl Write) -> ServerResult<()> {
+        self.write_dump(writer, DumpFormat::Pretty)
+    }
+
+    #[doc(hidden)]
+    /// Writes the state of the server to `writer`, in the given format
+    fn write_dump(&self, writer: impl Write, format: DumpFormat) -> ServerResult<()> {
+        let topic_handler = serde_json::to_value(&self.topic_handler)
+            .map_err(|err| ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError))?;
+        let clients_manager = serde_json::to_value(&self.clients_manager)
+            .map_err(|err| ServerError::new_kind(&err.to_string(), ServerErrorKind::DumpError))?;
+        let json = json!({
+            "topic_handler": topic_handler,
+            "clients_manager": clients_manager
+        });
+        match format {
+            DumpFormat::Pretty => serde_json::to_writer_pretty(writer, &json)?,
+            DumpFormat::Compact => serde_json::to_writer(writer, &json)?,
         }
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use crate::{server::server_error::ServerErrorKind, test_helpers::server::test_config, Server};
+
+    #[test]
+    fn test_restore_empty_object_fails() {
+        let result = Server::try_restore_from_reader(&b"{}"[..], &test_config(""), 2);
+        assert_eq!(result.err().unwrap().kind(), ServerErrorKind::DumpError);
+    }
+
+    #[test]
+    fn test_restore_non_object_fails() {
+        let result = Server::try_restore_from_reader(&b"[]"[..], &test_config(""), 2);
+        assert_eq!(result.err().unwrap().kind(), ServerErrorKind::DumpError);
+    }
+}
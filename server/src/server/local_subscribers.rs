@@ -0,0 +1,143 @@
+use std::sync::atomic::Ordering;
+
+use packets::{publish::Publish, qos::QoSLevel, subscribe::Subscribe, topic_filter::TopicFilter};
+use tracing::debug;
+
+use super::{Server, ServerResult};
+use crate::{client_id::ClientId, traits::Config};
+
+/// Callback through which an in-process subscriber (see
+/// [`Server::subscribe_local`]) receives the publishes
+pub type LocalSubscriber = Box<dyn Fn(&Publish) + Send + Sync>;
+
+impl<C: Config> Server<C> {
+    /// Subscribes the application that embeds the server to the given
+    /// topic filter, without going through the network. Each publish
+    /// that matches it (including the retained ones, which are delivered
+    /// right away) is passed to `callback`, in the thread that delivers
+    /// it to the subscribers. The callback must not block.
+    ///
+    /// Returns the id of the subscriber, to be used with
+    /// [`Server::unsubscribe_local`]
+    pub fn subscribe_local(
+        &self,
+        filter: &str,
+        callback: LocalSubscriber,
+    ) -> ServerResult<ClientId> {
+        let topic_filter = TopicFilter::new(filter, QoSLevel::QoSLevel1)?;
+        let id = ClientId::local(self.next_local_id.fetch_add(1, Ordering::Relaxed));
+        debug!("Suscribiendo <{}> a {}", id, filter);
+        // Se registra antes de suscribirlo, para no perder ningun publish
+        let mut local_subscribers = self.local_subscribers.write()?;
+        let retained = self
+            .topic_handler
+            .subscribe(&Subscribe::new(vec![topic_filter], 1), &id)?;
+        for publish in &retained {
+            callback(publish);
+        }
+        local_subscribers.insert(id.clone(), callback);
+        Ok(id)
+    }
+
+    /// Removes the in-process subscriber with the given id, so that
+    /// its callback no longer receives publishes. Returns false if
+    /// there was no such subscriber
+    pub fn unsubscribe_local(&self, id: &str) -> ServerResult<bool> {
+        match self.local_subscribers.write()?.remove_entry(id) {
+            Some((id, _)) => {
+                self.topic_handler.remove_client(&id)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Delivers the publish to the in-process subscriber with the given
+    /// id, if there is one. Returns false if the id is not of an
+    /// in-process subscriber
+    pub(super) fn deliver_local(&self, id: &str, publish: &Publish) -> ServerResult<bool> {
+        match self.local_subscribers.read()?.get(id) {
+            Some(callback) => {
+                callback(publish);
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Returns true if the id is of an in-process subscriber
+    pub(super) fn is_local_subscriber(&self, id: &str) -> ServerResult<bool> {
+        Ok(self.local_subscribers.read()?.contains_key(id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{mpsc, Mutex},
+        time::Duration,
+    };
+
+    use packets::{publish::Publish, qos::QoSLevel};
+
+    use crate::test_helpers::server::test_server;
+
+    #[test]
+    fn test_subscribe_local_receives_retained() {
+        let server = test_server("", 2);
+        let retained =
+            Publish::new(false, QoSLevel::QoSLevel0, true, "sensors/temp", "25", None).unwrap();
+        server.publish(retained).unwrap();
+
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        server
+            .subscribe_local(
+                "sensors/#",
+                Box::new(move |publish| {
+                    sender
+                        .lock()
+                        .unwrap()
+                        .send(publish.payload_str().unwrap().to_string())
+                        .unwrap();
+                }),
+            )
+            .unwrap();
+
+        assert_eq!(receiver.try_recv().unwrap(), "25");
+    }
+
+    #[test]
+    fn test_unsubscribe_local() {
+        let server = test_server("", 2);
+        let (sender, receiver) = mpsc::channel();
+        let sender = Mutex::new(sender);
+        let id = server
+            .subscribe_local(
+                "sensors/#",
+                Box::new(move |publish| {
+                    let _ = sender
+                        .lock()
+                        .unwrap()
+                        .send(publish.payload_str().unwrap().to_string());
+                }),
+            )
+            .unwrap();
+        let publish = Publish::new(
+            false,
+            QoSLevel::QoSLevel0,
+            false,
+            "sensors/temp",
+            "25",
+            None,
+        )
+        .unwrap();
+        server.publish(publish.clone()).unwrap();
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(5)).unwrap(), "25");
+
+        assert!(server.unsubscribe_local(&id).unwrap());
+        assert!(!server.unsubscribe_local(&id).unwrap());
+        server.publish(publish).unwrap();
+        assert!(receiver.recv_timeout(Duration::from_millis(300)).is_err());
+    }
+}
@@ -1,9 +1,11 @@
 use std::{
+    collections::HashMap,
     convert::TryFrom,
     io::{self, Read, Write},
     net::{SocketAddr, TcpListener, TcpStream},
+    path::Path,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         mpsc::{self, Receiver, Sender},
         Arc, Mutex, RwLock,
     },
@@ -11,9 +13,10 @@ use std::{
     time::{Duration, SystemTime},
 };
 
+use socket2::{SockRef, TcpKeepalive};
 use thread_joiner::ThreadJoiner;
 use threadpool::ThreadPool;
-use tracing::{debug, error, info, instrument, trace, warn};
+use tracing::{debug, error, info, instrument, trace, warn, Span};
 
 use packets::{
     connack::{Connack, ConnackReturnCode},
@@ -26,13 +29,25 @@ use packets::{
     unsuback::Unsuback, unsubscribe::Unsubscribe,
 };
 
+mod connect_limiter;
 mod dump;
+mod local_subscribers;
 mod packet_processing;
+mod pool_tuning;
+mod publish_limiter;
+mod rebind;
+mod reconcile;
 mod server_controller;
 pub mod server_error;
 
+pub use local_subscribers::LocalSubscriber;
+pub use packet_processing::SERVER_SOURCE_ID;
 pub use server_error::ServerError;
 
+use connect_limiter::ConnectLimiter;
+use publish_limiter::PublishLimiter;
+use rebind::{exhaustion_backoff, is_connection_error, is_resource_exhaustion};
+
 /// Maximum time between the client connection and the sending
 /// of the [`Connect`] packet
 const CONNECTION_WAIT_TIMEOUT: Duration = Duration::from_secs(180);
@@ -50,10 +65,11 @@ use packets::publish::Publish;
 use packets::qos::QoSLevel;
 
 use crate::{
-    clients_manager::{ClientsManager, ConnectInfo},
+    client_id::{self, ClientId, ClientIdRules},
+    clients_manager::{ClientTraffic, ClientsManager, ConnectInfo},
     network_connection::NetworkConnection,
     server::server_error::ServerErrorKind,
-    topic_handler::{Message, TopicHandler},
+    topic_handler::{Message, SubscriptionListener, TopicHandler, TopicTreeSnapshot},
     traits::*,
 };
 
@@ -61,10 +77,21 @@ pub use self::server_controller::ServerController;
 
 pub type ServerResult<T> = Result<T, ServerError>;
 #[doc(hidden)]
-pub type ClientId = String;
-#[doc(hidden)]
 pub type ClientIdArg = str;
 
+/// Event of the [`Server`] that operators may want to react to,
+/// reported to its [`ServerEventListener`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum ServerEvent {
+    /// The client reached the high water mark of its in-flight window
+    /// (see [`Config::inflight_window`]), so the publishes to it are
+    /// held back until it acknowledges the pending ones
+    SlowConsumer { id: String },
+}
+
+/// Callback invoked by the [`Server`] every time a [`ServerEvent`] happens
+pub type ServerEventListener = Box<dyn Fn(&ServerEvent) + Send + Sync>;
+
 /// Represents a Server that complies with the
 /// MQTT V3.1.1 protocol
 ///
@@ -111,6 +138,28 @@ pub struct Server<C: Config> {
     /// The only ones that are not processed in the Threadpool
     /// are the [`Connect`] and [`Disconnect`] packets.
     pool: Mutex<ThreadPool>,
+    /// Limits how many publishes per second the server routes,
+    /// if [`Config::max_global_publishes_per_second`] is specified
+    publish_limiter: Option<PublishLimiter>,
+    /// Limits how many connections are processed at the same time,
+    /// if [`Config::max_concurrent_connects`] is specified
+    connect_limiter: Option<ConnectLimiter>,
+    /// How many QoS 0 messages were dropped because the delivery channel
+    /// was full, if [`Config::delivery_channel_capacity`] is specified
+    dropped_deliveries: Arc<AtomicU64>,
+    /// How many messages were not delivered because their payload
+    /// exceeded [`Config::max_outgoing_message_size`]
+    oversized_deliveries: AtomicU64,
+    /// Held while the state is dumped, so that a snapshot taken on
+    /// demand does not overlap with the periodic dump
+    dump_lock: Mutex<()>,
+    /// Callbacks of the in-process subscribers (see
+    /// [`Server::subscribe_local`]), by their id
+    local_subscribers: RwLock<HashMap<ClientId, LocalSubscriber>>,
+    /// Number used in the id of the next in-process subscriber
+    next_local_id: AtomicU64,
+    /// Callback invoked every time a [`ServerEvent`] happens
+    event_listener: RwLock<Option<ServerEventListener>>,
 }
 
 impl<C: Config> Server<C> {
@@ -125,11 +174,22 @@ impl<C: Config> Server<C> {
                 } else {
                     warn!("No se encontro un archivo de DUMP - Creando servidor en blanco");
 
+                    let topic_handler = TopicHandler::new();
+                    let mut clients_manager = ClientsManager::new(config.authenticator());
+                    Server::apply_config(&topic_handler, &mut clients_manager, &config).ok()?;
                     let server = Arc::new(Self {
-                        clients_manager: RwLock::new(ClientsManager::new(config.authenticator())),
+                        clients_manager: RwLock::new(clients_manager),
+                        publish_limiter: PublishLimiter::from_config(&config),
+                        connect_limiter: ConnectLimiter::from_config(&config),
                         config,
-                        topic_handler: TopicHandler::new(),
+                        topic_handler,
                         pool: Mutex::new(ThreadPool::new(threadpool_size)),
+                        dropped_deliveries: Arc::new(AtomicU64::new(0)),
+                        oversized_deliveries: AtomicU64::new(0),
+                        dump_lock: Mutex::new(()),
+                        local_subscribers: RwLock::new(HashMap::new()),
+                        next_local_id: AtomicU64::new(0),
+                        event_listener: RwLock::new(None),
                     });
                     Some(server)
                 }
@@ -138,17 +198,125 @@ impl<C: Config> Server<C> {
         }
     }
 
+    /// Applies the options of the config to the topic handler and the
+    /// clients manager. It is used both when creating a new server and
+    /// when restoring one from a dump, so that they are configured alike
+    fn apply_config(
+        topic_handler: &TopicHandler,
+        clients_manager: &mut ClientsManager<TcpStream, SocketAddr>,
+        config: &C,
+    ) -> ServerResult<()> {
+        topic_handler.set_publish_lock_retries(config.publish_lock_retries())?;
+        topic_handler.set_retained_history_depth(config.retained_history_depth())?;
+        topic_handler.set_max_retained_messages(
+            config.max_retained_messages(),
+            config.retained_overflow(),
+        )?;
+        topic_handler.set_deduplicate_deliveries(config.deduplicate_deliveries())?;
+        topic_handler.set_suppress_resubscribe_retained(config.suppress_resubscribe_retained())?;
+        topic_handler.set_topic_normalizer(config.topic_normalizer())?;
+        clients_manager.set_reconnect_grace(config.reconnect_grace());
+        clients_manager.set_client_id_rules(ClientIdRules::from_config(config));
+        clients_manager.set_force_clean_session(config.force_clean_session());
+        clients_manager.set_require_credentials(config.require_credentials());
+        Ok(())
+    }
+
+    /// Returns the `n` most published topics, along with how many
+    /// times they were published to, from most to least published
+    pub fn top_topics(&self, n: usize) -> ServerResult<Vec<(String, u64)>> {
+        Ok(self.topic_handler.top_topics(n)?)
+    }
+
+    /// Returns a snapshot of the topic tree, to inspect the
+    /// subscriptions and retained messages of each topic
+    pub fn topic_tree(&self) -> ServerResult<TopicTreeSnapshot> {
+        Ok(self.topic_handler.dump_tree()?)
+    }
+
+    /// Returns the number of retained messages across all topics
+    pub fn retained_count(&self) -> ServerResult<usize> {
+        Ok(self.topic_handler.retained_count()?)
+    }
+
+    /// Returns how many QoS 0 messages were dropped because the channel
+    /// between the topic handler and the delivery thread was full (see
+    /// [`Config::delivery_channel_capacity`])
+    pub fn dropped_deliveries(&self) -> u64 {
+        self.dropped_deliveries.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many messages were not delivered to a subscriber
+    /// because their payload exceeded the outgoing limit (see
+    /// [`Config::max_outgoing_message_size`])
+    pub fn oversized_deliveries(&self) -> u64 {
+        self.oversized_deliveries.load(Ordering::Relaxed)
+    }
+
+    /// Returns how many bytes were received from and sent to
+    /// each connected client in its current connection
+    pub fn client_traffic(&self) -> ServerResult<Vec<ClientTraffic>> {
+        self.clients_manager.read()?.traffic()
+    }
+
+    /// Stops the delivery of publishes to the client with the given
+    /// id, without disconnecting it. Publishes are queued, up to a
+    /// limit, until the client is resumed
+    pub fn pause_client(&self, id: &str) -> ServerResult<()> {
+        self.clients_manager.read()?.client_do(id, |client| {
+            client.pause();
+            Ok(())
+        })
+    }
+
+    /// Resumes the delivery of publishes to the client with the given
+    /// id, sending the ones queued while it was paused
+    pub fn resume_client(&self, id: &str) -> ServerResult<()> {
+        self.clients_manager
+            .read()?
+            .client_do(id, |client| client.resume())
+    }
+
+    /// Sets the callback that is invoked every time a client subscribes,
+    /// unsubscribes or is removed from the server
+    pub fn set_subscription_listener(
+        &self,
+        listener: Option<SubscriptionListener>,
+    ) -> ServerResult<()> {
+        Ok(self.topic_handler.set_subscription_listener(listener)?)
+    }
+
+    /// Sets the callback that is invoked every time a [`ServerEvent`]
+    /// happens, for example when a client becomes a slow consumer
+    pub fn set_event_listener(&self, listener: Option<ServerEventListener>) -> ServerResult<()> {
+        *self.event_listener.write()? = listener;
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    /// Reports an event to the listener, if there is one
+    fn notify(&self, event: ServerEvent) -> ServerResult<()> {
+        if let Some(listener) = self.event_listener.read()?.as_ref() {
+            listener(&event);
+        }
+        Ok(())
+    }
+
     /// Run the server in a new thread.
     ///
     /// Returns a ServerController that can be used to stop the server
     ///
     /// This method does not return until the server initializes everything
-    /// necessary to start accepting connections
+    /// necessary to start accepting connections. It fails if the server
+    /// could not listen on its address (for example, if it is in use)
     #[instrument(skip(self) fields(ip = %self.config.ip(), port = %self.config.port()))]
     pub fn run(self: Arc<Self>) -> io::Result<ServerController> {
         let shutdown_bool = Arc::new(AtomicBool::new(false));
         let shutdown_bool_copy = shutdown_bool.clone();
         let (started_sender, started_receiver) = mpsc::channel();
+        let server_weak = Arc::downgrade(&self);
+        let pauser_server_weak = server_weak.clone();
+        let snapshotter_server_weak = server_weak.clone();
 
         let server_handle = thread::Builder::new()
             .name("server_loop".to_owned())
@@ -161,10 +329,46 @@ impl<C: Config> Server<C> {
                 }
             })?;
         trace!("Creando thread {:?}", server_handle.thread().id());
-        started_receiver.recv().unwrap_or_else(|e| {
+        // Si no se recibe nada, el servidor no pudo escuchar conexiones (ya terminó)
+        if let Err(e) = started_receiver.recv() {
             error!("Error iniciando el servidor: {}", e);
+            return Err(io::Error::other("No se pudo iniciar el servidor"));
+        }
+        let publisher = Box::new(move |publish| match server_weak.upgrade() {
+            Some(server) => server.publish(publish),
+            None => Err(ServerError::new_kind(
+                "El servidor esta apagado",
+                ServerErrorKind::Other,
+            )),
         });
-        let server_controller = ServerController::new(shutdown_bool_copy, server_handle);
+        let pauser = Box::new(
+            move |id: &str, paused: bool| match pauser_server_weak.upgrade() {
+                Some(server) if paused => server.pause_client(id),
+                Some(server) => server.resume_client(id),
+                None => Err(ServerError::new_kind(
+                    "El servidor esta apagado",
+                    ServerErrorKind::Other,
+                )),
+            },
+        );
+        let snapshotter =
+            Box::new(
+                move |path: Option<&Path>| match (snapshotter_server_weak.upgrade(), path) {
+                    (Some(server), Some(path)) => server.snapshot_to(path),
+                    (Some(server), None) => server.snapshot(),
+                    (None, _) => Err(ServerError::new_kind(
+                        "El servidor esta apagado",
+                        ServerErrorKind::Other,
+                    )),
+                },
+            );
+        let server_controller = ServerController::new(
+            shutdown_bool_copy,
+            server_handle,
+            publisher,
+            pauser,
+            snapshotter,
+        );
         Ok(server_controller)
     }
 
@@ -192,15 +396,29 @@ impl<C: Config> Server<C> {
     ) -> ServerResult<ConnectInfo> {
         debug!("Conectando cliente");
         let connect = self.wait_for_connect(network_connection)?;
-        let clean_session = *connect.clean_session();
         network_connection.alert(UNACK_RESENDING_FREQ)?;
+        network_connection
+            .stream()
+            .set_write_timeout(self.config.delivery_write_timeout())?;
+        let _permit = match &self.connect_limiter {
+            Some(limiter) => Some(limiter.acquire()?),
+            None => None,
+        };
         let connect_info = self
             .clients_manager
             .write()?
             .new_session(network_connection.try_clone()?, connect)?;
-        if connect_info.session_present && clean_session {
+        if connect_info.session_discarded {
             self.topic_handler.remove_client(&connect_info.id)?;
         }
+        let strict_ordering = self.config.strict_ordering(&connect_info.id);
+        let inflight_window = self.config.inflight_window();
+        self.clients_manager
+            .read()?
+            .client_do(&connect_info.id, |client| {
+                client.set_inflight_window(inflight_window)?;
+                client.set_strict_ordering(strict_ordering)
+            })?;
         Ok(connect_info)
     }
 
@@ -211,7 +429,7 @@ impl<C: Config> Server<C> {
     /// Returns true if the client should be disconnected gracefully.
     /// If it returns false or error, it should be disconnected
     /// ungracefully
-    #[instrument(skip(self, id, network_connection))]
+    #[instrument(skip(self, id, network_connection) fields(client_id = %id))]
     fn client_loop(
         self: &Arc<Self>,
         id: &ClientIdArg,
@@ -222,8 +440,20 @@ impl<C: Config> Server<C> {
             .clients_manager
             .read()?
             .client_do(id, |client| Ok(client.keep_alive()))?;
+        let pending_ack_timeout = self.config.pending_ack_timeout();
+        // Los clientes con id generada por el servidor tienen un tiempo de vida maximo
+        let lifetime_deadline = match self.config.generic_client_max_lifetime() {
+            Some(lifetime) if client_id::is_generated(id) => Some(SystemTime::now() + lifetime),
+            _ => None,
+        };
 
         loop {
+            if let Some(deadline) = lifetime_deadline {
+                if SystemTime::now() > deadline {
+                    warn!("Se alcanzo el tiempo de vida maximo del cliente con id generada");
+                    return Ok(false);
+                }
+            }
             match self.process_packet(network_connection, id) {
                 Ok(packet_type) => {
                     last_activity = SystemTime::now();
@@ -250,6 +480,24 @@ impl<C: Config> Server<C> {
                     return Ok(false);
                 }
             }
+            if let Some(timeout) = pending_ack_timeout {
+                let pending_since = self
+                    .clients_manager
+                    .read()?
+                    .client_do(id, |client| Ok(client.pending_since()))?;
+                // Solo cuenta el tiempo sin actividad desde que hay pendientes
+                if let Some(pending_since) = pending_since {
+                    let idle_since = pending_since.max(last_activity);
+                    if SystemTime::now()
+                        .duration_since(idle_since)
+                        .unwrap_or_default()
+                        > timeout
+                    {
+                        warn!("El cliente no responde con mensajes pendientes de confirmacion");
+                        return Ok(false);
+                    }
+                }
+            }
         }
     }
 
@@ -263,6 +511,12 @@ impl<C: Config> Server<C> {
     ///
     /// In case a Client TakeOver occurs and the previous session had LastWill,
     /// it is also published.
+    ///
+    /// If a presence topic prefix is configured, the client presence is
+    /// published when it connects and when it disconnects.
+    ///
+    /// If the [`Connack`] cannot be sent, the client is disconnected
+    /// ungracefully, as if the connection was lost afterwards.
     #[instrument(skip(self, connect_info, network_connection) fields(client_id = %connect_info.id))]
     fn manage_successful_connection(
         self: &Arc<Self>,
@@ -270,18 +524,32 @@ impl<C: Config> Server<C> {
         mut network_connection: NetworkConnection<TcpStream, SocketAddr>,
     ) -> ServerResult<()> {
         info!("Cliente aceptado");
-        network_connection.write_all(
+        let connack_result = network_connection.write_all(
             &Connack::new(connect_info.session_present, ConnackReturnCode::Accepted).encode()?,
-        )?;
+        );
         // En caso de que haya ocurrido una reconexion y el cliente
         // tenia un last will, se publica
         if let Some(last_will) = connect_info.takeover_last_will {
-            self.send_last_will(last_will, &connect_info.id)?;
+            if let Err(err) = self.send_last_will(last_will, &connect_info.id) {
+                error!("Error publicando LastWill del cliente reemplazado: {}", err);
+            }
         }
         let disconnect_info;
-        let gracefully = self
-            .client_loop(&connect_info.id, &mut network_connection)
-            .unwrap_or(false);
+        // Si no se pudo enviar el Connack, el cliente ya se desconecto,
+        // pero su sesion debe limpiarse igual
+        let gracefully = match connack_result {
+            Ok(()) => {
+                if let Err(err) = self.publish_presence(&connect_info.id, true) {
+                    error!("Error publicando presencia del cliente: {}", err);
+                }
+                self.client_loop(&connect_info.id, &mut network_connection)
+                    .unwrap_or(false)
+            }
+            Err(err) => {
+                warn!("Error enviando Connack: {}", err);
+                false
+            }
+        };
         debug!("Cliente desconectado (Gracefully: {})", gracefully);
         disconnect_info = self.clients_manager.write()?.disconnect(
             &connect_info.id,
@@ -294,6 +562,9 @@ impl<C: Config> Server<C> {
         if let Some(last_will) = disconnect_info.publish_last_will {
             self.send_last_will(last_will, &connect_info.id)?;
         }
+        if disconnect_info.disconnected {
+            self.publish_presence(&connect_info.id, false)?;
+        }
         Ok(())
     }
 
@@ -356,36 +627,63 @@ impl<C: Config> Server<C> {
 
     /// Accepts clients and processes them as log as a shutdown signal is not
     /// received from the [ServerController] corresponding to this server
+    ///
+    /// If the listener fails, it tries to listen again with backoff, and
+    /// only shuts down if it could not. Errors that only affect a single
+    /// connection are logged, and the server keeps accepting. If it runs
+    /// out of resources (such as file descriptors), it keeps the listener
+    /// and waits with backoff until it can accept connections again
     #[instrument(skip(self, shutdown_bool, started_sender) fields(ip = %self.config.ip(), port = %self.config.port()))]
     fn server_loop(
         self: Arc<Self>,
         shutdown_bool: Arc<AtomicBool>,
         started_sender: Sender<()>,
     ) -> ServerResult<()> {
-        let listener = TcpListener::bind(format!("{}:{}", self.config.ip(), self.config.port()))?;
+        let mut listener = self.bind_listener()?;
         let mut time_last_dump = SystemTime::now();
-        let dump_info_opt = self.config.dump_info();
+        let dump_interval = self.config.dump_config().map(|dump| dump.interval);
         started_sender.send(())?;
 
+        let mut exhausted_backoff = exhaustion_backoff();
         let mut thread_joiner = ThreadJoiner::new();
-        listener.set_nonblocking(true)?;
+        if let Some(bounds) = self.config.threadpool_size_bounds() {
+            let sv_copy = self.clone();
+            let shutdown_bool_copy = shutdown_bool.clone();
+            thread_joiner.spawn(move || sv_copy.pool_tuning_loop(bounds, shutdown_bool_copy));
+        }
+        if let Some(interval) = self.config.reconcile_interval() {
+            let sv_copy = self.clone();
+            let shutdown_bool_copy = shutdown_bool.clone();
+            thread_joiner.spawn(move || sv_copy.reconcile_loop(interval, shutdown_bool_copy));
+        }
         while !shutdown_bool.load(Ordering::Relaxed) {
             match self.accept_client(&listener) {
                 Ok(connection_stream) => {
+                    exhausted_backoff.reset();
                     let socket_addr = *connection_stream.id();
                     self.run_client(connection_stream, &mut thread_joiner)
                         .unwrap_or_else(|e| error!("{}: Error - {}", socket_addr, e));
                 }
                 Err(e) if e.kind() == ServerErrorKind::Idle => {
+                    exhausted_backoff.reset();
                     thread::sleep(ACCEPT_SLEEP_DUR);
                 }
+                // Sólo falló esa conexión (ya se logueó), el listener sigue funcionando
+                Err(e) if e.kind() == ServerErrorKind::ClientDisconnected => {}
+                // El listener sigue funcionando, se espera a que se liberen recursos
+                Err(e) if e.kind() == ServerErrorKind::ResourcesExhausted => {
+                    thread::sleep(exhausted_backoff.next_delay());
+                }
                 Err(e) => {
                     error!("Error de nueva conexion: {}", e);
-                    break;
+                    match self.rebind_listener(listener, &shutdown_bool) {
+                        Some(new_listener) => listener = new_listener,
+                        None => break,
+                    }
                 }
             }
-            if let Some(dump_info) = dump_info_opt {
-                if SystemTime::now().duration_since(time_last_dump).unwrap() >= dump_info.1 {
+            if let Some(dump_interval) = dump_interval {
+                if SystemTime::now().duration_since(time_last_dump).unwrap() >= dump_interval {
                     self.dump()?;
                     time_last_dump = SystemTime::now();
                 }
@@ -406,6 +704,9 @@ impl<C: Config> Server<C> {
         for (id, last_will) in shutdown_info.last_will_packets {
             self.send_last_will(last_will, &id)?;
         }
+        for id in shutdown_info.disconnected_ids {
+            self.publish_presence(&id, false)?;
+        }
         Ok(())
     }
 
@@ -413,7 +714,11 @@ impl<C: Config> Server<C> {
     /// connection.
     ///
     /// If no connection has been received, it returns an error of kind
-    /// [`ServerErrorKind::Idle`]
+    /// [`ServerErrorKind::Idle`]. If the connection could not be set up,
+    /// it is dropped and an error of kind [`ServerErrorKind::ClientDisconnected`]
+    /// is returned, since the listener can still accept other connections.
+    /// If there were no resources left to accept it, an error of kind
+    /// [`ServerErrorKind::ResourcesExhausted`] is returned
     #[instrument(skip(self, listener) fields(socket_addr))]
     fn accept_client(
         self: &Arc<Self>,
@@ -423,16 +728,52 @@ impl<C: Config> Server<C> {
             Err(error) if error.kind() == io::ErrorKind::WouldBlock => {
                 Err(ServerError::new_kind("Idle", ServerErrorKind::Idle))
             }
+            Err(error) if is_connection_error(&error) => {
+                warn!("Error aceptando conexion TCP, se descarta: {}", error);
+                Err(ServerError::new_kind(
+                    "Conexion descartada",
+                    ServerErrorKind::ClientDisconnected,
+                ))
+            }
+            Err(error) if is_resource_exhaustion(&error) => {
+                warn!("Recursos agotados aceptando conexion TCP: {}", error);
+                Err(ServerError::new_kind(
+                    "Recursos agotados",
+                    ServerErrorKind::ResourcesExhausted,
+                ))
+            }
             Err(error) => {
                 error!("Error aceptando conexion TCP: {}", error);
                 Err(ServerError::from(error))
             }
             Ok((stream, socket_addr)) => {
-                stream.set_read_timeout(Some(CONNECTION_WAIT_TIMEOUT))?;
+                let configured = stream
+                    .set_read_timeout(Some(CONNECTION_WAIT_TIMEOUT))
+                    .and_then(|_| self.set_socket_options(&stream));
+                if let Err(error) = configured {
+                    warn!(
+                        "{}: Error configurando la conexion, se descarta: {}",
+                        socket_addr, error
+                    );
+                    return Err(ServerError::new_kind(
+                        "Conexion descartada",
+                        ServerErrorKind::ClientDisconnected,
+                    ));
+                }
                 Ok(NetworkConnection::new(socket_addr, stream))
             }
         }
     }
+
+    /// Sets on the stream the socket options specified in
+    /// the configuration (TCP_NODELAY and SO_KEEPALIVE)
+    fn set_socket_options(&self, stream: &TcpStream) -> io::Result<()> {
+        stream.set_nodelay(self.config.tcp_nodelay())?;
+        if let Some(time) = self.config.tcp_keepalive() {
+            SockRef::from(stream).set_tcp_keepalive(&TcpKeepalive::new().with_time(time))?;
+        }
+        Ok(())
+    }
 }
 
 impl<C: Config> Drop for Server<C> {
@@ -445,3 +786,43 @@ impl<C: Config> Drop for Server<C> {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{TcpListener, TcpStream},
+        time::Duration,
+    };
+
+    use socket2::SockRef;
+
+    use super::Server;
+    use crate::{config::FileConfig, test_helpers::server::test_server, traits::Config};
+
+    fn accepted_stream(server: &Server<FileConfig>) -> TcpStream {
+        let listener = TcpListener::bind("localhost:0").unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        server.set_socket_options(&stream).unwrap();
+        stream
+    }
+
+    #[test]
+    fn test_socket_options_by_default() {
+        let server = test_server("", 1);
+        let stream = accepted_stream(&server);
+
+        assert!(stream.nodelay().unwrap());
+        assert!(!SockRef::from(&stream).keepalive().unwrap());
+    }
+
+    #[test]
+    fn test_socket_options_as_configured() {
+        let server = test_server("tcp_nodelay=false\ntcp_keepalive=30", 1);
+        assert_eq!(server.config.tcp_keepalive(), Some(Duration::from_secs(30)));
+        let stream = accepted_stream(&server);
+
+        assert!(!stream.nodelay().unwrap());
+        assert!(SockRef::from(&stream).keepalive().unwrap());
+    }
+}
@@ -1,24 +1,167 @@
-use packets::{packet_error::ErrorKind, pingresp::PingResp};
+use packets::{packet_error::ErrorKind, pingresp::PingResp, suback::Suback};
+
+use std::sync::{mpsc::SendError, PoisonError};
+
+use threadpool::ThreadPoolError;
+
+use crate::topic_handler::{
+    topic_handler_error::{TopicHandlerError, TopicHandlerErrorKind},
+    BoundedSink, MessageSink, Origin,
+};
 
 use super::*;
 
+/// Separator between the levels of a topic
+#[doc(hidden)]
+const TOPIC_LEVEL_SEP: char = '/';
+/// Id used as the source of the publishes originated
+/// in the server itself
+pub const SERVER_SOURCE_ID: &str = "$server";
+/// Maximum number of times a publish received from another
+/// broker can have been forwarded before it is dropped
+#[doc(hidden)]
+const MAX_BRIDGE_HOPS: u32 = 8;
+/// Prefix of the topics to which a client publishes to get the
+/// retained messages again, if enabled
+#[doc(hidden)]
+const REFRESH_TOPIC_PREFIX: &str = "$refresh/";
+/// Payload of the presence message of a connected client
+#[doc(hidden)]
+const PRESENCE_ONLINE: &str = "online";
+/// Payload of the presence message of a disconnected client
+#[doc(hidden)]
+const PRESENCE_OFFLINE: &str = "offline";
+/// How long to wait before retrying a publish whose subscribers
+/// could not be found without blocking
+#[doc(hidden)]
+const WOULD_BLOCK_RETRY_FREQ: Duration = Duration::from_millis(1);
+
+/// Forwards every message of `receiver` to a dispatcher started with
+/// `spawn_dispatcher`. If the dispatcher dies, the message that could
+/// not be sent is forwarded to a new one. If the new one dies before
+/// receiving it, an error is returned
+#[doc(hidden)]
+fn supervised_dispatch<F>(receiver: Receiver<Message>, spawn_dispatcher: F) -> ServerResult<()>
+where
+    F: Fn() -> ServerResult<Sender<Message>>,
+{
+    let mut dispatcher = spawn_dispatcher()?;
+    for message in receiver {
+        if let Err(SendError(message)) = dispatcher.send(message) {
+            warn!("El despachador de PUBLISH dejo de funcionar, reiniciandolo");
+            dispatcher = spawn_dispatcher()?;
+            dispatcher.send(message).map_err(|_| {
+                ServerError::new_kind(
+                    "No se pudo reiniciar el despachador de PUBLISH",
+                    ServerErrorKind::Other,
+                )
+            })?;
+        }
+    }
+    Ok(())
+}
+
+/// Submits `job` with `execute` (normally [`ThreadPool::execute`]).
+/// If it could not be submitted, the job is run in the current
+/// thread instead, so that a failure of the ThreadPool does not
+/// drop the packet being processed
+#[doc(hidden)]
+fn execute_or_inline<E, J>(execute: E, job: J)
+where
+    E: FnOnce(Box<dyn FnOnce() + Send>) -> Result<(), ThreadPoolError>,
+    J: FnOnce() + Send + 'static,
+{
+    let slot = Arc::new(Mutex::new(Some(job)));
+    let slot_copy = slot.clone();
+    let submitted = execute(Box::new(move || {
+        let job = slot_copy
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(job) = job {
+            job();
+        }
+    }));
+    if let Err(err) = submitted {
+        error!(
+            "No se pudo enviar la tarea al ThreadPool ({}), se procesa en el mismo thread",
+            err
+        );
+        let job = slot.lock().unwrap_or_else(PoisonError::into_inner).take();
+        if let Some(job) = job {
+            job();
+        }
+    }
+}
+
+/// Checks that the topic does not have a leading slash,
+/// a trailing slash or an empty level. Otherwise, it
+/// returns an error of kind [`ServerErrorKind::ProtocolViolation`]
+#[doc(hidden)]
+fn check_topic_levels(topic: &str) -> ServerResult<()> {
+    if topic.split(TOPIC_LEVEL_SEP).any(|level| level.is_empty()) {
+        return Err(ServerError::new_kind(
+            format!("Topic con niveles vacios: {}", topic),
+            ServerErrorKind::ProtocolViolation,
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that the payload of the publish is not bigger than
+/// `max_size`. Otherwise, it returns an error of kind
+/// [`ServerErrorKind::ProtocolViolation`]
+#[doc(hidden)]
+fn check_message_size(publish: &Publish, max_size: usize) -> ServerResult<()> {
+    if publish.payload().len() > max_size {
+        return Err(ServerError::new_kind(
+            format!(
+                "PUBLISH de {} bytes en {}, el maximo es {}",
+                publish.payload().len(),
+                publish.topic_name(),
+                max_size
+            ),
+            ServerErrorKind::ProtocolViolation,
+        ));
+    }
+    Ok(())
+}
+
 impl<C: Config> Server<C> {
-    /// Submit a job to the ThreadPool
+    /// Waits for the packets that are being processed in the
+    /// ThreadPool to finish, and shuts it down. From then on,
+    /// packets are processed in the thread that received them
+    pub fn drain_pool(&self) -> ServerResult<()> {
+        let pool = self.pool.lock()?.clone();
+        pool.shutdown();
+        Ok(())
+    }
+
+    /// Submit a job to the ThreadPool. If the ThreadPool fails
+    /// to accept it, the job is processed in the current thread
     fn to_threadpool<F>(self: &Arc<Self>, action: F, id: &ClientIdArg) -> ServerResult<()>
     where
-        F: FnOnce(Arc<Self>, &ClientId) -> ServerResult<()> + Send + 'static,
+        F: FnOnce(Arc<Self>, &ClientIdArg) -> ServerResult<()> + Send + 'static,
     {
         let sv_copy = self.clone();
         let id_copy = id.to_owned();
-        self.pool.lock()?.execute(move || {
-            action(sv_copy, &id_copy).unwrap_or_else(|e| {
-                if e.kind() != ServerErrorKind::ClientNotFound
-                    && e.kind() != ServerErrorKind::ClientDisconnected
-                {
-                    error!("{}", e);
-                }
-            });
-        })?;
+        // Se clona para no mantener el lock si la tarea se procesa en este thread
+        let pool = self.pool.lock()?.clone();
+        // El span del cliente se propaga al thread que procesa el paquete
+        let span = Span::current();
+        execute_or_inline(
+            |job| pool.execute(job),
+            move || {
+                let _entered = span.enter();
+                action(sv_copy, &id_copy).unwrap_or_else(|e| {
+                    if e.kind() != ServerErrorKind::ClientNotFound
+                        && e.kind() != ServerErrorKind::ClientDisconnected
+                    {
+                        error!("{}", e);
+                    }
+                });
+            },
+        );
         Ok(())
     }
 
@@ -38,6 +181,15 @@ impl<C: Config> Server<C> {
         match packet_type {
             PacketType::Publish => {
                 let publish = Publish::read_from(stream, control_byte)?;
+                if self.config.strict_topic_levels() {
+                    check_topic_levels(publish.topic_name())?;
+                }
+                if let Some(max_size) = self.config.max_incoming_message_size() {
+                    check_message_size(&publish, max_size)?;
+                }
+                // Se espera en el thread del cliente y no en el ThreadPool,
+                // para no demorar los paquetes de los demas clientes
+                self.throttle_publish()?;
                 self.to_threadpool(|server, id| server.handle_publish(publish, id), id)?;
             }
             PacketType::Puback => {
@@ -48,10 +200,20 @@ impl<C: Config> Server<C> {
             }
             PacketType::Subscribe => {
                 let subscribe = Subscribe::read_from(stream, control_byte)?;
+                if self.config.strict_topic_levels() {
+                    for topic in subscribe.topics() {
+                        check_topic_levels(topic.name())?;
+                    }
+                }
                 self.to_threadpool(|server, id| server.handle_subscribe(subscribe, id), id)?;
             }
             PacketType::Unsubscribe => {
                 let unsubscribe = Unsubscribe::read_from(stream, control_byte)?;
+                if self.config.strict_topic_levels() {
+                    for topic in unsubscribe.topic_filters() {
+                        check_topic_levels(topic.name())?;
+                    }
+                }
                 self.to_threadpool(|server, id| server.handle_unsubscribe(unsubscribe, id), id)?;
             }
             PacketType::PingReq => {
@@ -78,7 +240,7 @@ impl<C: Config> Server<C> {
     ///
     /// In case the client associated with the stream has disconnected,
     /// it returns an error of kin [`ServerErrorKind::ClientDisconnected`]
-    #[instrument(skip(self, stream, id))]
+    #[instrument(skip(self, stream, id) fields(client_id = %id))]
     pub fn process_packet<T: Read>(
         self: &Arc<Self>,
         stream: &mut T,
@@ -96,12 +258,78 @@ impl<C: Config> Server<C> {
         client_id_receiver: ClientId,
         publish: Publish,
     ) -> ServerResult<()> {
-        self.clients_manager
-            .read()?
-            .client_do(&client_id_receiver, |client| client.send_publish(publish))
+        if self.deliver_local(&client_id_receiver, &publish)? {
+            return Ok(());
+        }
+        if self.is_oversized(&publish, &client_id_receiver) {
+            return Ok(());
+        }
+        let became_slow =
+            self.clients_manager
+                .read()?
+                .client_do(&client_id_receiver, |client| {
+                    let was_slow = client.is_slow_consumer();
+                    match client.send_publish(publish) {
+                        Err(err) if err.kind() == ServerErrorKind::Timeout => {
+                            // El cliente no esta leyendo, se lo desconecta para no
+                            // bloquear el envio a los demas
+                            warn!(
+                                "<{}>: Timeout enviando PUBLISH - Desconectando",
+                                client_id_receiver
+                            );
+                            client.close_connection()?;
+                            Ok(false)
+                        }
+                        Err(err) if err.kind() == ServerErrorKind::QueueFull => {
+                            // No se puede descartar un QoS 1 en silencio: se desconecta
+                            // al cliente, que no esta consumiendo lo que se le envia
+                            warn!("{} - Desconectando", err);
+                            client.close_connection()?;
+                            Ok(false)
+                        }
+                        result => {
+                            result?;
+                            Ok(!was_slow && client.is_slow_consumer())
+                        }
+                    }
+                })?;
+        // Se notifica sin el lock de los clientes, por si el listener
+        // quiere actuar sobre el cliente
+        if became_slow {
+            warn!(
+                "<{}>: Cliente lento - Se retienen sus envios",
+                client_id_receiver
+            );
+            self.notify(ServerEvent::SlowConsumer {
+                id: client_id_receiver.to_string(),
+            })?;
+        }
+        Ok(())
     }
 
-    #[instrument(skip(self, threadpool_copy, message), fields(client_id_receiver = %message.client_id))]
+    /// Returns true if the payload of the publish exceeds
+    /// [`Config::max_outgoing_message_size`], in which case it must
+    /// not be delivered to the client. Each of them is counted in
+    /// `oversized_deliveries`
+    #[doc(hidden)]
+    fn is_oversized(&self, publish: &Publish, id: &ClientIdArg) -> bool {
+        match self.config.max_outgoing_message_size() {
+            Some(max_size) if publish.payload().len() > max_size => {
+                debug!(
+                    "<{}>: Descartando PUBLISH de {} bytes en {}, el maximo es {}",
+                    id,
+                    publish.payload().len(),
+                    publish.topic_name(),
+                    max_size
+                );
+                self.oversized_deliveries.fetch_add(1, Ordering::Relaxed);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    #[instrument(skip(self, threadpool_copy, message), fields(client_id = %message.client_id))]
     #[inline]
     fn publish_dispatch(
         self: &Arc<Self>,
@@ -112,8 +340,11 @@ impl<C: Config> Server<C> {
         let publish = message.packet;
         debug!("Enviando PUBLISH");
         let sv_copy = self.clone();
+        // El span del cliente se propaga al thread que envia el PUBLISH
+        let span = Span::current();
         threadpool_copy
             .execute(move || {
+                let _entered = span.enter();
                 sv_copy
                     ._send_publish(client_id_receiver, publish)
                     .unwrap_or_else(|e| {
@@ -149,56 +380,356 @@ impl<C: Config> Server<C> {
         Ok(())
     }
 
-    /// Send [`Publish`] to all clients that are subscribed to the topic
-    fn broadcast_publish(self: &Arc<Self>, publish: Publish) -> ServerResult<()> {
+    /// Starts a new publish dispatcher in the ThreadPool, and returns
+    /// the channel through which it receives the packets to be published
+    fn spawn_publish_dispatcher(self: &Arc<Self>) -> ServerResult<Sender<Message>> {
         let (sender, receiver) = mpsc::channel();
         let sv_copy = self.clone();
+        let span = Span::current();
         self.pool.lock()?.execute(move || {
+            let _entered = span.enter();
             sv_copy
                 .publish_dispatcher_loop(receiver)
                 .unwrap_or_else(|e| error!("Error despachando el PUBLISH: {}", e));
         })?;
+        Ok(sender)
+    }
 
-        self.topic_handler.publish(&publish, sender)?;
+    /// Send [`Publish`] to all clients that are subscribed to the topic
+    ///
+    /// If the dispatcher dies while sending the packets (for example,
+    /// because it panicked), a new one is started to send the rest
+    ///
+    /// If the subscribers could not be found without blocking (see
+    /// [`Config::publish_lock_retries`]), the publish is retried
+    /// until they are
+    ///
+    /// It does not wait for the global publish rate (see
+    /// [`Server::throttle_publish`]), so that it can be called from
+    /// the ThreadPool
+    fn broadcast_publish(self: &Arc<Self>, publish: Publish) -> ServerResult<()> {
+        self.broadcast_publish_then(publish, |_| Ok(()))
+    }
+
+    /// Same as [`Server::broadcast_publish`], but `on_accept` is called
+    /// once the topic handler has accepted the publish (that is, once
+    /// it was queued for all of its subscribers)
+    fn broadcast_publish_then<F>(
+        self: &Arc<Self>,
+        publish: Publish,
+        on_accept: F,
+    ) -> ServerResult<()>
+    where
+        F: FnOnce(&Arc<Self>) -> ServerResult<()> + Send + 'static,
+    {
+        self.broadcast_publish_from(publish, None, on_accept)
+    }
+
+    /// Same as [`Server::broadcast_publish_then`], but the messages
+    /// delivered are tagged with the `origin` of the publish, if any
+    fn broadcast_publish_from<F>(
+        self: &Arc<Self>,
+        publish: Publish,
+        origin: Option<Origin>,
+        on_accept: F,
+    ) -> ServerResult<()>
+    where
+        F: FnOnce(&Arc<Self>) -> ServerResult<()> + Send + 'static,
+    {
+        self._broadcast_publish(publish, origin, on_accept)
+    }
+
+    /// If the global publish rate is limited (see
+    /// [`Config::max_global_publishes_per_second`]), waits until
+    /// a new publish is allowed.
+    ///
+    /// It must be called before a publish is handed to the ThreadPool,
+    /// never from it, so that the threads of the pool are not kept
+    /// waiting while other packets (PUBACK, SUBSCRIBE, ...) are pending
+    fn throttle_publish(&self) -> ServerResult<()> {
+        if let Some(limiter) = &self.publish_limiter {
+            limiter.acquire()?;
+        }
         Ok(())
     }
 
+    #[doc(hidden)]
+    fn _broadcast_publish<F>(
+        self: &Arc<Self>,
+        publish: Publish,
+        origin: Option<Origin>,
+        on_accept: F,
+    ) -> ServerResult<()>
+    where
+        F: FnOnce(&Arc<Self>) -> ServerResult<()> + Send + 'static,
+    {
+        match self.config.delivery_channel_capacity() {
+            Some(capacity) => {
+                let (sender, receiver) = mpsc::sync_channel(capacity);
+                self.spawn_supervised_dispatch(receiver)?;
+                let sink = BoundedSink::new(
+                    sender,
+                    self.config.delivery_channel_block(),
+                    self.dropped_deliveries.clone(),
+                );
+                self.route_publish(&publish, &sink, &origin)?;
+            }
+            None => {
+                let (sender, receiver) = mpsc::channel();
+                self.spawn_supervised_dispatch(receiver)?;
+                self.route_publish(&publish, &sender, &origin)?;
+            }
+        }
+        on_accept(self)
+    }
+
+    /// Starts in the ThreadPool the delivery of the messages
+    /// received through `receiver`
+    fn spawn_supervised_dispatch(
+        self: &Arc<Self>,
+        receiver: Receiver<Message>,
+    ) -> ServerResult<()> {
+        let sv_copy = self.clone();
+        let span = Span::current();
+        self.pool.lock()?.execute(move || {
+            let _entered = span.enter();
+            supervised_dispatch(receiver, || sv_copy.spawn_publish_dispatcher())
+                .unwrap_or_else(|e| error!("Error despachando el PUBLISH: {}", e));
+        })?;
+        Ok(())
+    }
+
+    /// Delivers into `sink` a [`Message`] for each subscriber of the
+    /// publish, tagged with its `origin` if it has one.
+    ///
+    /// If the subscribers could not be found without blocking (see
+    /// [`Config::publish_lock_retries`]), nothing was delivered, so it
+    /// is retried right away. It is not queued in the ThreadPool, so
+    /// that the publishes of a client are not reordered ([MQTT-4.6.0-6])
+    fn route_publish<S: MessageSink>(
+        &self,
+        publish: &Publish,
+        sink: &S,
+        origin: &Option<Origin>,
+    ) -> Result<(), TopicHandlerError> {
+        loop {
+            let result = match origin {
+                Some(origin) => self.topic_handler.publish_from(publish, sink, origin),
+                None => self.topic_handler.publish(publish, sink),
+            };
+            match result {
+                Err(err) if err.kind() == TopicHandlerErrorKind::WouldBlock => {
+                    debug!("Suscripciones ocupadas, reintentando el PUBLISH");
+                    thread::sleep(WOULD_BLOCK_RETRY_FREQ);
+                }
+                result => return result,
+            }
+        }
+    }
+
     /// Publish the packet so that all clients subscribed
     /// to the topics can receive them
+    ///
+    /// If the packet has QoS 1, the [`Puback`] is sent once the topic
+    /// handler has accepted it, so that a publish is never acknowledged
+    /// before it is queued for its subscribers, unless
+    /// [`Config::early_puback`] is set.
+    ///
+    /// If it is a retained publish rejected because the maximum number of
+    /// retained messages was reached (see [`Config::retained_overflow`]),
+    /// it is dropped without delivering it, but it is still acknowledged
     pub fn handle_publish(
         self: &Arc<Self>,
         mut publish: Publish,
         id: &ClientIdArg,
     ) -> ServerResult<()> {
         publish.set_max_qos(QoSLevel::QoSLevel1);
+        if self.config.retained_refresh() {
+            if let Some(topic_filter) = publish.topic_name().strip_prefix(REFRESH_TOPIC_PREFIX) {
+                return self.handle_refresh(topic_filter, publish.packet_id(), id);
+            }
+        }
+        // Los retenidos vacios siempre se procesan, porque borran el mensaje retenido
+        if self.config.drop_empty_publishes()
+            && !publish.retain_flag()
+            && publish.payload().is_empty()
+        {
+            debug!(
+                "<{}>: Descartando PUBLISH vacio en {}",
+                id,
+                publish.topic_name()
+            );
+            if let Some(packet_id) = publish.packet_id() {
+                self.send_puback(packet_id, id)?;
+            }
+            return Ok(());
+        }
         let packet_id = publish.packet_id();
-        self.broadcast_publish(publish)?;
-        if let Some(packet_id) = packet_id {
-            self.clients_manager
-                .read()?
-                .client_do(id, |client| client.send_packet(&Puback::new(packet_id)?))?;
+        let result = match packet_id {
+            Some(packet_id) if self.config.early_puback() => {
+                self.send_puback(packet_id, id)?;
+                self.broadcast_publish(publish)
+            }
+            Some(packet_id) => {
+                let id = id.to_owned();
+                self.broadcast_publish_then(publish, move |server| {
+                    server.send_puback(packet_id, &id)
+                })
+            }
+            None => self.broadcast_publish(publish),
+        };
+        match result {
+            Err(err) if err.kind() == ServerErrorKind::RetainedRejected => {
+                // No se entrego a nadie, pero se confirma igual para que el
+                // cliente no lo reenvie indefinidamente
+                warn!("<{}>: {} - Se descarta el PUBLISH", id, err);
+                match packet_id {
+                    Some(packet_id) if !self.config.early_puback() => {
+                        self.send_puback(packet_id, id)
+                    }
+                    _ => Ok(()),
+                }
+            }
+            result => result,
         }
-        Ok(())
+    }
+
+    /// Sends again to the client the retained messages that match the
+    /// topic filter, of the topics it is subscribed to. The [`Publish`]
+    /// to `$refresh/<topic filter>` that requested them is acknowledged
+    /// (if it has QoS 1), but it is not routed to any subscriber
+    fn handle_refresh(
+        &self,
+        topic_filter: &str,
+        packet_id: Option<u16>,
+        id: &ClientIdArg,
+    ) -> ServerResult<()> {
+        debug!(
+            "<{}>: Reenviando mensajes retenidos de {}",
+            id, topic_filter
+        );
+        self.clients_manager.read()?.client_do(id, |client| {
+            if let Some(packet_id) = packet_id {
+                client.send_packet(&Puback::new(packet_id)?)?;
+            }
+            for retained in self.topic_handler.retained_for(topic_filter, client.id())? {
+                if !self.is_oversized(&retained, id) {
+                    client.send_publish(retained)?;
+                }
+            }
+            Ok(())
+        })
+    }
+
+    #[doc(hidden)]
+    /// Sends a [`Puback`] with the given packet identifier to the client
+    fn send_puback(&self, packet_id: u16, id: &ClientIdArg) -> ServerResult<()> {
+        self.clients_manager
+            .read()?
+            .client_do(id, |client| client.send_packet(&Puback::new(packet_id)?))
+    }
+
+    /// Publishes a [`Publish`] originated in the server itself (for
+    /// example, from a bridge or an administration tool), as if it had
+    /// been sent by a client with id [`SERVER_SOURCE_ID`]
+    ///
+    /// It can be called from any thread. If the global publish rate is
+    /// limited, it waits in the calling thread until it is allowed
+    #[instrument(skip(self, publish), fields(id = SERVER_SOURCE_ID, topic = publish.topic_name()))]
+    pub fn publish(self: &Arc<Self>, mut publish: Publish) -> ServerResult<()> {
+        if self.config.strict_topic_levels() {
+            check_topic_levels(publish.topic_name())?;
+        }
+        debug!("Publicando mensaje del servidor");
+        publish.set_max_qos(QoSLevel::QoSLevel1);
+        self.throttle_publish()?;
+        self.broadcast_publish(publish)
+    }
+
+    /// Publishes a [`Publish`] received from another broker (for example,
+    /// through a bridge), tagged with the `origin` it was received with.
+    /// If it came back to this broker (see [`Config::broker_id`]) or it
+    /// was forwarded too many times, it is dropped and false is returned,
+    /// so that publishes do not loop between bridged brokers
+    ///
+    /// It can be called from any thread
+    #[instrument(skip(self, publish), fields(id = SERVER_SOURCE_ID, topic = publish.topic_name()))]
+    pub fn publish_bridged(
+        self: &Arc<Self>,
+        mut publish: Publish,
+        origin: Origin,
+    ) -> ServerResult<bool> {
+        if origin.has_looped(self.config.broker_id(), MAX_BRIDGE_HOPS) {
+            debug!(
+                "Descartando PUBLISH originado en {} ({} saltos)",
+                origin.broker_id, origin.hops
+            );
+            return Ok(false);
+        }
+        if self.config.strict_topic_levels() {
+            check_topic_levels(publish.topic_name())?;
+        }
+        debug!("Publicando mensaje de otro broker");
+        publish.set_max_qos(QoSLevel::QoSLevel1);
+        self.throttle_publish()?;
+        self.broadcast_publish_from(publish, Some(origin.forwarded()), |_| Ok(()))?;
+        Ok(true)
+    }
+
+    /// Matches a [`Publish`] against the subscriptions synchronously and
+    /// returns the number of messages that would be delivered. It is not
+    /// sent to the clients, stored as retained nor counted
+    ///
+    /// Meant for benchmarks of the matching and fan-out logic
+    /// without network overhead
+    #[cfg(any(test, feature = "bench"))]
+    pub fn publish_and_wait(&self, publish: Publish) -> ServerResult<usize> {
+        Ok(self.topic_handler.count_deliveries(&publish)?)
     }
 
     /// Subscribes the client to all the topics specified in the
     /// [`Subscribe`] packet
     /// Send the corresponding Suback
+    ///
+    /// The client is locked from the moment it is subscribed until
+    /// the Suback is sent, so that no matching publish can be sent
+    /// to it before the Suback ([MQTT-3.8.4-2])
     fn handle_subscribe(&self, mut subscribe: Subscribe, id: &ClientIdArg) -> ServerResult<()> {
         subscribe.set_max_qos(QoSLevel::QoSLevel1);
-        let retained_messages = self.topic_handler.subscribe(&subscribe, id)?;
-        self.clients_manager
-            .read()?
-            .client_do(id, |client| client.send_packet(&subscribe.response()?))?;
-        if !retained_messages.is_empty() {
-            self.clients_manager.read()?.client_do(id, |client| {
-                for retained in retained_messages {
+        for (pattern, max_qos) in self.config.topic_qos_ceiling() {
+            subscribe.set_max_qos_matching(*max_qos, |topic| {
+                TopicHandler::matches(pattern, topic.name())
+            });
+        }
+        let max_subscriptions = self.config.max_subscriptions_per_client();
+        self.clients_manager.read()?.client_do(id, |client| {
+            let mut accepted = Vec::new();
+            let mut results = Vec::new();
+            for topic in subscribe.topics() {
+                if client.add_subscription(topic.name(), max_subscriptions) {
+                    results.push(Ok(topic.qos()));
+                    accepted.push(topic);
+                } else {
+                    warn!(
+                        "<{}>: Limite de suscripciones alcanzado, se rechaza {}",
+                        id,
+                        topic.name()
+                    );
+                    results.push(Err(()));
+                }
+            }
+            let packet_id = subscribe.packet_identifier();
+            let retained_messages = self
+                .topic_handler
+                .subscribe(&Subscribe::new(accepted, packet_id), client.id())?;
+            client.send_packet(&Suback::from_results(results, packet_id))?;
+            for retained in retained_messages {
+                if !self.is_oversized(&retained, id) {
                     client.send_publish(retained)?;
                 }
-                Ok(())
-            })?;
-        }
-        Ok(())
+            }
+            Ok(())
+        })
     }
 
     /// Unsubscribe the client from the topics specified in the
@@ -206,8 +737,12 @@ impl<C: Config> Server<C> {
     /// Send the corresponding [`Unsuback`]
     fn handle_unsubscribe(&self, unsubscribe: Unsubscribe, id: &ClientIdArg) -> ServerResult<()> {
         let packet_id = unsubscribe.packet_id();
-        self.topic_handler.unsubscribe(unsubscribe, id)?;
+        let topic_filters = unsubscribe.topic_filters();
         self.clients_manager.read()?.client_do(id, |client| {
+            self.topic_handler.unsubscribe(unsubscribe, client.id())?;
+            for topic_filter in topic_filters {
+                client.remove_subscription(topic_filter.name());
+            }
             client.send_packet(&Unsuback::new(packet_id)?)?;
             Ok(())
         })?;
@@ -225,12 +760,38 @@ impl<C: Config> Server<C> {
         debug!("Enviando LAST WILL");
         last_will.set_max_qos(QoSLevel::QoSLevel1);
 
+        self.throttle_publish()?;
         self.broadcast_publish(last_will)
     }
 
+    /// Publishes a retained message to `<prefix>/<id>/status` with
+    /// the presence of the client (`online` or `offline`), if the
+    /// presence topic prefix is configured
+    pub fn publish_presence(self: &Arc<Self>, id: &ClientIdArg, online: bool) -> ServerResult<()> {
+        if let Some(prefix) = self.config.presence_topic_prefix() {
+            let topic = format!("{}/{}/status", prefix, id);
+            let payload = if online {
+                PRESENCE_ONLINE
+            } else {
+                PRESENCE_OFFLINE
+            };
+            debug!("Publicando presencia: {}", payload);
+            self.publish(Publish::new(
+                false,
+                QoSLevel::QoSLevel0,
+                true,
+                &topic,
+                payload,
+                None,
+            )?)?;
+        }
+        Ok(())
+    }
+
     /// Waits until it receives the [`Connect`] packet. In case the
     /// read fails due to timeout, it returns an error of kind
-    /// [`ServerErrorKind::Timeout`]
+    /// [`ServerErrorKind::Timeout`]. The client id is validated
+    /// later, by the [`ClientsManager`](crate::clients_manager::ClientsManager)
     #[instrument(skip(self, network_connection))]
     pub fn wait_for_connect(
         &self,
@@ -256,3 +817,239 @@ impl<C: Config> Server<C> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            mpsc, Arc,
+        },
+        thread,
+    };
+
+    use packets::{
+        publish::Publish, qos::QoSLevel, subscribe::Subscribe, topic_filter::TopicFilter,
+    };
+
+    use threadpool::ThreadPoolError;
+
+    use super::{check_message_size, check_topic_levels, execute_or_inline, supervised_dispatch};
+    use crate::{
+        client_id::ClientId,
+        test_helpers::{client_id, server::test_server},
+        topic_handler::{Message, Origin},
+    };
+
+    fn make_message(id: &str) -> Message {
+        Message {
+            client_id: client_id(id),
+            packet: Publish::new(false, QoSLevel::QoSLevel0, false, "top", "msg", None).unwrap(),
+            origin: None,
+        }
+    }
+
+    #[test]
+    fn test_supervised_dispatch_restarts_dead_dispatcher() {
+        let (sender, receiver) = mpsc::channel();
+        for id in ["a", "b", "c"] {
+            sender.send(make_message(id)).unwrap();
+        }
+        drop(sender);
+
+        let (live_sender, live_receiver) = mpsc::channel();
+        let spawned = std::cell::Cell::new(0);
+        supervised_dispatch(receiver, || {
+            spawned.set(spawned.get() + 1);
+            if spawned.get() == 1 {
+                // El primer despachador muere antes de recibir nada
+                let (dead_sender, _) = mpsc::channel();
+                Ok(dead_sender)
+            } else {
+                Ok(live_sender.clone())
+            }
+        })
+        .unwrap();
+        drop(live_sender);
+
+        assert_eq!(spawned.get(), 2);
+        let ids: Vec<ClientId> = live_receiver.iter().map(|msg| msg.client_id).collect();
+        assert_eq!(ids, vec![client_id("a"), client_id("b"), client_id("c")]);
+    }
+
+    #[test]
+    fn test_supervised_dispatch_fails_if_dispatcher_cannot_be_restarted() {
+        let (sender, receiver) = mpsc::channel();
+        sender.send(make_message("a")).unwrap();
+        drop(sender);
+
+        let result = supervised_dispatch(receiver, || {
+            let (dead_sender, _) = mpsc::channel();
+            Ok(dead_sender)
+        });
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn test_valid_topic_levels() {
+        assert!(check_topic_levels("a").is_ok());
+        assert!(check_topic_levels("a/b/c").is_ok());
+        assert!(check_topic_levels("a/+/#").is_ok());
+    }
+
+    #[test]
+    fn test_leading_slash() {
+        assert!(check_topic_levels("/a").is_err());
+    }
+
+    #[test]
+    fn test_trailing_slash() {
+        assert!(check_topic_levels("a/").is_err());
+    }
+
+    #[test]
+    fn test_empty_level() {
+        assert!(check_topic_levels("a//b").is_err());
+    }
+
+    #[test]
+    fn test_message_size() {
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel0, false, "top", "12345", None).unwrap();
+        assert!(check_message_size(&publish, 5).is_ok());
+        assert!(check_message_size(&publish, 4).is_err());
+    }
+
+    #[test]
+    fn test_oversized_publishes_are_counted() {
+        let server = test_server("max_outgoing_message_size=4", 1);
+        let publish = |payload: &str| {
+            Publish::new(false, QoSLevel::QoSLevel0, false, "top", payload, None).unwrap()
+        };
+
+        assert!(server.is_oversized(&publish("12345"), "id"));
+        assert!(!server.is_oversized(&publish("1234"), "id"));
+        assert_eq!(server.oversized_deliveries(), 1);
+    }
+
+    #[test]
+    fn test_publish_and_wait_counts_matching_subscribers() {
+        let server = test_server("", 1);
+        for i in 0..10 {
+            let topic = TopicFilter::new("sensors/+", QoSLevel::QoSLevel0).unwrap();
+            let subscribe = Subscribe::new(vec![topic], 1);
+            server
+                .topic_handler
+                .subscribe(&subscribe, &client_id(&format!("client{}", i)))
+                .unwrap();
+        }
+
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel0, true, "sensors/temp", "25", None).unwrap();
+        assert_eq!(server.publish_and_wait(publish).unwrap(), 10);
+        let publish = Publish::new(false, QoSLevel::QoSLevel0, false, "other", "25", None).unwrap();
+        assert_eq!(server.publish_and_wait(publish).unwrap(), 0);
+        // No modifica el estado del servidor
+        assert_eq!(server.retained_count().unwrap(), 0);
+        assert!(server.top_topics(usize::MAX).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_on_accept_runs_after_topic_handler_accepts_publish() {
+        let server = test_server("publish_lock_retries=0", 4);
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Cambios de suscripciones en curso, para que algunos
+        // publish se tengan que reintentar
+        let flood_server = server.clone();
+        let flood_stop = stop.clone();
+        let flood = thread::spawn(move || {
+            while !flood_stop.load(Ordering::Relaxed) {
+                let topic = TopicFilter::new("t/#", QoSLevel::QoSLevel0).unwrap();
+                flood_server
+                    .topic_handler
+                    .subscribe(&Subscribe::new(vec![topic], 1), &client_id("flood"))
+                    .unwrap();
+                flood_server
+                    .topic_handler
+                    .remove_client(&client_id("flood"))
+                    .unwrap();
+            }
+        });
+
+        let (accepted_sender, accepted_receiver) = mpsc::channel();
+        for i in 0..50 {
+            let topic = format!("t/{}", i);
+            let publish =
+                Publish::new(false, QoSLevel::QoSLevel0, false, &topic, "msg", None).unwrap();
+            let accepted_sender = accepted_sender.clone();
+            server
+                .broadcast_publish_then(publish, move |server| {
+                    // El topic handler ya debe haber contado el publish
+                    let counted = server
+                        .top_topics(usize::MAX)?
+                        .iter()
+                        .any(|(name, _)| *name == topic);
+                    accepted_sender.send((topic, counted)).unwrap();
+                    Ok(())
+                })
+                .unwrap();
+        }
+        drop(accepted_sender);
+
+        let accepted: Vec<(String, bool)> = accepted_receiver.iter().collect();
+        stop.store(true, Ordering::Relaxed);
+        flood.join().unwrap();
+        // Los reintentos no cambian el orden de los publish
+        let expected: Vec<(String, bool)> = (0..50).map(|i| (format!("t/{}", i), true)).collect();
+        assert_eq!(accepted, expected);
+    }
+
+    #[test]
+    fn test_publish_bridged_drops_loops() {
+        let server = test_server("broker_id=norte", 4);
+        let publish = |topic: &str| {
+            Publish::new(false, QoSLevel::QoSLevel0, false, topic, "msg", None).unwrap()
+        };
+
+        // Volvio al broker en el que se origino
+        let looped = Origin::new("norte").forwarded().forwarded();
+        assert!(!server.publish_bridged(publish("loop"), looped).unwrap());
+        // Viene de otro broker
+        let bridged = Origin::new("sur").forwarded();
+        assert!(server.publish_bridged(publish("bridge"), bridged).unwrap());
+
+        let topics: Vec<String> = server
+            .top_topics(usize::MAX)
+            .unwrap()
+            .into_iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(topics, vec!["bridge"]);
+    }
+
+    #[test]
+    fn test_execute_or_inline_runs_job_inline_if_execute_fails() {
+        let processed = Arc::new(AtomicBool::new(false));
+        let processed_copy = processed.clone();
+        execute_or_inline(
+            |_job| Err(ThreadPoolError::new()),
+            move || processed_copy.store(true, Ordering::SeqCst),
+        );
+        assert!(processed.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn test_execute_or_inline_runs_job_once_if_execute_succeeds() {
+        let (sender, receiver) = mpsc::channel();
+        execute_or_inline(
+            |job| {
+                job();
+                Ok(())
+            },
+            move || sender.send(()).unwrap(),
+        );
+        assert!(receiver.try_recv().is_ok());
+        assert!(receiver.try_recv().is_err());
+    }
+}
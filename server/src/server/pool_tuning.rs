@@ -0,0 +1,151 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+use threadpool::ThreadPool;
+use tracing::{debug, error};
+
+use super::{Server, ServerResult};
+use crate::traits::Config;
+
+/// How often the server checks the load of its ThreadPool
+/// to decide whether to resize it
+const POOL_TUNING_FREQ: Duration = Duration::from_millis(500);
+
+/// Returns the size the ThreadPool should have, according to
+/// its current load and the number of connected clients.
+///
+/// If there are jobs waiting for a free thread, it grows to
+/// be able to run them all at once. When it is not fully used,
+/// it shrinks by half at most, but it keeps one thread for each
+/// connected client, so that their packets can be processed
+/// concurrently. The result is always within `bounds`
+#[doc(hidden)]
+fn next_pool_size(
+    size: usize,
+    active: usize,
+    pending: usize,
+    connections: usize,
+    (min, max): (usize, usize),
+) -> usize {
+    let next = if pending > 0 {
+        size + pending
+    } else if active < size {
+        (size / 2).max(active).max(connections)
+    } else {
+        size
+    };
+    next.clamp(min, max)
+}
+
+/// Resizes the ThreadPool according to its current load, the
+/// number of connected clients and the given bounds
+#[doc(hidden)]
+fn tune_pool(pool: &ThreadPool, connections: usize, bounds: (usize, usize)) -> ServerResult<()> {
+    let size = pool.size();
+    let next = next_pool_size(
+        size,
+        pool.active_count(),
+        pool.pending_count(),
+        connections,
+        bounds,
+    );
+    if next != size {
+        debug!(
+            "Cambiando el tamaño de la ThreadPool de {} a {}",
+            size, next
+        );
+        pool.set_size(next)?;
+    }
+    Ok(())
+}
+
+impl<C: Config> Server<C> {
+    /// Periodically resizes the ThreadPool within the `bounds`,
+    /// until `shutdown_bool` is set to true
+    pub(super) fn pool_tuning_loop(
+        self: Arc<Self>,
+        bounds: (usize, usize),
+        shutdown_bool: Arc<AtomicBool>,
+    ) {
+        while !shutdown_bool.load(Ordering::Relaxed) {
+            if let Err(err) = self.tune_pool(bounds) {
+                error!("Error ajustando el tamaño de la ThreadPool: {}", err);
+            }
+            thread::sleep(POOL_TUNING_FREQ);
+        }
+    }
+
+    #[doc(hidden)]
+    fn tune_pool(&self, bounds: (usize, usize)) -> ServerResult<()> {
+        let connections = self.clients_manager.read()?.connected_count()?;
+        let pool = self.pool.lock()?.clone();
+        tune_pool(&pool, connections, bounds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{thread, time::Duration};
+
+    use threadpool::ThreadPool;
+
+    use super::{next_pool_size, tune_pool};
+
+    #[test]
+    fn test_grows_with_pending_jobs() {
+        assert_eq!(next_pool_size(4, 4, 3, 0, (2, 16)), 7);
+        assert_eq!(next_pool_size(4, 4, 30, 0, (2, 16)), 16);
+    }
+
+    #[test]
+    fn test_shrinks_when_idle() {
+        assert_eq!(next_pool_size(16, 0, 0, 0, (2, 16)), 8);
+        assert_eq!(next_pool_size(16, 10, 0, 0, (2, 16)), 10);
+        assert_eq!(next_pool_size(3, 0, 0, 0, (2, 16)), 2);
+    }
+
+    #[test]
+    fn test_keeps_a_thread_per_connection() {
+        assert_eq!(next_pool_size(16, 0, 0, 12, (2, 16)), 12);
+        assert_eq!(next_pool_size(8, 0, 0, 100, (2, 16)), 16);
+    }
+
+    #[test]
+    fn test_stays_the_same_when_fully_used() {
+        assert_eq!(next_pool_size(8, 8, 0, 0, (2, 16)), 8);
+    }
+
+    #[test]
+    fn test_pool_grows_under_burst_and_shrinks_after_it() {
+        let bounds = (2, 8);
+        let pool = ThreadPool::new(2);
+
+        // Simulo una ráfaga de paquetes
+        for _ in 0..20 {
+            pool.execute(|| thread::sleep(Duration::from_millis(500)))
+                .unwrap();
+        }
+        for _ in 0..5 {
+            thread::sleep(Duration::from_millis(50));
+            tune_pool(&pool, 0, bounds).unwrap();
+        }
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(pool.size(), 8);
+
+        // Espero a que termine la ráfaga
+        while pool.active_count() + pool.pending_count() > 0 {
+            thread::sleep(Duration::from_millis(100));
+        }
+        for _ in 0..5 {
+            tune_pool(&pool, 0, bounds).unwrap();
+            thread::sleep(Duration::from_millis(50));
+        }
+        assert_eq!(pool.size(), 2);
+    }
+}
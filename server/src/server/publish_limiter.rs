@@ -0,0 +1,113 @@
+use std::{
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+use super::ServerResult;
+use crate::traits::Config;
+
+/// Tokens available in a [`PublishLimiter`], and when
+/// they were last refilled
+#[doc(hidden)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+/// Token bucket shared by every thread that publishes, which
+/// limits how many publishes per second the server routes
+/// (see [`Config::max_global_publishes_per_second`](crate::traits::Config::max_global_publishes_per_second))
+///
+/// It allows bursts of up to one second worth of publishes.
+/// Once they are exhausted, publishes are delayed until a new
+/// token is available, instead of being dropped
+pub(super) struct PublishLimiter {
+    rate: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl PublishLimiter {
+    /// Creates a limiter that allows `rate` publishes per second.
+    /// `rate` must be greater than zero
+    pub fn new(rate: u32) -> Self {
+        let rate = f64::from(rate.max(1));
+        Self {
+            rate,
+            bucket: Mutex::new(Bucket {
+                tokens: rate,
+                last_refill: Instant::now(),
+            }),
+        }
+    }
+
+    /// Creates the limiter specified by the configuration, if any
+    pub fn from_config<C: Config>(config: &C) -> Option<Self> {
+        config
+            .max_global_publishes_per_second()
+            .filter(|rate| *rate > 0)
+            .map(PublishLimiter::new)
+    }
+
+    /// Takes a token from the bucket, waiting until one
+    /// is available
+    pub fn acquire(&self) -> ServerResult<()> {
+        loop {
+            let wait = {
+                let mut bucket = self.bucket.lock()?;
+                let now = Instant::now();
+                let elapsed = now.duration_since(bucket.last_refill).as_secs_f64();
+                bucket.tokens = (bucket.tokens + elapsed * self.rate).min(self.rate);
+                bucket.last_refill = now;
+                if bucket.tokens >= 1.0 {
+                    bucket.tokens -= 1.0;
+                    return Ok(());
+                }
+                Duration::from_secs_f64((1.0 - bucket.tokens) / self.rate)
+            };
+            thread::sleep(wait);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        sync::Arc,
+        thread,
+        time::{Duration, Instant},
+    };
+
+    use super::PublishLimiter;
+
+    #[test]
+    fn test_burst_is_not_delayed() {
+        let limiter = PublishLimiter::new(10);
+        let start = Instant::now();
+        for _ in 0..10 {
+            limiter.acquire().unwrap();
+        }
+        assert!(start.elapsed() < Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_publishes_over_rate_are_delayed() {
+        let limiter = Arc::new(PublishLimiter::new(10));
+        let start = Instant::now();
+        // 3 threads toman 10 tokens cada uno, 10 de ellos sin esperar
+        let handles: Vec<_> = (0..3)
+            .map(|_| {
+                let limiter = limiter.clone();
+                thread::spawn(move || {
+                    for _ in 0..10 {
+                        limiter.acquire().unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(1900));
+    }
+}
@@ -0,0 +1,240 @@
+use std::{
+    io,
+    net::TcpListener,
+    sync::atomic::{AtomicBool, Ordering},
+    thread,
+    time::Duration,
+};
+
+use backoff::Backoff;
+use tracing::{error, info, warn};
+
+use super::{Server, ServerResult};
+use crate::traits::Config;
+
+/// How many times the server tries to bind its listener again
+/// after a fatal error accepting connections, before shutting down
+const REBIND_ATTEMPTS: u32 = 5;
+/// How long the server waits before the first attempt to bind
+/// its listener again
+const REBIND_BASE_DELAY: Duration = Duration::from_millis(200);
+/// By how much the wait between attempts is multiplied
+/// after each failed one
+const REBIND_BACKOFF_FACTOR: u32 = 2;
+/// The maximum time the server waits between attempts
+const REBIND_MAX_DELAY: Duration = Duration::from_secs(5);
+/// How long the server waits before accepting connections again
+/// after running out of resources for the first time in a row
+const EXHAUSTED_BASE_DELAY: Duration = Duration::from_millis(10);
+/// By how much the wait is multiplied each time the server
+/// runs out of resources in a row
+const EXHAUSTED_BACKOFF_FACTOR: u32 = 2;
+/// The maximum time the server waits after running out of resources
+const EXHAUSTED_MAX_DELAY: Duration = Duration::from_secs(1);
+/// EMFILE: the process reached its limit of open file descriptors
+const EMFILE: i32 = 24;
+/// ENFILE: the system reached its limit of open file descriptors
+const ENFILE: i32 = 23;
+
+/// Returns true if an error accepting a connection only affects that
+/// connection (for example, if the client aborted it before it was
+/// accepted), so the listener can keep accepting other ones.
+///
+/// The rest, such as running out of file descriptors, are considered
+/// errors of the listener
+pub(super) fn is_connection_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::PermissionDenied
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+            | io::ErrorKind::NetworkDown
+    )
+}
+
+/// Returns true if an error accepting a connection happened because
+/// the process or the system ran out of resources (for example, file
+/// descriptors). The listener is still valid, so the server only has
+/// to wait until some of them are released to keep accepting
+pub(super) fn is_resource_exhaustion(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::OutOfMemory
+        || matches!(error.raw_os_error(), Some(EMFILE) | Some(ENFILE))
+}
+
+/// Returns the backoff to wait with while the server keeps running
+/// out of resources to accept connections
+pub(super) fn exhaustion_backoff() -> Backoff {
+    Backoff::new(
+        EXHAUSTED_BASE_DELAY,
+        EXHAUSTED_BACKOFF_FACTOR,
+        EXHAUSTED_MAX_DELAY,
+    )
+}
+
+/// Calls `bind` until it succeeds, at most `attempts` times, waiting
+/// according to `backoff` before each attempt. It gives up early if
+/// `shutdown` is set while waiting.
+///
+/// Returns None if every attempt failed or the server is shutting down
+#[doc(hidden)]
+fn retry_with_backoff<T, F>(
+    mut bind: F,
+    attempts: u32,
+    mut backoff: Backoff,
+    shutdown: &AtomicBool,
+) -> Option<T>
+where
+    F: FnMut() -> ServerResult<T>,
+{
+    for attempt in 1..=attempts {
+        thread::sleep(backoff.next_delay());
+        if shutdown.load(Ordering::Relaxed) {
+            return None;
+        }
+        match bind() {
+            Ok(bound) => return Some(bound),
+            Err(e) => warn!(
+                "Intento {}/{} de volver a escuchar conexiones fallido: {}",
+                attempt, attempts, e
+            ),
+        }
+    }
+    None
+}
+
+impl<C: Config> Server<C> {
+    /// Binds a non blocking listener to the address of the server
+    pub(super) fn bind_listener(&self) -> ServerResult<TcpListener> {
+        let listener = TcpListener::bind(format!("{}:{}", self.config.ip(), self.config.port()))?;
+        listener.set_nonblocking(true)?;
+        Ok(listener)
+    }
+
+    /// Replaces a listener that failed to accept connections, binding
+    /// a new one to the same address. It is retried with exponential
+    /// backoff, so that a transient error does not shut the server down.
+    ///
+    /// It must not be used if the server only ran out of resources (see
+    /// [`is_resource_exhaustion`]), since the listener is still valid
+    ///
+    /// Returns None if the listener could not be bound again or the
+    /// server is shutting down
+    pub(super) fn rebind_listener(
+        &self,
+        listener: TcpListener,
+        shutdown: &AtomicBool,
+    ) -> Option<TcpListener> {
+        // Se libera el puerto antes de volver a tomarlo
+        drop(listener);
+        let backoff = Backoff::new(REBIND_BASE_DELAY, REBIND_BACKOFF_FACTOR, REBIND_MAX_DELAY);
+        let listener =
+            retry_with_backoff(|| self.bind_listener(), REBIND_ATTEMPTS, backoff, shutdown);
+        match &listener {
+            Some(_) => info!("Se volvio a escuchar conexiones"),
+            None => error!("No se pudo volver a escuchar conexiones"),
+        }
+        listener
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::Cell, io, sync::atomic::AtomicBool, time::Duration};
+
+    use backoff::Backoff;
+
+    use super::{is_connection_error, is_resource_exhaustion, retry_with_backoff};
+    use crate::server::ServerError;
+
+    fn backoff() -> Backoff {
+        Backoff::new(Duration::from_millis(1), 2, Duration::from_millis(10))
+    }
+
+    #[test]
+    fn test_recovers_after_transient_failures() {
+        let calls = Cell::new(0);
+        let shutdown = AtomicBool::new(false);
+
+        let result = retry_with_backoff(
+            || {
+                calls.set(calls.get() + 1);
+                if calls.get() < 3 {
+                    Err(ServerError::new_msg("Too many open files"))
+                } else {
+                    Ok("listener")
+                }
+            },
+            5,
+            backoff(),
+            &shutdown,
+        );
+
+        assert_eq!(result, Some("listener"));
+        assert_eq!(calls.get(), 3);
+    }
+
+    #[test]
+    fn test_gives_up_after_all_attempts() {
+        let calls = Cell::new(0);
+        let shutdown = AtomicBool::new(false);
+
+        let result: Option<()> = retry_with_backoff(
+            || {
+                calls.set(calls.get() + 1);
+                Err(ServerError::new_msg("Too many open files"))
+            },
+            5,
+            backoff(),
+            &shutdown,
+        );
+
+        assert!(result.is_none());
+        assert_eq!(calls.get(), 5);
+    }
+
+    #[test]
+    fn test_does_not_retry_while_shutting_down() {
+        let calls = Cell::new(0);
+        let shutdown = AtomicBool::new(true);
+
+        let result: Option<()> = retry_with_backoff(
+            || {
+                calls.set(calls.get() + 1);
+                Ok(())
+            },
+            5,
+            backoff(),
+            &shutdown,
+        );
+
+        assert!(result.is_none());
+        assert_eq!(calls.get(), 0);
+    }
+
+    #[test]
+    fn test_connection_errors_do_not_affect_the_listener() {
+        let aborted = io::Error::from(io::ErrorKind::ConnectionAborted);
+        assert!(is_connection_error(&aborted));
+        let reset = io::Error::from(io::ErrorKind::ConnectionReset);
+        assert!(is_connection_error(&reset));
+        // EMFILE: el proceso se quedó sin file descriptors
+        let too_many_files = io::Error::from_raw_os_error(24);
+        assert!(!is_connection_error(&too_many_files));
+    }
+
+    #[test]
+    fn test_running_out_of_file_descriptors_is_resource_exhaustion() {
+        // EMFILE y ENFILE: se agotaron los file descriptors
+        assert!(is_resource_exhaustion(&io::Error::from_raw_os_error(24)));
+        assert!(is_resource_exhaustion(&io::Error::from_raw_os_error(23)));
+        let aborted = io::Error::from(io::ErrorKind::ConnectionAborted);
+        assert!(!is_resource_exhaustion(&aborted));
+        let invalid = io::Error::from(io::ErrorKind::InvalidInput);
+        assert!(!is_resource_exhaustion(&invalid));
+    }
+}
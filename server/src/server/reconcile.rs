@@ -0,0 +1,94 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::{Duration, Instant},
+};
+
+use tracing::{error, warn};
+
+use super::{Server, ServerResult};
+use crate::{client_id::ClientId, traits::Config};
+
+/// How often the reconciliation loop checks whether
+/// the server is shutting down
+const RECONCILE_SHUTDOWN_CHECK_FREQ: Duration = Duration::from_millis(500);
+
+impl<C: Config> Server<C> {
+    /// Periodically removes the orphaned subscriptions (see
+    /// [`Server::reconcile_subscriptions`]) every `interval`,
+    /// until `shutdown_bool` is set to true
+    pub(super) fn reconcile_loop(
+        self: Arc<Self>,
+        interval: Duration,
+        shutdown_bool: Arc<AtomicBool>,
+    ) {
+        let mut last_reconcile = Instant::now();
+        while !shutdown_bool.load(Ordering::Relaxed) {
+            if last_reconcile.elapsed() >= interval {
+                if let Err(err) = self.reconcile_subscriptions() {
+                    error!("Error reconciliando suscripciones: {}", err);
+                }
+                last_reconcile = Instant::now();
+            }
+            thread::sleep(RECONCILE_SHUTDOWN_CHECK_FREQ.min(interval));
+        }
+    }
+
+    /// Removes from the [`TopicHandler`](crate::topic_handler::TopicHandler)
+    /// the subscriptions of the clients that the
+    /// [`ClientsManager`](crate::clients_manager::ClientsManager) has no
+    /// session for, which should not exist. The sessions of disconnected
+    /// clients that did not ask for a clean session, and the in-process
+    /// subscribers (see [`Server::subscribe_local`]), are kept.
+    ///
+    /// Returns the ids of the clients whose subscriptions were removed
+    pub fn reconcile_subscriptions(&self) -> ServerResult<Vec<ClientId>> {
+        // Se mantiene el lock del ClientsManager para que ningun
+        // cliente nuevo se conecte mientras se eliminan los huerfanos
+        let clients_manager = self.clients_manager.read()?;
+        let mut orphans = Vec::new();
+        for id in self.topic_handler.subscribed_clients()? {
+            if !clients_manager.has_session(&id) && !self.is_local_subscriber(&id)? {
+                orphans.push(id);
+            }
+        }
+        orphans.sort_unstable();
+        for id in &orphans {
+            warn!("Eliminando suscripciones huerfanas del cliente <{}>", id);
+            self.topic_handler.remove_client(id)?;
+        }
+        Ok(orphans)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use packets::{qos::QoSLevel, subscribe::Subscribe, topic_filter::TopicFilter};
+
+    use crate::test_helpers::{client_id, server::test_server};
+
+    #[test]
+    fn test_reconcile_removes_orphaned_subscriptions() {
+        let server = test_server("", 1);
+        // Ningun cliente con id "huerfano" se conecto al servidor
+        let topic = TopicFilter::new("sensors/#", QoSLevel::QoSLevel0).unwrap();
+        server
+            .topic_handler
+            .subscribe(&Subscribe::new(vec![topic], 1), &client_id("huerfano"))
+            .unwrap();
+
+        assert_eq!(
+            server.reconcile_subscriptions().unwrap(),
+            vec![client_id("huerfano")]
+        );
+        assert!(server
+            .topic_handler
+            .subscribed_clients()
+            .unwrap()
+            .is_empty());
+        assert!(server.reconcile_subscriptions().unwrap().is_empty());
+    }
+}
@@ -1,4 +1,5 @@
 use std::{
+    path::Path,
     sync::{
         atomic::{AtomicBool, Ordering},
         Arc,
@@ -6,8 +7,20 @@ use std::{
     thread::JoinHandle,
 };
 
+use packets::publish::Publish;
 use tracing::{error, trace};
 
+use super::ServerResult;
+
+/// Function that publishes a [`Publish`] from the server
+pub type Publisher = Box<dyn Fn(Publish) -> ServerResult<()> + Send + Sync>;
+/// Function that pauses (if the flag is true) or resumes (if it is
+/// false) the delivery of publishes to the client with the given id
+pub type ClientPauser = Box<dyn Fn(&str, bool) -> ServerResult<()> + Send + Sync>;
+/// Function that dumps the state of the server to the given
+/// path, or to the dump file of the config if it is None
+pub type Snapshotter = Box<dyn Fn(Option<&Path>) -> ServerResult<()> + Send + Sync>;
+
 /// It is responsible for shutting down the
 /// server from a different thread than
 /// the one running it
@@ -19,17 +32,70 @@ pub struct ServerController {
     /// Handle of the main server thread (the one
     /// that executes the server loop)
     handle: Option<JoinHandle<()>>,
+    /// Publishes messages originated in the
+    /// server (see [`Server::publish`](super::Server::publish))
+    publisher: Publisher,
+    /// Pauses and resumes the delivery of publishes to
+    /// a client (see [`Server::pause_client`](super::Server::pause_client))
+    pauser: ClientPauser,
+    /// Dumps the state of the server on demand (see
+    /// [`Server::snapshot`](super::Server::snapshot))
+    snapshotter: Snapshotter,
 }
 
 impl ServerController {
     /// Create a new [`ServerController`] for the server that
     /// runs on the thread associated with the *handle* received
-    pub fn new(shutdown_bool: Arc<AtomicBool>, handle: JoinHandle<()>) -> ServerController {
+    pub fn new(
+        shutdown_bool: Arc<AtomicBool>,
+        handle: JoinHandle<()>,
+        publisher: Publisher,
+        pauser: ClientPauser,
+        snapshotter: Snapshotter,
+    ) -> ServerController {
         ServerController {
             shutdown_bool,
             handle: Some(handle),
+            publisher,
+            pauser,
+            snapshotter,
         }
     }
+
+    /// Publishes a message from the server, as if it had been
+    /// sent by a client. It fails if the server was already
+    /// shut down
+    pub fn publish(&self, publish: Publish) -> ServerResult<()> {
+        (self.publisher)(publish)
+    }
+
+    /// Stops the delivery of publishes to the client with the given
+    /// id, without disconnecting it. They are queued, up to a limit,
+    /// until [`resume_client`](Self::resume_client) is called
+    pub fn pause_client(&self, id: &str) -> ServerResult<()> {
+        (self.pauser)(id, true)
+    }
+
+    /// Resumes the delivery of publishes to the client with the given
+    /// id, sending in order the ones queued while it was paused
+    pub fn resume_client(&self, id: &str) -> ServerResult<()> {
+        (self.pauser)(id, false)
+    }
+
+    /// Dumps the state of the server to the dump file of its config
+    /// right away, without waiting for the dump interval or for the
+    /// server to shut down. It fails if there is no dump file in the
+    /// config or if the server was already shut down
+    pub fn snapshot(&self) -> ServerResult<()> {
+        (self.snapshotter)(None)
+    }
+
+    /// Dumps the state of the server to `path` right away, so that it
+    /// can later be restored from there. It fails if the server was
+    /// already shut down
+    pub fn snapshot_to(&self, path: impl AsRef<Path>) -> ServerResult<()> {
+        (self.snapshotter)(Some(path.as_ref()))
+    }
 }
 
 impl Drop for ServerController {
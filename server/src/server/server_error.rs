@@ -1,4 +1,5 @@
 use std::{
+    error::Error,
     fmt, io,
     sync::{mpsc::SendError, PoisonError},
     time::SystemTimeError,
@@ -11,12 +12,14 @@ use packets::{
 use threadpool::ThreadPoolError;
 use tracing::error;
 
-use crate::topic_handler::topic_handler_error::TopicHandlerError;
+use crate::topic_handler::topic_handler_error::{TopicHandlerError, TopicHandlerErrorKind};
 
 #[derive(Debug)]
 pub struct ServerError {
     msg: String,
     kind: ServerErrorKind,
+    /// Underlying error that caused this one, if any
+    source: Option<Box<dyn Error + Send + Sync + 'static>>,
 }
 
 #[non_exhaustive]
@@ -31,6 +34,16 @@ pub enum ServerErrorKind {
     PoisonedLock,
     Irrecoverable,
     Idle,
+    WouldBlock,
+    /// A QoS 1 publish could not be queued for a client
+    /// because its queue was full
+    QueueFull,
+    /// The server ran out of resources (such as file descriptors)
+    /// to accept a new connection
+    ResourcesExhausted,
+    /// A retained publish was rejected because the maximum number
+    /// of retained messages was reached
+    RetainedRejected,
     Other,
 }
 
@@ -40,15 +53,21 @@ impl fmt::Display for ServerError {
     }
 }
 
-impl std::error::Error for ServerError {
+impl Error for ServerError {
     fn description(&self) -> &str {
         &self.msg
     }
+
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        self.source
+            .as_ref()
+            .map(|source| source.as_ref() as &(dyn Error + 'static))
+    }
 }
 
 impl From<io::Error> for ServerError {
     fn from(error: io::Error) -> Self {
-        match error.kind() {
+        let server_error = match error.kind() {
             io::ErrorKind::UnexpectedEof
             | io::ErrorKind::NotConnected
             | io::ErrorKind::BrokenPipe
@@ -56,17 +75,18 @@ impl From<io::Error> for ServerError {
                 "Se desconecto sin avisar",
                 ServerErrorKind::ClientDisconnected,
             ),
-            io::ErrorKind::WouldBlock => {
+            io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
                 ServerError::new_kind("Connection timeout", ServerErrorKind::Timeout)
             }
             _ => ServerError::new_msg(format!("{:?}", error)),
-        }
+        };
+        server_error.with_source(error)
     }
 }
 
 impl From<PacketError> for ServerError {
     fn from(packet_error: PacketError) -> Self {
-        match packet_error.kind() {
+        let server_error = match packet_error.kind() {
             ErrorKind::WouldBlock => {
                 ServerError::new_kind(&packet_error.to_string(), ServerErrorKind::Timeout)
             }
@@ -74,12 +94,20 @@ impl From<PacketError> for ServerError {
                 &packet_error.to_string(),
                 ServerErrorKind::ClientDisconnected,
             ),
+            // Paquetes malformados [MQTT-3.3.1-4], [MQTT-3.8.1-1], [MQTT-3.10.1-1]
+            ErrorKind::InvalidQoSLevel | ErrorKind::InvalidReservedBits => ServerError::new_kind(
+                format!("packet_error: {:?}", packet_error),
+                ServerErrorKind::ProtocolViolation,
+            ),
             _ => ServerError::new_msg(&format!("packet_error: {:?}", packet_error)),
-        }
+        };
+        server_error.with_source(packet_error)
     }
 }
 
 impl<T> From<PoisonError<T>> for ServerError {
+    // El PoisonError contiene el guard del lock, por lo que
+    // no se puede guardar como source
     fn from(err: PoisonError<T>) -> Self {
         ServerError::new_kind(&err.to_string(), ServerErrorKind::PoisonedLock)
     }
@@ -87,24 +115,39 @@ impl<T> From<PoisonError<T>> for ServerError {
 
 impl From<SystemTimeError> for ServerError {
     fn from(err: SystemTimeError) -> Self {
-        ServerError::new_msg(err.to_string())
+        ServerError::new_msg(err.to_string()).with_source(err)
     }
 }
 
 impl From<SendError<()>> for ServerError {
     fn from(err: SendError<()>) -> Self {
         error!("Error de Sender: {}", err);
-        ServerError::new_msg(&err.to_string())
+        ServerError::new_msg(&err.to_string()).with_source(err)
     }
 }
 
 impl From<TopicHandlerError> for ServerError {
     fn from(err: TopicHandlerError) -> Self {
+        if err.kind() == TopicHandlerErrorKind::WouldBlock {
+            return ServerError::new_kind(
+                format!("TopicHandlerError: {}", err),
+                ServerErrorKind::WouldBlock,
+            )
+            .with_source(err);
+        }
+        if err.kind() == TopicHandlerErrorKind::RetainedLimitReached {
+            return ServerError::new_kind(
+                format!("TopicHandlerError: {}", err),
+                ServerErrorKind::RetainedRejected,
+            )
+            .with_source(err);
+        }
         error!("Error de TopicHandler: {}", err);
         ServerError::new_kind(
             &format!("TopicHandlerError: {}", err.to_string()),
             ServerErrorKind::Irrecoverable,
         )
+        .with_source(err)
     }
 }
 
@@ -115,6 +158,7 @@ impl From<ThreadPoolError> for ServerError {
             &format!("ThreadPoolError: {}", err.to_string()),
             ServerErrorKind::Irrecoverable,
         )
+        .with_source(err)
     }
 }
 
@@ -124,6 +168,7 @@ impl From<serde_json::Error> for ServerError {
             &format!("Dump error: {}", err.to_string()),
             ServerErrorKind::Irrecoverable,
         )
+        .with_source(err)
     }
 }
 
@@ -132,6 +177,7 @@ impl ServerError {
         ServerError {
             msg: msg.into(),
             kind: ServerErrorKind::Other,
+            source: None,
         }
     }
 
@@ -139,10 +185,64 @@ impl ServerError {
         ServerError {
             msg: msg.into(),
             kind,
+            source: None,
         }
     }
 
+    /// Sets the underlying error that caused this one, which
+    /// is returned by [`source()`](Error::source)
+    pub fn with_source<E: Error + Send + Sync + 'static>(mut self, source: E) -> ServerError {
+        self.source = Some(Box::new(source));
+        self
+    }
+
     pub fn kind(&self) -> ServerErrorKind {
         self.kind
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, io};
+
+    use packets::packet_error::{ErrorKind, PacketError};
+
+    use super::{ServerError, ServerErrorKind};
+
+    #[test]
+    fn test_io_error_is_source() {
+        let err = ServerError::from(io::Error::other("causa"));
+        let source = err.source().unwrap();
+        let io_err = source.downcast_ref::<io::Error>().unwrap();
+        assert_eq!(io_err.kind(), io::ErrorKind::Other);
+        assert_eq!(io_err.to_string(), "causa");
+    }
+
+    #[test]
+    fn test_io_error_is_source_when_mapped_to_kind() {
+        let err = ServerError::from(io::Error::new(io::ErrorKind::BrokenPipe, "causa"));
+        assert_eq!(err.kind(), ServerErrorKind::ClientDisconnected);
+        assert!(err.source().unwrap().is::<io::Error>());
+    }
+
+    #[test]
+    fn test_new_error_has_no_source() {
+        let err = ServerError::new_kind("error", ServerErrorKind::Other);
+        assert!(err.source().is_none());
+    }
+
+    #[test]
+    fn test_invalid_qos_level_is_protocol_violation() {
+        let packet_error = PacketError::new_kind("QoS 3", ErrorKind::InvalidQoSLevel);
+        let err = ServerError::from(packet_error);
+        assert_eq!(err.kind(), ServerErrorKind::ProtocolViolation);
+        assert!(err.source().unwrap().is::<PacketError>());
+    }
+
+    #[test]
+    fn test_invalid_reserved_bits_is_protocol_violation() {
+        let packet_error = PacketError::new_kind("Flags", ErrorKind::InvalidReservedBits);
+        let err = ServerError::from(packet_error);
+        assert_eq!(err.kind(), ServerErrorKind::ProtocolViolation);
+    }
+}
@@ -1,2 +1,14 @@
 #[cfg(test)]
 pub mod iomock;
+#[cfg(test)]
+pub mod server;
+
+#[cfg(test)]
+use crate::client_id::{ClientId, ClientIdRules};
+
+/// Returns the [`ClientId`] of a client that connected with
+/// the given id, which must follow the default rules
+#[cfg(test)]
+pub fn client_id(id: &str) -> ClientId {
+    ClientId::new(id, &ClientIdRules::default()).unwrap()
+}
@@ -0,0 +1,27 @@
+use std::{io::Cursor, sync::Arc};
+
+use crate::{config::FileConfig, server::Server};
+
+/// Returns a [`FileConfig`] without dump nor accounts, listening on
+/// any port, along with the given `options` (one `key=value` per line)
+pub fn test_config(options: &str) -> FileConfig {
+    FileConfig::new_from_file(Cursor::new(format!(
+        "port=0
+dump_path=
+dump_time=
+log_path=bar.txt
+accounts_path=
+ip=localhost
+log_file_level=warn
+log_stdout_level=warn
+{}",
+        options
+    )))
+    .unwrap()
+}
+
+/// Returns a [`Server`] created from [`test_config`] with the given
+/// `options`, and a ThreadPool of `threadpool_size` threads
+pub fn test_server(options: &str, threadpool_size: usize) -> Arc<Server<FileConfig>> {
+    Server::new(test_config(options), threadpool_size).unwrap()
+}
@@ -1,32 +1,226 @@
 use serde::{Deserialize, Serialize};
 
 use std::{
-    collections::HashMap,
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
     fmt::Debug,
     ops::Deref,
-    sync::{mpsc::Sender, RwLock},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        mpsc::{SendError, Sender, SyncSender, TrySendError},
+        Arc, Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+        TryLockResult,
+    },
+    thread,
+    time::{Duration, Instant},
 };
 
+mod publish_counts;
+mod retained_index;
 pub mod topic_handler_error;
 
 use packets::qos::QoSLevel;
 use packets::{publish::Publish, subscribe::Subscribe, unsubscribe::Unsubscribe};
 
-use self::topic_handler_error::TopicHandlerError;
+use self::publish_counts::PublishCounts;
+use self::retained_index::RetainedIndex;
+use self::topic_handler_error::{TopicHandlerError, TopicHandlerErrorKind};
+use crate::client_id::ClientId;
+use crate::traits::{RetainedOverflow, TopicNormalizer};
 
-type Subscription = (String, SubscriptionData); // client_id, data
+type Subscription = (ClientId, SubscriptionData); // client_id, data
 type Subtopics = HashMap<String, Topic>; // key: subtopic name
-type Subscribers = HashMap<String, SubscriptionData>; // key: client_id
+type Subscribers = HashMap<ClientId, SubscriptionData>; // key: client_id
 type Subscriptions = HashMap<String, Subscribers>; // key: topic filter { key: client_id }
 
 const SEP: &str = "/";
 const MULTI_LEVEL_WILDCARD: &str = "#";
 const SINGLE_LEVEL_WILDCARD: &str = "+";
 const UNMATCH_WILDCARD: &str = "$";
+/// Prefix of the topic filters that read the retained message
+/// history of a topic, if it is enabled
+const HISTORY_PREFIX: &str = "$history/";
+/// How often a [`BoundedSink`] checks if there is room
+/// in its full channel
+const FULL_CHANNEL_RETRY_FREQ: Duration = Duration::from_millis(5);
 
 pub struct Message {
-    pub client_id: String,
+    pub client_id: ClientId,
     pub packet: Publish,
+    /// Where the packet was originated, if it was received from
+    /// another broker (see [`TopicHandler::publish_from`])
+    pub origin: Option<Origin>,
+}
+
+/// Broker in which a publish was originated, and how many times it was
+/// forwarded between brokers since then. It is only kept inside the
+/// server, so the packets sent to the clients are not modified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// Id of the broker that first received the publish
+    pub broker_id: String,
+    /// Number of times the publish was forwarded to another broker
+    pub hops: u32,
+}
+
+impl Origin {
+    /// Returns the origin of a publish received by the given broker
+    pub fn new(broker_id: &str) -> Self {
+        Self {
+            broker_id: broker_id.to_string(),
+            hops: 0,
+        }
+    }
+
+    /// Returns the origin of the publish after forwarding it once more
+    pub fn forwarded(&self) -> Self {
+        Self {
+            broker_id: self.broker_id.clone(),
+            hops: self.hops + 1,
+        }
+    }
+
+    /// Returns true if the publish came back to the broker it was
+    /// originated in, or it was forwarded at least `max_hops` times
+    pub fn has_looped(&self, local_broker_id: Option<&str>, max_hops: u32) -> bool {
+        local_broker_id == Some(self.broker_id.as_str()) || self.hops >= max_hops
+    }
+}
+
+/// Destination of the messages delivered by a [`TopicHandler`]
+/// when publishing, so they can be sent to something other than
+/// an [`std::sync::mpsc`] channel (such as an async channel)
+pub trait MessageSink {
+    /// Delivers the message
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the message could not be delivered,
+    /// in which case the publish is interrupted
+    fn send(&self, message: Message) -> Result<(), TopicHandlerError>;
+}
+
+impl<S: MessageSink + ?Sized> MessageSink for &S {
+    fn send(&self, message: Message) -> Result<(), TopicHandlerError> {
+        (**self).send(message)
+    }
+}
+
+impl MessageSink for Sender<Message> {
+    fn send(&self, message: Message) -> Result<(), TopicHandlerError> {
+        Sender::send(self, message)?;
+        Ok(())
+    }
+}
+
+/// [`MessageSink`] over a bounded channel. When the channel is full, a
+/// QoS 0 message waits up to `block_timeout` for the receiver to make room,
+/// and then it is dropped, counting it in `dropped`. A QoS 1 message is never
+/// dropped: it waits until there is room or the receiver is gone
+pub struct BoundedSink {
+    sender: SyncSender<Message>,
+    block_timeout: Duration,
+    dropped: Arc<AtomicU64>,
+}
+
+impl BoundedSink {
+    /// Creates a new BoundedSink that sends the messages through `sender`
+    pub fn new(
+        sender: SyncSender<Message>,
+        block_timeout: Duration,
+        dropped: Arc<AtomicU64>,
+    ) -> Self {
+        Self {
+            sender,
+            block_timeout,
+            dropped,
+        }
+    }
+}
+
+impl MessageSink for BoundedSink {
+    /// Delivers the message. If the channel is still full after
+    /// `block_timeout`, a QoS 0 message is dropped, and a QoS 1
+    /// message keeps waiting for room
+    ///
+    /// # Errors
+    ///
+    /// Returns an error of kind [`TopicHandlerErrorKind::DeliveryChannelClosed`]
+    /// if the receiver was dropped
+    fn send(&self, mut message: Message) -> Result<(), TopicHandlerError> {
+        if message.packet.qos() != QoSLevel::QoSLevel0 {
+            // Se bloquea hasta que haya lugar. No hay ningun lock del
+            // TopicHandler tomado mientras tanto
+            self.sender.send(message)?;
+            return Ok(());
+        }
+        let deadline = Instant::now() + self.block_timeout;
+        loop {
+            match self.sender.try_send(message) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(returned)) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        self.dropped.fetch_add(1, Ordering::Relaxed);
+                        return Ok(());
+                    }
+                    message = returned;
+                    thread::sleep(FULL_CHANNEL_RETRY_FREQ.min(deadline - now));
+                }
+                Err(TrySendError::Disconnected(message)) => return Err(SendError(message).into()),
+            }
+        }
+    }
+}
+
+#[doc(hidden)]
+/// Sink that tags every message with the origin of the publish
+struct OriginSink<'a, S> {
+    sink: S,
+    origin: &'a Origin,
+}
+
+impl<S: MessageSink> MessageSink for OriginSink<'_, S> {
+    fn send(&self, mut message: Message) -> Result<(), TopicHandlerError> {
+        message.origin = Some(self.origin.clone());
+        self.sink.send(message)
+    }
+}
+
+/// Change in the subscriptions of a [`TopicHandler`], reported to
+/// its [`SubscriptionListener`]
+#[derive(Debug, Clone, PartialEq)]
+pub enum SubscriptionEvent {
+    /// The client subscribed to the topic filter
+    Subscribed { client_id: ClientId, filter: String },
+    /// The client unsubscribed from the topic filter
+    Unsubscribed { client_id: ClientId, filter: String },
+    /// The client was removed, along with all of its subscriptions
+    ClientRemoved { client_id: ClientId },
+}
+
+/// Callback invoked by the [`TopicHandler`] every time its
+/// subscriptions change
+pub type SubscriptionListener = Box<dyn Fn(&SubscriptionEvent) + Send + Sync>;
+
+/// Snapshot of a node of the topic tree of a [`TopicHandler`],
+/// along with all the nodes under it
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TopicTreeSnapshot {
+    /// Name of the topic level. It is empty for the root
+    pub name: String,
+    /// Number of subscriptions to this exact topic
+    pub subscribers: usize,
+    /// Number of subscriptions with a multi level wildcard
+    /// right after this topic level
+    pub multilevel_subscribers: usize,
+    /// Number of subscriptions with a single level wildcard
+    /// right after this topic level
+    pub singlelevel_subscribers: usize,
+    /// True if the topic has a retained message
+    pub retained: bool,
+    /// Snapshots of the subtopics, sorted by name
+    pub subtopics: Vec<TopicTreeSnapshot>,
 }
 
 #[doc(hidden)]
@@ -36,8 +230,138 @@ struct SubscriptionData {
 }
 
 #[derive(Serialize, Deserialize)]
+#[serde(from = "SerializedTopicHandler")]
 pub struct TopicHandler {
     root: Topic,
+    /// Subscribers of each wildcard-free topic filter, so that they can
+    /// be found without walking the tree. The tree still keeps them,
+    /// as it is the one that gets dumped
+    #[serde(skip)]
+    exact_index: RwLock<Subscriptions>,
+    #[serde(skip)]
+    listener: RwLock<Option<SubscriptionListener>>,
+    /// If present, publishing does not block waiting for the locks
+    /// of the subscriptions, but tries to take them this number of
+    /// times before giving up
+    #[serde(skip)]
+    publish_lock_retries: RwLock<Option<u32>>,
+    /// Number of retained messages kept in the history of each topic.
+    /// If zero, the history is disabled
+    #[serde(skip)]
+    retained_history_depth: RwLock<usize>,
+    /// Topics with a retained message, from the oldest retained to the newest
+    #[serde(skip)]
+    retained_index: Mutex<RetainedIndex>,
+    /// Maximum number of retained messages across all topics, if any,
+    /// and what to do with a new one once it is reached
+    #[serde(skip)]
+    retained_limit: RwLock<Option<(usize, RetainedOverflow)>>,
+    /// If true, each client gets at most one copy of a publish, even
+    /// if several of its subscriptions match it
+    #[serde(skip)]
+    deduplicate_deliveries: RwLock<bool>,
+    /// If true, subscribing again to a topic filter the client is
+    /// already subscribed to does not return its retained messages
+    #[serde(skip)]
+    suppress_resubscribe_retained: RwLock<bool>,
+    /// Normalization applied to the topic names and topic
+    /// filters, both when publishing and when subscribing
+    #[serde(skip)]
+    topic_normalizer: RwLock<Option<Arc<dyn TopicNormalizer>>>,
+    /// Number of publications of the most published topics
+    #[serde(skip)]
+    publish_counts: PublishCounts,
+}
+
+#[doc(hidden)]
+#[derive(Deserialize)]
+/// Dumped fields of the TopicHandler, from which the rest are rebuilt
+struct SerializedTopicHandler {
+    root: Topic,
+}
+
+impl From<SerializedTopicHandler> for TopicHandler {
+    fn from(serialized: SerializedTopicHandler) -> Self {
+        let mut exact_index = HashMap::new();
+        serialized
+            .root
+            .collect_exact_subscriptions(None, &mut exact_index);
+        let mut retained_topics = Vec::new();
+        serialized
+            .root
+            .collect_retained_topics(None, &mut retained_topics);
+        Self {
+            root: serialized.root,
+            exact_index: RwLock::new(exact_index),
+            listener: RwLock::new(None),
+            publish_lock_retries: RwLock::new(None),
+            retained_history_depth: RwLock::new(0),
+            retained_index: Mutex::new(RetainedIndex::from_topics(retained_topics)),
+            retained_limit: RwLock::new(None),
+            deduplicate_deliveries: RwLock::new(false),
+            suppress_resubscribe_retained: RwLock::new(false),
+            topic_normalizer: RwLock::new(None),
+            publish_counts: PublishCounts::default(),
+        }
+    }
+}
+
+/// How the locks of the topic tree are taken while publishing
+#[doc(hidden)]
+#[derive(Debug, Clone, Copy)]
+enum LockMode {
+    /// Waits until each lock is free
+    Block,
+    /// Tries to take each lock without blocking, at most this number
+    /// of times after the first attempt. If one of them could not be
+    /// taken, it fails with an error of kind
+    /// [`TopicHandlerErrorKind::WouldBlock`]
+    Try(u32),
+}
+
+impl LockMode {
+    /// Takes a read lock of `lock`
+    fn read<T>(self, lock: &RwLock<T>) -> Result<RwLockReadGuard<'_, T>, TopicHandlerError> {
+        match self {
+            LockMode::Block => Ok(lock.read()?),
+            LockMode::Try(retries) => Self::try_with_retries(retries, || lock.try_read()),
+        }
+    }
+
+    /// Takes a write lock of `lock`
+    fn write<T>(self, lock: &RwLock<T>) -> Result<RwLockWriteGuard<'_, T>, TopicHandlerError> {
+        match self {
+            LockMode::Block => Ok(lock.write()?),
+            LockMode::Try(retries) => Self::try_with_retries(retries, || lock.try_write()),
+        }
+    }
+
+    /// Takes the lock of `mutex`
+    fn lock<T>(self, mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, TopicHandlerError> {
+        match self {
+            LockMode::Block => Ok(mutex.lock()?),
+            LockMode::Try(retries) => Self::try_with_retries(retries, || mutex.try_lock()),
+        }
+    }
+
+    /// Calls `try_lock` until it takes the lock, at most `retries`
+    /// times after the first attempt
+    fn try_with_retries<G>(
+        retries: u32,
+        mut try_lock: impl FnMut() -> TryLockResult<G>,
+    ) -> Result<G, TopicHandlerError> {
+        for _ in 0..=retries {
+            match try_lock() {
+                Ok(guard) => return Ok(guard),
+                Err(TryLockError::WouldBlock) => thread::yield_now(),
+                Err(TryLockError::Poisoned(err)) => return Err(err.into()),
+            }
+        }
+        Err(TopicHandlerError::new_kind(
+            "No se pudo tomar el lock sin bloquear",
+            TopicHandlerErrorKind::WouldBlock,
+        ))
+    }
 }
 
 #[doc(hidden)]
@@ -50,6 +374,9 @@ struct Topic {
     multilevel_subscribers: RwLock<Subscribers>,
     singlelevel_subscriptions: RwLock<Subscriptions>,
     retained_message: RwLock<Option<Publish>>,
+    /// Last retained messages, from oldest to newest
+    #[serde(default)]
+    retained_history: RwLock<VecDeque<Publish>>,
 }
 
 impl Debug for Topic {
@@ -72,70 +399,81 @@ impl Topic {
             multilevel_subscribers: RwLock::new(HashMap::new()),
             singlelevel_subscriptions: RwLock::new(HashMap::new()),
             retained_message: RwLock::new(None),
+            retained_history: RwLock::new(VecDeque::new()),
         }
     }
 
-    /// Sends a Publish packet to the clients who are subscribed into a certain topic
+    /// Stores the retained message of a Publish packet in its topic (keeping the
+    /// last `history_depth` ones). Nothing is sent: the subscribers are collected
+    /// beforehand with [`Topic::collect_matching_subs`]. The locks of the tree are
+    /// taken according to `mode`
     fn publish(
         &self,
         topic_name: Option<&str>,
-        sender: Sender<Message>,
         packet: &Publish,
-        is_root: bool,
+        history_depth: usize,
+        mode: LockMode,
     ) -> Result<(), TopicHandlerError> {
-        let matching = self.current_matching_subs(topic_name, is_root)?;
-        let mut packet_no_retain = packet.clone();
-        packet_no_retain.set_retain_flag(false);
-        TopicHandler::send_publish(&sender, &packet_no_retain, &matching)?;
         match topic_name {
             Some(topic) => {
                 let (current, rest) = Self::split(topic);
-                let subtopics = self.subtopics.read()?;
-                match subtopics.get(current) {
-                    None if packet.retain_flag() && !packet.payload().is_empty() => {
-                        // No hay suscriptores pero el mensaje es retained y tiene payload
-                        drop(subtopics);
-                        self.subtopics
-                            .write()?
-                            .entry(current.to_string())
-                            .or_insert_with(Topic::new)
-                            .publish(rest, sender, packet, false)?;
-                    }
-                    Some(subtopic) => {
-                        // Puede haber suscriptores
-                        subtopic.publish(rest, sender, packet, false)?;
+                loop {
+                    let subtopics = mode.read(&self.subtopics)?;
+                    if let Some(subtopic) = subtopics.get(current) {
+                        subtopic.publish(rest, packet, history_depth, mode)?;
                         // Si el mensaje era retained sin payload cabe la posibilidad que deje
                         // un nodo vacío (sacando el retained message), asi que limpiamos
-                        if subtopic.is_empty()? {
+                        if subtopic.is_empty(mode)? {
                             drop(subtopics);
-                            self.clean([current])?;
+                            self.clean([current], mode)?;
                         }
+                        break;
+                    }
+                    drop(subtopics);
+                    if !packet.retain_flag() || packet.payload().is_empty() {
+                        break;
                     }
-                    _ => (),
+                    // No hay suscriptores pero el mensaje es retained y tiene payload,
+                    // asi que se crea el nodo para guardarlo. El lock de escritura se
+                    // toma solo para insertarlo, para no frenar a los publish de otros
+                    // topics mientras se publica. Si otro thread lo limpia antes de
+                    // publicar, se vuelve a crear
+                    mode.write(&self.subtopics)?
+                        .entry(current.to_string())
+                        .or_insert_with(Topic::new);
                 }
             }
             None => {
-                self.update_retained_message(packet)?;
+                self.update_retained_message(packet, history_depth, mode)?;
             }
         }
         Ok(())
     }
 
-    /// Subscribe a client id into a topic
+    /// Subscribe a client id into a topic. Returns the matching retained
+    /// messages, unless `skip_resubscribed` is true and the client was
+    /// already subscribed to the same topic filter
     fn subscribe(
         &self,
         topic_name: Option<&str>,
-        client_id: &str,
+        client_id: &ClientId,
         sub_data: SubscriptionData,
         is_root: bool,
+        skip_resubscribed: bool,
     ) -> Result<Vec<Publish>, TopicHandlerError> {
         match topic_name {
-            Some(topic) => self.handle_sub_level(topic, client_id, sub_data, is_root),
+            Some(topic) => {
+                self.handle_sub_level(topic, client_id, sub_data, is_root, skip_resubscribed)
+            }
             None => {
-                self.subscribers
+                let existed = self
+                    .subscribers
                     .write()?
-                    .insert(client_id.to_string(), sub_data.clone());
-
+                    .insert(client_id.clone(), sub_data.clone())
+                    .is_some();
+                if existed && skip_resubscribed {
+                    return Ok(vec![]);
+                }
                 self.get_retained(sub_data.qos)
             }
         }
@@ -167,9 +505,9 @@ impl Topic {
 
                         if let Some(subtopic) = subtopics.get(current) {
                             subtopic.unsubscribe(rest, client_id)?;
-                            if subtopic.is_empty()? {
+                            if subtopic.is_empty(LockMode::Block)? {
                                 drop(subtopics);
-                                self.clean([current])?;
+                                self.clean([current], LockMode::Block)?;
                             }
                         }
                     }
@@ -191,7 +529,7 @@ impl Topic {
         let names = lock.keys().cloned().collect::<Vec<_>>();
         drop(lock);
 
-        self.clean(names.iter().map(String::as_str))?;
+        self.clean(names.iter().map(String::as_str), LockMode::Block)?;
         self.remove_subscriber(client_id)?;
         Ok(())
     }
@@ -216,66 +554,86 @@ impl Topic {
     fn handle_sub_level(
         &self,
         topic: &str,
-        user_id: &str,
+        user_id: &ClientId,
         sub_data: SubscriptionData,
         is_root: bool,
+        skip_resubscribed: bool,
     ) -> Result<Vec<Publish>, TopicHandlerError> {
         let (current, rest) = Self::split(topic);
         match (current, rest) {
-            (SINGLE_LEVEL_WILDCARD, _) => {
-                self.add_single_level_subscription(topic, user_id, sub_data, is_root)
-            }
-            (MULTI_LEVEL_WILDCARD, _) => {
-                self.add_multi_level_subscription(topic, user_id, sub_data, is_root)
-            }
-            _ => {
-                let subtopics = self.subtopics.read()?;
-                match subtopics.get(current) {
-                    None => {
-                        drop(subtopics);
-                        self.subtopics
-                            .write()?
-                            .entry(current.to_string())
-                            .or_insert_with(Topic::new)
-                            .subscribe(rest, user_id, sub_data, false)
-                    }
-                    Some(subtopic) => subtopic.subscribe(rest, user_id, sub_data, false),
+            (SINGLE_LEVEL_WILDCARD, _) => self.add_single_level_subscription(
+                topic,
+                user_id,
+                sub_data,
+                is_root,
+                skip_resubscribed,
+            ),
+            (MULTI_LEVEL_WILDCARD, _) => self.add_multi_level_subscription(
+                topic,
+                user_id,
+                sub_data,
+                is_root,
+                skip_resubscribed,
+            ),
+            _ => loop {
+                if let Some(subtopic) = self.subtopics.read()?.get(current) {
+                    return subtopic.subscribe(rest, user_id, sub_data, false, skip_resubscribed);
                 }
-            }
+                // Igual que al publicar, el lock de escritura se toma solo
+                // para crear el nodo
+                self.subtopics
+                    .write()?
+                    .entry(current.to_string())
+                    .or_insert_with(Topic::new);
+            },
         }
     }
 
     #[doc(hidden)]
     /// Adds a new client id with its data into a given topic's single level subscriptions
-    /// Returns the matching retained messages
+    /// Returns the matching retained messages, unless `skip_resubscribed` is true and
+    /// the client was already subscribed
     fn add_single_level_subscription(
         &self,
         topic: &str,
-        client_id: &str,
+        client_id: &ClientId,
         data: SubscriptionData,
         is_root: bool,
+        skip_resubscribed: bool,
     ) -> Result<Vec<Publish>, TopicHandlerError> {
         let mut single_level_subscriptions = self.singlelevel_subscriptions.write()?;
         let single_level_subscribers = single_level_subscriptions
             .entry(topic.to_string())
             .or_insert_with(HashMap::new);
-        single_level_subscribers.insert(client_id.to_string(), data.clone());
+        let existed = single_level_subscribers
+            .insert(client_id.clone(), data.clone())
+            .is_some();
+        if existed && skip_resubscribed {
+            return Ok(vec![]);
+        }
         self.get_retained_messages(Some(topic), data.qos, is_root)
     }
 
     #[doc(hidden)]
     /// Adds a new client id with its data into a given topic's multi level subscriptions
     ///
-    /// Returns the matching retained messages
+    /// Returns the matching retained messages, unless `skip_resubscribed` is true
+    /// and the client was already subscribed
     fn add_multi_level_subscription(
         &self,
         topic: &str,
-        client_id: &str,
+        client_id: &ClientId,
         data: SubscriptionData,
         is_root: bool,
+        skip_resubscribed: bool,
     ) -> Result<Vec<Publish>, TopicHandlerError> {
         let mut multilevel_subscribers = self.multilevel_subscribers.write()?;
-        multilevel_subscribers.insert(client_id.to_string(), data.clone());
+        let existed = multilevel_subscribers
+            .insert(client_id.clone(), data.clone())
+            .is_some();
+        if existed && skip_resubscribed {
+            return Ok(vec![]);
+        }
         self.get_retained_messages(Some(topic), data.qos, is_root)
     }
 
@@ -338,40 +696,111 @@ impl Topic {
     #[doc(hidden)]
     /// If the packet is a retained message, it either updates the retained message of the topic or
     /// it removes its retained message if it the packet has a zero-length payload ([MQTT-3.3.1-11])
-    fn update_retained_message(&self, packet: &Publish) -> Result<(), TopicHandlerError> {
+    ///
+    /// The last `history_depth` retained messages are kept in the history, which is
+    /// also cleared by a zero-length payload. Both locks are taken before changing
+    /// any of them, so that it has no effect if one of them can not be taken
+    fn update_retained_message(
+        &self,
+        packet: &Publish,
+        history_depth: usize,
+        mode: LockMode,
+    ) -> Result<(), TopicHandlerError> {
         if packet.retain_flag() {
-            let mut retained = self.retained_message.write()?;
+            let mut retained = mode.write(&self.retained_message)?;
+            let mut history = mode.write(&self.retained_history)?;
             if packet.payload().is_empty() {
                 *retained = None;
+                history.clear();
             } else {
                 *retained = Some(packet.clone());
+                history.push_back(packet.clone());
+                while history.len() > history_depth {
+                    history.pop_front();
+                }
             }
         }
         Ok(())
     }
 
     #[doc(hidden)]
-    /// Gets the matching subscriptions of the given topic for the given topic name
-    fn current_matching_subs(
+    /// Removes the retained message (and its history) of the given topic name
+    fn remove_retained(
+        &self,
+        topic_name: Option<&str>,
+        mode: LockMode,
+    ) -> Result<(), TopicHandlerError> {
+        match topic_name {
+            Some(topic) => {
+                let (current, rest) = Self::split(topic);
+                if let Some(subtopic) = mode.read(&self.subtopics)?.get(current) {
+                    subtopic.remove_retained(rest, mode)?;
+                }
+            }
+            None => {
+                let mut retained = mode.write(&self.retained_message)?;
+                let mut history = mode.write(&self.retained_history)?;
+                *retained = None;
+                history.clear();
+            }
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    /// Gets the retained message history of the given topic name,
+    /// from oldest to newest
+    fn get_retained_history(
+        &self,
+        topic_name: Option<&str>,
+        max_qos: QoSLevel,
+    ) -> Result<Vec<Publish>, TopicHandlerError> {
+        match topic_name {
+            Some(topic) => {
+                let (current, rest) = Self::split(topic);
+                match self.subtopics.read()?.get(current) {
+                    Some(subtopic) => subtopic.get_retained_history(rest, max_qos),
+                    None => Ok(vec![]),
+                }
+            }
+            None => Ok(self
+                .retained_history
+                .read()?
+                .iter()
+                .map(|retained| {
+                    let mut retained = retained.clone();
+                    retained.set_max_qos(max_qos);
+                    retained
+                })
+                .collect()),
+        }
+    }
+
+    #[doc(hidden)]
+    /// Appends to `matching` the wildcard subscriptions of this topic and its
+    /// subtopics that match the given topic name, taking the locks according
+    /// to `mode`
+    fn collect_matching_subs(
         &self,
         topic_name: Option<&str>,
         is_root: bool,
-    ) -> Result<Vec<Subscription>, TopicHandlerError> {
-        let mut matching = Vec::new();
+        mode: LockMode,
+        matching: &mut Vec<Subscription>,
+    ) -> Result<(), TopicHandlerError> {
         if !(is_root && Self::starts_with_unmatch(topic_name)) {
             matching.extend(Self::current_matching_single_level_subs(
                 topic_name,
-                self.singlelevel_subscriptions.read()?.deref(),
+                mode.read(&self.singlelevel_subscriptions)?.deref(),
             ));
-
-            matching.extend(self.multilevel_subscribers.read()?.clone());
+            matching.extend(mode.read(&self.multilevel_subscribers)?.clone());
         }
-
-        if topic_name.is_none() {
-            matching.extend(self.subscribers.read()?.clone());
+        if let Some(topic) = topic_name {
+            let (current, rest) = Self::split(topic);
+            if let Some(subtopic) = mode.read(&self.subtopics)?.get(current) {
+                subtopic.collect_matching_subs(rest, false, mode, matching)?;
+            }
         }
-
-        Ok(matching)
+        Ok(())
     }
 
     #[doc(hidden)]
@@ -408,12 +837,12 @@ impl Topic {
     #[doc(hidden)]
     /// Returns true if all the subtopics, subscribers, subscription and
     /// retained messages are empty
-    fn is_empty(&self) -> Result<bool, TopicHandlerError> {
-        Ok(self.subtopics.read()?.is_empty()
-            && self.subscribers.read()?.is_empty()
-            && self.multilevel_subscribers.read()?.is_empty()
-            && self.singlelevel_subscriptions.read()?.is_empty()
-            && self.retained_message.read()?.is_none())
+    fn is_empty(&self, mode: LockMode) -> Result<bool, TopicHandlerError> {
+        Ok(mode.read(&self.subtopics)?.is_empty()
+            && mode.read(&self.subscribers)?.is_empty()
+            && mode.read(&self.multilevel_subscribers)?.is_empty()
+            && mode.read(&self.singlelevel_subscriptions)?.is_empty()
+            && mode.read(&self.retained_message)?.is_none())
     }
 
     #[doc(hidden)]
@@ -428,6 +857,89 @@ impl Topic {
             && self.retained_message.get_mut()?.is_none())
     }
 
+    #[doc(hidden)]
+    /// Adds to `clients` the id of every client subscribed
+    /// to this node or any of its subtopics
+    fn collect_subscribed_clients(
+        &self,
+        clients: &mut HashSet<ClientId>,
+    ) -> Result<(), TopicHandlerError> {
+        clients.extend(self.subscribers.read()?.keys().cloned());
+        clients.extend(self.multilevel_subscribers.read()?.keys().cloned());
+        for subscribers in self.singlelevel_subscriptions.read()?.values() {
+            clients.extend(subscribers.keys().cloned());
+        }
+        for subtopic in self.subtopics.read()?.values() {
+            subtopic.collect_subscribed_clients(clients)?;
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    /// Returns a snapshot of this node and all of its subtopics
+    fn snapshot(&self, name: &str) -> Result<TopicTreeSnapshot, TopicHandlerError> {
+        let mut subtopics = Vec::new();
+        for (subtopic_name, subtopic) in self.subtopics.read()?.iter() {
+            subtopics.push(subtopic.snapshot(subtopic_name)?);
+        }
+        subtopics.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(TopicTreeSnapshot {
+            name: name.to_string(),
+            subscribers: self.subscribers.read()?.len(),
+            multilevel_subscribers: self.multilevel_subscribers.read()?.len(),
+            singlelevel_subscribers: self
+                .singlelevel_subscriptions
+                .read()?
+                .values()
+                .map(|subscribers| subscribers.len())
+                .sum(),
+            retained: self.retained_message.read()?.is_some(),
+            subtopics,
+        })
+    }
+
+    #[doc(hidden)]
+    /// Appends to `topics` the full name of every topic under this node
+    /// that has a retained message. It does not need to wait for locks,
+    /// as it is only used while the tree is being restored
+    fn collect_retained_topics(&self, topic_name: Option<&str>, topics: &mut Vec<String>) {
+        if let (Some(name), Ok(retained)) = (topic_name, self.retained_message.try_read()) {
+            if retained.is_some() {
+                topics.push(name.to_string());
+            }
+        }
+        if let Ok(subtopics) = self.subtopics.try_read() {
+            for (subtopic_name, subtopic) in subtopics.iter() {
+                let full_name = match topic_name {
+                    Some(name) => name.to_string() + SEP + subtopic_name,
+                    None => subtopic_name.to_string(),
+                };
+                subtopic.collect_retained_topics(Some(&full_name), topics);
+            }
+        }
+    }
+
+    #[doc(hidden)]
+    /// Inserts into `index` the subscribers of every wildcard-free topic
+    /// filter under this node. It does not need to wait for locks, as it is
+    /// only used while the tree is being restored
+    fn collect_exact_subscriptions(&self, topic_name: Option<&str>, index: &mut Subscriptions) {
+        if let (Some(name), Ok(subscribers)) = (topic_name, self.subscribers.try_read()) {
+            if !subscribers.is_empty() {
+                index.insert(name.to_string(), subscribers.clone());
+            }
+        }
+        if let Ok(subtopics) = self.subtopics.try_read() {
+            for (subtopic_name, subtopic) in subtopics.iter() {
+                let full_name = match topic_name {
+                    Some(name) => name.to_string() + SEP + subtopic_name,
+                    None => subtopic_name.to_string(),
+                };
+                subtopic.collect_exact_subscriptions(Some(&full_name), index);
+            }
+        }
+    }
+
     #[doc(hidden)]
     /// Returns true if the given topic name starts with the unmatch wildcard
     fn starts_with_unmatch(topic_name: Option<&str>) -> bool {
@@ -494,8 +1006,9 @@ impl Topic {
     fn clean<'a, I: IntoIterator<Item = &'a str>>(
         &self,
         subtopics: I,
+        mode: LockMode,
     ) -> Result<(), TopicHandlerError> {
-        let mut subtopics_dic = self.subtopics.write()?;
+        let mut subtopics_dic = mode.write(&self.subtopics)?;
 
         for name in subtopics {
             if let Some(node) = subtopics_dic.get_mut(name) {
@@ -512,65 +1025,538 @@ impl Topic {
 impl TopicHandler {
     /// Creates a new TopicHandler
     pub fn new() -> Self {
-        Self { root: Topic::new() }
+        Self {
+            root: Topic::new(),
+            exact_index: RwLock::new(HashMap::new()),
+            listener: RwLock::new(None),
+            publish_lock_retries: RwLock::new(None),
+            retained_history_depth: RwLock::new(0),
+            retained_index: Mutex::new(RetainedIndex::default()),
+            retained_limit: RwLock::new(None),
+            deduplicate_deliveries: RwLock::new(false),
+            suppress_resubscribe_retained: RwLock::new(false),
+            topic_normalizer: RwLock::new(None),
+            publish_counts: PublishCounts::default(),
+        }
+    }
+
+    /// Sets the callback that is invoked every time a client subscribes,
+    /// unsubscribes or is removed. There is none by default
+    pub fn set_subscription_listener(
+        &self,
+        listener: Option<SubscriptionListener>,
+    ) -> Result<(), TopicHandlerError> {
+        *self.listener.write()? = listener;
+        Ok(())
     }
 
     /// Subscribe a client id into a set of topics given a Subscribe packet
     pub fn subscribe(
         &self,
         packet: &Subscribe,
-        client_id: &str,
+        client_id: &ClientId,
     ) -> Result<Vec<Publish>, TopicHandlerError> {
         let topics = packet.topics();
         let topics: Vec<&packets::topic_filter::TopicFilter> = topics.iter().collect();
+        let history_enabled = *self.retained_history_depth.read()? > 0;
+        let skip_resubscribed = *self.suppress_resubscribe_retained.read()?;
         let mut retained = Vec::new();
         for topic_filter in topics {
+            let name = self.normalize(topic_filter.name())?;
+            if history_enabled {
+                if let Some(topic_name) = name.strip_prefix(HISTORY_PREFIX) {
+                    retained.extend(
+                        self.root
+                            .get_retained_history(Some(topic_name), topic_filter.qos())?,
+                    );
+                    continue;
+                }
+            }
             let data = SubscriptionData {
                 qos: topic_filter.qos(),
             };
+            if Self::is_exact(&name) {
+                self.exact_index
+                    .write()?
+                    .entry(name.to_string())
+                    .or_insert_with(HashMap::new)
+                    .insert(client_id.clone(), data.clone());
+            }
             retained.extend(self.root.subscribe(
-                Some(topic_filter.name()),
+                Some(&name),
                 client_id,
                 data,
                 true,
+                skip_resubscribed,
             )?);
+            self.notify(SubscriptionEvent::Subscribed {
+                client_id: client_id.clone(),
+                filter: name.to_string(),
+            })?;
         }
         Ok(retained)
     }
 
-    /// Sends a Publish packet to the clients who are subscribed into a certain topic
-    pub fn publish(
-        &self,
-        packet: &Publish,
-        sender: Sender<Message>,
-    ) -> Result<(), TopicHandlerError> {
-        let full_topic = packet.topic_name();
-        self.root.publish(Some(full_topic), sender, packet, true)?;
-        Ok(())
+    /// Sends a Publish packet to the clients who are subscribed into a certain topic,
+    /// delivering a [`Message`] for each of them into `sender`. The subscribers
+    /// are collected first, so no lock is held while `sender` delivers them
+    pub fn publish<S: MessageSink>(
+        &self,
+        packet: &Publish,
+        sender: S,
+    ) -> Result<(), TopicHandlerError> {
+        let normalized = self.normalize_publish(packet)?;
+        let packet = normalized.as_ref();
+        let retries = *self.publish_lock_retries.read()?;
+        let mode = retries.map_or(LockMode::Block, LockMode::Try);
+        // Se juntan los suscriptores antes de enviar, para no tener tomado
+        // ningun lock mientras el sender se bloquea
+        let matching = self.matching_subs(packet.topic_name(), mode)?;
+        self.publish_to(packet, &sender, matching, mode)
+    }
+
+    /// Returns the number of [`Message`]s that [`TopicHandler::publish`]
+    /// would deliver for the packet, without storing it as retained nor
+    /// counting the publication
+    #[cfg(any(test, feature = "bench"))]
+    pub fn count_deliveries(&self, packet: &Publish) -> Result<usize, TopicHandlerError> {
+        let normalized = self.normalize_publish(packet)?;
+        let mut matching = self.matching_subs(normalized.topic_name(), LockMode::Block)?;
+        if *self.deduplicate_deliveries.read()? {
+            matching = Self::deduplicate(matching);
+        }
+        Ok(matching.len())
+    }
+
+    /// Same as [`TopicHandler::publish`], for a publish received from
+    /// another broker. Every [`Message`] delivered is tagged with its
+    /// origin, so that it can be told apart if it is forwarded again
+    pub fn publish_from<S: MessageSink>(
+        &self,
+        packet: &Publish,
+        sender: S,
+        origin: &Origin,
+    ) -> Result<(), TopicHandlerError> {
+        self.publish(
+            packet,
+            OriginSink {
+                sink: sender,
+                origin,
+            },
+        )
+    }
+
+    /// Makes [`TopicHandler::publish`] find the subscribers without
+    /// blocking, so that a publish is never held back by concurrent
+    /// subscription changes. Each lock is tried `retries` times after
+    /// the first attempt; if one of them is still taken, the publish
+    /// fails with an error of kind [`TopicHandlerErrorKind::WouldBlock`]
+    /// before sending anything, so it can be retried later.
+    ///
+    /// If `retries` is None, publishing blocks until the locks are free,
+    /// which is the default
+    pub fn set_publish_lock_retries(&self, retries: Option<u32>) -> Result<(), TopicHandlerError> {
+        *self.publish_lock_retries.write()? = retries;
+        Ok(())
+    }
+
+    /// Makes every topic keep its last `depth` retained messages. Besides
+    /// getting the latest retained message as usual, a client can then
+    /// subscribe to `$history/<topic>` to get the whole history of
+    /// `<topic>`, from oldest to newest. These subscriptions are only
+    /// reads, so the client is not actually subscribed to anything
+    ///
+    /// If `depth` is None or zero, the history is disabled and
+    /// `$history/` is a regular topic, which is the default
+    pub fn set_retained_history_depth(
+        &self,
+        depth: Option<usize>,
+    ) -> Result<(), TopicHandlerError> {
+        *self.retained_history_depth.write()? = depth.unwrap_or(0);
+        Ok(())
+    }
+
+    /// Limits the number of retained messages across all topics to `max`.
+    /// Once it is reached, a retained publish to a topic without a retained
+    /// message either removes the oldest retained message to make room for
+    /// it, or fails with an error of kind
+    /// [`TopicHandlerErrorKind::RetainedLimitReached`] before sending
+    /// anything, according to `overflow`. Replacing or removing the retained
+    /// message of a topic is always allowed
+    ///
+    /// If `max` is None, there is no limit, which is the default
+    pub fn set_max_retained_messages(
+        &self,
+        max: Option<usize>,
+        overflow: RetainedOverflow,
+    ) -> Result<(), TopicHandlerError> {
+        *self.retained_limit.write()? = max.map(|max| (max, overflow));
+        Ok(())
+    }
+
+    /// Makes each client get at most one copy of a publish, with the
+    /// highest QoS among its matching subscriptions. Otherwise, which
+    /// is the default, it gets one copy for each matching subscription
+    pub fn set_deduplicate_deliveries(&self, deduplicate: bool) -> Result<(), TopicHandlerError> {
+        *self.deduplicate_deliveries.write()? = deduplicate;
+        Ok(())
+    }
+
+    /// Makes subscribing again to a topic filter the client is already
+    /// subscribed to not return its retained messages, so that they are
+    /// only delivered on the first subscription. Otherwise, which is the
+    /// default, they are returned on every subscription
+    pub fn set_suppress_resubscribe_retained(
+        &self,
+        suppress: bool,
+    ) -> Result<(), TopicHandlerError> {
+        *self.suppress_resubscribe_retained.write()? = suppress;
+        Ok(())
+    }
+
+    /// Applies `normalizer` to every topic name and topic filter, both
+    /// when publishing and when subscribing, so that the topics that are
+    /// normalized to the same name match each other. The publishes are
+    /// delivered with the normalized topic name
+    ///
+    /// If it is None, topics are matched byte-exact, which is the default
+    pub fn set_topic_normalizer(
+        &self,
+        normalizer: Option<Arc<dyn TopicNormalizer>>,
+    ) -> Result<(), TopicHandlerError> {
+        *self.topic_normalizer.write()? = normalizer;
+        Ok(())
+    }
+
+    /// Returns the number of retained messages across all topics
+    pub fn retained_count(&self) -> Result<usize, TopicHandlerError> {
+        Ok(self.retained_index.lock()?.len())
+    }
+
+    #[doc(hidden)]
+    /// Returns the topic name or topic filter normalized
+    /// by the topic normalizer, if there is one
+    fn normalize<'a>(&self, topic: &'a str) -> Result<Cow<'a, str>, TopicHandlerError> {
+        Ok(match self.topic_normalizer.read()?.as_ref() {
+            Some(normalizer) => Cow::Owned(normalizer.normalize(topic)),
+            None => Cow::Borrowed(topic),
+        })
+    }
+
+    #[doc(hidden)]
+    /// Returns the publish with its topic name normalized
+    /// by the topic normalizer, if there is one
+    fn normalize_publish<'a>(
+        &self,
+        packet: &'a Publish,
+    ) -> Result<Cow<'a, Publish>, TopicHandlerError> {
+        let topic_name = match self.normalize(packet.topic_name())? {
+            Cow::Owned(topic_name) if topic_name != packet.topic_name() => topic_name,
+            _ => return Ok(Cow::Borrowed(packet)),
+        };
+        let publish = Publish::new(
+            packet.dup_flag(),
+            packet.qos(),
+            packet.retain_flag(),
+            &topic_name,
+            packet.payload(),
+            packet.packet_id(),
+        )
+        .map_err(|err| TopicHandlerError::new(&err.to_string()))?;
+        Ok(Cow::Owned(publish))
+    }
+
+    #[doc(hidden)]
+    /// If the packet is a retained message, it takes the lock of the retained
+    /// index (so that retained publishes are stored one at a time) and returns
+    /// it along with how many of the oldest retained messages must be evicted
+    /// to make room for it. If the limit was reached and they can not be
+    /// evicted, it returns an error of kind
+    /// [`TopicHandlerErrorKind::RetainedLimitReached`]
+    fn make_room_for_retained(
+        &self,
+        packet: &Publish,
+        mode: LockMode,
+    ) -> Result<Option<(MutexGuard<'_, RetainedIndex>, usize)>, TopicHandlerError> {
+        if !packet.retain_flag() {
+            return Ok(None);
+        }
+        let index = mode.lock(&self.retained_index)?;
+        if packet.payload().is_empty() || index.contains(packet.topic_name()) {
+            return Ok(Some((index, 0)));
+        }
+        match *self.retained_limit.read()? {
+            Some((max, overflow)) if index.len() >= max => {
+                let to_evict = index.len() + 1 - max;
+                if overflow == RetainedOverflow::Reject || to_evict > index.len() {
+                    return Err(TopicHandlerError::new_kind(
+                        "Se alcanzo el maximo de mensajes retenidos",
+                        TopicHandlerErrorKind::RetainedLimitReached,
+                    ));
+                }
+                Ok(Some((index, to_evict)))
+            }
+            _ => Ok(Some((index, 0))),
+        }
+    }
+
+    #[doc(hidden)]
+    /// Once the packet was published, it evicts the oldest retained messages
+    /// (if needed) and records the retained message of the packet in the index.
+    /// Each message is removed from the index only after it is removed from the
+    /// tree, so that they stay consistent if a lock can not be taken
+    fn index_retained(
+        &self,
+        index: Option<(MutexGuard<'_, RetainedIndex>, usize)>,
+        packet: &Publish,
+        mode: LockMode,
+    ) -> Result<(), TopicHandlerError> {
+        if let Some((mut index, to_evict)) = index {
+            for _ in 0..to_evict {
+                if let Some(oldest) = index.oldest().map(str::to_string) {
+                    self.root.remove_retained(Some(&oldest), mode)?;
+                    index.remove(&oldest);
+                }
+            }
+            if packet.payload().is_empty() {
+                index.remove(packet.topic_name());
+            } else {
+                index.stamp(packet.topic_name().to_string());
+            }
+        }
+        Ok(())
+    }
+
+    #[doc(hidden)]
+    /// If the packet is retained, it stores its message in the tree and in the
+    /// retained index, evicting the oldest ones if needed. The retained index
+    /// is released before returning, so it is never held while delivering
+    fn store_retained(&self, packet: &Publish, mode: LockMode) -> Result<(), TopicHandlerError> {
+        if !packet.retain_flag() {
+            return Ok(());
+        }
+        let retained_index = self.make_room_for_retained(packet, mode)?;
+        let history_depth = *self.retained_history_depth.read()?;
+        self.root
+            .publish(Some(packet.topic_name()), packet, history_depth, mode)?;
+        self.index_retained(retained_index, packet, mode)
+    }
+
+    #[doc(hidden)]
+    /// Returns the subscriptions that match the topic name, both from the
+    /// exact index and from the wildcard subscriptions of the tree, taking
+    /// the locks according to `mode`
+    fn matching_subs(
+        &self,
+        topic_name: &str,
+        mode: LockMode,
+    ) -> Result<Vec<Subscription>, TopicHandlerError> {
+        let mut matching: Vec<Subscription> = Vec::new();
+        if let Some(subscribers) = mode.read(&self.exact_index)?.get(topic_name) {
+            matching.extend(
+                subscribers
+                    .iter()
+                    .map(|(id, data)| (id.clone(), data.clone())),
+            );
+        }
+        self.root
+            .collect_matching_subs(Some(topic_name), true, mode, &mut matching)?;
+        Ok(matching)
+    }
+
+    #[doc(hidden)]
+    /// Stores the packet in the tree and then sends it to the given
+    /// subscribers, which were already collected. If enabled, each
+    /// client gets only one copy.
+    ///
+    /// With [`LockMode::Try`], every lock is taken before sending anything,
+    /// so if one of them can not be taken the publish has no effect other
+    /// than creating empty nodes, and it can be retried
+    fn publish_to(
+        &self,
+        packet: &Publish,
+        sender: &dyn MessageSink,
+        mut matching: Vec<Subscription>,
+        mode: LockMode,
+    ) -> Result<(), TopicHandlerError> {
+        if *self.deduplicate_deliveries.read()? {
+            matching = Self::deduplicate(matching);
+        }
+        self.store_retained(packet, mode)?;
+        match self.publish_counts.increment(packet.topic_name(), mode) {
+            // El publish ya se guardo, asi que no se reintenta solo por
+            // no haberlo podido contar
+            Err(err) if err.kind() == TopicHandlerErrorKind::WouldBlock => {}
+            result => result?,
+        }
+        let mut packet_no_retain = packet.clone();
+        packet_no_retain.set_retain_flag(false);
+        Self::send_publish(sender, &packet_no_retain, &matching)
+    }
+
+    #[doc(hidden)]
+    /// Keeps a single subscription for each client, with the
+    /// highest QoS among its matching ones
+    fn deduplicate(matching: Vec<Subscription>) -> Vec<Subscription> {
+        let mut positions: HashMap<ClientId, usize> = HashMap::new();
+        let mut deduplicated: Vec<Subscription> = Vec::new();
+        for (id, data) in matching {
+            match positions.get(&id) {
+                Some(&pos) => {
+                    let kept = &mut deduplicated[pos].1;
+                    if data.qos as u8 > kept.qos as u8 {
+                        kept.qos = data.qos;
+                    }
+                }
+                None => {
+                    positions.insert(id.clone(), deduplicated.len());
+                    deduplicated.push((id, data));
+                }
+            }
+        }
+        deduplicated
+    }
+
+    /// Returns the retained messages that match the topic filter, of the
+    /// topics that the client is subscribed to. Each one has the highest
+    /// QoS among the subscriptions of the client that match its topic, as
+    /// if the client had just subscribed to them
+    pub fn retained_for(
+        &self,
+        topic_filter: &str,
+        client_id: &ClientId,
+    ) -> Result<Vec<Publish>, TopicHandlerError> {
+        let topic_filter = self.normalize(topic_filter)?;
+        let retained =
+            self.root
+                .get_retained_messages(Some(&topic_filter), QoSLevel::QoSLevel1, true)?;
+        let mut messages = Vec::new();
+        for mut publish in retained {
+            let mut matching: Vec<Subscription> = Vec::new();
+            if let Some(data) = self
+                .exact_index
+                .read()?
+                .get(publish.topic_name())
+                .and_then(|subscribers| subscribers.get(client_id))
+            {
+                matching.push((client_id.clone(), data.clone()));
+            }
+            self.root.collect_matching_subs(
+                Some(publish.topic_name()),
+                true,
+                LockMode::Block,
+                &mut matching,
+            )?;
+            let max_qos = matching
+                .into_iter()
+                .filter(|(id, _)| id == client_id)
+                .map(|(_, data)| data.qos)
+                .max_by_key(|qos| *qos as u8);
+            if let Some(max_qos) = max_qos {
+                publish.set_max_qos(max_qos);
+                messages.push(publish);
+            }
+        }
+        Ok(messages)
+    }
+
+    /// Unsubscribe a client_id from a set of topics given a Unsubscribe packet
+    pub fn unsubscribe(
+        &self,
+        packet: Unsubscribe,
+        client_id: &ClientId,
+    ) -> Result<(), TopicHandlerError> {
+        for topic_name in packet.topic_filters() {
+            let name = self.normalize(topic_name.name())?;
+            self.root.unsubscribe(Some(&name), client_id)?;
+            self.remove_from_index(client_id, &name)?;
+            self.notify(SubscriptionEvent::Unsubscribed {
+                client_id: client_id.clone(),
+                filter: name.to_string(),
+            })?;
+        }
+        Ok(())
+    }
+
+    /// Removes a client and all of its subscriptions
+    pub fn remove_client(&self, client_id: &ClientId) -> Result<(), TopicHandlerError> {
+        self.root.remove_client(client_id)?;
+        self.exact_index.write()?.retain(|_, subscribers| {
+            subscribers.remove(client_id);
+            !subscribers.is_empty()
+        });
+        self.notify(SubscriptionEvent::ClientRemoved {
+            client_id: client_id.clone(),
+        })?;
+        Ok(())
+    }
+
+    /// Returns the ids of all the clients with at least one subscription
+    pub fn subscribed_clients(&self) -> Result<HashSet<ClientId>, TopicHandlerError> {
+        let mut clients = HashSet::new();
+        self.root.collect_subscribed_clients(&mut clients)?;
+        Ok(clients)
+    }
+
+    /// Returns the `n` topics with the most publications, along with
+    /// how many times they were published to, from most to least published.
+    /// Only the 1024 most published topics are counted, so a topic that is
+    /// rarely published to may be missing
+    pub fn top_topics(&self, n: usize) -> Result<Vec<(String, u64)>, TopicHandlerError> {
+        self.publish_counts.top(n)
+    }
+
+    /// Returns a snapshot of the whole topic tree, with the number of
+    /// subscriptions and whether there is a retained message on each
+    /// topic. It only takes read locks, one node at a time, so it may
+    /// not reflect changes made while it is being built
+    pub fn dump_tree(&self) -> Result<TopicTreeSnapshot, TopicHandlerError> {
+        self.root.snapshot("")
+    }
+
+    /// Returns true if a certain topic name matches a given topic filter
+    pub fn matches(topic_filter: &str, topic_name: &str) -> bool {
+        Topic::topic_filter_matches(topic_filter, topic_name)
     }
 
-    /// Unsubscribe a client_id from a set of topics given a Unsubscribe packet
-    pub fn unsubscribe(
+    #[doc(hidden)]
+    /// Returns true if the topic filter has no wildcards
+    fn is_exact(topic_filter: &str) -> bool {
+        !topic_filter
+            .split(SEP)
+            .any(|level| level == SINGLE_LEVEL_WILDCARD || level == MULTI_LEVEL_WILDCARD)
+    }
+
+    #[doc(hidden)]
+    /// Removes the client from the exact index entry of the topic filter
+    fn remove_from_index(
         &self,
-        packet: Unsubscribe,
         client_id: &str,
+        topic_filter: &str,
     ) -> Result<(), TopicHandlerError> {
-        for topic_name in packet.topic_filters() {
-            self.root.unsubscribe(Some(topic_name.name()), client_id)?;
+        let mut exact_index = self.exact_index.write()?;
+        if let Some(subscribers) = exact_index.get_mut(topic_filter) {
+            subscribers.remove(client_id);
+            if subscribers.is_empty() {
+                exact_index.remove(topic_filter);
+            }
         }
         Ok(())
     }
 
-    /// Removes a client and all of its subscriptions
-    pub fn remove_client(&self, client_id: &str) -> Result<(), TopicHandlerError> {
-        self.root.remove_client(client_id)?;
+    #[doc(hidden)]
+    /// Reports a change in the subscriptions to the listener, if there is one
+    fn notify(&self, event: SubscriptionEvent) -> Result<(), TopicHandlerError> {
+        if let Some(listener) = self.listener.read()?.as_ref() {
+            listener(&event);
+        }
         Ok(())
     }
 
     #[doc(hidden)]
     /// Sends a publish packet to the given subscribers, adjusting the QoS if needed
     fn send_publish(
-        sender: &Sender<Message>,
+        sender: &dyn MessageSink,
         packet: &Publish,
         subscribers: &[Subscription],
     ) -> Result<(), TopicHandlerError> {
@@ -578,8 +1564,9 @@ impl TopicHandler {
             let mut to_be_sent = packet.clone();
             to_be_sent.set_max_qos(data.qos);
             sender.send(Message {
-                client_id: id.to_string(),
+                client_id: id.clone(),
                 packet: to_be_sent,
+                origin: None,
             })?;
         }
         Ok(())
@@ -588,9 +1575,28 @@ impl TopicHandler {
 
 #[cfg(test)]
 mod tests {
-    use super::{Topic, TopicHandler};
-
-    use std::{collections::HashSet, sync::mpsc::channel, vec};
+    use super::{
+        topic_handler_error::{TopicHandlerError, TopicHandlerErrorKind},
+        BoundedSink, Message, MessageSink, Origin, SubscriptionEvent, Topic, TopicHandler,
+        TopicTreeSnapshot,
+    };
+    use crate::{
+        client_id::ClientId,
+        test_helpers::client_id,
+        traits::{LowercaseTopics, RetainedOverflow},
+    };
+
+    use std::{
+        collections::HashSet,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, Ordering},
+            mpsc::{channel, sync_channel},
+            Arc, Mutex,
+        },
+        thread,
+        time::{Duration, Instant},
+        vec,
+    };
 
     use packets::publish::Publish;
     use packets::qos::QoSLevel;
@@ -609,6 +1615,13 @@ mod tests {
         )
     }
 
+    fn build_subscribe_qos1(topic: &str) -> Subscribe {
+        Subscribe::new(
+            vec![TopicFilter::new(topic, QoSLevel::QoSLevel1).unwrap()],
+            123,
+        )
+    }
+
     fn build_unsubscribe(topic: &str) -> Unsubscribe {
         Unsubscribe::new(
             123,
@@ -623,7 +1636,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let message = receiver.recv().unwrap();
@@ -639,7 +1652,7 @@ mod tests {
 
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let message = receiver.recv().unwrap();
@@ -655,8 +1668,8 @@ mod tests {
 
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
-        handler.subscribe(&subscribe, "user2").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
+        handler.subscribe(&subscribe, &client_id("user2")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let mut pending_users: HashSet<String> = ["user".to_string(), "user2".to_string()]
@@ -664,12 +1677,77 @@ mod tests {
             .cloned()
             .collect();
         for msg in receiver {
-            assert!(pending_users.contains(&msg.client_id));
-            pending_users.remove(&msg.client_id);
+            assert!(pending_users.contains(msg.client_id.as_str()));
+            pending_users.remove(msg.client_id.as_str());
             assert_eq!(msg.packet.topic_name(), "topic/auto/casa");
         }
     }
 
+    #[test]
+    fn test_exact_subscribers_are_delivered_from_index() {
+        let publish = build_publish("topic/auto/casa", "unMensaje");
+        let handler = TopicHandler::new();
+        let (sender, receiver) = channel();
+
+        for i in 0..5000 {
+            let id = client_id(&format!("user{}", i));
+            handler
+                .subscribe(&build_subscribe("topic/auto/casa"), &id)
+                .unwrap();
+        }
+        handler
+            .subscribe(&build_subscribe("topic/+/casa"), &client_id("single"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("topic/#"), &client_id("multi"))
+            .unwrap();
+        // Los suscriptores exactos se encuentran sin recorrer el árbol
+        assert_eq!(
+            handler.exact_index.read().unwrap()["topic/auto/casa"].len(),
+            5000
+        );
+        assert_eq!(handler.exact_index.read().unwrap().len(), 1);
+
+        handler.publish(&publish, sender).unwrap();
+
+        // Cada cliente recibe el mensaje una sola vez
+        let mut received = HashSet::new();
+        for msg in receiver {
+            assert!(received.insert(msg.client_id));
+        }
+        assert_eq!(received.len(), 5002);
+        assert!(received.contains("single"));
+        assert!(received.contains("multi"));
+    }
+
+    #[test]
+    fn test_unsubscribe_and_remove_client_update_index() {
+        let handler = TopicHandler::new();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user1"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user2"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("c"), &client_id("user1"))
+            .unwrap();
+
+        handler
+            .unsubscribe(build_unsubscribe("a/b"), &client_id("user2"))
+            .unwrap();
+        assert_eq!(handler.exact_index.read().unwrap()["a/b"].len(), 1);
+
+        handler.remove_client(&client_id("user1")).unwrap();
+        assert!(handler.exact_index.read().unwrap().is_empty());
+
+        let (sender, receiver) = channel();
+        handler
+            .publish(&build_publish("a/b", "msg"), sender)
+            .unwrap();
+        assert!(receiver.recv().is_err());
+    }
+
     #[test]
     fn test_5000_subscribers() {
         let subscribe = build_subscribe("topic/auto/casa");
@@ -681,14 +1759,14 @@ mod tests {
         let mut pending_users = HashSet::new();
         for i in 0..5000 {
             let id = format!("user{}", i);
-            handler.subscribe(&subscribe, &id).unwrap();
+            handler.subscribe(&subscribe, &client_id(&id)).unwrap();
             pending_users.insert(id);
         }
         handler.publish(&publish, sender).unwrap();
 
         for msg in receiver {
-            assert!(pending_users.contains(&msg.client_id));
-            pending_users.remove(&msg.client_id);
+            assert!(pending_users.contains(msg.client_id.as_str()));
+            pending_users.remove(msg.client_id.as_str());
             assert_eq!(msg.packet.topic_name(), "topic/auto/casa");
         }
     }
@@ -700,13 +1778,95 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let message = receiver.recv().unwrap();
         assert_eq!(message.packet.qos(), QoSLevel::QoSLevel0);
     }
 
+    #[test]
+    fn test_subscription_listener_receives_events_in_order() {
+        let handler = TopicHandler::new();
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let events_copy = events.clone();
+        handler
+            .set_subscription_listener(Some(Box::new(move |event| {
+                events_copy.lock().unwrap().push(event.clone())
+            })))
+            .unwrap();
+
+        handler
+            .subscribe(&build_subscribe("a/+"), &client_id("user"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("b/#"), &client_id("user"))
+            .unwrap();
+        handler
+            .unsubscribe(build_unsubscribe("a/+"), &client_id("user"))
+            .unwrap();
+        handler.remove_client(&client_id("user")).unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                SubscriptionEvent::Subscribed {
+                    client_id: client_id("user"),
+                    filter: "a/+".to_string()
+                },
+                SubscriptionEvent::Subscribed {
+                    client_id: client_id("user"),
+                    filter: "b/#".to_string()
+                },
+                SubscriptionEvent::Unsubscribed {
+                    client_id: client_id("user"),
+                    filter: "a/+".to_string()
+                },
+                SubscriptionEvent::ClientRemoved {
+                    client_id: client_id("user")
+                },
+            ]
+        );
+    }
+
+    struct CapturingSink {
+        messages: Mutex<Vec<Message>>,
+    }
+
+    impl MessageSink for &CapturingSink {
+        fn send(&self, message: Message) -> Result<(), TopicHandlerError> {
+            self.messages.lock()?.push(message);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_publish_into_custom_sink() {
+        let handler = TopicHandler::new();
+        let sink = CapturingSink {
+            messages: Mutex::new(Vec::new()),
+        };
+
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("exact"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/+"), &client_id("wildcard"))
+            .unwrap();
+        handler
+            .publish(&build_publish("a/b", "unMensaje"), &sink)
+            .unwrap();
+
+        let messages = sink.messages.into_inner().unwrap();
+        let ids: HashSet<ClientId> = messages.iter().map(|m| m.client_id.clone()).collect();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(
+            ids,
+            HashSet::from([client_id("exact"), client_id("wildcard")])
+        );
+        assert!(messages.iter().all(|m| m.packet.payload() == b"unMensaje"));
+    }
+
     #[test]
     fn test_unsubscribe_stop_sending_messages_to_client() {
         let subscribe = build_subscribe("topic/auto/casa");
@@ -717,9 +1877,11 @@ mod tests {
 
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&first_publish, sender.clone()).unwrap();
-        handler.unsubscribe(unsubscribe, "user").unwrap();
+        handler
+            .unsubscribe(unsubscribe, &client_id("user"))
+            .unwrap();
         handler.publish(&second_publish, sender).unwrap();
         let message = receiver.recv().unwrap();
         assert_eq!(message.client_id, "user");
@@ -737,9 +1899,9 @@ mod tests {
 
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&first_publish, sender.clone()).unwrap();
-        handler.remove_client("user").unwrap();
+        handler.remove_client(&client_id("user")).unwrap();
         handler.publish(&second_publish, sender).unwrap();
         let message = receiver.recv().unwrap();
         assert_eq!(message.client_id, "user");
@@ -758,9 +1920,11 @@ mod tests {
 
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&first_publish, sender.clone()).unwrap();
-        handler.unsubscribe(unsubscribe, "user").unwrap();
+        handler
+            .unsubscribe(unsubscribe, &client_id("user"))
+            .unwrap();
         handler.publish(&second_publish, sender).unwrap();
         let first_message = receiver.recv().unwrap();
         let second_message = receiver.recv().unwrap();
@@ -777,7 +1941,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         assert!(receiver.recv().is_err());
@@ -790,7 +1954,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let message = receiver.recv().unwrap();
@@ -807,7 +1971,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let message = receiver.recv().unwrap();
@@ -844,7 +2008,7 @@ mod tests {
            let handler = TopicHandler::new();
            let (sender, receiver) = channel();
 
-           handler.subscribe(&subscribe, "user").unwrap();
+           handler.subscribe(&subscribe, &client_id("user")).unwrap();
            handler.publish(&publish, sender).unwrap();
 
            let message = receiver.recv().unwrap();
@@ -872,7 +2036,7 @@ mod tests {
            let handler = TopicHandler::new();
            let (sender, receiver) = channel();
 
-           handler.subscribe(&subscribe, "user").unwrap();
+           handler.subscribe(&subscribe, &client_id("user")).unwrap();
            handler.publish(&publish, sender).unwrap();
 
            let message = receiver.recv().unwrap();
@@ -900,7 +2064,7 @@ mod tests {
            let handler = TopicHandler::new();
            let (sender, receiver) = channel();
 
-           handler.subscribe(&subscribe, "user").unwrap();
+           handler.subscribe(&subscribe, &client_id("user")).unwrap();
            handler.publish(&publish, sender).unwrap();
 
            let message = receiver.recv().unwrap();
@@ -928,7 +2092,7 @@ mod tests {
            let handler = TopicHandler::new();
            let (sender, receiver) = channel();
 
-           handler.subscribe(&subscribe, "user").unwrap();
+           handler.subscribe(&subscribe, &client_id("user")).unwrap();
            handler.publish(&publish, sender).unwrap();
 
            let message = receiver.recv().unwrap();
@@ -957,7 +2121,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let message = receiver.recv().unwrap();
@@ -985,9 +2149,11 @@ mod tests {
 
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&first_publish, sender.clone()).unwrap();
-        handler.unsubscribe(unsubscribe, "user").unwrap();
+        handler
+            .unsubscribe(unsubscribe, &client_id("user"))
+            .unwrap();
         handler.publish(&second_publish, sender).unwrap();
         let message = receiver.recv().unwrap();
         assert_eq!(message.client_id, "user");
@@ -1035,7 +2201,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let message = receiver.recv().unwrap();
@@ -1052,7 +2218,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let message = receiver.recv().unwrap();
@@ -1069,7 +2235,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender.clone()).unwrap();
         handler.publish(&publish, sender.clone()).unwrap();
         handler.publish(&publish, sender).unwrap();
@@ -1094,7 +2260,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender).unwrap();
 
         let message = receiver.recv().unwrap();
@@ -1114,7 +2280,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender.clone()).unwrap();
         handler.publish(&publish, sender.clone()).unwrap();
         handler.publish(&publish, sender).unwrap();
@@ -1151,9 +2317,11 @@ mod tests {
 
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&first_publish, sender.clone()).unwrap();
-        handler.unsubscribe(unsubscribe, "user").unwrap();
+        handler
+            .unsubscribe(unsubscribe, &client_id("user"))
+            .unwrap();
         handler.publish(&second_publish, sender).unwrap();
         let message = receiver.recv().unwrap();
         assert_eq!(message.client_id, "user");
@@ -1172,9 +2340,11 @@ mod tests {
 
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&first_publish, sender.clone()).unwrap();
-        handler.unsubscribe(unsubscribe, "user").unwrap();
+        handler
+            .unsubscribe(unsubscribe, &client_id("user"))
+            .unwrap();
         handler.publish(&second_publish, sender).unwrap();
         let message = receiver.recv().unwrap();
         assert_eq!(message.client_id, "user");
@@ -1193,9 +2363,9 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish, sender.clone()).unwrap();
-        handler.remove_client("user").unwrap();
+        handler.remove_client(&client_id("user")).unwrap();
         handler.publish(&publish, sender.clone()).unwrap();
         handler.publish(&publish, sender).unwrap();
 
@@ -1215,8 +2385,8 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user1").unwrap();
-        handler.subscribe(&subscribe, "user2").unwrap();
+        handler.subscribe(&subscribe, &client_id("user1")).unwrap();
+        handler.subscribe(&subscribe, &client_id("user2")).unwrap();
 
         handler.publish(&publish, sender.clone()).unwrap();
 
@@ -1231,7 +2401,7 @@ mod tests {
             "topic/subtopic///leaf//Orangutan"
         );
 
-        handler.remove_client("user1").unwrap();
+        handler.remove_client(&client_id("user1")).unwrap();
 
         handler.publish(&publish, sender).unwrap();
 
@@ -1253,7 +2423,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "admin").unwrap();
+        handler.subscribe(&subscribe, &client_id("admin")).unwrap();
 
         handler.publish(&publish, sender).unwrap();
 
@@ -1272,7 +2442,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "admin").unwrap();
+        handler.subscribe(&subscribe, &client_id("admin")).unwrap();
 
         handler.publish(&publish, sender).unwrap();
 
@@ -1287,7 +2457,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "admin").unwrap();
+        handler.subscribe(&subscribe, &client_id("admin")).unwrap();
 
         handler.publish(&publish, sender).unwrap();
 
@@ -1302,11 +2472,11 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "admin").unwrap();
+        handler.subscribe(&subscribe, &client_id("admin")).unwrap();
 
         handler.publish(&publish, sender).unwrap();
 
-        assert_eq!(receiver.recv().unwrap().packet.payload(), ":D");
+        assert_eq!(receiver.recv().unwrap().packet.payload(), b":D");
     }
 
     #[test]
@@ -1317,11 +2487,11 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "admin").unwrap();
+        handler.subscribe(&subscribe, &client_id("admin")).unwrap();
 
         handler.publish(&publish, sender).unwrap();
 
-        assert_eq!(receiver.recv().unwrap().packet.payload(), ":D");
+        assert_eq!(receiver.recv().unwrap().packet.payload(), b":D");
     }
 
     #[test]
@@ -1340,14 +2510,258 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 1);
         assert!(retained_messages[0].retain_flag());
-        assert_eq!(retained_messages[0].payload(), "#0000FF");
+        assert_eq!(retained_messages[0].payload(), b"#0000FF");
         assert_eq!(retained_messages[0].topic_name(), "topic");
     }
 
+    #[test]
+    fn test_retained_for_subscribed_topics() {
+        let handler = TopicHandler::new();
+        let (sender, _r) = channel();
+        handler
+            .publish(&build_retained("a/b", "ab"), sender.clone())
+            .unwrap();
+        handler
+            .publish(&build_retained("a/c", "ac"), sender)
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user"))
+            .unwrap();
+
+        // Solo recibe los de topics a los que esta suscripto, con su QoS
+        let retained = handler.retained_for("a/#", &client_id("user")).unwrap();
+        assert_eq!(retained.len(), 1);
+        assert_eq!(retained[0].payload(), b"ab");
+        assert_eq!(retained[0].qos(), QoSLevel::QoSLevel0);
+        assert!(handler
+            .retained_for("a/#", &client_id("other"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_retained_history() {
+        let handler = TopicHandler::new();
+        handler.set_retained_history_depth(Some(3)).unwrap();
+        let (sender, _r) = channel();
+        for payload in ["uno", "dos", "tres", "cuatro"] {
+            let publish =
+                Publish::new(false, QoSLevel::QoSLevel1, true, "a/b", payload, Some(123)).unwrap();
+            handler.publish(&publish, sender.clone()).unwrap();
+        }
+
+        let latest = handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user"))
+            .unwrap();
+        let history = handler
+            .subscribe(&build_subscribe("$history/a/b"), &client_id("user"))
+            .unwrap();
+
+        assert_eq!(latest.len(), 1);
+        assert_eq!(latest[0].payload(), b"cuatro");
+        let payloads: Vec<&str> = history.iter().map(|p| p.payload_str().unwrap()).collect();
+        assert_eq!(payloads, vec!["dos", "tres", "cuatro"]);
+        assert!(history.iter().all(|p| p.topic_name() == "a/b"));
+    }
+
+    #[test]
+    fn test_retained_history_disabled_by_default() {
+        let handler = TopicHandler::new();
+        let (sender, _r) = channel();
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel1, true, "a/b", "uno", Some(123)).unwrap();
+        handler.publish(&publish, sender).unwrap();
+
+        let history = handler
+            .subscribe(&build_subscribe("$history/a/b"), &client_id("user"))
+            .unwrap();
+
+        assert!(history.is_empty());
+    }
+
+    fn build_retained(topic: &str, message: &str) -> Publish {
+        Publish::new(false, QoSLevel::QoSLevel1, true, topic, message, Some(123)).unwrap()
+    }
+
+    #[test]
+    fn test_publish_from_tags_messages_with_origin() {
+        let handler = TopicHandler::new();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user"))
+            .unwrap();
+        let (sender, receiver) = channel();
+        let origin = Origin::new("norte").forwarded();
+
+        handler
+            .publish_from(&build_publish("a/b", "uno"), sender.clone(), &origin)
+            .unwrap();
+        handler
+            .publish(&build_publish("a/b", "dos"), sender)
+            .unwrap();
+
+        let origins: Vec<Option<Origin>> = receiver.try_iter().map(|msg| msg.origin).collect();
+        assert_eq!(origins, vec![Some(origin), None]);
+    }
+
+    #[test]
+    fn test_origin_has_looped() {
+        let origin = Origin::new("norte");
+
+        assert!(origin.has_looped(Some("norte"), 8));
+        assert!(!origin.has_looped(Some("sur"), 8));
+        assert!(!origin.has_looped(None, 8));
+        assert!(origin.forwarded().forwarded().has_looped(None, 2));
+    }
+
+    #[test]
+    fn test_overlapping_subscriptions_get_one_copy_each() {
+        let handler = TopicHandler::new();
+        handler
+            .subscribe(&build_subscribe("a/#"), &client_id("user"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user"))
+            .unwrap();
+        let (sender, receiver) = channel();
+
+        handler
+            .publish(&build_publish("a/b", "uno"), sender)
+            .unwrap();
+
+        assert_eq!(receiver.try_iter().count(), 2);
+    }
+
+    #[test]
+    fn test_deduplicate_deliveries() {
+        let handler = TopicHandler::new();
+        handler.set_deduplicate_deliveries(true).unwrap();
+        let subscribe = Subscribe::new(
+            vec![
+                TopicFilter::new("a/#", QoSLevel::QoSLevel0).unwrap(),
+                TopicFilter::new("a/+", QoSLevel::QoSLevel1).unwrap(),
+                TopicFilter::new("a/b", QoSLevel::QoSLevel0).unwrap(),
+            ],
+            123,
+        );
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("other"))
+            .unwrap();
+        let (sender, receiver) = channel();
+
+        handler
+            .publish(&build_publish("a/b", "uno"), sender)
+            .unwrap();
+
+        let mut messages: Vec<Message> = receiver.try_iter().collect();
+        messages.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].client_id, "other");
+        assert_eq!(messages[1].client_id, "user");
+        // Se usa el mayor QoS entre las suscripciones
+        assert_eq!(messages[1].packet.qos(), QoSLevel::QoSLevel1);
+    }
+
+    #[test]
+    fn test_deduplicate_deliveries_without_blocking() {
+        let handler = TopicHandler::new();
+        handler.set_deduplicate_deliveries(true).unwrap();
+        handler.set_publish_lock_retries(Some(3)).unwrap();
+        handler
+            .subscribe(&build_subscribe("a/#"), &client_id("user"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user"))
+            .unwrap();
+        let (sender, receiver) = channel();
+
+        handler
+            .publish(&build_publish("a/b", "uno"), sender)
+            .unwrap();
+
+        assert_eq!(receiver.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn test_max_retained_messages_evicts_oldest() {
+        let handler = TopicHandler::new();
+        handler
+            .set_max_retained_messages(Some(2), RetainedOverflow::EvictOldest)
+            .unwrap();
+        let (sender, _r) = channel();
+        for topic in ["a", "b", "c", "d"] {
+            handler
+                .publish(&build_retained(topic, "uno"), sender.clone())
+                .unwrap();
+        }
+
+        let retained = handler
+            .subscribe(&build_subscribe("#"), &client_id("user"))
+            .unwrap();
+
+        assert_eq!(handler.retained_count().unwrap(), 2);
+        let mut topics: Vec<&str> = retained.iter().map(|p| p.topic_name()).collect();
+        topics.sort_unstable();
+        assert_eq!(topics, vec!["c", "d"]);
+    }
+
+    #[test]
+    fn test_max_retained_messages_rejects_new_topics() {
+        let handler = TopicHandler::new();
+        handler
+            .set_max_retained_messages(Some(2), RetainedOverflow::Reject)
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("#"), &client_id("user"))
+            .unwrap();
+        let (sender, receiver) = channel();
+        for topic in ["a", "b"] {
+            handler
+                .publish(&build_retained(topic, "uno"), sender.clone())
+                .unwrap();
+        }
+
+        let err = handler
+            .publish(&build_retained("c", "uno"), sender.clone())
+            .unwrap_err();
+        // Reemplazar el mensaje de un topic que ya tiene uno sigue permitido
+        handler
+            .publish(&build_retained("a", "dos"), sender.clone())
+            .unwrap();
+
+        assert_eq!(err.kind(), TopicHandlerErrorKind::RetainedLimitReached);
+        assert_eq!(handler.retained_count().unwrap(), 2);
+        // El publish rechazado no se le envia a nadie
+        let topics: Vec<String> = receiver
+            .try_iter()
+            .map(|message| message.packet.topic_name().to_string())
+            .collect();
+        assert_eq!(topics, vec!["a", "b", "a"]);
+    }
+
+    #[test]
+    fn test_removing_retained_message_frees_room() {
+        let handler = TopicHandler::new();
+        handler
+            .set_max_retained_messages(Some(1), RetainedOverflow::Reject)
+            .unwrap();
+        let (sender, _r) = channel();
+        handler
+            .publish(&build_retained("a", "uno"), sender.clone())
+            .unwrap();
+
+        handler
+            .publish(&build_retained("a", ""), sender.clone())
+            .unwrap();
+
+        assert_eq!(handler.retained_count().unwrap(), 0);
+        assert!(handler.publish(&build_retained("b", "uno"), sender).is_ok());
+    }
+
     #[test]
     fn test_retained_messages_not_on_siblings() {
         let subscribe = build_subscribe("other_topic");
@@ -1364,7 +2778,7 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 0);
     }
@@ -1385,11 +2799,11 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 1);
         assert!(retained_messages[0].retain_flag());
-        assert_eq!(retained_messages[0].payload(), "#0000FF");
+        assert_eq!(retained_messages[0].payload(), b"#0000FF");
         assert_eq!(retained_messages[0].topic_name(), "topic/subtopic");
     }
 
@@ -1421,7 +2835,7 @@ mod tests {
 
         handler.publish(&publish_1, sender.clone()).unwrap();
         handler.publish(&publish_2, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 0);
     }
@@ -1442,11 +2856,11 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 1);
         assert!(retained_messages[0].retain_flag());
-        assert_eq!(retained_messages[0].payload(), "#0000FF");
+        assert_eq!(retained_messages[0].payload(), b"#0000FF");
         assert_eq!(retained_messages[0].topic_name(), "topic/a/subtopic");
     }
 
@@ -1466,11 +2880,11 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 1);
         assert!(retained_messages[0].retain_flag());
-        assert_eq!(retained_messages[0].payload(), "#0000FF");
+        assert_eq!(retained_messages[0].payload(), b"#0000FF");
     }
 
     #[test]
@@ -1489,11 +2903,11 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 1);
         assert!(retained_messages[0].retain_flag());
-        assert_eq!(retained_messages[0].payload(), "#0000FF");
+        assert_eq!(retained_messages[0].payload(), b"#0000FF");
         assert_eq!(retained_messages[0].topic_name(), "topic/a/subtopic");
     }
 
@@ -1513,11 +2927,11 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 1);
         assert!(retained_messages[0].retain_flag());
-        assert_eq!(retained_messages[0].payload(), "#0000FF");
+        assert_eq!(retained_messages[0].payload(), b"#0000FF");
         assert_eq!(
             retained_messages[0].topic_name(),
             "topic/a//b/subtopic/cat/white"
@@ -1540,7 +2954,7 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 0);
     }
@@ -1561,11 +2975,11 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 1);
         assert!(retained_messages[0].retain_flag());
-        assert_eq!(retained_messages[0].payload(), "#0000FF");
+        assert_eq!(retained_messages[0].payload(), b"#0000FF");
     }
 
     #[test]
@@ -1595,11 +3009,11 @@ mod tests {
         handler.publish(&publish1, sender.clone()).unwrap();
         handler.publish(&publish2, sender).unwrap();
 
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 1);
         assert!(retained_messages[0].retain_flag());
-        assert_eq!(retained_messages[0].payload(), "spam");
+        assert_eq!(retained_messages[0].payload(), b"spam");
     }
 
     #[test]
@@ -1611,7 +3025,7 @@ mod tests {
         let (sender, _r) = channel();
 
         handler.publish(&publish, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 0);
     }
@@ -1635,7 +3049,7 @@ mod tests {
 
         handler.publish(&publish_1, sender.clone()).unwrap();
         handler.publish(&publish_2, sender).unwrap();
-        let retained_messages = handler.subscribe(&subscribe, "user").unwrap();
+        let retained_messages = handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         assert_eq!(retained_messages.len(), 0);
     }
@@ -1655,7 +3069,7 @@ mod tests {
         let handler = TopicHandler::new();
         let (sender, receiver) = channel();
 
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
         handler.publish(&publish_1, sender).unwrap();
 
         let msg = receiver.recv().unwrap();
@@ -1668,7 +3082,7 @@ mod tests {
         let subscribe = build_subscribe("topic");
         let publish = build_publish("topic", "unMensaje");
         let handler = TopicHandler::new();
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         let json = serde_json::to_string(&handler).unwrap();
         let deserialized_handler: TopicHandler = serde_json::from_str(&json).unwrap();
@@ -1686,7 +3100,7 @@ mod tests {
         let subscribe = build_subscribe("topic");
         let publish = build_publish("topic", "unMensaje");
         let handler = TopicHandler::new();
-        handler.subscribe(&subscribe, "user").unwrap();
+        handler.subscribe(&subscribe, &client_id("user")).unwrap();
 
         let json = serde_json::to_value(&handler).unwrap();
         let deserialized_handler: TopicHandler = serde_json::from_value(json).unwrap();
@@ -1699,6 +3113,22 @@ mod tests {
         assert_eq!(message.packet.topic_name(), "topic");
     }
 
+    #[test]
+    fn test_dump_keeps_retained_count() {
+        let handler = TopicHandler::new();
+        let (sender, _r) = channel();
+        for topic in ["a", "a/b", "c"] {
+            handler
+                .publish(&build_retained(topic, "uno"), sender.clone())
+                .unwrap();
+        }
+
+        let json = serde_json::to_value(&handler).unwrap();
+        let deserialized_handler: TopicHandler = serde_json::from_value(json).unwrap();
+
+        assert_eq!(deserialized_handler.retained_count().unwrap(), 3);
+    }
+
     #[test]
     fn test_dump_5000_subscribers() {
         let subscribes = [
@@ -1715,7 +3145,9 @@ mod tests {
         let mut pending_users = HashSet::new();
         for i in 0..5000 {
             let id = format!("user{}", i);
-            handler.subscribe(&subscribes[i % 3], &id).unwrap();
+            handler
+                .subscribe(&subscribes[i % 3], &client_id(&id))
+                .unwrap();
             pending_users.insert(id);
         }
 
@@ -1725,9 +3157,422 @@ mod tests {
         deserialized_handler.publish(&publish, sender).unwrap();
 
         for msg in receiver {
-            assert!(pending_users.contains(&msg.client_id));
-            pending_users.remove(&msg.client_id);
+            assert!(pending_users.contains(msg.client_id.as_str()));
+            pending_users.remove(msg.client_id.as_str());
             assert_eq!(msg.packet.topic_name(), "topic/auto/casa");
         }
     }
+
+    #[test]
+    fn test_publish_with_lock_retries_does_not_block() {
+        let handler = TopicHandler::new();
+        handler.set_publish_lock_retries(Some(10)).unwrap();
+        handler
+            .subscribe(&build_subscribe("a/#"), &client_id("user"))
+            .unwrap();
+        let (sender, receiver) = channel();
+
+        // Simulo un cambio de suscripciones en curso
+        let guard = handler.root.subtopics.write().unwrap();
+        let err = handler
+            .publish(&build_publish("a/b", "msg"), sender.clone())
+            .unwrap_err();
+        assert_eq!(err.kind(), TopicHandlerErrorKind::WouldBlock);
+        assert!(receiver.try_recv().is_err());
+        drop(guard);
+
+        handler
+            .publish(&build_publish("a/b", "msg"), sender)
+            .unwrap();
+        assert_eq!(receiver.recv().unwrap().client_id, "user");
+    }
+
+    #[test]
+    fn test_retained_publish_with_lock_retries_does_not_block() {
+        let handler = TopicHandler::new();
+        handler.set_publish_lock_retries(Some(10)).unwrap();
+        handler
+            .subscribe(&build_subscribe("a/#"), &client_id("user"))
+            .unwrap();
+        let (sender, receiver) = channel();
+        let mut publish = build_publish("a/b", "msg");
+        publish.set_retain_flag(true);
+
+        // Simulo otro publish retenido en curso
+        let guard = handler.retained_index.lock().unwrap();
+        let err = handler.publish(&publish, sender.clone()).unwrap_err();
+        assert_eq!(err.kind(), TopicHandlerErrorKind::WouldBlock);
+        drop(guard);
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(handler.retained_count().unwrap(), 0);
+
+        handler.publish(&publish, sender).unwrap();
+        assert_eq!(receiver.recv().unwrap().client_id, "user");
+        assert_eq!(handler.retained_count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_publish_is_not_starved_by_concurrent_subscribes() {
+        let handler = Arc::new(TopicHandler::new());
+        handler.set_publish_lock_retries(Some(100)).unwrap();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/+"), &client_id("user"))
+            .unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+
+        // Varios threads se suscriben y desuscriben sin parar
+        let mut threads = Vec::new();
+        for i in 0..4 {
+            let handler = handler.clone();
+            let stop = stop.clone();
+            threads.push(thread::spawn(move || {
+                let client_id = client_id(&format!("flood{}", i));
+                let mut j = 0;
+                while !stop.load(Ordering::Relaxed) {
+                    let topic = format!("a/{}/#", j % 10);
+                    handler
+                        .subscribe(&build_subscribe(&topic), &client_id)
+                        .unwrap();
+                    handler
+                        .unsubscribe(build_unsubscribe(&topic), &client_id)
+                        .unwrap();
+                    j += 1;
+                }
+            }));
+        }
+
+        let (sender, receiver) = channel();
+        for _ in 0..100 {
+            let start = Instant::now();
+            loop {
+                match handler.publish(&build_publish("a/b", "msg"), sender.clone()) {
+                    Ok(()) => break,
+                    Err(err) => assert_eq!(err.kind(), TopicHandlerErrorKind::WouldBlock),
+                }
+            }
+            assert!(start.elapsed() < Duration::from_secs(1));
+        }
+        stop.store(true, Ordering::Relaxed);
+        for thread in threads {
+            thread.join().unwrap();
+        }
+        drop(sender);
+
+        // Cada publish llego una vez por suscripcion
+        assert_eq!(receiver.iter().count(), 200);
+    }
+
+    #[test]
+    fn test_dump_tree() {
+        let handler = TopicHandler::new();
+        let (sender, _receiver) = channel();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user1"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("user2"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/#"), &client_id("user1"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/+/c"), &client_id("user3"))
+            .unwrap();
+        let mut retained = build_publish("d", "unMensaje");
+        retained.set_retain_flag(true);
+        handler.publish(&retained, sender).unwrap();
+
+        let leaf = |name: &str, subscribers, retained| TopicTreeSnapshot {
+            name: name.to_string(),
+            subscribers,
+            multilevel_subscribers: 0,
+            singlelevel_subscribers: 0,
+            retained,
+            subtopics: vec![],
+        };
+        let expected = TopicTreeSnapshot {
+            name: "".to_string(),
+            subscribers: 0,
+            multilevel_subscribers: 0,
+            singlelevel_subscribers: 0,
+            retained: false,
+            subtopics: vec![
+                TopicTreeSnapshot {
+                    name: "a".to_string(),
+                    subscribers: 0,
+                    multilevel_subscribers: 1,
+                    singlelevel_subscribers: 1,
+                    retained: false,
+                    subtopics: vec![leaf("b", 2, false)],
+                },
+                leaf("d", 0, true),
+            ],
+        };
+        assert_eq!(handler.dump_tree().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_top_topics() {
+        let handler = TopicHandler::new();
+        let (sender, _receiver) = channel();
+
+        for _ in 0..5 {
+            handler
+                .publish(&build_publish("a/b", "unMensaje"), sender.clone())
+                .unwrap();
+        }
+        handler
+            .publish(&build_publish("c", "unMensaje"), sender)
+            .unwrap();
+
+        assert_eq!(handler.top_topics(1).unwrap(), vec![("a/b".to_string(), 5)]);
+        assert_eq!(
+            handler.top_topics(5).unwrap(),
+            vec![("a/b".to_string(), 5), ("c".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_counting_publications_does_not_grow_the_tree() {
+        let handler = TopicHandler::new();
+        let (sender, _receiver) = channel();
+
+        for i in 0..100 {
+            handler
+                .publish(
+                    &build_publish(&format!("sensores/{}", i), "unMensaje"),
+                    sender.clone(),
+                )
+                .unwrap();
+        }
+
+        assert!(handler.dump_tree().unwrap().subtopics.is_empty());
+        assert_eq!(handler.top_topics(usize::MAX).unwrap().len(), 100);
+    }
+
+    #[test]
+    fn test_bounded_sink_drops_when_full() {
+        let handler = TopicHandler::new();
+        for id in ["a", "b", "c", "d", "e"] {
+            handler
+                .subscribe(&build_subscribe("topic"), &client_id(id))
+                .unwrap();
+        }
+        // Nadie recibe del channel, como si el thread de envio estuviera trabado
+        let (sender, receiver) = sync_channel(2);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let sink = BoundedSink::new(sender, Duration::from_millis(20), dropped.clone());
+        let publish =
+            Publish::new(false, QoSLevel::QoSLevel0, false, "topic", "msg", None).unwrap();
+
+        handler.publish(&publish, sink).unwrap();
+
+        assert_eq!(receiver.try_iter().count(), 2);
+        assert_eq!(dropped.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn test_bounded_sink_never_drops_qos1() {
+        let handler = TopicHandler::new();
+        for id in ["a", "b", "c", "d", "e"] {
+            handler
+                .subscribe(&build_subscribe_qos1("topic"), &client_id(id))
+                .unwrap();
+        }
+        let (sender, receiver) = sync_channel(2);
+        let dropped = Arc::new(AtomicU64::new(0));
+        let sink = BoundedSink::new(sender, Duration::from_millis(20), dropped.clone());
+        let receiving = thread::spawn(move || {
+            // El receptor tarda mas que el timeout en hacer lugar
+            thread::sleep(Duration::from_millis(100));
+            receiver.iter().count()
+        });
+
+        handler
+            .publish(&build_publish("topic", "msg"), sink)
+            .unwrap();
+
+        assert_eq!(receiving.join().unwrap(), 5);
+        assert_eq!(dropped.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn test_publish_blocked_on_full_sink_does_not_block_subscriptions() {
+        let handler = Arc::new(TopicHandler::new());
+        for id in ["a", "b"] {
+            handler
+                .subscribe(&build_subscribe_qos1("topic"), &client_id(id))
+                .unwrap();
+        }
+        let (sender, receiver) = sync_channel(1);
+        let sink = BoundedSink::new(
+            sender,
+            Duration::from_millis(20),
+            Arc::new(AtomicU64::new(0)),
+        );
+        let handler_copy = handler.clone();
+        let publishing = thread::spawn(move || {
+            handler_copy
+                .publish(&build_publish("topic", "msg"), sink)
+                .unwrap();
+        });
+        thread::sleep(Duration::from_millis(100));
+
+        // El publish esta bloqueado esperando lugar, pero no tiene
+        // ningun lock tomado
+        handler
+            .subscribe(&build_subscribe_qos1("topic"), &client_id("c"))
+            .unwrap();
+        handler
+            .unsubscribe(build_unsubscribe("topic"), &client_id("a"))
+            .unwrap();
+
+        assert_eq!(receiver.iter().count(), 2);
+        publishing.join().unwrap();
+    }
+
+    #[test]
+    fn test_bounded_sink_closed_channel() {
+        let handler = TopicHandler::new();
+        handler
+            .subscribe(&build_subscribe("topic"), &client_id("user"))
+            .unwrap();
+        let (sender, receiver) = sync_channel(2);
+        drop(receiver);
+        let sink = BoundedSink::new(
+            sender,
+            Duration::from_millis(20),
+            Arc::new(AtomicU64::new(0)),
+        );
+
+        let err = handler
+            .publish(&build_publish("topic", "msg"), sink)
+            .unwrap_err();
+
+        assert_eq!(err.kind(), TopicHandlerErrorKind::DeliveryChannelClosed);
+    }
+
+    #[test]
+    fn test_suppress_resubscribe_retained() {
+        let handler = TopicHandler::new();
+        handler.set_suppress_resubscribe_retained(true).unwrap();
+        let mut retained = build_publish("a/b", "retenido");
+        retained.set_retain_flag(true);
+        handler.publish(&retained, channel().0).unwrap();
+
+        for filter in ["a/b", "a/+", "a/#"] {
+            let subscribe = build_subscribe(filter);
+            assert_eq!(
+                handler
+                    .subscribe(&subscribe, &client_id("user"))
+                    .unwrap()
+                    .len(),
+                1
+            );
+            assert!(handler
+                .subscribe(&subscribe, &client_id("user"))
+                .unwrap()
+                .is_empty());
+            // Otro cliente lo recibe en su primera suscripcion
+            assert_eq!(
+                handler
+                    .subscribe(&subscribe, &client_id("other"))
+                    .unwrap()
+                    .len(),
+                1
+            );
+        }
+    }
+
+    // Publica `per_thread` mensajes a topics distintos desde cada uno de
+    // los `threads` threads, y devuelve cuantos publish por segundo se hicieron
+    fn distinct_topics_throughput(threads: usize, per_thread: usize) -> f64 {
+        let handler = Arc::new(TopicHandler::new());
+        let start = Instant::now();
+        let handles: Vec<_> = (0..threads)
+            .map(|i| {
+                let handler = handler.clone();
+                thread::spawn(move || {
+                    let (sender, _receiver) = channel();
+                    for j in 0..per_thread {
+                        let topic = format!("bench/{}/{}/value", i, j);
+                        handler
+                            .publish(&build_publish(&topic, "msg"), sender.clone())
+                            .unwrap();
+                    }
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+        (threads * per_thread) as f64 / start.elapsed().as_secs_f64()
+    }
+
+    // Se corre a mano con `cargo test -- --ignored`
+    #[test]
+    #[ignore = "mide tiempos reales, es inestable en CI"]
+    fn test_concurrent_publish_to_distinct_topics_scales() {
+        const THREADS: usize = 8;
+        let cores = thread::available_parallelism()
+            .map(|cores| cores.get())
+            .unwrap_or(1)
+            .min(THREADS);
+
+        let single = distinct_topics_throughput(1, 2000);
+        let parallel = distinct_topics_throughput(THREADS, 2000);
+
+        // Con los nodos creandose bajo el lock de escritura del padre, los
+        // threads se serializan y no se gana nada con mas de un nucleo.
+        // Se deja margen para el ruido de la medicion
+        let expected = single * (cores as f64 * 0.25).max(0.5);
+        assert!(
+            parallel >= expected,
+            "{} threads: {:.0} publish/s, 1 thread: {:.0} publish/s",
+            THREADS,
+            parallel,
+            single
+        );
+    }
+
+    #[test]
+    fn test_topic_normalizer_applies_to_publish_and_subscribe() {
+        let handler = TopicHandler::new();
+        handler
+            .set_topic_normalizer(Some(Arc::new(LowercaseTopics)))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("a/b"), &client_id("exact"))
+            .unwrap();
+        handler
+            .subscribe(&build_subscribe("A/+"), &client_id("wildcard"))
+            .unwrap();
+        let (sender, receiver) = channel();
+
+        handler
+            .publish(&build_publish("A/B", "uno"), sender)
+            .unwrap();
+
+        let mut messages: Vec<Message> = receiver.try_iter().collect();
+        messages.sort_by(|a, b| a.client_id.cmp(&b.client_id));
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].client_id, "exact");
+        assert_eq!(messages[1].client_id, "wildcard");
+        assert_eq!(messages[0].packet.topic_name(), "a/b");
+
+        // Desuscribirse tambien usa el topic normalizado
+        handler
+            .unsubscribe(build_unsubscribe("A/B"), &client_id("exact"))
+            .unwrap();
+        let (sender, receiver) = channel();
+        handler
+            .publish(&build_publish("a/b", "dos"), sender)
+            .unwrap();
+        let messages: Vec<Message> = receiver.try_iter().collect();
+        assert_eq!(messages.len(), 1);
+        assert_eq!(messages[0].client_id, "wildcard");
+    }
 }
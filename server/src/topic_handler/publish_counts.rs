@@ -0,0 +1,120 @@
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+use super::{topic_handler_error::TopicHandlerError, LockMode};
+
+/// Maximum number of topics whose publications are counted
+const MAX_COUNTED_TOPICS: usize = 1024;
+
+/// Number of publications of each topic, kept apart from the topic tree
+/// so that counting does not create (or keep alive) any of its nodes.
+///
+/// At most `capacity` topics are counted. Once it is reached, a new topic
+/// replaces the least published one, so rarely published topics may be
+/// forgotten, but the counts that are kept are exact
+pub(super) struct PublishCounts {
+    counts: RwLock<HashMap<String, AtomicU64>>,
+    capacity: usize,
+}
+
+impl PublishCounts {
+    /// Returns an empty counter of at most `capacity` topics
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            counts: RwLock::new(HashMap::new()),
+            capacity,
+        }
+    }
+
+    /// Counts a publication to the topic, taking the lock according
+    /// to `mode`. The write lock is only taken the first time the
+    /// topic is published to
+    pub fn increment(&self, topic: &str, mode: LockMode) -> Result<(), TopicHandlerError> {
+        if let Some(count) = mode.read(&self.counts)?.get(topic) {
+            count.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        let mut counts = mode.write(&self.counts)?;
+        if let Some(count) = counts.get(topic) {
+            // Otro thread lo agrego mientras se esperaba el lock
+            count.fetch_add(1, Ordering::Relaxed);
+            return Ok(());
+        }
+        if counts.len() >= self.capacity {
+            let least = counts
+                .iter()
+                .min_by_key(|(_, count)| count.load(Ordering::Relaxed))
+                .map(|(name, _)| name.clone());
+            if let Some(name) = least {
+                counts.remove(&name);
+            }
+        }
+        counts.insert(topic.to_string(), AtomicU64::new(1));
+        Ok(())
+    }
+
+    /// Returns the `n` most published topics along with their
+    /// counts, from most to least published
+    pub fn top(&self, n: usize) -> Result<Vec<(String, u64)>, TopicHandlerError> {
+        let mut counts: Vec<(String, u64)> = self
+            .counts
+            .read()?
+            .iter()
+            .map(|(name, count)| (name.clone(), count.load(Ordering::Relaxed)))
+            .collect();
+        counts.sort_by(|(name_a, count_a), (name_b, count_b)| {
+            count_b.cmp(count_a).then_with(|| name_a.cmp(name_b))
+        });
+        counts.truncate(n);
+        Ok(counts)
+    }
+}
+
+impl Default for PublishCounts {
+    fn default() -> Self {
+        Self::new(MAX_COUNTED_TOPICS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::LockMode, PublishCounts};
+
+    #[test]
+    fn test_counts_publications() {
+        let counts = PublishCounts::new(10);
+        for _ in 0..3 {
+            counts.increment("a", LockMode::Block).unwrap();
+        }
+        counts.increment("b", LockMode::Block).unwrap();
+
+        assert_eq!(
+            counts.top(5).unwrap(),
+            vec![("a".to_string(), 3), ("b".to_string(), 1)]
+        );
+    }
+
+    #[test]
+    fn test_new_topic_replaces_least_published_when_full() {
+        let counts = PublishCounts::new(2);
+        for _ in 0..5 {
+            counts.increment("hot", LockMode::Block).unwrap();
+        }
+        counts.increment("cold", LockMode::Block).unwrap();
+        for topic in 0..100 {
+            counts
+                .increment(&format!("once/{}", topic), LockMode::Block)
+                .unwrap();
+        }
+
+        let top = counts.top(usize::MAX).unwrap();
+        assert_eq!(top.len(), 2);
+        assert_eq!(top[0], ("hot".to_string(), 5));
+        assert_eq!(top[1].1, 1);
+    }
+}
@@ -0,0 +1,89 @@
+use std::collections::{BTreeMap, HashMap};
+
+/// Index of the topics that have a retained message, ordered by
+/// when their current retained message was stored, so that the
+/// total number of retained messages can be bounded
+#[derive(Debug, Default)]
+pub(super) struct RetainedIndex {
+    /// Stamp given to the next retained message
+    next_stamp: u64,
+    /// Topic of each retained message, by stamp (oldest first)
+    by_stamp: BTreeMap<u64, String>,
+    /// Stamp of the retained message of each topic
+    by_topic: HashMap<String, u64>,
+}
+
+impl RetainedIndex {
+    /// Returns an index with the given topics, as if their retained
+    /// messages were stored in that order
+    pub fn from_topics<I: IntoIterator<Item = String>>(topics: I) -> Self {
+        let mut index = Self::default();
+        for topic in topics {
+            index.stamp(topic);
+        }
+        index
+    }
+
+    /// Returns the number of retained messages
+    pub fn len(&self) -> usize {
+        self.by_topic.len()
+    }
+
+    /// Returns true if the topic has a retained message
+    pub fn contains(&self, topic: &str) -> bool {
+        self.by_topic.contains_key(topic)
+    }
+
+    /// Records that a new retained message was stored in the topic,
+    /// making it the newest one
+    pub fn stamp(&mut self, topic: String) {
+        self.remove(&topic);
+        self.by_stamp.insert(self.next_stamp, topic.clone());
+        self.by_topic.insert(topic, self.next_stamp);
+        self.next_stamp += 1;
+    }
+
+    /// Records that the topic no longer has a retained message
+    pub fn remove(&mut self, topic: &str) {
+        if let Some(stamp) = self.by_topic.remove(topic) {
+            self.by_stamp.remove(&stamp);
+        }
+    }
+
+    /// Returns the topic with the oldest retained message
+    pub fn oldest(&self) -> Option<&str> {
+        self.by_stamp.values().next().map(String::as_str)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RetainedIndex;
+
+    #[test]
+    fn test_oldest_first() {
+        let mut index = RetainedIndex::from_topics(["a".to_string(), "b".to_string()]);
+        index.stamp("c".to_string());
+        // Volver a retener en "a" la hace la mas nueva
+        index.stamp("a".to_string());
+
+        assert_eq!(index.len(), 3);
+        assert_eq!(index.oldest(), Some("b"));
+        index.remove("b");
+        assert_eq!(index.oldest(), Some("c"));
+        index.remove("c");
+        assert_eq!(index.oldest(), Some("a"));
+        index.remove("a");
+        assert_eq!(index.oldest(), None);
+    }
+
+    #[test]
+    fn test_remove() {
+        let mut index = RetainedIndex::from_topics(["a".to_string(), "b".to_string()]);
+        index.remove("a");
+
+        assert!(!index.contains("a"));
+        assert!(index.contains("b"));
+        assert_eq!(index.len(), 1);
+    }
+}
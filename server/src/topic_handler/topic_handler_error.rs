@@ -9,6 +9,20 @@ use super::Message;
 #[derive(Debug)]
 pub struct TopicHandlerError {
     msg: String,
+    kind: TopicHandlerErrorKind,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopicHandlerErrorKind {
+    /// A lock could not be taken without blocking. The
+    /// operation had no effect, so it can be retried
+    WouldBlock,
+    /// The maximum number of retained messages was reached,
+    /// so the retained publish was rejected without effect
+    RetainedLimitReached,
+    /// The receiver of the delivered messages was dropped
+    DeliveryChannelClosed,
+    Other,
 }
 
 impl Display for TopicHandlerError {
@@ -25,10 +39,19 @@ impl Error for TopicHandlerError {
 
 impl TopicHandlerError {
     pub fn new(msg: &str) -> TopicHandlerError {
+        TopicHandlerError::new_kind(msg, TopicHandlerErrorKind::Other)
+    }
+
+    pub fn new_kind(msg: &str, kind: TopicHandlerErrorKind) -> TopicHandlerError {
         TopicHandlerError {
             msg: msg.to_string(),
+            kind,
         }
     }
+
+    pub fn kind(&self) -> TopicHandlerErrorKind {
+        self.kind
+    }
 }
 
 const DEFAULT_MSG: &str = "TopicHandlerError: No se pudo desbloquear contenido del Topic";
@@ -41,6 +64,9 @@ impl<T> From<PoisonError<T>> for TopicHandlerError {
 
 impl From<SendError<Message>> for TopicHandlerError {
     fn from(err: SendError<Message>) -> TopicHandlerError {
-        TopicHandlerError::new(&format!("No se pudo enviar paquete al servidor ({})", err))
+        TopicHandlerError::new_kind(
+            &format!("No se pudo enviar paquete al servidor ({})", err),
+            TopicHandlerErrorKind::DeliveryChannelClosed,
+        )
     }
 }
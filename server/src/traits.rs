@@ -1,6 +1,9 @@
+use packets::qos::QoSLevel;
 use std::{
     fmt, io,
     net::{Shutdown, TcpStream},
+    path::PathBuf,
+    sync::Arc,
     time::Duration,
 };
 
@@ -25,12 +28,36 @@ pub enum LoginResult {
     UsernameNotFound,
     InvalidPassword,
     Accepted,
+    /// The credentials could not be verified because of a
+    /// transient failure (the login backend is not available
+    /// or timed out), so the client should try again later
+    TemporarilyUnavailable,
 }
 
 pub trait Login: fmt::Debug + Send + Sync + 'static {
     fn login(&mut self, user_name: &str, password: &str) -> io::Result<LoginResult>;
 }
 
+/// Transformation applied to every topic name and topic filter handled
+/// by the server, both when publishing and when subscribing, so that
+/// the topics that are normalized to the same name match each other
+pub trait TopicNormalizer: Send + Sync + 'static {
+    /// Returns the normalized topic name or topic filter. It must
+    /// keep the level separators and wildcards of `topic` as they are
+    fn normalize(&self, topic: &str) -> String;
+}
+
+/// [`TopicNormalizer`] that makes topics case insensitive,
+/// by turning them to lowercase
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LowercaseTopics;
+
+impl TopicNormalizer for LowercaseTopics {
+    fn normalize(&self, topic: &str) -> String {
+        topic.to_lowercase()
+    }
+}
+
 impl TryClone for TcpStream {
     fn try_clone(&self) -> io::Result<Self>
     where
@@ -59,6 +86,64 @@ impl Close for TcpStream {
     }
 }
 
+/// Format in which the state of the server is dumped
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DumpFormat {
+    /// Indented JSON, easier to inspect
+    #[default]
+    Pretty,
+    /// JSON without whitespace, smaller and faster to write
+    Compact,
+}
+
+/// What the server does with a new retained message when
+/// the maximum number of retained messages was reached
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RetainedOverflow {
+    /// The oldest retained message is removed to make room for it
+    #[default]
+    EvictOldest,
+    /// The publish is rejected: it is not delivered to any subscriber,
+    /// but a QoS 1 publish is still acknowledged
+    Reject,
+}
+
+/// Configuration of the periodic dumps of the server state
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DumpConfig {
+    /// File the state is dumped to, and restored from
+    pub path: PathBuf,
+    /// Time between each dump
+    pub interval: Duration,
+    /// Format of the dump
+    pub format: DumpFormat,
+    /// If true, the dump is written to a temporary file which then
+    /// replaces the previous one, so that a dump interrupted halfway
+    /// does not leave a corrupted file behind
+    pub atomic: bool,
+}
+
+impl DumpConfig {
+    /// Creates a new DumpConfig with the default format,
+    /// that is not written atomically
+    pub fn new(path: impl Into<PathBuf>, interval: Duration) -> Self {
+        Self {
+            path: path.into(),
+            interval,
+            format: DumpFormat::default(),
+            atomic: false,
+        }
+    }
+}
+
+/// Default maximum length, in bytes, of the client ids
+/// accepted by the server
+pub const DEFAULT_MAX_CLIENT_ID_LENGTH: usize = 256;
+
+/// Default time the topic handler waits for room in
+/// a full delivery channel before dropping a message
+pub const DEFAULT_DELIVERY_CHANNEL_BLOCK: Duration = Duration::from_millis(100);
+
 /// Config trait for the server
 pub trait Config: Send + Sync + Clone + 'static {
     /// Returns the port to be connected
@@ -66,8 +151,20 @@ pub trait Config: Send + Sync + Clone + 'static {
 
     /// Returns dump info, if specified. This info is
     /// a tuple with the dump path and time interval.
-    /// Otherwise, it returns None
-    fn dump_info(&self) -> Option<(&str, Duration)>;
+    /// Otherwise, it returns None. Defaults to None
+    #[deprecated(note = "use `dump_config` instead")]
+    fn dump_info(&self) -> Option<(&str, Duration)> {
+        None
+    }
+
+    /// Returns the configuration of the dumps, if specified.
+    /// Otherwise, the server is not dumped. Defaults to the
+    /// one built from [`Config::dump_info`]
+    fn dump_config(&self) -> Option<DumpConfig> {
+        #[allow(deprecated)]
+        self.dump_info()
+            .map(|(path, interval)| DumpConfig::new(path, interval))
+    }
 
     /// Returns the path to the logs directory
     fn log_path(&self) -> &str;
@@ -76,4 +173,278 @@ pub trait Config: Send + Sync + Clone + 'static {
     fn ip(&self) -> &str;
 
     fn authenticator(&self) -> Option<Box<dyn Login>>;
+
+    /// Returns true if topics with a leading slash, a trailing
+    /// slash or an empty level must be rejected. Defaults to false
+    fn strict_topic_levels(&self) -> bool {
+        false
+    }
+
+    /// Returns a list of topic filter patterns, along with the
+    /// maximum QoS that subscriptions to topics matching them can
+    /// be granted. Defaults to an empty list
+    fn topic_qos_ceiling(&self) -> &[(String, QoSLevel)] {
+        &[]
+    }
+
+    /// Returns true if the client with the given id must receive
+    /// its publishes strictly in order, regardless of their QoS.
+    /// Defaults to false
+    fn strict_ordering(&self, _client_id: &str) -> bool {
+        false
+    }
+
+    /// Returns the maximum number of topic filters a client can be
+    /// subscribed to at the same time, if specified. Defaults to None
+    fn max_subscriptions_per_client(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the maximum time that sending a packet to a client
+    /// can take, if specified. Clients that exceed it are disconnected.
+    /// Defaults to None
+    fn delivery_write_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the minimum and maximum size of the packet
+    /// processing ThreadPool, if specified. When present, the
+    /// server resizes the ThreadPool within those bounds
+    /// according to its load. Defaults to None
+    fn threadpool_size_bounds(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Returns the maximum length, in bytes, of the client ids.
+    /// Clients with longer ids are rejected. Defaults to
+    /// [`DEFAULT_MAX_CLIENT_ID_LENGTH`]
+    fn max_client_id_length(&self) -> usize {
+        DEFAULT_MAX_CLIENT_ID_LENGTH
+    }
+
+    /// Returns true if client ids with characters other than
+    /// `0-9a-zA-Z` must be rejected. Defaults to false
+    fn strict_client_ids(&self) -> bool {
+        false
+    }
+
+    /// Returns how many times each subscription lock is retried
+    /// when publishing, if specified. When present, publishes never
+    /// block waiting for concurrent subscription changes; if a lock
+    /// is still taken after the retries, the publish is retried
+    /// from the start. Defaults to None, which makes publishes block
+    fn publish_lock_retries(&self) -> Option<u32> {
+        None
+    }
+
+    /// Returns the prefix of the presence topics, if specified.
+    /// When present, the server publishes a retained `online`
+    /// message to `<prefix>/<client_id>/status` when a client
+    /// connects, and `offline` when it disconnects. Defaults to None
+    fn presence_topic_prefix(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns the reconnect grace, if specified. If a client takes
+    /// over its own persistent session within this time since its
+    /// previous connection was established, the Last Will of that
+    /// connection is not published. Defaults to None
+    fn reconnect_grace(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the maximum number of publishes per second that the
+    /// server routes, across all clients, if specified. Publishes
+    /// over this rate are delayed until they are allowed: the server
+    /// stops reading from the client that sent them in the meantime.
+    /// Defaults to None
+    fn max_global_publishes_per_second(&self) -> Option<u32> {
+        None
+    }
+
+    /// Returns how many retained messages are kept in the history
+    /// of each topic, if specified. When present, subscribing to
+    /// `$history/<topic>` returns the last retained messages of
+    /// `<topic>`, from oldest to newest. Defaults to None
+    fn retained_history_depth(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns true if the PUBACK of a QoS 1 publish must be sent as
+    /// soon as it is received, before the publish is queued for its
+    /// subscribers. Otherwise, it is sent once the publish is queued,
+    /// so that a crash in between does not lose an acknowledged
+    /// message. Defaults to false
+    fn early_puback(&self) -> bool {
+        false
+    }
+
+    /// Returns the maximum number of retained messages across all
+    /// topics, if specified. When it is reached, a retained publish
+    /// to a topic without a retained message is handled according
+    /// to [`Config::retained_overflow`]. Defaults to None
+    fn max_retained_messages(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns what to do with a new retained message when the
+    /// [`Config::max_retained_messages`] were reached. Defaults
+    /// to [`RetainedOverflow::EvictOldest`]
+    fn retained_overflow(&self) -> RetainedOverflow {
+        RetainedOverflow::default()
+    }
+
+    /// Returns true if each client must get at most one copy of a
+    /// publish, with the highest QoS among its matching subscriptions.
+    /// Otherwise, it gets one copy for each matching subscription.
+    /// Defaults to false
+    fn deduplicate_deliveries(&self) -> bool {
+        false
+    }
+
+    /// Returns the id of this server among the brokers it is bridged
+    /// with, if specified. Publishes received from another broker that
+    /// were originated in this one are dropped, to break loops; so it
+    /// must be different for each broker. Defaults to None
+    fn broker_id(&self) -> Option<&str> {
+        None
+    }
+
+    /// Returns true if every connection must be handled as if it asked
+    /// for a clean session, so that no session is kept after the client
+    /// disconnects. Defaults to false
+    fn force_clean_session(&self) -> bool {
+        false
+    }
+
+    /// Returns how often the server looks for subscriptions of clients
+    /// it no longer has a session for, and removes them, if specified.
+    /// Defaults to None
+    fn reconcile_interval(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns true if TCP_NODELAY must be set on the accepted
+    /// connections, so that small packets are sent right away
+    /// instead of being buffered. Defaults to true
+    fn tcp_nodelay(&self) -> bool {
+        true
+    }
+
+    /// Returns how long a connection must be idle before TCP keepalive
+    /// probes are sent to check that the peer is still alive, if
+    /// SO_KEEPALIVE must be set on the accepted connections. Defaults
+    /// to None
+    fn tcp_keepalive(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns how long a client with QoS 1 messages waiting to be
+    /// acknowledged can go without sending any packet, if specified.
+    /// Clients that exceed it are considered dead and disconnected,
+    /// without waiting for the TCP connection to time out. Defaults
+    /// to None
+    fn pending_ack_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns true if a client can publish to `$refresh/<topic>` to get
+    /// the retained messages of the topics it is subscribed to that match
+    /// `<topic>` again, without subscribing again. It is not part of the
+    /// MQTT protocol. Defaults to false
+    fn retained_refresh(&self) -> bool {
+        false
+    }
+
+    /// Returns how many messages can be waiting in the channel between
+    /// the topic handler and the thread that delivers each publish, if
+    /// specified. When it is full, the topic handler waits up to
+    /// [`Config::delivery_channel_block`] for room, and then drops the
+    /// message if it has QoS 0. QoS 1 messages are never dropped, they
+    /// wait until there is room. Defaults to None, which leaves the
+    /// channel unbounded
+    fn delivery_channel_capacity(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns how long the topic handler waits for room in a full
+    /// delivery channel before dropping a QoS 0 message (see
+    /// [`Config::delivery_channel_capacity`]). Defaults to
+    /// [`DEFAULT_DELIVERY_CHANNEL_BLOCK`]
+    fn delivery_channel_block(&self) -> Duration {
+        DEFAULT_DELIVERY_CHANNEL_BLOCK
+    }
+
+    /// Returns true if subscribing again to a topic filter the client is
+    /// already subscribed to must not deliver its retained messages again,
+    /// so that they are only delivered on the first subscription.
+    /// Defaults to false
+    fn suppress_resubscribe_retained(&self) -> bool {
+        false
+    }
+
+    /// Returns true if the non-retained publishes with an empty payload
+    /// must be dropped instead of delivered. When false, they are delivered
+    /// to the subscribers as-is, with an empty payload. Empty retained
+    /// publishes always clear the retained message of the topic, and are
+    /// not affected by this option. Defaults to false
+    fn drop_empty_publishes(&self) -> bool {
+        false
+    }
+
+    /// Returns how many client connections can be processed at the same
+    /// time, if specified. The connections over this limit wait until
+    /// one of the others finishes, which smooths the load when many
+    /// clients reconnect at once. Defaults to None (no limit)
+    fn max_concurrent_connects(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the low and high water marks of QoS 1 publishes waiting
+    /// to be acknowledged by each client, if specified. Once a client
+    /// reaches the high one, new publishes to it are held back until it
+    /// drains below the low one. At most 1000 are held back: after that,
+    /// new QoS 0 publishes are dropped, and the client is disconnected
+    /// if a QoS 1 one can not be held back. Defaults to None (no flow
+    /// control)
+    fn inflight_window(&self) -> Option<(usize, usize)> {
+        None
+    }
+
+    /// Returns true if clients that do not send both a user name and a
+    /// password must be rejected, even if there is no authenticator (in
+    /// which case the credentials are not validated). Defaults to false
+    fn require_credentials(&self) -> bool {
+        false
+    }
+
+    /// Returns the normalization applied to every topic name and topic
+    /// filter, if any. Defaults to None, which keeps them byte-exact
+    fn topic_normalizer(&self) -> Option<Arc<dyn TopicNormalizer>> {
+        None
+    }
+
+    /// Returns how long a client with an id assigned by the server (one
+    /// that connected without an id) can stay connected, if specified.
+    /// Once it is exceeded, the client is disconnected, as they are
+    /// expected to be short-lived. Defaults to None (no limit)
+    fn generic_client_max_lifetime(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Returns the maximum size (in bytes) of the payload of the
+    /// publishes received from clients, if specified. A client that
+    /// sends a bigger one is disconnected, as a protocol violation.
+    /// Defaults to None (no limit)
+    fn max_incoming_message_size(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the maximum size (in bytes) of the payload of the
+    /// publishes delivered to subscribers, if specified. Bigger
+    /// messages are dropped instead of delivered, and counted by the
+    /// server. Defaults to None (no limit)
+    fn max_outgoing_message_size(&self) -> Option<usize> {
+        None
+    }
 }
@@ -1,20 +1,31 @@
+use backoff::Backoff;
 use packets::{
     connack::Connack,
     connect::ConnectBuilder,
+    qos::QoSLevel,
     traits::{MQTTDecoding, MQTTEncoding},
 };
 use rand::Rng;
 use server::{
-    traits::{Login, LoginResult},
+    traits::{
+        DumpConfig, Login, LoginResult, LowercaseTopics, RetainedOverflow, TopicNormalizer,
+        DEFAULT_MAX_CLIENT_ID_LENGTH,
+    },
     Config, Server, ServerController,
 };
 use std::{
     collections::HashMap,
     io::{Read, Write},
-    net::TcpStream,
+    net::{TcpListener, TcpStream},
+    sync::Arc,
+    thread,
     time::Duration,
 };
 
+mod scripted_client;
+#[allow(unused_imports)] // No todos los archivos de tests lo usan
+pub use scripted_client::ScriptedClient;
+
 #[macro_export]
 // Crea un HashMap<String, String> (dentro de un Some()),
 // a partir de una serie de tuplas (key: &str, value: &str)
@@ -40,6 +51,7 @@ macro_rules! tpc {
 #[derive(Debug, Clone)]
 struct AuthMock {
     users: HashMap<String, String>,
+    unavailable: bool,
 }
 
 impl Login for AuthMock {
@@ -48,7 +60,9 @@ impl Login for AuthMock {
         user_name: &str,
         password: &str,
     ) -> std::io::Result<server::traits::LoginResult> {
-        if self.users.contains_key(user_name) {
+        if self.unavailable {
+            Ok(LoginResult::TemporarilyUnavailable)
+        } else if self.users.contains_key(user_name) {
             if self.users[user_name] == password {
                 Ok(LoginResult::Accepted)
             } else {
@@ -61,12 +75,35 @@ impl Login for AuthMock {
 }
 
 #[derive(Clone)]
-struct ConfigMock {
+pub struct ConfigMock {
     port: u16,
-    dump_info: Option<(String, Duration)>,
+    pub dump_config: Option<DumpConfig>,
     log_path: String,
     auth: Option<Box<AuthMock>>,
     ip: String,
+    pub topic_qos_ceiling: Vec<(String, QoSLevel)>,
+    pub max_subscriptions_per_client: Option<usize>,
+    pub delivery_write_timeout: Option<Duration>,
+    pub max_client_id_length: usize,
+    pub presence_topic_prefix: Option<String>,
+    pub reconnect_grace: Option<Duration>,
+    pub max_global_publishes_per_second: Option<u32>,
+    pub retained_history_depth: Option<usize>,
+    pub early_puback: bool,
+    pub max_retained_messages: Option<usize>,
+    pub retained_overflow: RetainedOverflow,
+    pub force_clean_session: bool,
+    pub pending_ack_timeout: Option<Duration>,
+    pub retained_refresh: bool,
+    pub auth_unavailable: bool,
+    pub drop_empty_publishes: bool,
+    pub max_concurrent_connects: Option<usize>,
+    pub inflight_window: Option<(usize, usize)>,
+    pub require_credentials: bool,
+    pub lowercase_topics: bool,
+    pub generic_client_max_lifetime: Option<Duration>,
+    pub max_incoming_message_size: Option<usize>,
+    pub max_outgoing_message_size: Option<usize>,
 }
 
 impl Config for ConfigMock {
@@ -74,10 +111,8 @@ impl Config for ConfigMock {
         self.port
     }
 
-    fn dump_info(&self) -> Option<(&str, Duration)> {
-        self.dump_info
-            .as_ref()
-            .map(|(path, duration)| (path.as_str(), *duration))
+    fn dump_config(&self) -> Option<DumpConfig> {
+        self.dump_config.clone()
     }
 
     fn log_path(&self) -> &str {
@@ -89,9 +124,102 @@ impl Config for ConfigMock {
     }
 
     fn authenticator(&self) -> Option<Box<dyn Login>> {
-        let authenticator = self.auth.clone()?;
+        let mut authenticator = self.auth.clone()?;
+        authenticator.unavailable = self.auth_unavailable;
         Some(authenticator)
     }
+
+    fn topic_qos_ceiling(&self) -> &[(String, QoSLevel)] {
+        &self.topic_qos_ceiling
+    }
+
+    fn max_subscriptions_per_client(&self) -> Option<usize> {
+        self.max_subscriptions_per_client
+    }
+
+    fn delivery_write_timeout(&self) -> Option<Duration> {
+        self.delivery_write_timeout
+    }
+
+    fn max_client_id_length(&self) -> usize {
+        self.max_client_id_length
+    }
+
+    fn presence_topic_prefix(&self) -> Option<&str> {
+        self.presence_topic_prefix.as_deref()
+    }
+
+    fn reconnect_grace(&self) -> Option<Duration> {
+        self.reconnect_grace
+    }
+
+    fn max_global_publishes_per_second(&self) -> Option<u32> {
+        self.max_global_publishes_per_second
+    }
+
+    fn retained_history_depth(&self) -> Option<usize> {
+        self.retained_history_depth
+    }
+
+    fn early_puback(&self) -> bool {
+        self.early_puback
+    }
+
+    fn max_retained_messages(&self) -> Option<usize> {
+        self.max_retained_messages
+    }
+
+    fn retained_overflow(&self) -> RetainedOverflow {
+        self.retained_overflow
+    }
+
+    fn force_clean_session(&self) -> bool {
+        self.force_clean_session
+    }
+
+    fn pending_ack_timeout(&self) -> Option<Duration> {
+        self.pending_ack_timeout
+    }
+
+    fn retained_refresh(&self) -> bool {
+        self.retained_refresh
+    }
+
+    fn drop_empty_publishes(&self) -> bool {
+        self.drop_empty_publishes
+    }
+
+    fn max_concurrent_connects(&self) -> Option<usize> {
+        self.max_concurrent_connects
+    }
+
+    fn inflight_window(&self) -> Option<(usize, usize)> {
+        self.inflight_window
+    }
+
+    fn require_credentials(&self) -> bool {
+        self.require_credentials
+    }
+
+    fn topic_normalizer(&self) -> Option<Arc<dyn TopicNormalizer>> {
+        if self.lowercase_topics {
+            Some(Arc::new(LowercaseTopics))
+        } else {
+            None
+        }
+    }
+
+    fn generic_client_max_lifetime(&self) -> Option<Duration> {
+        self.generic_client_max_lifetime
+    }
+
+    fn max_incoming_message_size(&self) -> Option<usize> {
+        self.max_incoming_message_size
+    }
+
+    fn max_outgoing_message_size(&self) -> Option<usize> {
+        self.max_outgoing_message_size
+    }
 }
 
 impl ConfigMock {
@@ -102,10 +230,38 @@ impl ConfigMock {
     ) -> ConfigMock {
         ConfigMock {
             port,
-            dump_info: dump_info.map(|(str, dur)| (str.to_string(), dur)),
+            dump_config: dump_info.map(|(path, interval)| DumpConfig::new(path, interval)),
             log_path: "tests/files/logs".to_string(),
-            auth: users.map(|u| Box::new(AuthMock { users: u })),
+            auth: users.map(|u| {
+                Box::new(AuthMock {
+                    users: u,
+                    unavailable: false,
+                })
+            }),
             ip: "localhost".to_string(),
+            topic_qos_ceiling: Vec::new(),
+            max_subscriptions_per_client: None,
+            delivery_write_timeout: None,
+            max_client_id_length: DEFAULT_MAX_CLIENT_ID_LENGTH,
+            presence_topic_prefix: None,
+            reconnect_grace: None,
+            max_global_publishes_per_second: None,
+            retained_history_depth: None,
+            early_puback: false,
+            max_retained_messages: None,
+            retained_overflow: RetainedOverflow::EvictOldest,
+            force_clean_session: false,
+            pending_ack_timeout: None,
+            retained_refresh: false,
+            auth_unavailable: false,
+            drop_empty_publishes: false,
+            max_concurrent_connects: None,
+            inflight_window: None,
+            require_credentials: false,
+            lowercase_topics: false,
+            generic_client_max_lifetime: None,
+            max_incoming_message_size: None,
+            max_outgoing_message_size: None,
         }
     }
 }
@@ -114,20 +270,34 @@ pub fn start_server(
     dump_info: Option<(&str, Duration)>,
     users: Option<HashMap<String, String>>,
 ) -> (ServerController, u16) {
-    let mut port = random_port();
-    let mut server = Server::new(ConfigMock::new(port, dump_info, users.clone()), 20).unwrap();
+    start_server_with_config(ConfigMock::new(0, dump_info, users))
+}
+
+pub fn start_server_with_config(mut config: ConfigMock) -> (ServerController, u16) {
+    let mut backoff =
+        Backoff::new(Duration::from_millis(10), 2, Duration::from_millis(200)).with_jitter(0.5);
     for _ in 0..50 {
         // Intento crear el servidor bindeando a 50 puertos al azar
+        config.port = random_port();
+        let server = Server::new(config.clone(), 20).unwrap();
         if let Ok(controller) = server.run() {
-            return (controller, port);
-        } else {
-            port = random_port();
-            server = Server::new(ConfigMock::new(port, dump_info, users.clone()), 20).unwrap();
+            return (controller, config.port);
         }
+        thread::sleep(backoff.next_delay());
     }
     panic!("No se pudo crear servidor para ejecutar el test");
 }
 
+// Obtiene un puerto libre
+#[allow(dead_code)] // No todos los archivos de tests lo usan
+pub fn free_port() -> u16 {
+    TcpListener::bind("localhost:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port()
+}
+
 fn random_port() -> u16 {
     // Esos números salen de esta información
     // https://en.wikipedia.org/wiki/List_of_TCP_and_UDP_port_numbers#Dynamic,_private_or_ephemeral_ports
@@ -0,0 +1,120 @@
+// No todos los archivos de tests usan todos los metodos
+#![allow(dead_code)]
+
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+use packets::{
+    connect::ConnectBuilder,
+    helpers::PacketType,
+    puback::Puback,
+    publish::Publish,
+    suback::Suback,
+    subscribe::Subscribe,
+    topic_filter::TopicFilter,
+    traits::{MQTTDecoding, MQTTEncoding},
+};
+
+use super::connect_client;
+
+/// Client that talks to the server through a real connection, with
+/// methods that send and expect whole packets. Each `expect_*` method
+/// blocks until the next packet arrives (up to the read timeout of the
+/// connection), so tests do not need to sleep to wait for the server
+pub struct ScriptedClient {
+    stream: TcpStream,
+}
+
+impl ScriptedClient {
+    /// Connects to the server in `port` and waits for the CONNACK
+    pub fn connect(builder: ConnectBuilder, port: u16) -> Self {
+        Self {
+            stream: connect_client(builder, port, true),
+        }
+    }
+
+    /// Returns the underlying connection, to send or read raw bytes
+    pub fn stream(&mut self) -> &mut TcpStream {
+        &mut self.stream
+    }
+
+    /// Subscribes to the given topic filters and waits for the SUBACK
+    pub fn subscribe(&mut self, topic_filters: Vec<TopicFilter>, packet_id: u16) -> Suback {
+        let subscribe = Subscribe::new(topic_filters, packet_id);
+        self.send(&subscribe.encode().unwrap());
+        self.expect_suback(packet_id)
+    }
+
+    /// Sends the publish, without waiting for its PUBACK
+    pub fn publish(&mut self, publish: &Publish) {
+        self.send(&publish.encode().unwrap());
+    }
+
+    /// Acknowledges the publish with the given packet id
+    pub fn puback(&mut self, packet_id: u16) {
+        self.send(&Puback::new(packet_id).unwrap().encode().unwrap());
+    }
+
+    /// Waits for the next packet and asserts that it is a PUBLISH
+    pub fn expect_publish(&mut self) -> Publish {
+        let control = self.expect_packet(PacketType::Publish);
+        Publish::read_from(&mut self.stream, control).unwrap()
+    }
+
+    /// Waits for the next packet and asserts that it is a
+    /// SUBACK with the given packet id
+    pub fn expect_suback(&mut self, packet_id: u16) -> Suback {
+        let control = self.expect_packet(PacketType::Suback);
+        let suback = Suback::read_from(&mut self.stream, control).unwrap();
+        assert_eq!(suback.packet_id(), packet_id);
+        suback
+    }
+
+    /// Waits for the next packet and asserts that it is a
+    /// PUBACK with the given packet id
+    pub fn expect_puback(&mut self, packet_id: u16) -> Puback {
+        let control = self.expect_packet(PacketType::Puback);
+        let puback = Puback::read_from(&mut self.stream, control).unwrap();
+        assert_eq!(puback.packet_id(), packet_id);
+        puback
+    }
+
+    /// Waits for a PUBLISH and for the PUBACK with the given packet id, in
+    /// any order, since the server can send them in either order when the
+    /// client is subscribed to the topic it published to
+    pub fn expect_puback_and_publish(&mut self, packet_id: u16) -> Publish {
+        let mut control = [0u8];
+        self.stream.read_exact(&mut control).unwrap();
+        match PacketType::from_code(control[0] >> 4).unwrap() {
+            PacketType::Puback => {
+                let puback = Puback::read_from(&mut self.stream, control[0]).unwrap();
+                assert_eq!(puback.packet_id(), packet_id);
+                self.expect_publish()
+            }
+            PacketType::Publish => {
+                let publish = Publish::read_from(&mut self.stream, control[0]).unwrap();
+                self.expect_puback(packet_id);
+                publish
+            }
+            other => panic!("Se esperaba PUBACK o PUBLISH, llego {:?}", other),
+        }
+    }
+
+    fn send(&mut self, bytes: &[u8]) {
+        self.stream.write_all(bytes).unwrap();
+    }
+
+    // Lee el primer byte del siguiente paquete y verifica su tipo
+    fn expect_packet(&mut self, packet_type: PacketType) -> u8 {
+        let mut control = [0u8];
+        self.stream.read_exact(&mut control).unwrap();
+        assert_eq!(
+            PacketType::from_code(control[0] >> 4).unwrap(),
+            packet_type,
+            "Se esperaba un paquete de otro tipo"
+        );
+        control[0]
+    }
+}
@@ -1,16 +1,24 @@
 mod common;
 use crate::common::*;
+use logger::Logger;
 use packets::connack::*;
 use packets::connect::*;
 use packets::disconnect::Disconnect;
 use packets::packet_error::ErrorKind;
 use packets::pingreq::PingReq;
 use packets::pingresp::PingResp;
+use packets::publish::Publish;
 use packets::traits::{MQTTDecoding, MQTTEncoding};
+use server::traits::{DumpConfig, DumpFormat};
+use server::{shutdown, Server};
 use std::fs;
 use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::path::Path;
+use std::sync::{mpsc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
+use tracing::Level;
 
 #[test]
 fn test_connect_clean_session_true() {
@@ -47,6 +55,45 @@ fn test_connect_incorrect_password() {
     );
 }
 
+#[test]
+fn test_connect_login_temporarily_unavailable() {
+    let mut config = ConfigMock::new(0, None, usr![("user", "password")]);
+    config.auth_unavailable = true;
+    let (_s, port) = start_server_with_config(config);
+    let connect_builder = ConnectBuilder::new("id", 0, true)
+        .unwrap()
+        .with_user_name("user")
+        .unwrap()
+        .with_password("password")
+        .unwrap();
+    let mut stream = connect_client(connect_builder, port, false);
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 2);
+    let mut rest = [0u8; 3];
+    stream.read_exact(&mut rest).unwrap();
+    // Return code 3: Server unavailable
+    assert_eq!(rest[2], 3);
+}
+
+#[test]
+fn test_connect_require_credentials_without_user_name() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.require_credentials = true;
+    let (_s, port) = start_server_with_config(config);
+    let connect_builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(connect_builder, port, false);
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 2);
+    let mut rest = [0u8; 3];
+    stream.read_exact(&mut rest).unwrap();
+    // Return code 5: Not authorized
+    assert_eq!(rest[2], 5);
+}
+
 #[test]
 fn test_connect_correct_password() {
     let (_s, port) = start_server(None, usr![("user", "password")]);
@@ -97,6 +144,36 @@ fn test_connect_present_after_reconnection() {
     assert!(connack.session_present());
 }
 
+#[test]
+fn test_connect_clean_session_over_persistent_session_is_not_present() {
+    let (_s, port) = start_server(None, None);
+    // Me conecto con clean_session = false
+    let mut connect_builder = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream = connect_client(connect_builder, port, false);
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 2);
+    let mut connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert!(!connack.session_present());
+
+    // Me desconecto
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+
+    // Me reconecto con clean_session = true
+    connect_builder = ConnectBuilder::new("id", 0, true).unwrap();
+    stream = connect_client(connect_builder, port, false);
+
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 2);
+    connack = Connack::read_from(&mut stream, control[0]).unwrap();
+
+    // La sesion anterior se descarta: session present debería ser false
+    assert!(!connack.session_present());
+}
+
 #[test]
 fn test_pings() {
     let (_s, port) = start_server(None, None);
@@ -188,6 +265,31 @@ fn test_takeover_only_works_with_same_username() {
     assert_eq!(err.kind(), ErrorKind::IdentifierRejected);
 }
 
+#[test]
+fn test_max_concurrent_connects() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.max_concurrent_connects = Some(2);
+    let (_s, port) = start_server_with_config(config);
+
+    // Muchos clientes persistentes se conectan a la vez
+    let handles: Vec<_> = (0..30)
+        .map(|i| {
+            thread::spawn(move || {
+                let connection = ConnectBuilder::new(&format!("id{}", i), 0, false).unwrap();
+                let mut stream = connect_client(connection, port, false);
+                let mut control = [0u8];
+                stream.read_exact(&mut control).unwrap();
+                assert_eq!(control[0] >> 4, 2);
+                Connack::read_from(&mut stream, control[0]).unwrap();
+                stream
+            })
+        })
+        .collect();
+    // Todos reciben el CONNACK, aunque se procesen de a 2
+    let streams: Vec<_> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+    assert_eq!(streams.len(), 30);
+}
+
 #[test]
 fn test_session_present_dump() {
     let _ = fs::remove_file("tests/files/dumps/dump1.json");
@@ -219,3 +321,290 @@ fn test_session_present_dump() {
     let connack = Connack::read_from(&mut stream, control[0]).unwrap();
     assert!(connack.session_present());
 }
+
+#[test]
+fn test_snapshot_on_demand() {
+    let path = "tests/files/dumps/dump6.json";
+    let snapshot_path = "tests/files/dumps/snapshot6.json";
+    let _ = fs::remove_file(path);
+    let _ = fs::remove_file(snapshot_path);
+    let (controller, port) = start_server(Some((path, Duration::from_secs(60))), None);
+
+    // Me conecto con clean session en false
+    let connection = ConnectBuilder::new("id", 0, false).unwrap();
+    let _stream = connect_client(connection, port, true);
+    thread::sleep(Duration::from_millis(100));
+
+    // Se dumpea sin esperar al intervalo ni apagar el servidor
+    controller.snapshot().unwrap();
+    assert!(Path::new(path).exists());
+    controller.snapshot_to(snapshot_path).unwrap();
+    assert!(!Path::new("tests/files/dumps/snapshot6.json.tmp").exists());
+    drop(controller);
+
+    // El servidor restaurado desde la snapshot tiene la sesión del cliente
+    let (_s, port) = start_server(Some((snapshot_path, Duration::from_secs(60))), None);
+    let connection = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream = connect_client(connection, port, false);
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert!(connack.session_present());
+}
+
+#[test]
+fn test_snapshot_without_dump_config_fails() {
+    let (controller, _port) = start_server(None, None);
+    assert!(controller.snapshot().is_err());
+}
+
+#[cfg(unix)]
+#[test]
+fn test_run_until_signal_stops_on_sigterm() {
+    use server::run_until_signal;
+    use signal_hook::{consts::SIGTERM, low_level::raise};
+
+    let port = free_port();
+    let handle = thread::spawn(move || run_until_signal(ConfigMock::new(port, None, None)));
+
+    // Espero a que el servidor acepte conexiones
+    let connection = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = None;
+    for _ in 0..50 {
+        if TcpStream::connect(format!("localhost:{}", port)).is_ok() {
+            stream = Some(connect_client(connection, port, true));
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert!(stream.is_some());
+
+    raise(SIGTERM).unwrap();
+    assert!(handle.join().unwrap().is_ok());
+    // El servidor se detuvo, no acepta nuevas conexiones
+    assert!(TcpStream::connect(format!("localhost:{}", port)).is_err());
+}
+
+#[test]
+fn test_dump_to_writer_and_restore_from_reader() {
+    let port = free_port();
+    let server = Server::new(ConfigMock::new(port, None, None), 20).unwrap();
+    let controller = server.clone().run().unwrap();
+
+    // Me conecto con clean session en false
+    let connection = ConnectBuilder::new("id", 0, false).unwrap();
+    let _stream = connect_client(connection, port, true);
+    thread::sleep(Duration::from_millis(100));
+
+    let mut dump = Vec::new();
+    server.dump_to_writer(&mut dump).unwrap();
+    drop(controller);
+
+    let port = free_port();
+    let config = ConfigMock::new(port, None, None);
+    let restored = Server::try_restore_from_reader(&dump[..], &config, 20).unwrap();
+    let _controller = restored.run().unwrap();
+
+    // La sesión del cliente sigue presente en el servidor restaurado
+    let connection = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream = connect_client(connection, port, false);
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let connack = Connack::read_from(&mut stream, control[0]).unwrap();
+    assert!(connack.session_present());
+}
+
+#[test]
+fn test_run_fails_if_port_is_in_use() {
+    let port = free_port();
+    let server = Server::new(ConfigMock::new(port, None, None), 2).unwrap();
+    let _controller = server.run().unwrap();
+
+    let server = Server::new(ConfigMock::new(port, None, None), 2).unwrap();
+    assert!(server.run().is_err());
+}
+
+#[test]
+fn test_restore_dump_with_text_payloads() {
+    // Dump escrito cuando los payloads se guardaban como strings
+    let dump = fs::File::open("tests/files/legacy_dump.json").unwrap();
+    let config = ConfigMock::new(free_port(), None, None);
+    let server = Server::try_restore_from_reader(dump, &config, 2).unwrap();
+
+    let (sender, receiver) = mpsc::channel();
+    let sender = Mutex::new(sender);
+    server
+        .subscribe_local(
+            "sensors/#",
+            Box::new(move |publish| {
+                let _ = sender.lock().unwrap().send(publish.payload().to_vec());
+            }),
+        )
+        .unwrap();
+    assert_eq!(receiver.try_recv().unwrap(), b"25");
+}
+
+#[test]
+fn test_restore_dump_with_unacknowledged_packets_without_resends() {
+    // Dump escrito cuando no se contaban los reenvios de los paquetes sin confirmar
+    let dump = fs::File::open("tests/files/legacy_unacknowledged_dump.json").unwrap();
+    let port = free_port();
+    let config = ConfigMock::new(port, None, None);
+    let server = Server::try_restore_from_reader(dump, &config, 2).unwrap();
+    let _controller = server.run().unwrap();
+
+    // Al reconectarse, se le reenvia el publish que no habia confirmado
+    let builder = ConnectBuilder::new("sensor_reader", 0, false).unwrap();
+    let mut stream = connect_client(builder, port, true);
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let publish = Publish::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(publish.packet_id(), Some(7));
+    assert_eq!(publish.payload(), b"25");
+}
+
+#[test]
+fn test_client_leaving_before_connack_does_not_leave_session() {
+    let port = free_port();
+    let server = Server::new(ConfigMock::new(port, None, None), 20).unwrap();
+    let _controller = server.clone().run().unwrap();
+
+    // Me conecto con clean session en true, y cierro la conexion
+    // sin esperar el Connack
+    let connection = ConnectBuilder::new("id", 0, true).unwrap();
+    let stream = connect_client(connection, port, false);
+    stream.shutdown(Shutdown::Both).unwrap();
+    drop(stream);
+    thread::sleep(Duration::from_millis(500));
+
+    // No queda ninguna sesion del cliente en el servidor
+    assert!(server.client_traffic().unwrap().is_empty());
+    let mut dump = Vec::new();
+    server.dump_to_writer(&mut dump).unwrap();
+    assert!(!String::from_utf8(dump).unwrap().contains("\"id\""));
+}
+
+#[test]
+fn test_connect_with_client_id_over_max_length_is_rejected() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.max_client_id_length = 5;
+    let (_s, port) = start_server_with_config(config);
+
+    let connect_builder = ConnectBuilder::new("id_muy_larga", 0, true).unwrap();
+    let mut stream = connect_client(connect_builder, port, false);
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 2);
+    let err = Connack::read_from(&mut stream, control[0]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::IdentifierRejected);
+}
+
+#[test]
+fn test_connect_with_reserved_client_id_prefix_is_rejected() {
+    let (_s, port) = start_server(None, None);
+
+    let connect_builder = ConnectBuilder::new("__CLIENT__1", 0, true).unwrap();
+    let mut stream = connect_client(connect_builder, port, false);
+
+    let mut control = [0u8];
+    stream.read_exact(&mut control).unwrap();
+    let err = Connack::read_from(&mut stream, control[0]).unwrap_err();
+    assert_eq!(err.kind(), ErrorKind::IdentifierRejected);
+}
+
+#[test]
+fn test_connect_with_invalid_utf8_client_id_closes_without_connack() {
+    let (_s, port) = start_server(None, None);
+    let mut stream = TcpStream::connect(format!("localhost:{}", port)).unwrap();
+    stream
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+
+    // CONNECT armado a mano, con un byte invalido en el client id
+    let mut connect = vec![16u8, 15];
+    connect.extend([0, 4, b'M', b'Q', b'T', b'T', 4, 2, 0, 0]);
+    connect.extend([0, 3, b'i', 0xFF, b'd']);
+    stream.write_all(&connect).unwrap();
+
+    // El servidor cierra la conexion sin mandar un CONNACK
+    let mut buf = [0u8; 1];
+    match stream.read(&mut buf) {
+        Ok(0) => (),
+        Ok(_) => panic!("Se recibio un paquete en lugar del cierre de la conexion"),
+        Err(err) => assert_ne!(err.kind(), std::io::ErrorKind::WouldBlock),
+    }
+}
+
+#[test]
+fn test_dump_config_dumps_periodically() {
+    let path = "tests/files/dumps/dump5.json";
+    let _ = fs::remove_file(path);
+    let mut config = ConfigMock::new(0, None, None);
+    let mut dump_config = DumpConfig::new(path, Duration::from_millis(300));
+    dump_config.format = DumpFormat::Compact;
+    dump_config.atomic = true;
+    config.dump_config = Some(dump_config);
+    let (_s, _port) = start_server_with_config(config);
+
+    // Se dumpea sin necesidad de apagar el servidor
+    thread::sleep(Duration::from_millis(1000));
+    let dump = fs::read_to_string(path).unwrap();
+    assert!(!dump.contains('\n'));
+    assert!(!Path::new("tests/files/dumps/dump5.json.tmp").exists());
+
+    // Y se vuelve a dumpear en el siguiente intervalo
+    fs::remove_file(path).unwrap();
+    thread::sleep(Duration::from_millis(1000));
+    assert!(Path::new(path).exists());
+}
+
+#[test]
+fn test_generic_client_max_lifetime() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.generic_client_max_lifetime = Some(Duration::from_secs(1));
+    let (_s, port) = start_server_with_config(config);
+    let start = Instant::now();
+    let builder = ConnectBuilder::new("", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+
+    // El servidor cierra la conexion al cumplirse el tiempo de vida
+    let mut buf = [0u8];
+    assert!(!matches!(stream.read(&mut buf), Ok(n) if n > 0));
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_secs(1));
+    assert!(elapsed < Duration::from_secs(5));
+}
+
+#[test]
+fn test_shutdown_dumps_and_flushes_logs() {
+    let log_path = std::env::temp_dir().join(format!("shutdown_test_{}", std::process::id()));
+    let logger = Logger::new(log_path.to_str().unwrap(), Level::INFO, Level::ERROR).unwrap();
+    let path = "tests/files/dumps/dump7.json";
+    let _ = fs::remove_file(path);
+    let port = free_port();
+    let mut config = ConfigMock::new(port, None, None);
+    // El intervalo es largo para que solo se dumpee al apagarlo
+    config.dump_config = Some(DumpConfig::new(path, Duration::from_secs(600)));
+    let server = Server::new(config, 20).unwrap();
+    let controller = server.clone().run().unwrap();
+    let connection = ConnectBuilder::new("id", 0, false).unwrap();
+    let _stream = connect_client(connection, port, true);
+
+    shutdown(&server, controller, Some(&logger)).unwrap();
+
+    // Antes de dropear el servidor, el dump y los logs ya estan escritos
+    let dump = fs::read_to_string(path).unwrap();
+    assert!(dump.contains("\"id\""));
+    let mut logs = String::new();
+    for entry in fs::read_dir(&log_path).unwrap() {
+        logs += &fs::read_to_string(entry.unwrap().path()).unwrap();
+    }
+    assert!(logs.contains("Apagando servidor"));
+
+    drop(server);
+    drop(logger);
+    fs::remove_file(path).unwrap();
+    fs::remove_dir_all(log_path).unwrap();
+}
@@ -0,0 +1,110 @@
+mod common;
+use std::{
+    io::{self, Read, Write},
+    sync::{Arc, Mutex},
+    thread,
+    time::Duration,
+};
+
+use packets::{
+    connect::ConnectBuilder,
+    disconnect::Disconnect,
+    publish::Publish,
+    qos::QoSLevel::*,
+    subscribe::Subscribe,
+    traits::{MQTTDecoding, MQTTEncoding},
+};
+use tracing::Level;
+use tracing_subscriber::fmt::MakeWriter;
+
+use crate::common::*;
+
+// Writer que guarda en memoria todo lo que se loguea
+#[derive(Clone, Default)]
+struct CapturedLogs {
+    buffer: Arc<Mutex<Vec<u8>>>,
+}
+
+impl CapturedLogs {
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8_lossy(&self.buffer.lock().unwrap())
+            .lines()
+            .map(str::to_string)
+            .collect()
+    }
+}
+
+impl Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for CapturedLogs {
+    type Writer = CapturedLogs;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+#[test]
+fn test_session_events_are_tagged_with_client_id() {
+    let logs = CapturedLogs::default();
+    let subscriber = tracing_subscriber::fmt()
+        .with_ansi(false)
+        .with_max_level(Level::DEBUG)
+        .with_writer(logs.clone())
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).unwrap();
+
+    let (_s, port) = start_server(None, None);
+    let mut subscriber = connect_client(ConnectBuilder::new("sub", 0, true).unwrap(), port, true);
+    let mut publisher = connect_client(ConnectBuilder::new("pub", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 1);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let mut suback = [0u8; 4];
+    subscriber.read_exact(&mut suback).unwrap();
+
+    let publish = Publish::new(false, QoSLevel0, false, "topic", "message", None).unwrap();
+    publisher.write_all(&publish.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    Publish::read_from(&mut subscriber, control[0]).unwrap();
+
+    for stream in [&mut subscriber, &mut publisher] {
+        stream
+            .write_all(&Disconnect::new().encode().unwrap())
+            .unwrap();
+    }
+    thread::sleep(Duration::from_millis(300));
+
+    let lines = logs.lines();
+    let processing: Vec<_> = lines
+        .iter()
+        .filter(|line| line.contains("Procesando"))
+        .collect();
+    // SUBSCRIBE y DISCONNECT de sub, PUBLISH y DISCONNECT de pub
+    assert!(processing.len() >= 4, "{:#?}", lines);
+    for line in processing {
+        assert!(
+            line.contains("client_id=sub") || line.contains("client_id=pub"),
+            "Evento sin client_id: {}",
+            line
+        );
+    }
+    let delivery = lines
+        .iter()
+        .find(|line| line.contains("Enviando PUBLISH"))
+        .expect("No se logueo el envio del PUBLISH");
+    assert!(delivery.contains("client_id=sub"), "{}", delivery);
+}
@@ -1,24 +1,30 @@
 mod common;
 use std::{
+    collections::HashSet,
     fs,
     io::{Read, Write},
+    sync::{mpsc, Mutex},
     thread,
     time::Duration,
 };
 
 use packets::{
+    connack::Connack,
     connect::{ConnectBuilder, LastWill},
     disconnect::Disconnect,
     puback::Puback,
     publish::Publish,
     qos::QoSLevel::*,
-    suback::Suback,
+    suback::{self, Suback},
     subscribe::Subscribe,
     topic_filter::TopicFilter,
     traits::{MQTTDecoding, MQTTEncoding},
+    unsuback::Unsuback,
+    unsubscribe::Unsubscribe,
 };
 
 use crate::common::*;
+use server::{traits::RetainedOverflow, Server, ServerEvent};
 
 #[test]
 fn test_subscription_qos0() {
@@ -54,33 +60,15 @@ fn test_subscription_qos0() {
 fn test_subscription_qos1() {
     let (_s, port) = start_server(None, None);
     let builder = ConnectBuilder::new("id", 0, true).unwrap();
-    let mut stream = connect_client(builder, port, true);
-    let mut control = [0u8];
-
-    // Mando subscribe
-    let subscribe = Subscribe::new(tpc![("topic", QoSLevel1)], 123);
-    stream.write_all(&subscribe.encode().unwrap()).unwrap();
-    thread::sleep(Duration::from_millis(100));
+    let mut client = ScriptedClient::connect(builder, port);
 
-    // Recibo suback
-    stream.read_exact(&mut control).unwrap();
-    assert_eq!(control[0] >> 4, 9);
-    let suback = Suback::read_from(&mut stream, control[0]).unwrap();
-    assert_eq!(suback.packet_id(), 123);
+    // Me suscribo y recibo el suback
+    client.subscribe(tpc![("topic", QoSLevel1)], 123);
 
-    // Mando publish
+    // Mando publish y recibo puback y publish
     let publish = Publish::new(false, QoSLevel1, false, "topic", "message", Some(10)).unwrap();
-    stream.write_all(&publish.encode().unwrap()).unwrap();
-    thread::sleep(Duration::from_millis(100));
-
-    // Recibo puback
-    stream.read_exact(&mut control).unwrap();
-    let recv_puback = Puback::read_from(&mut stream, control[0]).unwrap();
-    assert_eq!(recv_puback.packet_id(), 10);
-
-    // Recibo publish
-    stream.read_exact(&mut control).unwrap();
-    let recv_publish = Publish::read_from(&mut stream, control[0]).unwrap();
+    client.publish(&publish);
+    let recv_publish = client.expect_puback_and_publish(10);
     assert_eq!(recv_publish.encode().unwrap(), publish.encode().unwrap());
 }
 
@@ -244,7 +232,7 @@ fn test_last_will() {
     let recv_publish = Publish::read_from(&mut stream_2, control[0]).unwrap();
     // ignoro el primer byte por si le ponen la dup flag
     assert_eq!(recv_publish.topic_name(), "topic");
-    assert_eq!(recv_publish.payload(), "message");
+    assert_eq!(recv_publish.payload(), b"message");
 }
 
 #[test]
@@ -361,6 +349,48 @@ fn test_retained_message() {
     assert_eq!(recv_publish.encode().unwrap(), publish.encode().unwrap());
 }
 
+#[test]
+fn test_last_will_fires_once_on_takeover_then_graceful_disconnect() {
+    let (_s, port) = start_server(None, None);
+    let mut watcher =
+        ScriptedClient::connect(ConnectBuilder::new("watcher", 0, false).unwrap(), port);
+    watcher.subscribe(tpc![("will", QoSLevel1)], 1);
+
+    let with_will = || {
+        ConnectBuilder::new("id", 0, false)
+            .unwrap()
+            .with_last_will(LastWill::new(
+                TopicFilter::new("will", QoSLevel1).unwrap(),
+                "message".to_string(),
+                false,
+            ))
+    };
+    let _stream_1 = connect_client(with_will(), port, true);
+
+    // El TakeOver publica el LastWill de la sesion reemplazada
+    let mut stream_2 = connect_client(with_will(), port, true);
+    let will = watcher.expect_publish();
+    assert_eq!(will.payload(), b"message");
+    assert_eq!(will.qos(), QoSLevel1);
+
+    // Como cualquier publish QoS 1, se reenvia hasta que se confirme
+    let resent = watcher.expect_publish();
+    assert!(resent.dup_flag());
+    assert_eq!(resent.packet_id(), will.packet_id());
+    watcher.puback(will.packet_id().unwrap());
+
+    // La nueva sesion se desconecta gracefully: no se publica otro LastWill
+    stream_2
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    watcher
+        .stream()
+        .set_read_timeout(Some(Duration::from_secs(2)))
+        .unwrap();
+    let mut control = [0u8];
+    assert!(watcher.stream().read_exact(&mut control).is_err());
+}
+
 #[test]
 fn test_retained_message_in_last_will() {
     let (_s, port) = start_server(None, None);
@@ -401,7 +431,7 @@ fn test_retained_message_in_last_will() {
     stream_1.read_exact(&mut control).unwrap();
     assert_eq!(control[0] >> 4, 3);
     let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
-    assert_eq!(recv_publish.payload(), "lw");
+    assert_eq!(recv_publish.payload(), b"lw");
     assert_eq!(recv_publish.topic_name(), "topic");
     assert!(!recv_publish.retain_flag()); // no me deberia llegar al principio como retained
 
@@ -420,7 +450,7 @@ fn test_retained_message_in_last_will() {
     stream_3.read_exact(&mut control).unwrap();
     assert_eq!(control[0] >> 4, 3);
     let recv_publish = Publish::read_from(&mut stream_3, control[0]).unwrap();
-    assert_eq!(recv_publish.payload(), "lw");
+    assert_eq!(recv_publish.payload(), b"lw");
     assert_eq!(recv_publish.topic_name(), "topic");
     assert!(recv_publish.retain_flag());
 }
@@ -477,7 +507,7 @@ fn test_subscription_dump() {
     stream.read_exact(&mut control).unwrap();
     let publish = Publish::read_from(&mut stream, control[0]).unwrap();
     assert!(publish.packet_id().is_none());
-    assert_eq!(publish.payload(), "msg");
+    assert_eq!(publish.payload(), b"msg");
 }
 
 #[test]
@@ -543,7 +573,1079 @@ fn test_subscription_dump_qos1() {
     // Debería recibir el publish
     stream_1.read_exact(&mut control).unwrap();
     let publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
-    assert_eq!(publish.payload(), "msg");
+    assert_eq!(publish.payload(), b"msg");
+    assert_eq!(publish.topic_name(), "topic");
+    assert_eq!(publish.qos(), QoSLevel1);
+}
+
+#[test]
+fn test_dump_keeps_inflight_messages() {
+    let _ = fs::remove_file("tests/files/dumps/dump4.json");
+    let (s, port) = start_server(
+        Some(("tests/files/dumps/dump4.json", Duration::from_secs(10))),
+        None,
+    );
+    let builder_1 = ConnectBuilder::new("id1", 0, false).unwrap();
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let mut stream_2 = connect_client(builder_2, port, true);
+
+    // Me suscribo con cliente 1 y QoS 1
+    stream_1
+        .write_all(
+            &Subscribe::new(tpc![("topic", QoSLevel1)], 123)
+                .encode()
+                .unwrap(),
+        )
+        .unwrap();
+
+    let mut control = [0u8];
+    stream_1.read_exact(&mut control).unwrap();
+    Suback::read_from(&mut stream_1, control[0]).unwrap();
+
+    // Envio publish QoS 1 con cliente 2
+    stream_2
+        .write_all(
+            &Publish::new(false, QoSLevel1, false, "topic", "msg", Some(123))
+                .unwrap()
+                .encode()
+                .unwrap(),
+        )
+        .unwrap();
+
+    stream_2.read_exact(&mut control).unwrap();
+    Puback::read_from(&mut stream_2, control[0]).unwrap();
+    stream_2
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+
+    // Cliente 1 recibe el publish pero no manda el puback
+    stream_1.read_exact(&mut control).unwrap();
+    let publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(publish.payload(), b"msg");
+
+    stream_1
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+
+    // Apago server: debería dumpear, incluyendo el publish sin puback
+    drop(s);
+
+    // Vuelvo a cargar el dump y conecto cliente 1
+    let (_s, port) = start_server(
+        Some(("tests/files/dumps/dump4.json", Duration::from_secs(10))),
+        None,
+    );
+    let builder_1 = ConnectBuilder::new("id1", 0, false).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+
+    // Debería recibir nuevamente el publish, con la flag de DUP
+    stream_1.read_exact(&mut control).unwrap();
+    let publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(publish.payload(), b"msg");
     assert_eq!(publish.topic_name(), "topic");
     assert_eq!(publish.qos(), QoSLevel1);
+    assert!(publish.dup_flag());
+}
+
+#[test]
+fn test_topic_qos_ceiling() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.topic_qos_ceiling = vec![("sensors/#".to_string(), QoSLevel0)];
+    let (_s, port) = start_server_with_config(config);
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let mut control = [0u8];
+
+    // Mando subscribe con QoS 1 a un topic con techo de QoS 0
+    let subscribe = Subscribe::new(tpc![("sensors/temp", QoSLevel1)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+
+    // Recibo suback, deberia otorgar QoS 0
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let suback = Suback::read_from(&mut stream_1, control[0]).unwrap();
+    let expected = Suback::new_from_vec(vec![QoSLevel0 as u8], 123).unwrap();
+    assert_eq!(suback.encode().unwrap(), expected.encode().unwrap());
+
+    // Mando publish con QoS 1
+    let publish = Publish::new(false, QoSLevel1, false, "sensors/temp", "25", Some(10)).unwrap();
+    stream_2.write_all(&publish.encode().unwrap()).unwrap();
+
+    // Recibo publish con QoS 0
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(recv_publish.payload(), b"25");
+    assert_eq!(recv_publish.qos(), QoSLevel0);
+}
+
+#[test]
+fn test_subscribe_local_receives_client_publish() {
+    let port = free_port();
+    let server = Server::new(ConfigMock::new(port, None, None), 20).unwrap();
+    let _controller = server.clone().run().unwrap();
+    let (sender, receiver) = std::sync::mpsc::channel();
+    let sender = std::sync::Mutex::new(sender);
+    server
+        .subscribe_local(
+            "sensors/#",
+            Box::new(move |publish| {
+                let _ = sender.lock().unwrap().send(publish.clone());
+            }),
+        )
+        .unwrap();
+
+    // Un cliente TCP publica en un topic que matchea
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+    let publish = Publish::new(false, QoSLevel0, false, "sensors/temp", "25", None).unwrap();
+    stream.write_all(&publish.encode().unwrap()).unwrap();
+
+    let received = receiver.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(received.topic_name(), "sensors/temp");
+    assert_eq!(received.payload(), b"25");
+}
+
+#[test]
+fn test_publish_with_qos_3_closes_connection() {
+    let (_s, port) = start_server(None, None);
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+
+    // PUBLISH armado a mano con los dos bits de QoS en 1
+    let mut publish = vec![0b00110110u8, 7];
+    publish.extend([0, 3, b'a', b'/', b'b', 0, 10]);
+    stream.write_all(&publish).unwrap();
+
+    // El servidor cierra la conexion sin mandar un PUBACK
+    let mut buf = [0u8; 1];
+    match stream.read(&mut buf) {
+        Ok(0) => (),
+        Ok(_) => panic!("Se recibio un paquete en lugar del cierre de la conexion"),
+        Err(err) => assert_ne!(err.kind(), std::io::ErrorKind::WouldBlock),
+    }
+}
+
+#[test]
+fn test_subscribe_with_invalid_flags_closes_connection() {
+    let (_s, port) = start_server(None, None);
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+
+    // SUBSCRIBE con los flags del header en 0000 en lugar de 0010
+    let mut subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123)
+        .encode()
+        .unwrap();
+    subscribe[0] &= 0b11110000;
+    stream.write_all(&subscribe).unwrap();
+
+    // El servidor cierra la conexion sin mandar un SUBACK
+    let mut buf = [0u8; 1];
+    match stream.read(&mut buf) {
+        Ok(0) => (),
+        Ok(_) => panic!("Se recibio un paquete en lugar del cierre de la conexion"),
+        Err(err) => assert_ne!(err.kind(), std::io::ErrorKind::WouldBlock),
+    }
+}
+
+#[test]
+fn test_empty_publish_is_delivered() {
+    let (_s, port) = start_server(None, None);
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let mut control = [0u8];
+
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    Suback::read_from(&mut stream_1, control[0]).unwrap();
+
+    // Mando publish sin payload y sin retain
+    let publish = Publish::new(false, QoSLevel0, false, "topic", "", None).unwrap();
+    stream_2.write_all(&publish.encode().unwrap()).unwrap();
+
+    // Se recibe tal cual, con el payload vacio
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(recv_publish.topic_name(), "topic");
+    assert!(recv_publish.payload().is_empty());
+    assert!(!recv_publish.retain_flag());
+}
+
+#[test]
+fn test_drop_empty_publishes() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.drop_empty_publishes = true;
+    let (_s, port) = start_server_with_config(config);
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let mut control = [0u8];
+
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    Suback::read_from(&mut stream_1, control[0]).unwrap();
+
+    // El publish vacio se descarta, pero igual se confirma
+    let empty = Publish::new(false, QoSLevel1, false, "topic", "", Some(10)).unwrap();
+    stream_2.write_all(&empty.encode().unwrap()).unwrap();
+    stream_2.read_exact(&mut control).unwrap();
+    let puback = Puback::read_from(&mut stream_2, control[0]).unwrap();
+    assert_eq!(puback.packet_id(), 10);
+
+    // Lo primero que recibe el suscriptor es el publish con payload
+    let publish = Publish::new(false, QoSLevel0, false, "topic", "msg", None).unwrap();
+    stream_2.write_all(&publish.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(recv_publish.payload(), b"msg");
+}
+
+#[test]
+fn test_suback_is_sent_before_matching_publishes() {
+    let (_s, port) = start_server(None, None);
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+
+    // El cliente 2 publica continuamente en el topic
+    let publisher = thread::spawn(move || {
+        let publish = Publish::new(false, QoSLevel0, false, "topic", "msg", None).unwrap();
+        for _ in 0..200 {
+            stream_2.write_all(&publish.encode().unwrap()).unwrap();
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+
+    // Me suscribo mientras el cliente 2 publica
+    thread::sleep(Duration::from_millis(50));
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+
+    // Lo primero que recibo tiene que ser el suback
+    let mut control = [0u8];
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let suback = Suback::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(suback.packet_id(), 123);
+
+    publisher.join().unwrap();
+}
+
+#[test]
+fn test_max_subscriptions_per_client() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.max_subscriptions_per_client = Some(2);
+    let (_s, port) = start_server_with_config(config);
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+    let mut control = [0u8];
+
+    // Me suscribo a mas topics que el limite
+    let subscribe = Subscribe::new(
+        tpc![("a", QoSLevel0), ("b", QoSLevel1), ("c", QoSLevel0)],
+        1,
+    );
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+
+    stream.read_exact(&mut control).unwrap();
+    let suback = Suback::read_from(&mut stream, control[0]).unwrap();
+    let expected = Suback::new_from_vec(vec![0, 1, suback::FAILURE], 1).unwrap();
+    assert_eq!(suback.encode().unwrap(), expected.encode().unwrap());
+
+    // Un topic nuevo sigue fallando, pero uno al que ya estoy suscripto no
+    let subscribe = Subscribe::new(tpc![("d", QoSLevel0), ("a", QoSLevel1)], 2);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+
+    stream.read_exact(&mut control).unwrap();
+    let suback = Suback::read_from(&mut stream, control[0]).unwrap();
+    let expected = Suback::new_from_vec(vec![suback::FAILURE, 1], 2).unwrap();
+    assert_eq!(suback.encode().unwrap(), expected.encode().unwrap());
+
+    // Al desuscribirme, libero lugar
+    let unsubscribe = Unsubscribe::new(3, tpc![("b", QoSLevel0)]).unwrap();
+    stream.write_all(&unsubscribe.encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 11);
+    Unsuback::read_from(&mut stream, control[0]).unwrap();
+
+    let subscribe = Subscribe::new(tpc![("d", QoSLevel0)], 4);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+
+    stream.read_exact(&mut control).unwrap();
+    let suback = Suback::read_from(&mut stream, control[0]).unwrap();
+    let expected = Suback::new_from_vec(vec![0], 4).unwrap();
+    assert_eq!(suback.encode().unwrap(), expected.encode().unwrap());
+}
+
+#[test]
+fn test_delivery_write_timeout_disconnects_stuck_subscriber() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.delivery_write_timeout = Some(Duration::from_millis(500));
+    let (_s, port) = start_server_with_config(config);
+
+    let mut subscribers = vec![];
+    for id in ["stuck", "reader"] {
+        let builder = ConnectBuilder::new(id, 0, true).unwrap();
+        let mut stream = connect_client(builder, port, true);
+        let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 1);
+        stream.write_all(&subscribe.encode().unwrap()).unwrap();
+        let mut control = [0u8];
+        stream.read_exact(&mut control).unwrap();
+        Suback::read_from(&mut stream, control[0]).unwrap();
+        subscribers.push(stream);
+    }
+    let mut reader = subscribers.pop().unwrap();
+    let mut stuck = subscribers.pop().unwrap();
+
+    // Se publican suficientes mensajes como para llenar los buffers
+    // del subscriber que no lee
+    const PUBLISHES: usize = 300;
+    let payload = "a".repeat(60_000);
+    let builder = ConnectBuilder::new("publisher", 0, true).unwrap();
+    let mut publisher = connect_client(builder, port, true);
+    let publisher = thread::spawn(move || {
+        let publish =
+            Publish::new(false, QoSLevel0, false, "topic", payload.as_str(), None).unwrap();
+        for _ in 0..PUBLISHES {
+            publisher.write_all(&publish.encode().unwrap()).unwrap();
+        }
+        publisher
+    });
+
+    // El que lee recibe todos los mensajes
+    let mut control = [0u8];
+    for _ in 0..PUBLISHES {
+        reader.read_exact(&mut control).unwrap();
+        assert_eq!(control[0] >> 4, 3);
+        Publish::read_from(&mut reader, control[0]).unwrap();
+    }
+    let _publisher = publisher.join().unwrap();
+
+    // El que no lee deberia haber sido desconectado: al leer lo que
+    // quedo en el buffer, la conexion termina
+    stuck
+        .set_read_timeout(Some(Duration::from_secs(10)))
+        .unwrap();
+    let mut buf = vec![0u8; 65536];
+    loop {
+        match stuck.read(&mut buf) {
+            Ok(0) => break,
+            Ok(_) => continue,
+            Err(err) => {
+                assert!(
+                    err.kind() != std::io::ErrorKind::WouldBlock
+                        && err.kind() != std::io::ErrorKind::TimedOut
+                );
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_server_publish_reaches_subscriber() {
+    let (server, port) = start_server(None, None);
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+    let mut control = [0u8];
+
+    // Mando subscribe
+    let subscribe = Subscribe::new(tpc![("$SYS/clients", QoSLevel0)], 123);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+
+    // Recibo suback
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream, control[0]).unwrap();
+
+    // Publico desde el servidor, desde otro thread
+    let publish = Publish::new(false, QoSLevel0, false, "$SYS/clients", "1", None).unwrap();
+    let publish_copy = publish.clone();
+    let _server = thread::spawn(move || {
+        server.publish(publish_copy).unwrap();
+        server
+    })
+    .join()
+    .unwrap();
+
+    // Recibo publish
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream, control[0]).unwrap();
+    assert_eq!(recv_publish.encode().unwrap(), publish.encode().unwrap());
+}
+
+#[test]
+fn test_paused_client_receives_publishes_on_resume() {
+    let (server, port) = start_server(None, None);
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+    let mut control = [0u8];
+
+    // Mando subscribe
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+
+    // Recibo suback
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream, control[0]).unwrap();
+
+    server.pause_client("id").unwrap();
+
+    // Publico varios mensajes mientras esta pausado, esperando
+    // entre cada uno para que se encolen en orden
+    let publishes: Vec<Publish> = (0..3)
+        .map(|i| Publish::new(false, QoSLevel0, false, "topic", i.to_string(), None).unwrap())
+        .collect();
+    for publish in &publishes {
+        server.publish(publish.clone()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+    }
+
+    // No recibo nada
+    stream
+        .set_read_timeout(Some(Duration::from_millis(300)))
+        .unwrap();
+    assert!(stream.read_exact(&mut control).is_err());
+    stream.set_read_timeout(None).unwrap();
+
+    // Al reanudar, recibo los mensajes en orden
+    server.resume_client("id").unwrap();
+    for publish in &publishes {
+        stream.read_exact(&mut control).unwrap();
+        assert_eq!(control[0] >> 4, 3);
+        let recv_publish = Publish::read_from(&mut stream, control[0]).unwrap();
+        assert_eq!(recv_publish.payload(), publish.payload());
+    }
+}
+
+#[test]
+fn test_presence_topic() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.presence_topic_prefix = Some("clients".to_string());
+    let (_s, port) = start_server_with_config(config);
+    let builder = ConnectBuilder::new("id", 0, true).unwrap();
+    let mut stream = connect_client(builder, port, true);
+    let builder_obs = ConnectBuilder::new("obs", 0, true).unwrap();
+    let mut stream_obs = connect_client(builder_obs, port, true);
+    let mut control = [0u8];
+    thread::sleep(Duration::from_millis(100));
+
+    // Me suscribo a la presencia del cliente
+    let subscribe = Subscribe::new(tpc![("clients/id/status", QoSLevel0)], 123);
+    stream_obs.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_obs.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_obs, control[0]).unwrap();
+
+    // Recibo el mensaje retenido online
+    stream_obs.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_obs, control[0]).unwrap();
+    assert_eq!(recv_publish.topic_name(), "clients/id/status");
+    assert_eq!(recv_publish.payload(), b"online");
+    assert!(recv_publish.retain_flag());
+
+    // El cliente se desconecta, recibo offline
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    stream_obs.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_obs, control[0]).unwrap();
+    assert_eq!(recv_publish.topic_name(), "clients/id/status");
+    assert_eq!(recv_publish.payload(), b"offline");
+
+    // El mensaje retenido ahora es offline
+    let builder_late = ConnectBuilder::new("late", 0, true).unwrap();
+    let mut stream_late = connect_client(builder_late, port, true);
+    let subscribe = Subscribe::new(tpc![("clients/id/status", QoSLevel0)], 124);
+    stream_late.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_late.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_late, control[0]).unwrap();
+    stream_late.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_late, control[0]).unwrap();
+    assert_eq!(recv_publish.payload(), b"offline");
+    assert!(recv_publish.retain_flag());
+}
+
+// Se suscribe con un cliente, se reemplaza su conexion por otra
+// con el mismo `clean_session` y se verifica que los publish
+// lleguen a la nueva conexion
+fn assert_takeover_keeps_subscriptions(port: u16) {
+    let builder = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream_old = connect_client(builder, port, true);
+    let builder_pub = ConnectBuilder::new("pub", 0, true).unwrap();
+    let mut stream_pub = connect_client(builder_pub, port, true);
+    let mut control = [0u8];
+
+    // Mando subscribe
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream_old.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_old.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_old, control[0]).unwrap();
+
+    // Takeover
+    let builder = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream_new = connect_client(builder, port, true);
+    thread::sleep(Duration::from_millis(100));
+
+    // Mando publish
+    let publish = Publish::new(false, QoSLevel0, false, "topic", "message", None).unwrap();
+    stream_pub.write_all(&publish.encode().unwrap()).unwrap();
+
+    // Recibo publish en la nueva conexion
+    stream_new.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_new, control[0]).unwrap();
+    assert_eq!(recv_publish.payload(), b"message");
+}
+
+#[test]
+fn test_takeover_keeps_subscriptions() {
+    let (_s, port) = start_server(None, None);
+    assert_takeover_keeps_subscriptions(port);
+}
+
+#[test]
+fn test_takeover_within_reconnect_grace_keeps_subscriptions() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.reconnect_grace = Some(Duration::from_secs(60));
+    let (_s, port) = start_server_with_config(config);
+    assert_takeover_keeps_subscriptions(port);
+}
+
+#[test]
+fn test_max_global_publishes_per_second() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.max_global_publishes_per_second = Some(20);
+    let (_s, port) = start_server_with_config(config);
+    let builder = ConnectBuilder::new("sub", 0, true).unwrap();
+    let mut stream_sub = connect_client(builder, port, true);
+    let mut control = [0u8];
+
+    // Mando subscribe
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream_sub.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_sub.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_sub, control[0]).unwrap();
+
+    // 3 clientes publican 15 mensajes cada uno al mismo tiempo
+    let start = std::time::Instant::now();
+    let publishers: Vec<_> = (0..3)
+        .map(|i| {
+            let builder = ConnectBuilder::new(&format!("pub{}", i), 0, true).unwrap();
+            let mut stream = connect_client(builder, port, true);
+            thread::spawn(move || {
+                let publish =
+                    Publish::new(false, QoSLevel0, false, "topic", "message", None).unwrap();
+                let bytes = publish.encode().unwrap();
+                for _ in 0..15 {
+                    stream.write_all(&bytes).unwrap();
+                }
+                stream
+            })
+        })
+        .collect();
+
+    // Recibo todos los mensajes, con a lo sumo 20 por segundo,
+    // tras la rafaga inicial de 20 mensajes
+    for _ in 0..45 {
+        stream_sub.read_exact(&mut control).unwrap();
+        assert_eq!(control[0] >> 4, 3);
+        let _ = Publish::read_from(&mut stream_sub, control[0]).unwrap();
+    }
+    let elapsed = start.elapsed();
+    assert!(elapsed >= Duration::from_millis(1100), "{:?}", elapsed);
+    for publisher in publishers {
+        publisher.join().unwrap();
+    }
+}
+
+#[test]
+fn test_global_publish_rate_does_not_delay_other_clients_packets() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.max_global_publishes_per_second = Some(10);
+    let (_s, port) = start_server_with_config(config);
+    let builder_pub = ConnectBuilder::new("pub", 0, true).unwrap();
+    let mut stream_pub = connect_client(builder_pub, port, true);
+    let builder_sub = ConnectBuilder::new("sub", 0, true).unwrap();
+    let mut stream_sub = connect_client(builder_sub, port, true);
+    let mut control = [0u8];
+
+    // Mando mas publish de los que se pueden procesar en varios segundos
+    let publish = Publish::new(false, QoSLevel0, false, "topic", "message", None).unwrap();
+    let bytes = publish.encode().unwrap();
+    for _ in 0..60 {
+        stream_pub.write_all(&bytes).unwrap();
+    }
+    thread::sleep(Duration::from_millis(100));
+
+    // El subscribe del otro cliente se responde sin esperar a los publish
+    let start = std::time::Instant::now();
+    let subscribe = Subscribe::new(tpc![("other", QoSLevel0)], 123);
+    stream_sub.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_sub.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_sub, control[0]).unwrap();
+    assert!(start.elapsed() < Duration::from_secs(1));
+}
+
+#[test]
+fn test_retained_history() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.retained_history_depth = Some(3);
+    let (_s, port) = start_server_with_config(config);
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let mut control = [0u8];
+
+    // Mando 3 publish retained de cliente 2
+    for payload in ["uno", "dos", "tres"] {
+        let publish = Publish::new(false, QoSLevel0, true, "topic", payload, None).unwrap();
+        stream_2.write_all(&publish.encode().unwrap()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+    }
+
+    // Pido el historial con cliente 1
+    let subscribe = Subscribe::new(tpc![("$history/topic", QoSLevel0)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_1, control[0]).unwrap();
+
+    // Recibo los 3 mensajes en orden
+    for payload in ["uno", "dos", "tres"] {
+        stream_1.read_exact(&mut control).unwrap();
+        assert_eq!(control[0] >> 4, 3);
+        let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+        assert_eq!(recv_publish.topic_name(), "topic");
+        assert_eq!(recv_publish.payload(), payload.as_bytes());
+    }
+}
+
+#[test]
+fn test_max_retained_messages_evicts_oldest() {
+    let port = free_port();
+    let mut config = ConfigMock::new(port, None, None);
+    config.max_retained_messages = Some(2);
+    let server = Server::new(config, 20).unwrap();
+    let _controller = server.clone().run().unwrap();
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let mut control = [0u8];
+
+    // Mando 3 publish retained de cliente 2, en topics distintos
+    for topic in ["a", "b", "c"] {
+        let publish = Publish::new(false, QoSLevel0, true, topic, "retenido", None).unwrap();
+        stream_2.write_all(&publish.encode().unwrap()).unwrap();
+        thread::sleep(Duration::from_millis(100));
+    }
+    assert_eq!(server.retained_count().unwrap(), 2);
+
+    // Me suscribo a todo con cliente 1
+    let subscribe = Subscribe::new(tpc![("#", QoSLevel0)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_1, control[0]).unwrap();
+
+    // Solo quedan los 2 mas nuevos
+    let mut topics = Vec::new();
+    for _ in 0..2 {
+        stream_1.read_exact(&mut control).unwrap();
+        assert_eq!(control[0] >> 4, 3);
+        let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+        topics.push(recv_publish.topic_name().to_string());
+    }
+    topics.sort_unstable();
+    assert_eq!(topics, vec!["b", "c"]);
+
+    // No llega ningun otro retained
+    stream_1
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    assert!(stream_1.read_exact(&mut control).is_err());
+}
+
+#[test]
+fn test_max_retained_messages_reject_acknowledges_and_drops() {
+    let port = free_port();
+    let mut config = ConfigMock::new(port, None, None);
+    config.max_retained_messages = Some(1);
+    config.retained_overflow = RetainedOverflow::Reject;
+    let server = Server::new(config, 20).unwrap();
+    let _controller = server.clone().run().unwrap();
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let mut control = [0u8];
+
+    // Los dos publish retained con QoS 1 reciben su PUBACK, aunque
+    // el segundo se rechaza por haber alcanzado el maximo
+    for (packet_id, topic) in [(1, "a"), (2, "b")] {
+        let publish =
+            Publish::new(false, QoSLevel1, true, topic, "retenido", Some(packet_id)).unwrap();
+        stream_2.write_all(&publish.encode().unwrap()).unwrap();
+        stream_2.read_exact(&mut control).unwrap();
+        assert_eq!(control[0] >> 4, 4);
+        let puback = Puback::read_from(&mut stream_2, control[0]).unwrap();
+        assert_eq!(puback.packet_id(), packet_id);
+    }
+    assert_eq!(server.retained_count().unwrap(), 1);
+
+    // Me suscribo a todo con cliente 1
+    let subscribe = Subscribe::new(tpc![("#", QoSLevel0)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_1, control[0]).unwrap();
+
+    // Solo queda el primero
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(recv_publish.topic_name(), "a");
+    stream_1
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    assert!(stream_1.read_exact(&mut control).is_err());
+
+    // El cliente que publico sigue conectado
+    let publish = Publish::new(false, QoSLevel1, false, "c", "hola", Some(3)).unwrap();
+    stream_2.write_all(&publish.encode().unwrap()).unwrap();
+    stream_2.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 4);
+}
+
+#[test]
+fn test_force_clean_session_keeps_no_session() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.force_clean_session = true;
+    let (_s, port) = start_server_with_config(config);
+    let mut control = [0u8];
+
+    // Me conecto con clean_session false y me suscribo a topic
+    let builder_1 = ConnectBuilder::new("id1", 0, false).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel1)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_1, control[0]).unwrap();
+
+    stream_1
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    drop(stream_1);
+    thread::sleep(Duration::from_millis(100));
+
+    // Me reconecto: la sesion no deberia estar presente
+    let builder_1 = ConnectBuilder::new("id1", 0, false).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, false);
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 2);
+    let connack = Connack::read_from(&mut stream_1, control[0]).unwrap();
+    assert!(!connack.session_present());
+
+    // Y no deberia recibir publicaciones de topic
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let publish = Publish::new(false, QoSLevel0, false, "topic", "message", None).unwrap();
+    stream_2.write_all(&publish.encode().unwrap()).unwrap();
+
+    stream_1
+        .set_read_timeout(Some(Duration::from_millis(500)))
+        .unwrap();
+    assert_eq!(
+        stream_1.read_exact(&mut control).unwrap_err().kind(),
+        std::io::ErrorKind::WouldBlock
+    );
+}
+
+#[test]
+fn test_reconcile_keeps_persistent_sessions() {
+    let port = free_port();
+    let server = Server::new(ConfigMock::new(port, None, None), 20).unwrap();
+    let _controller = server.clone().run().unwrap();
+    let mut control = [0u8];
+
+    // Me conecto con clean_session false, me suscribo y me desconecto
+    let builder = ConnectBuilder::new("id", 0, false).unwrap();
+    let mut stream = connect_client(builder, port, true);
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream, control[0]).unwrap();
+    stream
+        .write_all(&Disconnect::new().encode().unwrap())
+        .unwrap();
+    drop(stream);
+    thread::sleep(Duration::from_millis(100));
+
+    // La sesion persistente no es huerfana
+    assert!(server.reconcile_subscriptions().unwrap().is_empty());
+}
+
+#[test]
+fn test_pending_ack_timeout_disconnects_unresponsive_subscriber() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.pending_ack_timeout = Some(Duration::from_secs(1));
+    let (_s, port) = start_server_with_config(config);
+    let mut control = [0u8];
+
+    // El suscriptor nunca confirma los publish ni manda otros paquetes,
+    // como si la conexion estuviera medio abierta
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel1)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_1, control[0]).unwrap();
+
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let publish = Publish::new(false, QoSLevel1, false, "topic", "message", Some(10)).unwrap();
+    stream_2.write_all(&publish.encode().unwrap()).unwrap();
+
+    // Recibo el publish (y sus reenvios) hasta que el servidor corta
+    let start = std::time::Instant::now();
+    loop {
+        match stream_1.read(&mut control) {
+            Ok(1) => {
+                assert_eq!(control[0] >> 4, 3);
+                let _ = Publish::read_from(&mut stream_1, control[0]).unwrap();
+            }
+            Ok(_) => break,
+            Err(err) => {
+                assert_ne!(err.kind(), std::io::ErrorKind::WouldBlock);
+                break;
+            }
+        }
+    }
+    assert!(start.elapsed() < Duration::from_secs(5));
+}
+
+#[test]
+fn test_retained_refresh() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.retained_refresh = true;
+    let (_s, port) = start_server_with_config(config);
+    let builder_1 = ConnectBuilder::new("id1", 0, true).unwrap();
+    let mut stream_1 = connect_client(builder_1, port, true);
+    let builder_2 = ConnectBuilder::new("id2", 0, true).unwrap();
+    let mut stream_2 = connect_client(builder_2, port, true);
+    let mut control = [0u8];
+
+    // Cliente 2 retiene un mensaje en topic
+    let retained = Publish::new(false, QoSLevel0, true, "topic", "retenido", None).unwrap();
+    stream_2.write_all(&retained.encode().unwrap()).unwrap();
+    thread::sleep(Duration::from_millis(100));
+
+    // Cliente 1 se suscribe y recibe el retenido
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel0)], 123);
+    stream_1.write_all(&subscribe.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut stream_1, control[0]).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(recv_publish.payload(), b"retenido");
+
+    // Pide los retenidos de nuevo
+    let refresh = Publish::new(false, QoSLevel1, false, "$refresh/topic", "", Some(5)).unwrap();
+    stream_1.write_all(&refresh.encode().unwrap()).unwrap();
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 4);
+    let puback = Puback::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(puback.packet_id(), 5);
+    stream_1.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 3);
+    let recv_publish = Publish::read_from(&mut stream_1, control[0]).unwrap();
+    assert_eq!(recv_publish.topic_name(), "topic");
+    assert_eq!(recv_publish.payload(), b"retenido");
+    assert!(recv_publish.retain_flag());
+}
+
+// Lee los PUBLISH que lleguen hasta que pase el timeout del stream,
+// y devuelve sus packet ids (sin repetir los reenvios)
+fn read_publish_ids(stream: &mut std::net::TcpStream) -> HashSet<u16> {
+    let mut ids = HashSet::new();
+    let mut control = [0u8];
+    while stream.read_exact(&mut control).is_ok() {
+        assert_eq!(control[0] >> 4, 3);
+        let publish = Publish::read_from(stream, control[0]).unwrap();
+        ids.insert(publish.packet_id().unwrap());
+    }
+    ids
+}
+
+#[test]
+fn test_inflight_window_bounds_unacknowledged() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.inflight_window = Some((2, 5));
+    let (_s, port) = start_server_with_config(config);
+    let mut subscriber = connect_client(ConnectBuilder::new("slow", 0, true).unwrap(), port, true);
+    let mut publisher = connect_client(ConnectBuilder::new("fast", 0, true).unwrap(), port, true);
+    let mut control = [0u8];
+
+    let subscribe = Subscribe::new(tpc![("topic", QoSLevel1)], 123);
+    subscriber.write_all(&subscribe.encode().unwrap()).unwrap();
+    subscriber.read_exact(&mut control).unwrap();
+    assert_eq!(control[0] >> 4, 9);
+    let _ = Suback::read_from(&mut subscriber, control[0]).unwrap();
+
+    // El publisher manda 20 mensajes sin esperar al suscriptor
+    for id in 1..=20 {
+        let publish = Publish::new(false, QoSLevel1, false, "topic", "message", Some(id)).unwrap();
+        publisher.write_all(&publish.encode().unwrap()).unwrap();
+        publisher.read_exact(&mut control).unwrap();
+        assert_eq!(control[0] >> 4, 4);
+        let _ = Puback::read_from(&mut publisher, control[0]).unwrap();
+    }
+
+    // El suscriptor no confirma nada: solo recibe hasta el limite
+    subscriber
+        .set_read_timeout(Some(Duration::from_secs(1)))
+        .unwrap();
+    let first = read_publish_ids(&mut subscriber);
+    assert_eq!(first.len(), 5);
+
+    // Confirma 4: queda 1 sin confirmar, por debajo del minimo
+    for id in first.iter().take(4) {
+        subscriber
+            .write_all(&Puback::new(*id).unwrap().encode().unwrap())
+            .unwrap();
+    }
+    let second = read_publish_ids(&mut subscriber);
+    let new_ids: HashSet<_> = second.difference(&first).collect();
+    assert_eq!(new_ids.len(), 4);
+}
+
+#[test]
+fn test_lowercase_topics_match_regardless_of_case() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.lowercase_topics = true;
+    let (_s, port) = start_server_with_config(config);
+    let mut subscriber =
+        ScriptedClient::connect(ConnectBuilder::new("sub", 0, true).unwrap(), port);
+    let mut publisher = ScriptedClient::connect(ConnectBuilder::new("pub", 0, true).unwrap(), port);
+
+    subscriber.subscribe(tpc![("a/b", QoSLevel0)], 1);
+    let publish = Publish::new(false, QoSLevel0, false, "A/B", "message", None).unwrap();
+    publisher.publish(&publish);
+
+    let received = subscriber.expect_publish();
+    assert_eq!(received.topic_name(), "a/b");
+    assert_eq!(received.payload(), b"message");
+}
+
+#[test]
+fn test_publish_over_incoming_limit_closes_connection() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.max_incoming_message_size = Some(4);
+    let (_s, port) = start_server_with_config(config);
+    let mut publisher = ScriptedClient::connect(ConnectBuilder::new("pub", 0, true).unwrap(), port);
+
+    let publish = Publish::new(false, QoSLevel1, false, "topic", "12345", Some(1)).unwrap();
+    publisher.publish(&publish);
+
+    // El servidor cierra la conexion sin mandar el PUBACK
+    let mut buf = [0u8; 1];
+    match publisher.stream().read(&mut buf) {
+        Ok(0) => (),
+        Ok(_) => panic!("Se recibio un paquete en lugar del cierre de la conexion"),
+        Err(err) => assert_ne!(err.kind(), std::io::ErrorKind::WouldBlock),
+    }
+}
+
+#[test]
+fn test_publish_over_outgoing_limit_is_not_delivered() {
+    let mut config = ConfigMock::new(0, None, None);
+    config.max_outgoing_message_size = Some(4);
+    let (_s, port) = start_server_with_config(config);
+    let mut subscriber =
+        ScriptedClient::connect(ConnectBuilder::new("sub", 0, true).unwrap(), port);
+    let mut publisher = ScriptedClient::connect(ConnectBuilder::new("pub", 0, true).unwrap(), port);
+
+    subscriber.subscribe(tpc![("topic", QoSLevel0)], 1);
+    // El publicador no tiene limite de entrada, asi que se acepta
+    let big = Publish::new(false, QoSLevel1, false, "topic", "12345", Some(1)).unwrap();
+    publisher.publish(&big);
+    publisher.expect_puback(1);
+    let small = Publish::new(false, QoSLevel0, false, "topic", "1234", None).unwrap();
+    publisher.publish(&small);
+
+    // Solo llega el que no supera el limite de salida
+    let received = subscriber.expect_publish();
+    assert_eq!(received.payload(), b"1234");
+}
+
+#[test]
+fn test_slow_consumer_event_and_flag() {
+    let port = free_port();
+    let mut config = ConfigMock::new(port, None, None);
+    config.inflight_window = Some((1, 3));
+    let server = Server::new(config, 20).unwrap();
+    let (event_sender, events) = mpsc::channel();
+    let event_sender = Mutex::new(event_sender);
+    server
+        .set_event_listener(Some(Box::new(move |event| {
+            event_sender.lock().unwrap().send(event.clone()).unwrap();
+        })))
+        .unwrap();
+    let _controller = server.clone().run().unwrap();
+    let mut subscriber =
+        ScriptedClient::connect(ConnectBuilder::new("slow", 0, true).unwrap(), port);
+    let mut publisher =
+        ScriptedClient::connect(ConnectBuilder::new("fast", 0, true).unwrap(), port);
+
+    subscriber.subscribe(tpc![("topic", QoSLevel1)], 1);
+    for id in 1..=5 {
+        let publish = Publish::new(false, QoSLevel1, false, "topic", "message", Some(id)).unwrap();
+        publisher.publish(&publish);
+        publisher.expect_puback(id);
+    }
+
+    // El suscriptor no confirma nada, y alcanza el limite una sola vez
+    let event = events.recv_timeout(Duration::from_secs(5)).unwrap();
+    assert_eq!(
+        event,
+        ServerEvent::SlowConsumer {
+            id: "slow".to_string()
+        }
+    );
+    assert!(events.recv_timeout(Duration::from_millis(500)).is_err());
+    let traffic = server.client_traffic().unwrap();
+    let slow = traffic.iter().find(|client| client.id == "slow").unwrap();
+    assert!(slow.slow_consumer);
+    let fast = traffic.iter().find(|client| client.id == "fast").unwrap();
+    assert!(!fast.slow_consumer);
 }